@@ -0,0 +1,100 @@
+//! Compares the two ways the reaper lists its children, on a system with many processes:
+//! reading the stat entry of every process in /proc, and reading the children of its threads
+//! from `/proc/<pid>/task/<tid>/children`.
+//!
+//! Run with `cargo bench --bench child_scan`. The amount of processes which are started besides
+//! the children is taken from `PROCESSES`, and defaults to 5000.
+
+use std::fs::{read_dir, read_to_string};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+const ROUNDS: u32 = 50;
+const CHILDREN: usize = 10;
+
+/// The children of the process, as found by reading the stat entry of every process.
+fn scan_all(parent: u32) -> Vec<u32> {
+    read_dir("/proc")
+        .unwrap()
+        .filter_map(|de| de.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| {
+            read_to_string(format!("/proc/{}/stat", pid))
+                .ok()
+                .and_then(|stat| {
+                    let rest = &stat[stat.rfind(')')? + 1..];
+                    rest.split_whitespace().nth(1)?.parse::<u32>().ok()
+                })
+                == Some(parent)
+        })
+        .collect()
+}
+
+/// The children of the process, as listed for each of its threads.
+fn read_children(parent: u32) -> Vec<u32> {
+    read_dir(format!("/proc/{}/task", parent))
+        .unwrap()
+        .filter_map(|task| read_to_string(task.ok()?.path().join("children")).ok())
+        .flat_map(|content| {
+            content
+                .split_whitespace()
+                .filter_map(|pid| pid.parse().ok())
+                .collect::<Vec<u32>>()
+        })
+        .collect()
+}
+
+fn time(name: &str, f: impl Fn() -> Vec<u32>) {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        assert_eq!(f().len(), CHILDREN);
+    }
+    let per_round = start.elapsed() / ROUNDS;
+    println!("{:>14}: {:?} per listing", name, per_round);
+}
+
+fn main() {
+    let processes: usize = std::env::var("PROCESSES")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(5000);
+
+    // the processes are orphaned right away, so they are not children, and are in a process
+    // group of their own so they can be cleaned up at once
+    let mut spawner = Command::new("sh")
+        .arg("-c")
+        .arg(format!(
+            "for i in $(seq {}); do sleep 600 & done",
+            processes
+        ))
+        .process_group(0)
+        .spawn()
+        .expect("failed to start the processes");
+    let group = spawner.id() as libc::pid_t;
+    assert!(spawner.wait().unwrap().success());
+    let children: Vec<Child> = (0..CHILDREN)
+        .map(|_| Command::new("sleep").arg("600").spawn().unwrap())
+        .collect();
+    // let the processes settle
+    std::thread::sleep(Duration::from_millis(500));
+
+    let own = std::process::id();
+    let total = read_dir("/proc")
+        .unwrap()
+        .filter(|de| {
+            de.as_ref()
+                .ok()
+                .and_then(|de| de.file_name().to_str()?.parse::<u32>().ok())
+                .is_some()
+        })
+        .count();
+    println!("{} processes, {} of which are children", total, CHILDREN);
+    time("full scan", || scan_all(own));
+    time("children file", || read_children(own));
+
+    unsafe { libc::kill(-group, libc::SIGKILL) };
+    for mut child in children {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
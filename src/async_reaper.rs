@@ -0,0 +1,206 @@
+//! An async front-end for the reaper, for applications which run on tokio, behind the `async`
+//! feature.
+//!
+//! The reaper still supervises the services in its own loop, on a thread of its own. The
+//! threads of the runtime don't block the signals of the reaper, so the signals are caught
+//! through `tokio::signal` instead, and passed on to the loop. What happens to the services is
+//! yielded as a stream of [`ReaperEvent`]s, and the services are controlled through async
+//! methods.
+//!
+//! [`ReaperEvent`]: enum.ReaperEvent.html
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::FromRawFd;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+
+use futures_core::Stream;
+use nix::fcntl::OFlag;
+use nix::sys::signal::Signal;
+use nix::unistd::pipe2;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::events::ReaperEvent;
+use crate::handle::{Operation, NOT_RUNNING};
+use crate::observer::EventForwarder;
+use crate::power::PowerAction;
+use crate::{ControlHandle, Reaper, ReaperError, ServiceStatus};
+
+/// The signals which are caught for the reaper. SIGHUP and SIGWINCH are ignored unless the
+/// reaper has a config loader or a primary service.
+const SIGNALS: [Signal; 8] = [
+    Signal::SIGCHLD,
+    Signal::SIGINT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+    Signal::SIGIO,
+    Signal::SIGHUP,
+    Signal::SIGWINCH,
+];
+
+/// Given to the function which sets up the reaper in [`AsyncReaper::spawn`], to attach the
+/// reaper to the front-end.
+///
+/// [`AsyncReaper::spawn`]: struct.AsyncReaper.html#method.spawn
+pub struct Frontend {
+    events: mpsc::UnboundedSender<ReaperEvent>,
+    /// The read end of the pipe the caught signals are passed on through.
+    signals: File,
+    attached: oneshot::Sender<ControlHandle>,
+}
+
+impl Frontend {
+    /// Attach the reaper, which is to be spawned right after, on the same thread.
+    pub fn attach<'a>(self, reaper: Reaper<'a>) -> Reaper<'a> {
+        let events = self.events;
+        // the stream might have been dropped, which leaves the reaper running
+        let mut reaper = reaper.observer(EventForwarder(move |event| {
+            let _ = events.send(event);
+        }));
+        if let Err(e) = reaper.events.inject_from(self.signals) {
+            error!(
+                "Failed to receive the signals caught by the async runtime: {}",
+                e
+            );
+        }
+        let _ = self.attached.send(reaper.control_handle());
+        reaper
+    }
+}
+
+/// A reaper running on a thread of its own, controlled from a tokio runtime. This is a stream
+/// of what happens to the services, which ends once the reaper returned.
+pub struct AsyncReaper {
+    handle: ControlHandle,
+    events: mpsc::UnboundedReceiver<ReaperEvent>,
+    outcome: oneshot::Receiver<Result<PowerAction, ReaperError>>,
+}
+
+impl AsyncReaper {
+    /// Run a reaper on a new thread. Setup is called on that thread, to create the reaper,
+    /// [`attach`] it and spawn the services, returning what [`Reaper::spawn`] returns. This
+    /// returns once the reaper is attached, or fails if setup returned without attaching it.
+    ///
+    /// This must be called in a runtime with IO enabled, which must keep running as long as
+    /// the reaper does, as it catches the signals for the reaper.
+    ///
+    /// [`attach`]: struct.Frontend.html#method.attach
+    /// [`Reaper::spawn`]: struct.Reaper.html#method.spawn
+    pub async fn spawn<F>(setup: F) -> io::Result<Self>
+    where
+        F: FnOnce(Frontend) -> Result<PowerAction, ReaperError> + Send + 'static,
+    {
+        // the signals are caught before the reaper exists, so none are missed
+        let mut caught = Vec::new();
+        for &sig in &SIGNALS {
+            caught.push((sig, signal(SignalKind::from_raw(sig as libc::c_int))?));
+        }
+        let (rx, tx) = pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        let (rx, tx) = unsafe { (File::from_raw_fd(rx), Arc::new(File::from_raw_fd(tx))) };
+        for (sig, mut stream) in caught {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                while stream.recv().await.is_some() {
+                    match (&*tx).write(&[sig as u8]) {
+                        Ok(_) => (),
+                        // the reaper has plenty of signals to handle already
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                        // the reaper returned
+                        Err(_) => break,
+                    }
+                }
+            });
+        }
+
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let (attached_tx, attached) = oneshot::channel();
+        let (outcome_tx, outcome) = oneshot::channel();
+        let frontend = Frontend {
+            events: events_tx,
+            signals: rx,
+            attached: attached_tx,
+        };
+        thread::Builder::new()
+            .name("reaper".to_string())
+            .spawn(move || {
+                let _ = outcome_tx.send(setup(frontend));
+            })?;
+        let handle = attached
+            .await
+            .map_err(|_| io::Error::other("the reaper was not attached"))?;
+        Ok(AsyncReaper {
+            handle,
+            events,
+            outcome,
+        })
+    }
+
+    /// Start a stopped service, like `rsinitctl start`.
+    pub async fn start(&self, name: &str) -> Result<(), String> {
+        self.request(Operation::Start, name).await
+    }
+
+    /// Stop a service, which is not respawned until it is started again. This returns once the
+    /// reaper sent the service its stop signal, not once it exited.
+    pub async fn stop(&self, name: &str) -> Result<(), String> {
+        self.request(Operation::Stop, name).await
+    }
+
+    /// Stop a service and start it again once it exited. This returns once the reaper sent the
+    /// service its stop signal.
+    pub async fn restart(&self, name: &str) -> Result<(), String> {
+        self.request(Operation::Restart, name).await
+    }
+
+    async fn request(&self, op: Operation, name: &str) -> Result<(), String> {
+        let (tx, rx) = oneshot::channel();
+        self.handle.submit(
+            op,
+            name,
+            Box::new(move |outcome| {
+                let _ = tx.send(outcome);
+            }),
+        )?;
+        rx.await.map_err(|_| NOT_RUNNING.to_string())?
+    }
+
+    /// Get the status of the named service.
+    pub fn status(&self, name: &str) -> Option<ServiceStatus> {
+        self.handle.status(name)
+    }
+
+    /// The handle on the services of the reaper, e.g. to list them, or to pass on to another
+    /// task.
+    pub fn handle(&self) -> &ControlHandle {
+        &self.handle
+    }
+
+    /// Wait for the next event, `None` is returned once the reaper returned.
+    pub async fn next_event(&mut self) -> Option<ReaperEvent> {
+        self.events.recv().await
+    }
+
+    /// Wait for the reaper to return, e.g. once all services were stopped on SIGTERM, with
+    /// what the signal asks to do with the system.
+    ///
+    /// # Panics
+    ///
+    /// If setup panicked.
+    pub async fn wait(self) -> Result<PowerAction, ReaperError> {
+        self.outcome.await.expect("the reaper panicked")
+    }
+}
+
+impl Stream for AsyncReaper {
+    type Item = ReaperEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ReaperEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
@@ -0,0 +1,766 @@
+//! Setting up the filesystems every Linux system expects, for when init really is the first
+//! process, e.g. in a bare initramfs or a minimal container, where nothing mounted them yet. The
+//! reaper relies on /proc to find its children.
+//!
+//! The filesystems listed in `/etc/fstab` can be mounted as well, like `mount -a` does, and the
+//! rest of the basic setup init is expected to do: setting the hostname, bringing up the
+//! loopback interface, applying kernel parameters and setting the clock. The kernel settings
+//! which decide what happens when things go wrong, like panics, are best applied first thing,
+//! through [`KernelSettings`].
+//!
+//! [`KernelSettings`]: struct.KernelSettings.html
+
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::mount::{mount, MsFlags};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::unistd::sethostname;
+
+use crate::mounts;
+use crate::sys;
+
+/// Where the filesystems to mount at boot are listed.
+pub const FSTAB_PATH: &str = "/etc/fstab";
+
+/// Where the hostname is configured.
+pub const HOSTNAME_PATH: &str = "/etc/hostname";
+
+/// The kernel parameters to apply at boot, after the ones in [`SYSCTL_DIR`].
+///
+/// [`SYSCTL_DIR`]: constant.SYSCTL_DIR.html
+pub const SYSCTL_CONF: &str = "/etc/sysctl.conf";
+
+/// A directory of `.conf` files with kernel parameters to apply at boot, in the order of their
+/// names.
+pub const SYSCTL_DIR: &str = "/etc/sysctl.d";
+
+/// Where the seed of the random number generator is kept between boots.
+pub const RANDOM_SEED_PATH: &str = "/var/lib/rsinit/random-seed";
+
+/// Where the unique id of the machine is kept.
+pub const MACHINE_ID_PATH: &str = "/etc/machine-id";
+
+/// The real time clock which keeps the time while the machine is off.
+pub const RTC_PATH: &str = "/dev/rtc0";
+
+/// Where the time is saved on shutdown, so the clock of a machine without a real time clock at
+/// least doesn't go back in time on the next boot.
+pub const CLOCK_STAMP_PATH: &str = "/var/lib/rsinit/clock";
+
+/// How many bytes of randomness are saved as the seed, as much as the pool of the kernel holds.
+const RANDOM_SEED_SIZE: usize = 512;
+
+/// A filesystem which is mounted while booting.
+struct PseudoFilesystem {
+    fstype: &'static str,
+    target: &'static str,
+    flags: MsFlags,
+    options: Option<&'static str>,
+}
+
+/// Mount the pseudo filesystems at their usual places, unless something is mounted there
+/// already, so this can be done regardless of what set up the system before: proc at /proc,
+/// sysfs at /sys, devtmpfs at /dev, devpts at /dev/pts and tmpfs at /run. Missing mount points
+/// are created. This stops at the first filesystem which fails to mount, as the later ones
+/// depend on the earlier ones.
+pub fn mount_pseudo_filesystems() -> io::Result<()> {
+    let nosuid_nodev_noexec = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+    let filesystems = [
+        PseudoFilesystem {
+            fstype: "proc",
+            target: "/proc",
+            flags: nosuid_nodev_noexec,
+            options: None,
+        },
+        PseudoFilesystem {
+            fstype: "sysfs",
+            target: "/sys",
+            flags: nosuid_nodev_noexec,
+            options: None,
+        },
+        PseudoFilesystem {
+            fstype: "devtmpfs",
+            target: "/dev",
+            flags: MsFlags::MS_NOSUID,
+            options: Some("mode=0755"),
+        },
+        PseudoFilesystem {
+            fstype: "devpts",
+            target: "/dev/pts",
+            flags: MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+            // group 5 is the tty group by convention
+            options: Some("gid=5,mode=0620,ptmxmode=0666"),
+        },
+        PseudoFilesystem {
+            fstype: "tmpfs",
+            target: "/run",
+            flags: MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            options: Some("mode=0755"),
+        },
+    ];
+
+    for fs in &filesystems {
+        // without /proc nothing is known to be mounted, which is the case it is mounted for
+        let mounted = mounts::mount_points().unwrap_or_default();
+        if mounted.iter().any(|point| point == fs.target) {
+            debug!("{} is mounted already", fs.target);
+            continue;
+        }
+        fs::create_dir_all(fs.target)?;
+        mount(
+            Some(fs.fstype),
+            fs.target,
+            Some(fs.fstype),
+            fs.flags,
+            fs.options,
+        )
+        .map_err(|e| io::Error::other(format!("failed to mount {}: {}", fs.target, e)))?;
+        debug!("Mounted {} at {}", fs.fstype, fs.target);
+    }
+    Ok(())
+}
+
+/// A filesystem listed in fstab.
+#[derive(Debug)]
+struct FstabEntry {
+    source: String,
+    target: String,
+    fstype: String,
+    flags: MsFlags,
+    // the options which are not flags, passed on to the filesystem
+    data: Vec<String>,
+    auto: bool,
+    // failing to mount the filesystem is not an error
+    nofail: bool,
+}
+
+impl FstabEntry {
+    /// Parse a line like `UUID=1234 /var ext4 defaults,noatime 0 2`. The last two fields are
+    /// ignored, as there is no fsck to run.
+    fn parse(line: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 {
+            return Err(format!("expected at least 3 fields in {:?}", line));
+        }
+        let mut entry = FstabEntry {
+            source: source_device(&mounts::unescape(fields[0])),
+            target: mounts::normalize(&mounts::unescape(fields[1])).to_string(),
+            fstype: fields[2].to_string(),
+            flags: MsFlags::empty(),
+            data: Vec::new(),
+            auto: true,
+            nofail: false,
+        };
+        for option in fields.get(3).unwrap_or(&"defaults").split(',') {
+            let (flag, set) = match option {
+                "defaults" | "rw" | "auto" | "user" | "nouser" | "users" | "owner" | "group"
+                | "_netdev" => continue,
+                "noauto" => {
+                    entry.auto = false;
+                    continue;
+                }
+                "nofail" => {
+                    entry.nofail = true;
+                    continue;
+                }
+                // options meant for userspace tools
+                _ if option.starts_with("x-") || option.starts_with("comment=") => continue,
+                "ro" => (MsFlags::MS_RDONLY, true),
+                "nosuid" => (MsFlags::MS_NOSUID, true),
+                "suid" => (MsFlags::MS_NOSUID, false),
+                "nodev" => (MsFlags::MS_NODEV, true),
+                "dev" => (MsFlags::MS_NODEV, false),
+                "noexec" => (MsFlags::MS_NOEXEC, true),
+                "exec" => (MsFlags::MS_NOEXEC, false),
+                "sync" => (MsFlags::MS_SYNCHRONOUS, true),
+                "async" => (MsFlags::MS_SYNCHRONOUS, false),
+                "noatime" => (MsFlags::MS_NOATIME, true),
+                "atime" => (MsFlags::MS_NOATIME, false),
+                "nodiratime" => (MsFlags::MS_NODIRATIME, true),
+                "diratime" => (MsFlags::MS_NODIRATIME, false),
+                "relatime" => (MsFlags::MS_RELATIME, true),
+                "norelatime" => (MsFlags::MS_RELATIME, false),
+                "strictatime" => (MsFlags::MS_STRICTATIME, true),
+                "bind" => (MsFlags::MS_BIND, true),
+                "rbind" => (MsFlags::MS_BIND | MsFlags::MS_REC, true),
+                _ => {
+                    entry.data.push(option.to_string());
+                    continue;
+                }
+            };
+            entry.flags.set(flag, set);
+        }
+        Ok(entry)
+    }
+
+    fn mount(&self) -> nix::Result<()> {
+        let data = self.data.join(",");
+        mount(
+            Some(self.source.as_str()),
+            self.target.as_str(),
+            Some(self.fstype.as_str()),
+            self.flags,
+            if data.is_empty() {
+                None
+            } else {
+                Some(data.as_str())
+            },
+        )
+    }
+}
+
+/// Find the device for a source like `UUID=1234` through the links udev maintains.
+fn source_device(source: &str) -> String {
+    let tags = [
+        ("UUID=", "by-uuid"),
+        ("LABEL=", "by-label"),
+        ("PARTUUID=", "by-partuuid"),
+        ("PARTLABEL=", "by-partlabel"),
+    ];
+    for (tag, dir) in &tags {
+        if let Some(value) = source.strip_prefix(tag) {
+            return format!("/dev/disk/{}/{}", dir, value.trim_matches('"'));
+        }
+    }
+    source.to_string()
+}
+
+/// Mount the filesystems listed in the fstab at path, in the order they are listed, skipping
+/// the ones marked `noauto`, swap and the ones which are mounted already, like the root
+/// filesystem. A filesystem which fails to mount is logged and skipped, so one bad entry
+/// doesn't keep the system from booting; an error is only returned if the fstab can't be read.
+/// Returns how many filesystems failed to mount.
+pub fn mount_fstab(path: &str) -> io::Result<usize> {
+    let content = fs::read_to_string(path)?;
+    let mut failed = 0;
+    for (idx, entry) in parse_fstab(&content) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                error!("Ignoring line {} of {}: {}", idx + 1, path, e);
+                failed += 1;
+                continue;
+            }
+        };
+        if !entry.auto || entry.fstype == "swap" || entry.target == "none" {
+            debug!("Not mounting {} at boot", entry.target);
+            continue;
+        }
+        let mounted = mounts::mount_points().unwrap_or_default();
+        if mounted.contains(&entry.target) {
+            debug!("{} is mounted already", entry.target);
+            continue;
+        }
+        match entry.mount() {
+            Ok(_) => info!("Mounted {} at {}", entry.source, entry.target),
+            Err(e) if entry.nofail => {
+                warn!(
+                    "Failed to mount {} at {}: {}",
+                    entry.source, entry.target, e
+                )
+            }
+            Err(e) => {
+                error!(
+                    "Failed to mount {} at {}: {}",
+                    entry.source, entry.target, e
+                );
+                failed += 1;
+            }
+        }
+    }
+    Ok(failed)
+}
+
+/// The entries of an fstab, with the index of their line. Empty lines and comments are skipped.
+fn parse_fstab(content: &str) -> impl Iterator<Item = (usize, Result<FstabEntry, String>)> + '_ {
+    content
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(idx, line)| (idx, FstabEntry::parse(line)))
+}
+
+/// The valid entries of the fstab at path, or none if there is no fstab. Invalid entries are
+/// reported by [`mount_fstab`].
+///
+/// [`mount_fstab`]: fn.mount_fstab.html
+fn fstab_entries(path: &str) -> io::Result<Vec<FstabEntry>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(parse_fstab(&content)
+            .filter_map(|(_, entry)| entry.ok())
+            .collect()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Remount the root filesystem read-write, as the kernel mounts it read-only when booted with
+/// `ro`, so it can be checked first. It is left read-only if the entry for `/` in the fstab at
+/// path has the `ro` option; the other options of that entry are applied as well. Returns
+/// whether the root filesystem was remounted.
+pub fn remount_root_rw(fstab: &str) -> io::Result<bool> {
+    let entry = fstab_entries(fstab)?
+        .into_iter()
+        .find(|entry| entry.target == "/");
+    let current = statvfs("/")
+        .map_err(|e| io::Error::other(format!("failed to inspect /: {}", e)))?
+        .flags();
+    if !current.contains(FsFlags::ST_RDONLY) {
+        return Ok(false);
+    }
+    let (flags, data) = match &entry {
+        Some(entry) if entry.flags.contains(MsFlags::MS_RDONLY) => {
+            debug!("Leaving / read-only, as the fstab asks");
+            return Ok(false);
+        }
+        // the mount options are replaced by a remount, so they are taken from the fstab
+        Some(entry) => (
+            entry.flags - (MsFlags::MS_BIND | MsFlags::MS_REC),
+            entry.data.join(","),
+        ),
+        // or kept as they are
+        None => {
+            let kept = [
+                (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+                (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+                (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+                (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+                (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+            ];
+            let flags = kept
+                .iter()
+                .filter(|(st, _)| current.contains(*st))
+                .fold(MsFlags::empty(), |flags, (_, ms)| flags | *ms);
+            (flags, String::new())
+        }
+    };
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REMOUNT | flags,
+        Some(data.as_str()).filter(|data| !data.is_empty()),
+    )
+    .map_err(|e| io::Error::other(format!("failed to remount / read-write: {}", e)))?;
+    Ok(true)
+}
+
+/// Start swapping to the swap areas in the fstab at path, like `swapon -a`, skipping the ones
+/// marked `noauto` and the ones which are in use already. The `pri=N` and `discard` options
+/// are applied. A swap area which fails is logged and skipped, unless it is marked `nofail`.
+/// Returns how many swap areas failed.
+pub fn swapon_all(fstab: &str) -> io::Result<usize> {
+    // /proc/swaps lists the swap areas in use by their canonical path
+    let active: Vec<PathBuf> = fs::read_to_string("/proc/swaps")?
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|path| PathBuf::from(mounts::unescape(path)))
+        .collect();
+
+    let mut failed = 0;
+    for entry in fstab_entries(fstab)? {
+        if entry.fstype != "swap" || !entry.auto {
+            continue;
+        }
+        let path = fs::canonicalize(&entry.source).unwrap_or_else(|_| PathBuf::from(&entry.source));
+        if active.contains(&path) {
+            debug!("Swapping to {} already", entry.source);
+            continue;
+        }
+        let priority = entry
+            .data
+            .iter()
+            .find_map(|option| option.strip_prefix("pri="))
+            .and_then(|priority| priority.parse().ok());
+        let discard = entry
+            .data
+            .iter()
+            .any(|option| option == "discard" || option.starts_with("discard="));
+        let res = CString::new(entry.source.as_str())
+            .map_err(io::Error::from)
+            .and_then(|source| sys::swap_on(&source, priority, discard));
+        match res {
+            Ok(_) => info!("Swapping to {}", entry.source),
+            Err(e) if entry.nofail => warn!("Failed to swap to {}: {}", entry.source, e),
+            Err(e) => {
+                error!("Failed to swap to {}: {}", entry.source, e);
+                failed += 1;
+            }
+        }
+    }
+    Ok(failed)
+}
+
+/// Set the hostname to the first line of the file at path which isn't empty or a comment.
+/// Returns the hostname, or `None` if the file doesn't name one.
+pub fn set_hostname(path: &str) -> io::Result<Option<String>> {
+    let content = fs::read_to_string(path)?;
+    let name = content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'));
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    sethostname(name).map_err(|e| io::Error::other(format!("failed to set hostname: {}", e)))?;
+    Ok(Some(name.to_string()))
+}
+
+/// Bring up the loopback interface, which the kernel creates but leaves down.
+pub fn bring_up_loopback() -> io::Result<()> {
+    let name = CString::new("lo").expect("no nul byte in interface name");
+    sys::interface_up(&name)
+}
+
+/// Apply the kernel parameters in the `.conf` files in dir, in the order of their names, and
+/// then the ones in conf, like `sysctl --system` does. Lines read like `kernel.panic = 10`, or
+/// `-kernel.panic = 10` if failing to apply the parameter doesn't matter. A parameter or file
+/// which fails to apply is logged and skipped. Returns how many parameters failed to apply.
+pub fn apply_sysctl(conf: &str, dir: &str) -> usize {
+    let mut files: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+            .collect(),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            error!("Failed to read {}: {}", dir, e);
+            Vec::new()
+        }
+    };
+    files.sort();
+    files.push(PathBuf::from(conf));
+
+    let mut failed = 0;
+    for path in &files {
+        match fs::read_to_string(path) {
+            Ok(content) => failed += apply_sysctl_file(path, &content),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => {
+                error!("Failed to read {}: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+/// Apply the kernel parameters in the content of the file at path. Returns how many failed.
+fn apply_sysctl_file(path: &Path, content: &str) -> usize {
+    let mut failed = 0;
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let (line, optional) = match line.strip_prefix('-') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        let (key, value) = match line.find('=') {
+            Some(idx) => (line[..idx].trim(), line[idx + 1..].trim()),
+            None => {
+                error!(
+                    "Ignoring line {} of {}: expected key = value",
+                    idx + 1,
+                    path.display()
+                );
+                failed += 1;
+                continue;
+            }
+        };
+        // `kernel.panic` names /proc/sys/kernel/panic. When dots separate the key, slashes
+        // stand for dots, e.g. in `net.ipv4.conf.eth0/100.rp_filter` for a VLAN interface.
+        let file: String = match key.find(['.', '/']) {
+            Some(idx) if key.as_bytes()[idx] == b'.' => key
+                .chars()
+                .map(|c| match c {
+                    '.' => '/',
+                    '/' => '.',
+                    c => c,
+                })
+                .collect(),
+            _ => key.to_string(),
+        };
+        match fs::write(Path::new("/proc/sys").join(&file), value) {
+            Ok(_) => debug!("Set {} to {}", key, value),
+            Err(e) if optional => debug!("Failed to set {}: {}", key, e),
+            Err(e) => {
+                error!("Failed to set {} from {}: {}", key, path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+/// Mix the seed saved at path into the random number generator of the kernel, so it doesn't
+/// start out as predictable on machines which lack sources of entropy early on. The seed is not
+/// credited as entropy, as the same image might boot with the same seed more than once. A new
+/// seed is saved right away, so a seed is never used twice if the machine crashes before it is
+/// shut down. Returns whether there was a seed.
+pub fn load_random_seed(path: &str) -> io::Result<bool> {
+    let seed = match fs::read(path) {
+        Ok(seed) => seed,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/urandom")?
+        .write_all(&seed)?;
+    save_random_seed(path)?;
+    Ok(!seed.is_empty())
+}
+
+/// Save a new seed for the random number generator at path, for [`load_random_seed`] to use on
+/// the next boot. This is best done right before shutting down, and after the seed was loaded.
+///
+/// [`load_random_seed`]: fn.load_random_seed.html
+pub fn save_random_seed(path: &str) -> io::Result<()> {
+    let mut seed = [0; RANDOM_SEED_SIZE];
+    File::open("/dev/urandom")?.read_exact(&mut seed)?;
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // written next to it and renamed, so a crash never leaves half a seed behind
+    let tmp = format!("{}.tmp", path);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)?;
+    file.write_all(&seed)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Generate the id of the machine at path, unless it has a valid one already: 32 lowercase
+/// hexadecimal digits, as a random UUID. Returns the new id, or `None` if it was there already.
+/// This fails on a read-only root filesystem, in which case the id has to be part of the image.
+pub fn init_machine_id(path: &str) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(id) if is_machine_id(id.trim()) => return Ok(None),
+        Ok(_) => warn!("{} holds no valid machine id, replacing it", path),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    // version 4 and variant 1, like the UUIDs of systemd-machine-id-setup
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let id: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let tmp = format!("{}.tmp", path);
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o444)
+        .open(&tmp)?
+        .write_all(format!("{}\n", id).as_bytes())?;
+    fs::rename(&tmp, path)?;
+    Ok(Some(id))
+}
+
+fn is_machine_id(id: &str) -> bool {
+    id.len() == 32
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+        && id.bytes().any(|b| b != b'0')
+}
+
+/// Set the system clock to the time of the real time clock at rtc, like `hwclock --hctosys`.
+/// The real time clock keeps UTC, or the local time if utc is false, as when it is shared with
+/// Windows. Returns the time the system clock was set to.
+pub fn hwclock_to_system(rtc: &str, utc: bool) -> io::Result<SystemTime> {
+    let file = File::open(rtc)?;
+    let time = sys::rtc_read_time(file.as_raw_fd())?;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_sec = time.sec;
+    tm.tm_min = time.min;
+    tm.tm_hour = time.hour;
+    tm.tm_mday = time.mday;
+    tm.tm_mon = time.mon;
+    tm.tm_year = time.year;
+    // whether daylight saving time is in effect is left to mktime
+    tm.tm_isdst = -1;
+    let secs = unsafe {
+        if utc {
+            libc::timegm(&mut tm)
+        } else {
+            libc::mktime(&mut tm)
+        }
+    };
+    if secs < 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} holds an invalid time", rtc),
+        ));
+    }
+    sys::set_wall_clock(secs, 0)?;
+    Ok(UNIX_EPOCH + Duration::from_secs(secs as u64))
+}
+
+/// Set the real time clock at rtc to the time of the system clock, like `hwclock --systohc`, so
+/// it keeps the time NTP corrected while the machine is off. See [`hwclock_to_system`] for utc.
+///
+/// [`hwclock_to_system`]: fn.hwclock_to_system.html
+pub fn system_to_hwclock(rtc: &str, utc: bool) -> io::Result<()> {
+    let file = File::open(rtc)?;
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "the clock is before 1970"))?
+        .as_secs() as sys::TimeT;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let res = unsafe {
+        if utc {
+            libc::gmtime_r(&secs, &mut tm)
+        } else {
+            libc::localtime_r(&secs, &mut tm)
+        }
+    };
+    if res.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let time = sys::RtcTime {
+        sec: tm.tm_sec,
+        min: tm.tm_min,
+        hour: tm.tm_hour,
+        mday: tm.tm_mday,
+        mon: tm.tm_mon,
+        year: tm.tm_year,
+        wday: tm.tm_wday,
+        yday: tm.tm_yday,
+        isdst: 0,
+    };
+    sys::rtc_set_time(file.as_raw_fd(), &time)
+}
+
+/// Step the system clock forward to the time saved at path by [`save_clock_stamp`], if it is
+/// behind, e.g. at 1970 on a board without a real time clock. The clock is never stepped back.
+/// Returns the time the clock was set to, or `None` if it was left alone or nothing was saved.
+///
+/// [`save_clock_stamp`]: fn.save_clock_stamp.html
+pub fn advance_clock(path: &str) -> io::Result<Option<SystemTime>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let secs: u64 = content.trim().parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} holds no time", path),
+        )
+    })?;
+    let saved = UNIX_EPOCH + Duration::from_secs(secs);
+    if SystemTime::now() >= saved {
+        return Ok(None);
+    }
+    sys::set_wall_clock(secs as sys::TimeT, 0)?;
+    Ok(Some(saved))
+}
+
+/// Save the time of the system clock at path, for [`advance_clock`] to use on the next boot.
+///
+/// [`advance_clock`]: fn.advance_clock.html
+pub fn save_clock_stamp(path: &str) -> io::Result<()> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "the clock is before 1970"))?
+        .as_secs();
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, format!("{}\n", secs))?;
+    fs::rename(&tmp, path)
+}
+
+/// What the kernel does when things go wrong: on panics, oopses, the magic SysRq key and crashing
+/// processes. Settings which are not set are left as the kernel has them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KernelSettings {
+    panic: Option<i64>,
+    panic_on_oops: Option<bool>,
+    sysrq: Option<u32>,
+    core_pattern: Option<String>,
+}
+
+impl KernelSettings {
+    pub fn new() -> Self {
+        KernelSettings::default()
+    }
+
+    /// Reboot the given amount of seconds after a panic, as `kernel.panic`. With 0 the system
+    /// hangs, and with a negative amount it reboots right away.
+    pub fn panic_timeout(mut self, secs: i64) -> Self {
+        self.panic = Some(secs);
+        self
+    }
+
+    /// Panic on an oops, as `kernel.panic_on_oops`, rather than carrying on with a kernel in an
+    /// unknown state.
+    pub fn panic_on_oops(mut self, panic: bool) -> Self {
+        self.panic_on_oops = Some(panic);
+        self
+    }
+
+    /// Enable the functions of the magic SysRq key in the given bitmask, as `kernel.sysrq`: 0
+    /// disables it, and 1 enables all functions.
+    pub fn sysrq(mut self, mask: u32) -> Self {
+        self.sysrq = Some(mask);
+        self
+    }
+
+    /// Name the core dumps of crashing processes after the given pattern, as
+    /// `kernel.core_pattern`, e.g. `/var/crash/core.%e.%p`. A pattern starting with `|` pipes
+    /// the dumps to a program.
+    pub fn core_pattern(mut self, pattern: &str) -> Self {
+        self.core_pattern = Some(pattern.to_string());
+        self
+    }
+
+    /// Apply the settings. A setting which fails to apply is logged and skipped. Returns how
+    /// many settings failed to apply.
+    pub fn apply(&self) -> usize {
+        let settings = [
+            ("kernel/panic", self.panic.map(|secs| secs.to_string())),
+            (
+                "kernel/panic_on_oops",
+                self.panic_on_oops.map(|panic| (panic as u8).to_string()),
+            ),
+            ("kernel/sysrq", self.sysrq.map(|mask| mask.to_string())),
+            ("kernel/core_pattern", self.core_pattern.clone()),
+        ];
+        let mut failed = 0;
+        for (key, value) in &settings {
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+            match fs::write(Path::new("/proc/sys").join(key), value) {
+                Ok(_) => debug!("Set {} to {}", key.replace('/', "."), value),
+                Err(e) => {
+                    error!("Failed to set {}: {}", key.replace('/', "."), e);
+                    failed += 1;
+                }
+            }
+        }
+        failed
+    }
+}
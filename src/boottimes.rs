@@ -0,0 +1,114 @@
+//! When booting reached each of its phases, and when every service was first spawned and
+//! became ready, measured from when the kernel booted, to find out where boot time goes.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::json::Value;
+
+static PHASES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+/// The time since the kernel booted, including the time it was suspended.
+pub fn since_boot() -> Duration {
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut ts);
+    }
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Record that booting finished the named phase, like mounting the filesystems. Only the
+/// first time a phase is finished is recorded.
+pub fn phase(name: &'static str) {
+    if let Ok(mut phases) = PHASES.lock() {
+        if !phases.iter().any(|(phase, _)| *phase == name) {
+            phases.push((name, since_boot()));
+        }
+    }
+}
+
+/// When a service was first spawned, and became ready, since the kernel booted.
+#[derive(Debug)]
+struct ServiceTiming {
+    name: String,
+    spawned: Duration,
+    ready: Option<Duration>,
+}
+
+/// When the services were first spawned and became ready, in the order they were spawned.
+/// Later restarts are not recorded, so this tells how the services came up while booting.
+#[derive(Debug, Default)]
+pub(crate) struct BootTimes {
+    services: Vec<ServiceTiming>,
+    all_ready: bool,
+}
+
+impl BootTimes {
+    /// Record that a process of the service was spawned, which is ready right away unless it
+    /// notifies when it is.
+    pub(crate) fn spawned(&mut self, name: &str, ready: bool) {
+        if self.services.iter().any(|service| service.name == name) {
+            return;
+        }
+        let now = since_boot();
+        self.services.push(ServiceTiming {
+            name: name.to_string(),
+            spawned: now,
+            ready: Some(now).filter(|_| ready),
+        });
+        self.all_ready = false;
+        self.check_all_ready();
+    }
+
+    /// Record that the service notified it is ready.
+    pub(crate) fn ready(&mut self, name: &str) {
+        let service = self
+            .services
+            .iter_mut()
+            .find(|service| service.name == name && service.ready.is_none());
+        if let Some(service) = service {
+            service.ready = Some(since_boot());
+            self.check_all_ready();
+        }
+    }
+
+    /// Record the phase in which all services spawned so far are ready, the first time they
+    /// are.
+    fn check_all_ready(&mut self) {
+        if !self.all_ready && self.services.iter().all(|service| service.ready.is_some()) {
+            self.all_ready = true;
+            phase("services ready");
+        }
+    }
+
+    /// Describe the phases of booting in the order they were finished, and the services in
+    /// the order they were spawned, with the times in seconds since the kernel booted.
+    pub(crate) fn to_json(&self) -> Value {
+        let phases: Vec<Value> = PHASES
+            .lock()
+            .map(|phases| {
+                phases
+                    .iter()
+                    .map(|(name, at)| {
+                        Value::object()
+                            .with("name", *name)
+                            .with("at", at.as_secs_f64())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let services: Vec<Value> = self
+            .services
+            .iter()
+            .map(|service| {
+                Value::object()
+                    .with("name", service.name.as_str())
+                    .with("spawned", service.spawned.as_secs_f64())
+                    .with("ready", service.ready.map(|at| at.as_secs_f64()))
+            })
+            .collect();
+        Value::object()
+            .with("phases", phases)
+            .with("services", services)
+    }
+}
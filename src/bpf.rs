@@ -0,0 +1,453 @@
+//! An experimental source of process lifecycle events, which attaches small BPF programs to the
+//! `sched_process_fork` and `sched_process_exit` tracepoints. Every fork and exit in the system
+//! is streamed to init through a BPF ring buffer, so the full lineage of every process is known,
+//! even for processes which were reparented or started a new session, without scanning /proc.
+//!
+//! The programs are assembled by hand, with the offsets of the fields they read taken from the
+//! tracepoint formats of the running kernel, so no compiler or BPF library is needed. BPF ring
+//! buffers require Linux 5.8 or newer, and tracefs must be mounted.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use nix::unistd::Pid;
+
+use crate::lineage::ProcessEvent;
+
+const TRACEFS_PATHS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+const BPF_MAP_CREATE: libc::c_int = 0;
+const BPF_PROG_LOAD: libc::c_int = 5;
+const BPF_MAP_TYPE_RINGBUF: u32 = 27;
+const BPF_PROG_TYPE_TRACEPOINT: u32 = 5;
+const BPF_FUNC_RINGBUF_OUTPUT: i32 = 130;
+const BPF_PSEUDO_MAP_FD: u8 = 1;
+
+const BPF_RINGBUF_BUSY_BIT: u32 = 1 << 31;
+const BPF_RINGBUF_DISCARD_BIT: u32 = 1 << 30;
+const BPF_RINGBUF_HDR_SZ: usize = 8;
+
+const PERF_TYPE_TRACEPOINT: u32 = 2;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_EVENT_IOC_SET_BPF: libc::c_ulong = 0x4004_2408;
+
+/// The size of the ring buffer, which must be a power of 2 multiple of the page size.
+const RING_PAGES: usize = 64;
+
+/// The size of the log the verifier may write when a program is rejected.
+const VERIFIER_LOG_SIZE: usize = 64 * 1024;
+
+const KIND_FORK: i32 = 0;
+const KIND_EXIT: i32 = 1;
+/// The size of an event written by the programs: the kind and two pids, padded to 8 bytes.
+const EVENT_SIZE: usize = 16;
+
+#[repr(C)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+#[repr(C)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+}
+
+/// The first version of `struct perf_event_attr`, which every kernel accepts.
+#[repr(C)]
+struct PerfEventAttr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Insn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+impl Insn {
+    const fn new(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Self {
+        Insn {
+            code,
+            regs: (src << 4) | dst,
+            off,
+            imm,
+        }
+    }
+
+    /// `dst = src`
+    const fn mov_reg(dst: u8, src: u8) -> Self {
+        Insn::new(0xbf, dst, src, 0, 0)
+    }
+
+    /// `dst = imm`
+    const fn mov_imm(dst: u8, imm: i32) -> Self {
+        Insn::new(0xb7, dst, 0, 0, imm)
+    }
+
+    /// `dst += imm`
+    const fn add_imm(dst: u8, imm: i32) -> Self {
+        Insn::new(0x07, dst, 0, 0, imm)
+    }
+
+    /// `dst = *(u32 *)(src + off)`
+    const fn load_word(dst: u8, src: u8, off: i16) -> Self {
+        Insn::new(0x61, dst, src, off, 0)
+    }
+
+    /// `*(u32 *)(dst + off) = src`
+    const fn store_word(dst: u8, off: i16, src: u8) -> Self {
+        Insn::new(0x63, dst, src, off, 0)
+    }
+
+    /// `*(u32 *)(dst + off) = imm`
+    const fn store_imm(dst: u8, off: i16, imm: i32) -> Self {
+        Insn::new(0x62, dst, 0, off, imm)
+    }
+
+    /// `dst = map`, which takes two instructions.
+    const fn load_map(dst: u8, map: RawFd) -> [Self; 2] {
+        [
+            Insn::new(0x18, dst, BPF_PSEUDO_MAP_FD, 0, map),
+            Insn::new(0, 0, 0, 0, 0),
+        ]
+    }
+
+    const fn call(helper: i32) -> Self {
+        Insn::new(0x85, 0, 0, 0, helper)
+    }
+
+    const fn exit() -> Self {
+        Insn::new(0x95, 0, 0, 0, 0)
+    }
+}
+
+/// Assemble a program which writes an event of the given kind to the ring buffer, with the two
+/// pids read from the given offsets in the tracepoint record, or 0 for a missing offset.
+fn assemble(kind: i32, fields: [Option<i16>; 2], ring: RawFd) -> Vec<Insn> {
+    let mut prog = vec![
+        Insn::mov_reg(6, 1),
+        Insn::store_imm(10, -16, kind),
+        Insn::store_imm(10, -12, 0),
+        Insn::store_imm(10, -8, 0),
+        Insn::store_imm(10, -4, 0),
+    ];
+    for (idx, field) in fields.iter().enumerate() {
+        if let Some(off) = field {
+            prog.push(Insn::load_word(2, 6, *off));
+            prog.push(Insn::store_word(10, -12 + 4 * idx as i16, 2));
+        }
+    }
+    prog.extend_from_slice(&Insn::load_map(1, ring));
+    prog.extend_from_slice(&[
+        Insn::mov_reg(2, 10),
+        Insn::add_imm(2, -(EVENT_SIZE as i32)),
+        Insn::mov_imm(3, EVENT_SIZE as i32),
+        Insn::mov_imm(4, 0),
+        Insn::call(BPF_FUNC_RINGBUF_OUTPUT),
+        Insn::mov_imm(0, 0),
+        Insn::exit(),
+    ]);
+    prog
+}
+
+/// A tracepoint of the running kernel: its id, and the offsets of its fields by name.
+struct Tracepoint {
+    id: u64,
+    fields: HashMap<String, i16>,
+}
+
+impl Tracepoint {
+    fn find(name: &str) -> io::Result<Self> {
+        let dir = TRACEFS_PATHS
+            .iter()
+            .map(|tracefs| format!("{}/events/sched/{}", tracefs, name))
+            .find(|dir| fs::metadata(dir).is_ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("tracepoint {} not found, is tracefs mounted?", name),
+                )
+            })?;
+        let id = fs::read_to_string(format!("{}/id", dir))?
+            .trim()
+            .parse()
+            .map_err(|_| invalid(format!("malformed id of tracepoint {}", name)))?;
+        // every field is described as `field:pid_t pid;	offset:24;	size:4;	signed:1;`
+        let fields = fs::read_to_string(format!("{}/format", dir))?
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.trim().split(';');
+                let field = parts.next()?.strip_prefix("field:")?;
+                let name = field.rsplit(' ').next()?;
+                let offset = parts.next()?.trim().strip_prefix("offset:")?.parse().ok()?;
+                Some((name.to_string(), offset))
+            })
+            .collect();
+        Ok(Tracepoint { id, fields })
+    }
+
+    fn field(&self, name: &str) -> io::Result<i16> {
+        self.fields
+            .get(name)
+            .copied()
+            .ok_or_else(|| invalid(format!("tracepoint has no field {}", name)))
+    }
+}
+
+/// The stream of fork and exit events. Its file descriptor becomes readable when events are
+/// pending.
+pub(crate) struct ProcessEvents {
+    ring: RawFd,
+    programs: Vec<RawFd>,
+    perf_events: Vec<RawFd>,
+    consumer: *mut libc::c_void,
+    producer: *mut libc::c_void,
+    page_size: usize,
+    ring_size: usize,
+}
+
+impl ProcessEvents {
+    /// Load and attach the programs. This requires CAP_BPF and CAP_PERFMON, or CAP_SYS_ADMIN.
+    pub(crate) fn open() -> io::Result<Self> {
+        let fork = Tracepoint::find("sched_process_fork")?;
+        let exit = Tracepoint::find("sched_process_exit")?;
+
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let ring_size = page_size * RING_PAGES;
+        let mut events = ProcessEvents {
+            ring: create_ring(ring_size)?,
+            programs: Vec::new(),
+            perf_events: Vec::new(),
+            consumer: libc::MAP_FAILED,
+            producer: libc::MAP_FAILED,
+            page_size,
+            ring_size,
+        };
+
+        let fork_fields = [
+            Some(fork.field("parent_pid")?),
+            Some(fork.field("child_pid")?),
+        ];
+        let exit_fields = [Some(exit.field("pid")?), None];
+        for (tracepoint, kind, fields) in [
+            (&fork, KIND_FORK, fork_fields),
+            (&exit, KIND_EXIT, exit_fields),
+        ] {
+            let program = load_program(&assemble(kind, fields, events.ring))?;
+            events.programs.push(program);
+            events.perf_events.push(attach(tracepoint.id, program)?);
+        }
+
+        unsafe {
+            // the consumer position is written by us, the producer position and the data are
+            // only read. The data is mapped twice in a row, so records which wrap around are
+            // contiguous.
+            events.consumer = libc::mmap(
+                std::ptr::null_mut(),
+                page_size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                events.ring,
+                0,
+            );
+            if events.consumer == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            events.producer = libc::mmap(
+                std::ptr::null_mut(),
+                page_size + 2 * ring_size,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                events.ring,
+                page_size as libc::off_t,
+            );
+            if events.producer == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(events)
+    }
+
+    /// Take all pending events, oldest first. The tracepoints don't distinguish processes from
+    /// threads, so these are events of either.
+    pub(crate) fn read(&mut self) -> Vec<ProcessEvent> {
+        let mut events = Vec::new();
+        unsafe {
+            let consumer = &*(self.consumer as *const AtomicU64);
+            let producer = &*(self.producer as *const AtomicU64);
+            let data = (self.producer as *const u8).add(self.page_size);
+
+            let mut pos = consumer.load(Ordering::Acquire);
+            while pos < producer.load(Ordering::Acquire) {
+                let record = data.add(pos as usize & (self.ring_size - 1));
+                let len = (*(record as *const AtomicU32)).load(Ordering::Acquire);
+                if len & BPF_RINGBUF_BUSY_BIT != 0 {
+                    // still being written
+                    break;
+                }
+                let size = (len & !BPF_RINGBUF_DISCARD_BIT) as usize;
+                if len & BPF_RINGBUF_DISCARD_BIT == 0 && size == EVENT_SIZE {
+                    let fields = record.add(BPF_RINGBUF_HDR_SZ) as *const i32;
+                    let (first, second) = (
+                        Pid::from_raw(fields.add(1).read_unaligned()),
+                        Pid::from_raw(fields.add(2).read_unaligned()),
+                    );
+                    events.push(match fields.read_unaligned() {
+                        KIND_FORK => ProcessEvent::Fork {
+                            parent: first,
+                            child: second,
+                        },
+                        _ => ProcessEvent::Exit(first),
+                    });
+                }
+                pos += ((BPF_RINGBUF_HDR_SZ + size + 7) & !7) as u64;
+                consumer.store(pos, Ordering::Release);
+            }
+        }
+        events
+    }
+}
+
+impl AsRawFd for ProcessEvents {
+    fn as_raw_fd(&self) -> RawFd {
+        self.ring
+    }
+}
+
+impl Drop for ProcessEvents {
+    fn drop(&mut self) {
+        unsafe {
+            if self.producer != libc::MAP_FAILED {
+                libc::munmap(self.producer, self.page_size + 2 * self.ring_size);
+            }
+            if self.consumer != libc::MAP_FAILED {
+                libc::munmap(self.consumer, self.page_size);
+            }
+            // closing the perf events detaches the programs
+            for fd in self.perf_events.iter().chain(&self.programs) {
+                libc::close(*fd);
+            }
+            libc::close(self.ring);
+        }
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn bpf<T>(cmd: libc::c_int, attr: &T) -> io::Result<RawFd> {
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *const T,
+            std::mem::size_of::<T>() as libc::c_uint,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd as RawFd)
+}
+
+fn create_ring(size: usize) -> io::Result<RawFd> {
+    bpf(
+        BPF_MAP_CREATE,
+        &MapCreateAttr {
+            map_type: BPF_MAP_TYPE_RINGBUF,
+            key_size: 0,
+            value_size: 0,
+            max_entries: size as u32,
+            map_flags: 0,
+        },
+    )
+}
+
+/// Load a tracepoint program. If the verifier rejects it, its log is part of the error.
+fn load_program(insns: &[Insn]) -> io::Result<RawFd> {
+    let license = b"GPL\0";
+    let mut log = vec![0u8; VERIFIER_LOG_SIZE];
+    let attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_TRACEPOINT,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 1,
+        log_size: log.len() as u32,
+        log_buf: log.as_mut_ptr() as u64,
+    };
+    bpf(BPF_PROG_LOAD, &attr).map_err(|e| {
+        let len = log.iter().position(|b| *b == 0).unwrap_or(log.len());
+        let log = String::from_utf8_lossy(&log[..len]);
+        io::Error::new(
+            e.kind(),
+            format!("failed to load program: {}: {}", e, log.trim()),
+        )
+    })
+}
+
+/// Attach a program to a tracepoint, through a perf event. A program attached to a tracepoint
+/// runs on every CPU, so a single event suffices.
+fn attach(tracepoint: u64, program: RawFd) -> io::Result<RawFd> {
+    let attr = PerfEventAttr {
+        kind: PERF_TYPE_TRACEPOINT,
+        size: std::mem::size_of::<PerfEventAttr>() as u32,
+        config: tracepoint,
+        sample_period: 1,
+        sample_type: 0,
+        read_format: 0,
+        flags: 0,
+        wakeup_events: 1,
+        bp_type: 0,
+        config1: 0,
+    };
+    unsafe {
+        let fd = libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const PerfEventAttr,
+            -1 as libc::pid_t,
+            0 as libc::c_int,
+            -1 as libc::c_int,
+            PERF_FLAG_FD_CLOEXEC,
+        ) as RawFd;
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // the C libraries disagree on the type of the request
+        if libc::ioctl(fd, PERF_EVENT_IOC_SET_BPF as _, program) < 0
+            || libc::ioctl(fd, PERF_EVENT_IOC_ENABLE as _, 0) < 0
+        {
+            let err = io::Error::last_os_error();
+            libc::close(fd);
+            return Err(err);
+        }
+        Ok(fd)
+    }
+}
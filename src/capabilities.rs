@@ -0,0 +1,123 @@
+//! Linux capabilities, so a service can run as an unprivileged user and still do the one
+//! privileged thing it needs, such as binding to a port below 1024, or run as root with only a
+//! few of the privileges of root.
+
+use std::io;
+
+use crate::sys;
+
+/// A capability, as listed in capabilities(7), e.g. `NetBindService` for
+/// `CAP_NET_BIND_SERVICE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Chown = 0,
+    DacOverride = 1,
+    DacReadSearch = 2,
+    Fowner = 3,
+    Fsetid = 4,
+    Kill = 5,
+    Setgid = 6,
+    Setuid = 7,
+    Setpcap = 8,
+    LinuxImmutable = 9,
+    NetBindService = 10,
+    NetBroadcast = 11,
+    NetAdmin = 12,
+    NetRaw = 13,
+    IpcLock = 14,
+    IpcOwner = 15,
+    SysModule = 16,
+    SysRawio = 17,
+    SysChroot = 18,
+    SysPtrace = 19,
+    SysPacct = 20,
+    SysAdmin = 21,
+    SysBoot = 22,
+    SysNice = 23,
+    SysResource = 24,
+    SysTime = 25,
+    SysTtyConfig = 26,
+    Mknod = 27,
+    Lease = 28,
+    AuditWrite = 29,
+    AuditControl = 30,
+    Setfcap = 31,
+    MacOverride = 32,
+    MacAdmin = 33,
+    Syslog = 34,
+    WakeAlarm = 35,
+    BlockSuspend = 36,
+    AuditRead = 37,
+    Perfmon = 38,
+    Bpf = 39,
+    CheckpointRestore = 40,
+}
+
+/// The capabilities a process keeps, as a bit mask.
+pub(crate) fn mask(caps: &[Capability]) -> u64 {
+    caps.iter().fold(0, |mask, cap| mask | 1 << *cap as u64)
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Remove all capabilities which are not in the mask from the bounding set of the calling
+/// process, so neither it nor anything it executes can ever gain them. This requires
+/// CAP_SETPCAP, so it is done before changing user.
+pub(crate) fn limit_bounding_set(mask: u64) -> io::Result<()> {
+    for cap in 0..64 {
+        if mask & (1 << cap) != 0 {
+            continue;
+        }
+        if let Err(e) = sys::prctl(libc::PR_CAPBSET_DROP, cap, 0) {
+            // past the last capability the kernel knows of
+            if e.raw_os_error() == Some(libc::EINVAL) {
+                break;
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Keep the permitted capabilities when changing from root to another user.
+pub(crate) fn keep_on_setuid() -> io::Result<()> {
+    sys::prctl(libc::PR_SET_KEEPCAPS, 1, 0).map(drop)
+}
+
+/// Set the capabilities of the calling process to those in the mask, and raise them in the
+/// ambient set, so they are kept across exec of a program without file capabilities. Only raw
+/// syscalls are used, so this can be done in between fork and exec.
+pub(crate) fn set(mask: u64) -> io::Result<()> {
+    let header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [mask as u32, (mask >> 32) as u32].map(|bits| CapData {
+        effective: bits,
+        permitted: bits,
+        inheritable: bits,
+    });
+    if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let ambient = libc::PR_CAP_AMBIENT;
+    sys::prctl(ambient, libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong, 0)?;
+    for cap in (0..64).filter(|cap| mask & (1 << cap) != 0) {
+        sys::prctl(ambient, libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong, cap)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,227 @@
+//! Optional cgroup v2 tracking of services. Every persistent command is placed in a cgroup of
+//! its own below a common root, so all processes of a service are listed by its `cgroup.procs`,
+//! and can be killed at once through `cgroup.kill`, instead of having to scan /proc for them.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+use crate::sys::PreparedWrite;
+
+/// The cgroup processes exempted from being killed along with their service are moved to, below
+/// the root.
+const EXEMPT_CGROUP: &str = "exempt";
+
+/// The period `cpu.max` quotas are given over, in microseconds.
+const CPU_PERIOD: u64 = 100_000;
+
+/// The resource limits of a service, enforced by the controllers of its cgroup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct CgroupLimits {
+    pub(crate) memory_max: Option<u64>,
+    /// In percent of a single CPU.
+    pub(crate) cpu_quota: Option<u32>,
+    pub(crate) tasks_max: Option<u64>,
+}
+
+impl CgroupLimits {
+    pub(crate) const fn new() -> Self {
+        CgroupLimits {
+            memory_max: None,
+            cpu_quota: None,
+            tasks_max: None,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == CgroupLimits::new()
+    }
+
+    /// The controllers the limits need, with the control file and value of every limit.
+    fn controls(&self) -> Vec<(&'static str, &'static str, String)> {
+        let mut controls = Vec::new();
+        if let Some(bytes) = self.memory_max {
+            controls.push(("memory", "memory.max", bytes.to_string()));
+        }
+        if let Some(percent) = self.cpu_quota {
+            let quota = CPU_PERIOD * u64::from(percent) / 100;
+            controls.push(("cpu", "cpu.max", format!("{} {}", quota, CPU_PERIOD)));
+        }
+        if let Some(tasks) = self.tasks_max {
+            controls.push(("pids", "pids.max", tasks.to_string()));
+        }
+        controls
+    }
+}
+
+/// The cgroup below which the cgroups of all services are created.
+pub(crate) struct CgroupRoot {
+    path: PathBuf,
+    // the path as it appears in /proc/<pid>/cgroup, relative to the cgroup2 mount
+    relative: String,
+}
+
+impl CgroupRoot {
+    /// Create the root at path, which must be on a cgroup2 filesystem.
+    pub(crate) fn create(path: &str) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        let mounts = fs::read_to_string("/proc/self/mounts")?;
+        let mount = mounts
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let dir = fields.nth(1)?;
+                match fields.next()? {
+                    "cgroup2" => Some(Path::new(dir)),
+                    _ => None,
+                }
+            })
+            .find(|dir| path.starts_with(dir))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} is not on a cgroup2 filesystem", path),
+                )
+            })?;
+        let relative = format!("/{}", path.strip_prefix(mount).unwrap_or(&path).display());
+
+        fs::create_dir_all(path.join(EXEMPT_CGROUP))?;
+        Ok(CgroupRoot {
+            relative: relative.trim_end_matches('/').to_string(),
+            path,
+        })
+    }
+
+    /// Get the cgroup of a service, creating it if needed. The name must make a cgroup right
+    /// below the root, which is not the one for exempted processes, so a name like `..` or
+    /// `/sys/fs/cgroup` can't place the service elsewhere.
+    pub(crate) fn service(&self, name: &str) -> io::Result<Cgroup> {
+        if name.is_empty()
+            || name.contains('/')
+            || name == "."
+            || name == ".."
+            || name == EXEMPT_CGROUP
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} can't be the name of a cgroup", name),
+            ));
+        }
+        let path = self.path.join(name);
+        fs::create_dir_all(&path)?;
+        Ok(Cgroup { path })
+    }
+
+    /// Check if the process is in any cgroup below the root.
+    pub(crate) fn contains(&self, pid: Pid) -> bool {
+        fs::read_to_string(format!("/proc/{}/cgroup", pid))
+            .map(|content| {
+                // the unified hierarchy is the one with id 0
+                content
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("0::"))
+                    .any(|path| path.starts_with(&self.relative) && path != self.relative)
+            })
+            .unwrap_or(false)
+    }
+
+    /// Enable the controller for the cgroups of the services, and for the root itself if its
+    /// parent does not do so already.
+    fn enable_controller(&self, controller: &str) -> io::Result<()> {
+        let dirs = self
+            .path
+            .parent()
+            .into_iter()
+            .chain(Some(self.path.as_path()));
+        for dir in dirs {
+            let control = dir.join("cgroup.subtree_control");
+            let enabled = fs::read_to_string(&control)?;
+            if !enabled.split_whitespace().any(|c| c == controller) {
+                fs::write(&control, format!("+{}", controller))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Enforce the limits on the cgroup of a service, enabling the controllers they need.
+    pub(crate) fn limit(&self, cgroup: &Cgroup, limits: &CgroupLimits) -> io::Result<()> {
+        for (controller, file, value) in limits.controls() {
+            self.enable_controller(controller).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("failed to enable the {} controller: {}", controller, e),
+                )
+            })?;
+            fs::write(cgroup.path.join(file), &value).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("failed to set {} to {}: {}", file, value, e),
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Move the process out of the cgroup of its service, so it survives the service.
+    pub(crate) fn exempt(&self, pid: Pid) -> io::Result<()> {
+        fs::write(
+            self.path.join(EXEMPT_CGROUP).join("cgroup.procs"),
+            pid.to_string(),
+        )
+    }
+}
+
+/// The cgroup of a single service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Prepare moving a child into the cgroup in between fork and exec.
+    pub(crate) fn join_on_exec(&self) -> io::Result<PreparedWrite> {
+        let procs = self.path.join("cgroup.procs");
+        PreparedWrite::new(&procs.to_string_lossy(), "0")
+    }
+
+    /// Move a running process into the cgroup.
+    pub(crate) fn add(&self, pid: Pid) -> io::Result<()> {
+        fs::write(self.path.join("cgroup.procs"), pid.to_string())
+    }
+
+    /// List all processes in the cgroup.
+    pub(crate) fn procs(&self) -> Vec<Pid> {
+        match fs::read_to_string(self.path.join("cgroup.procs")) {
+            Ok(content) => content
+                .lines()
+                .filter_map(|line| line.trim().parse().ok())
+                .map(Pid::from_raw)
+                .collect(),
+            Err(e) => {
+                warn!("Failed to list processes of cgroup {:?}: {}", self.path, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Kill all processes in the cgroup, returning how many there were. Kernels older than 5.14
+    /// lack `cgroup.kill`, in which case the processes are sent SIGKILL one by one.
+    pub(crate) fn kill(&self) -> io::Result<usize> {
+        let procs = self.procs();
+        if procs.is_empty() {
+            return Ok(0);
+        }
+        match fs::write(self.path.join("cgroup.kill"), "1") {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                for pid in &procs {
+                    let _ = kill(*pid, Signal::SIGKILL);
+                }
+                Ok(procs.len())
+            }
+            res => res.map(|_| procs.len()),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Noticing jumps of the wall clock, e.g. when NTP steps it or it is restored from the RTC. All
+//! timers of the reaper run on the monotonic clock, so they are not affected, but a jump is
+//! reported, as it explains odd timestamps in logs and heartbeats.
+//!
+//! A timer on the wall clock which is armed to expire far in the future is canceled by the
+//! kernel whenever the wall clock is set, which makes its file descriptor readable.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Instant, SystemTime};
+
+use crate::sys;
+
+/// `TFD_TIMER_CANCEL_ON_SET`, cancel an absolute wall clock timer when the clock is set.
+const TFD_TIMER_CANCEL_ON_SET: libc::c_int = 1 << 1;
+
+/// A jump of the wall clock, relative to the monotonic clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ClockJump {
+    Forward(std::time::Duration),
+    Backward(std::time::Duration),
+}
+
+pub(crate) struct ClockWatch {
+    fd: RawFd,
+    // both clocks when the timer was last armed, to measure the jump against
+    wall: SystemTime,
+    monotonic: Instant,
+}
+
+impl ClockWatch {
+    pub(crate) fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut watch = ClockWatch {
+            fd,
+            wall: SystemTime::now(),
+            monotonic: Instant::now(),
+        };
+        if let Err(e) = watch.arm() {
+            unsafe { libc::close(fd) };
+            return Err(e);
+        }
+        Ok(watch)
+    }
+
+    /// Arm the timer again, after the clock was set.
+    fn arm(&mut self) -> io::Result<()> {
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            // as far in the future as it gets, so it never expires
+            it_value: libc::timespec {
+                tv_sec: sys::TimeT::MAX,
+                tv_nsec: 0,
+            },
+        };
+        let flags = libc::TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET;
+        if unsafe { libc::timerfd_settime(self.fd, flags, &spec, std::ptr::null_mut()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.wall = SystemTime::now();
+        self.monotonic = Instant::now();
+        Ok(())
+    }
+
+    /// Find out how far the wall clock jumped, once the timer was canceled. Returns None if it
+    /// was not.
+    pub(crate) fn jump(&mut self) -> Option<ClockJump> {
+        let mut expirations = 0u64;
+        let res = unsafe {
+            libc::read(
+                self.fd,
+                &mut expirations as *mut u64 as *mut libc::c_void,
+                std::mem::size_of::<u64>(),
+            )
+        };
+        if res >= 0 || io::Error::last_os_error().raw_os_error() != Some(libc::ECANCELED) {
+            return None;
+        }
+        let expected = self.wall + self.monotonic.elapsed();
+        let jump = match SystemTime::now().duration_since(expected) {
+            Ok(ahead) => ClockJump::Forward(ahead),
+            Err(e) => ClockJump::Backward(e.duration()),
+        };
+        if let Err(e) = self.arm() {
+            warn!("Failed to keep watching the system clock: {}", e);
+        }
+        Some(jump)
+    }
+}
+
+impl AsRawFd for ClockWatch {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for ClockWatch {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
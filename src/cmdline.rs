@@ -0,0 +1,158 @@
+//! Access to options passed to rsinit on the kernel command line. Options containing a dot are
+//! not passed on to init by the kernel, so `/proc/cmdline` is read instead. The same options
+//! can be given in [`OPTIONS_PATH`], which the kernel command line overrides. A reaper which
+//! does not boot the system, like the one of a user session, reads them from a file of its own.
+//!
+//! [`OPTIONS_PATH`]: constant.OPTIONS_PATH.html
+
+use std::fs::read_to_string;
+use std::sync::Mutex;
+
+/// The options of the system, separated by whitespace like on the kernel command line. Lines
+/// starting with `#` are comments.
+pub const OPTIONS_PATH: &str = "/etc/rsinit/options";
+
+/// The file the options are read from, instead of the kernel command line.
+static SOURCE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Read the options from the file at the given path from now on, instead of from the kernel
+/// command line. A file which can't be read holds no options.
+pub fn read_from(path: &str) {
+    *SOURCE.lock().unwrap() = Some(path.to_string());
+}
+
+/// Get all whitespace separated words of the options file and the kernel command line, in that
+/// order.
+fn words() -> Vec<String> {
+    let sources = match SOURCE.lock().unwrap().clone() {
+        Some(path) => vec![path],
+        None => vec![OPTIONS_PATH.to_string(), "/proc/cmdline".to_string()],
+    };
+    sources
+        .iter()
+        .filter_map(|source| read_to_string(source).ok())
+        .flat_map(|content| {
+            content
+                .lines()
+                .filter(|line| !line.trim_start().starts_with('#'))
+                .flat_map(str::split_whitespace)
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Check if the given flag, e.g. `rsinit.profile`, is present on the kernel command line.
+pub fn has_flag(name: &str) -> bool {
+    words().iter().any(|w| w == name)
+}
+
+/// Get the value of an option like `rsinit.target=rescue` on the kernel command line. If the
+/// option is present multiple times, the last one wins.
+pub fn value(name: &str) -> Option<String> {
+    words().into_iter().rev().find_map(|w| {
+        let (key, value) = w.split_at(w.find('=')?);
+        if key == name {
+            Some(value[1..].to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// Get the values of every occurrence of an option like `console=ttyS0` on the kernel command
+/// line, in order.
+pub fn values(name: &str) -> Vec<String> {
+    words()
+        .into_iter()
+        .filter_map(|w| {
+            let (key, value) = w.split_at(w.find('=')?);
+            if key == name {
+                Some(value[1..].to_string())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Get the options whose name starts with the given prefix, like `rsinit.signal.SIGPWR=poweroff`
+/// for `rsinit.signal.`, as the rest of their name and their value, in order.
+pub fn with_prefix(prefix: &str) -> Vec<(String, String)> {
+    words()
+        .into_iter()
+        .filter_map(|w| {
+            let (key, value) = w.split_at(w.find('=')?);
+            let rest = key.strip_prefix(prefix)?;
+            Some((rest.to_string(), value[1..].to_string()))
+        })
+        .collect()
+}
+
+/// Get the names of the consoles the kernel writes to, like `ttyS0` and `tty1`, from the
+/// `console=` options on the kernel command line, without their options like the baud rate in
+/// `console=ttyS0,115200n8`. Every console is only listed once.
+pub fn consoles() -> Vec<String> {
+    let mut consoles: Vec<String> = Vec::new();
+    for value in values("console") {
+        let name = value.split(',').next().unwrap_or_default();
+        if !name.is_empty() && !consoles.iter().any(|console| console == name) {
+            consoles.push(name.to_string());
+        }
+    }
+    consoles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the source is global, so every case reads the same file in a single test
+    #[test]
+    fn read_options() {
+        let path = std::env::temp_dir().join(format!("rsinit-options-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "# rsinit.target=commented\n\
+             quiet rsinit.target=rescue\tconsole=ttyS0,115200n8\n\
+             \x20 # also=commented\n\
+             console=tty1 console=ttyS0 console= console=,9600 rsinit.target=multi\n\
+             rsinit.signal.SIGPWR=poweroff rsinit.signal.SIGINT=reboot=now rsinit.signal.=x\n\
+             noequals= =novalue\n",
+        )
+        .unwrap();
+        read_from(path.to_str().unwrap());
+
+        let cases = &[("quiet", true), ("rsinit.target", false), ("also", false)];
+        for (name, present) in cases {
+            assert_eq!(has_flag(name), *present, "{:?}", name);
+        }
+        let cases = &[
+            ("rsinit.target", Some("multi")),
+            ("noequals", Some("")),
+            ("", Some("novalue")),
+            ("quiet", None),
+            ("missing", None),
+        ];
+        for (name, value_of) in cases {
+            assert_eq!(value(name).as_deref(), *value_of, "{:?}", name);
+        }
+        assert_eq!(
+            values("console"),
+            ["ttyS0,115200n8", "tty1", "ttyS0", "", ",9600"]
+        );
+        assert_eq!(consoles(), ["ttyS0", "tty1"]);
+        assert_eq!(
+            with_prefix("rsinit.signal."),
+            [
+                ("SIGPWR".to_string(), "poweroff".to_string()),
+                ("SIGINT".to_string(), "reboot=now".to_string()),
+                (String::new(), "x".to_string()),
+            ]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!has_flag("quiet"));
+        assert_eq!(values("console"), Vec::<String>::new());
+    }
+}
@@ -1,15 +1,153 @@
-use std::process::Command;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::Signal;
+use nix::unistd::getpid;
+
+use crate::capabilities::{self, Capability};
+use crate::cgroup::CgroupLimits;
+use crate::credentials::{Credentials, Passwd};
+use crate::devices::PrivateDev;
+use crate::emergency::SHELL;
+pub use crate::events::PersistentCommandError;
+use crate::health::{HealthCheck, HealthProbe};
+use crate::lsm::SecurityLabel;
+use crate::namespaces::{Namespace, Namespaces, NetnsJoin};
+use crate::orphan::{KillMode, OrphanPolicy};
+#[cfg(feature = "pam")]
+use crate::pam::PamSession;
+use crate::restart::{FailureAction, RestartAction, RestartFlags, RestartPolicy, SpawnHistory};
+use crate::seccomp::{self, CompiledFilter, SyscallFilter};
+use crate::sys::{self, PreparedWrite};
+use crate::tmpfiles::{self, EntryType};
+use crate::tty::{open_pty, open_tty, set_controlling_tty, term_for};
+
+/// How long the restart of a command is delayed after its first start failure in a row. Every
+/// further one doubles it.
+const START_FAILURE_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long the restart of a command which keeps failing to start is delayed at most.
+const MAX_START_FAILURE_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long the pre-start hook of a command may run before it is killed, by default.
+const PRE_START_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Waits for Enter to be pressed before executing the command given as its arguments, see
+/// [`PersistentCommand::ask_first`].
+const ASK_FIRST_SCRIPT: &str =
+    "printf '\\nPlease press Enter to activate this console. ' && read -r _ && exec \"$@\"";
 
 pub struct PersistentCommand<'a> {
     cmd: &'a str,
     args: &'a str,
+    name: Option<&'a str>,
+    derived_name: Option<String>,
+    after: Vec<&'a str>,
+    requires: Vec<&'a str>,
+    bound_to: Vec<&'a str>,
+    required_mounts: Vec<&'a str>,
+    required_paths: Vec<&'a str>,
+    groups: Vec<&'a str>,
+    targets: Vec<&'a str>,
+    resource_class: Option<&'a str>,
+    hold: bool,
+    watched_files: Vec<&'a str>,
+    pipe_to: Vec<&'a str>,
+    reload_signal: Option<Signal>,
+    shutdown_notice: Option<Signal>,
 
-    restart_on_success: bool,
-    restart_on_error: bool,
-    restart_on_signal: bool,
+    restart_flags: RestartFlags,
+    restart_policy: Option<Box<dyn RestartPolicy + 'a>>,
+    exit_reasons: Vec<(i32, &'a str, Option<RestartAction>)>,
+    success_codes: Vec<i32>,
+    success_signals: Vec<Signal>,
+    orphan_policy: OrphanPolicy,
+    kill_mode: Option<KillMode>,
+    pre_start: Option<OneShotCommand<'a>>,
+    pre_start_timeout: Duration,
+    // set once the pre-start hook ran for the next spawn, which was admitted before it ran
+    pre_started: bool,
+    on_success: Option<OneShotCommand<'a>>,
+    on_failure: Option<OneShotCommand<'a>>,
+    failure_action: FailureAction<'a>,
 
     spawn_limit: Option<usize>,
     spawns: usize,
+    spawn_limit_window: Option<(usize, Duration)>,
+    spawn_times: VecDeque<Instant>,
+    exit_signals: BTreeMap<Signal, u32>,
+    reset_spawns_after: Option<Duration>,
+    crash_loop_limit: Option<(usize, Duration)>,
+    // the failures in a row of processes which exited soon after they were spawned
+    fast_failures: usize,
+    start_grace: Option<Duration>,
+    // the start failures in a row, which back off the restarts
+    start_failures: u32,
+
+    security_label: Option<SecurityLabel<'a>>,
+    session_keyring: bool,
+    login_uid: Option<u32>,
+    rlimits: Vec<(libc::c_int, libc::rlimit)>,
+    nice: Option<i32>,
+    oom_score_adj: Option<i32>,
+    cgroup_limits: CgroupLimits,
+    io_priority: Option<IoPriority>,
+    cpu_affinity: Vec<usize>,
+    sched_policy: Option<SchedPolicy>,
+
+    uid: Option<u32>,
+    gid: Option<u32>,
+    user: Option<&'a str>,
+    capabilities: Option<u64>,
+    no_new_privs: bool,
+    syscall_filter: Option<SyscallFilter>,
+
+    env: Vec<(String, String)>,
+    env_file: Option<&'a str>,
+    environment_files: Vec<(&'a str, bool)>,
+    current_dir: Option<&'a str>,
+    directories: Vec<(&'a str, u32)>,
+    umask: Option<u32>,
+    chroot: Option<&'a str>,
+    private_devices: bool,
+    namespaces: Vec<Namespace>,
+    netns: Option<&'a str>,
+    allowed_devices: Vec<&'a str>,
+
+    stdin: Option<Input<'a>>,
+    stdout_path: Option<&'a str>,
+    stderr_path: Option<&'a str>,
+    tty: Option<&'a str>,
+    pty: bool,
+    login_shell: bool,
+    shell: bool,
+    ask_first: bool,
+
+    password_prompt: Option<&'a str>,
+    password: Option<Vec<u8>>,
+
+    leave_running: bool,
+    supervisor: bool,
+    primary: bool,
+    pid_file: Option<&'a str>,
+    daemonizes: Option<bool>,
+    notify: bool,
+    notify_socket: Option<String>,
+    watchdog: Option<Duration>,
+    health_check: Option<HealthCheck>,
+    startup_check: Option<HealthCheck>,
+
+    #[cfg(feature = "pam")]
+    pam_service: Option<&'a str>,
+    #[cfg(feature = "pam")]
+    pam_session: Option<PamSession>,
 }
 
 impl<'a> PersistentCommand<'a> {
@@ -17,58 +155,1440 @@ impl<'a> PersistentCommand<'a> {
         PersistentCommand {
             cmd,
             args,
+            name: None,
+            derived_name: None,
+            after: Vec::new(),
+            requires: Vec::new(),
+            bound_to: Vec::new(),
+            required_mounts: Vec::new(),
+            required_paths: Vec::new(),
+            groups: Vec::new(),
+            targets: Vec::new(),
+            resource_class: None,
+            hold: false,
+            watched_files: Vec::new(),
+            pipe_to: Vec::new(),
+            reload_signal: None,
+            shutdown_notice: None,
 
-            restart_on_success: false,
-            restart_on_error: false,
-            restart_on_signal: false,
+            restart_flags: RestartFlags::new(),
+            exit_reasons: Vec::new(),
+            restart_policy: None,
+            success_codes: Vec::new(),
+            success_signals: Vec::new(),
+            orphan_policy: OrphanPolicy::KillOnFailure,
+            kill_mode: None,
+            pre_start: None,
+            pre_start_timeout: PRE_START_TIMEOUT,
+            pre_started: false,
+            on_success: None,
+            on_failure: None,
+            failure_action: FailureAction::None,
 
             spawn_limit: None,
             spawns: 0,
+            spawn_limit_window: None,
+            spawn_times: VecDeque::new(),
+            exit_signals: BTreeMap::new(),
+            reset_spawns_after: None,
+            crash_loop_limit: None,
+            fast_failures: 0,
+            start_grace: None,
+            start_failures: 0,
+
+            security_label: None,
+            session_keyring: false,
+            login_uid: None,
+            rlimits: Vec::new(),
+            nice: None,
+            oom_score_adj: None,
+            cgroup_limits: CgroupLimits::new(),
+            io_priority: None,
+            cpu_affinity: Vec::new(),
+            sched_policy: None,
+
+            uid: None,
+            gid: None,
+            user: None,
+            capabilities: None,
+            no_new_privs: false,
+            syscall_filter: None,
+
+            env: Vec::new(),
+            env_file: None,
+            environment_files: Vec::new(),
+            current_dir: None,
+            directories: Vec::new(),
+            umask: None,
+            chroot: None,
+            private_devices: false,
+            namespaces: Vec::new(),
+            netns: None,
+            allowed_devices: Vec::new(),
+
+            stdin: None,
+            stdout_path: None,
+            stderr_path: None,
+            tty: None,
+            pty: false,
+            login_shell: false,
+            shell: false,
+            ask_first: false,
+
+            password_prompt: None,
+            password: None,
+
+            leave_running: false,
+            supervisor: false,
+            primary: false,
+            pid_file: None,
+            daemonizes: None,
+            notify: false,
+            notify_socket: None,
+            watchdog: None,
+            health_check: None,
+            startup_check: None,
+
+            #[cfg(feature = "pam")]
+            pam_service: None,
+            #[cfg(feature = "pam")]
+            pam_session: None,
+        }
+    }
+
+    /// A console on which the given user is logged in automatically, without getty or login.
+    /// The login shell of the user is started on the terminal, which is handed over to the
+    /// user. If PAM support is enabled, the session is opened through the `login` PAM service.
+    /// As with getty, the console is respawned whenever the shell exits.
+    pub fn autologin(user: &'a str, tty: &'a str) -> Self {
+        let cmd = PersistentCommand {
+            user: Some(user),
+            tty: Some(tty),
+            login_shell: true,
+            ..PersistentCommand::new("", "")
+        };
+        #[cfg(feature = "pam")]
+        let cmd = cmd.pam_session("login");
+
+        cmd.restart_on_success(true)
+            .restart_on_error(true)
+            .restart_on_signal(true)
+    }
+
+    /// A login prompt on the terminal at the given path, like `/dev/tty1` or `/dev/ttyS0`, with
+    /// `agetty`. As with [`tty`], the terminal becomes the controlling terminal of the getty. It
+    /// is respawned whenever it exits, as it does after every logout, though at most 10 times
+    /// in 2 minutes, so a terminal which doesn't work doesn't keep the system busy. The service
+    /// is named after the terminal, like `getty@tty1`.
+    ///
+    /// [`tty`]: #method.tty
+    pub fn getty(tty: &'a str) -> Self {
+        // `-` has agetty use the terminal it is started on
+        let mut cmd = PersistentCommand::new("/sbin/agetty", "--noclear -")
+            .tty(tty)
+            .restart_on_success(true)
+            .restart_on_error(true)
+            .restart_on_signal(true)
+            .spawn_limit_window(10, Duration::from_secs(120));
+        cmd.derived_name = Some(format!("getty@{}", basename(tty)));
+        cmd
+    }
+
+    /// A command line which is run by `/bin/sh -c`, so it can use quoting, pipes, redirections
+    /// and the like, e.g. `exec daemon --motd 'Hello world' 2>&1 | logger -t daemon`. The
+    /// service is named after the first word of the line, and as with any service, stopping it
+    /// signals the whole process group, so every command the shell started is stopped with it.
+    pub fn shell(line: &'a str) -> Self {
+        PersistentCommand {
+            shell: true,
+            ..PersistentCommand::new(SHELL, line)
         }
     }
 
+    /// A pipeline of commands without a shell, like
+    /// `/usr/sbin/daemon --foreground | /usr/bin/logger -t daemon`, see [`pipe_to`].
+    ///
+    /// [`pipe_to`]: #method.pipe_to
+    pub fn pipeline(line: &'a str) -> Self {
+        let mut stages = line.split('|');
+        let (cmd, args) = split_command(stages.next().unwrap_or_default());
+        let mut pcmd = PersistentCommand::new(cmd, args);
+        for stage in stages {
+            pcmd = pcmd.pipe_to(stage.trim());
+        }
+        pcmd
+    }
+
+    /// Set the name the command is known by as a service. If no name is set, the file name of
+    /// the executable is used. If another service has that name as well, a name which is
+    /// derived from the arguments is used instead, see [`Reaper::spawn`].
+    ///
+    /// [`Reaper::spawn`]: ../struct.Reaper.html#method.spawn
+    pub fn name(mut self, name: &'a str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Start the command only once the named service is running, and ready if it notifies,
+    /// unless that service fails permanently. One-shot commands always run before any
+    /// persistent command is started.
+    pub fn after(mut self, name: &'a str) -> Self {
+        self.after.push(name);
+        self
+    }
+
+    /// Start the command only once the named service is running, and ready if it notifies, or,
+    /// if it is a one-shot command, once it succeeded. If the service fails permanently, the
+    /// command is held back until the service is started through the control socket.
+    pub fn requires(mut self, name: &'a str) -> Self {
+        self.requires.push(name);
+        self
+    }
+
+    /// Restart the command whenever the named service exits and is started again, and stop it
+    /// when that service exits for good, e.g. a proxy which connects to the service only once when
+    /// it starts. Like with [`requires`], the command is only started once the service runs.
+    ///
+    /// [`requires`]: #method.requires
+    pub fn bind_to(mut self, name: &'a str) -> Self {
+        self.bound_to.push(name);
+        self
+    }
+
+    pub(crate) fn bound_to(&self) -> &[&'a str] {
+        &self.bound_to
+    }
+
+    /// Start the command only once a filesystem is mounted at the given path, for services
+    /// which use storage that shows up late, e.g. a disk which is mounted by a script.
+    pub fn requires_mount(mut self, path: &'a str) -> Self {
+        self.required_mounts.push(path);
+        self
+    }
+
+    pub(crate) fn required_mounts(&self) -> &[&'a str] {
+        &self.required_mounts
+    }
+
+    /// Start the command only once a file exists at the given path, e.g. the node of a device
+    /// which is plugged in later on. The path is checked for every so often.
+    pub fn requires_path(mut self, path: &'a str) -> Self {
+        self.required_paths.push(path);
+        self
+    }
+
+    pub(crate) fn required_paths(&self) -> &[&'a str] {
+        &self.required_paths
+    }
+
+    /// The names of the services the command depends on, and whether each is required.
+    pub(crate) fn dependencies(&self) -> impl Iterator<Item = (&'a str, bool)> + '_ {
+        self.after
+            .iter()
+            .map(|name| (*name, false))
+            .chain(self.requires.iter().map(|name| (*name, true)))
+            .chain(self.bound_to.iter().map(|name| (*name, true)))
+    }
+
+    /// Add the service to a group, so it can be managed along with the other services in the
+    /// group through the control socket, as `@group`. A service can be in any number of groups.
+    pub fn group(mut self, name: &'a str) -> Self {
+        self.groups.push(name);
+        self
+    }
+
+    pub(crate) fn groups(&self) -> &[&'a str] {
+        &self.groups
+    }
+
+    /// Make the service part of a boot target, like `default`, `single` or `network-online`,
+    /// so it is started when the target is active, see [`Reaper::target`]. A service can be
+    /// part of any number of targets. A service which is not part of any target is part of
+    /// all of them.
+    ///
+    /// [`Reaper::target`]: struct.Reaper.html#method.target
+    pub fn target(mut self, name: &'a str) -> Self {
+        self.targets.push(name);
+        self
+    }
+
+    pub(crate) fn targets(&self) -> &[&'a str] {
+        &self.targets
+    }
+
+    /// Check if the service is part of the given boot target.
+    pub(crate) fn in_target(&self, target: &str) -> bool {
+        self.targets.is_empty() || self.targets.contains(&target)
+    }
+
+    /// Tag the service with a resource class, like `disk-heavy`, so the amount of services of
+    /// the class which are starting at the same time can be limited with [`start_limit`].
+    ///
+    /// [`start_limit`]: struct.Reaper.html#method.start_limit
+    pub fn resource_class(mut self, class: &'a str) -> Self {
+        self.resource_class = Some(class);
+        self
+    }
+
+    pub(crate) fn class(&self) -> Option<&'a str> {
+        self.resource_class
+    }
+
+    /// Don't start the service until it is released through the control socket, e.g. to bring
+    /// up a system in stages.
+    pub fn hold(mut self, hold: bool) -> Self {
+        self.hold = hold;
+        self
+    }
+
+    pub(crate) fn is_held(&self) -> bool {
+        self.hold
+    }
+
+    /// Restart the service when the file at the given path changes, e.g. its configuration. A
+    /// burst of changes only restarts the service once, after things have quieted down.
+    pub fn restart_on_change(mut self, path: &'a str) -> Self {
+        self.watched_files.push(path);
+        self
+    }
+
+    /// Send the given signal to the service when a file it watches changes, instead of
+    /// restarting it, for services which can reload their configuration.
+    pub fn reload_signal(mut self, signal: Signal) -> Self {
+        self.reload_signal = Some(signal);
+        self
+    }
+
+    pub(crate) fn watched_files(&self) -> &[&'a str] {
+        &self.watched_files
+    }
+
+    pub(crate) fn change_signal(&self) -> Option<Signal> {
+        self.reload_signal
+    }
+
+    /// Send the process the given signal as soon as the system starts shutting down, ahead of
+    /// SIGTERM, e.g. so it stops accepting new work. It gets the drain period of the reaper to
+    /// finish its work and exit, see [`Reaper::shutdown_drain`].
+    ///
+    /// [`Reaper::shutdown_drain`]: struct.Reaper.html#method.shutdown_drain
+    pub fn shutdown_notice(mut self, signal: Signal) -> Self {
+        self.shutdown_notice = Some(signal);
+        self
+    }
+
+    pub(crate) fn notice_signal(&self) -> Option<Signal> {
+        self.shutdown_notice
+    }
+
     pub fn restart_on_success(mut self, restart: bool) -> Self {
-        self.restart_on_success = restart;
+        self.restart_flags.on_success = restart;
         self
     }
 
     pub fn restart_on_error(mut self, restart: bool) -> Self {
-        self.restart_on_error = restart;
+        self.restart_flags.on_error = restart;
+        self
+    }
+
+    pub fn restart_on_signal(mut self, restart: bool) -> Self {
+        self.restart_flags.on_signal = restart;
+        self
+    }
+
+    /// Only restart the command after an error if it exited with one of the given codes. This
+    /// implies `restart_on_error`.
+    pub fn restart_on_codes(mut self, codes: &[i32]) -> Self {
+        self.restart_flags.on_error = true;
+        self.restart_flags.codes.extend_from_slice(codes);
+        self
+    }
+
+    /// Don't restart the command if it was killed by one of the given signals, e.g. SIGTERM
+    /// sent by an administrator, even if `restart_on_signal` is set.
+    pub fn no_restart_on_signals(mut self, signals: &[Signal]) -> Self {
+        self.restart_flags.no_signals.extend_from_slice(signals);
+        self
+    }
+
+    /// Stop restarting the command once its processes were killed by the given signal a number
+    /// of times, e.g. to keep restarting after SIGTERM but give up on a service which keeps
+    /// crashing with SIGSEGV. Giving up is logged as an error.
+    pub fn give_up_on_signal(mut self, signal: Signal, times: u32) -> Self {
+        self.restart_flags.signal_limits.push((signal, times));
+        self
+    }
+
+    /// Explain what an exit code of the command means, e.g. `78` as `"configuration error"`, which
+    /// is shown along with the exit in the log, on the console and in the status of the service.
+    /// If an action is given, it decides whether the command is restarted after exiting with
+    /// that code, instead of the restart policy, e.g. to not restart a command which can't work
+    /// until its configuration is fixed. The spawn limits of the command still apply.
+    pub fn exit_code_reason(
+        mut self,
+        code: i32,
+        reason: &'a str,
+        action: Option<RestartAction>,
+    ) -> Self {
+        self.exit_reasons.push((code, reason, action));
+        self
+    }
+
+    /// What the given exit code of the command means, if that is known.
+    pub(crate) fn exit_reason(&self, code: i32) -> Option<&'a str> {
+        self.exit_reasons
+            .iter()
+            .find(|(c, _, _)| *c == code)
+            .map(|(_, reason, _)| *reason)
+    }
+
+    /// Run a command, with its arguments separated by whitespace, before every process of the
+    /// command is spawned, e.g. to remove a stale socket file. The process is only spawned once
+    /// the hook exited, whether it succeeded or not. The reaper carries on meanwhile, and kills
+    /// a hook which runs for longer than its [timeout].
+    ///
+    /// [timeout]: #method.pre_start_timeout
+    pub fn pre_start(mut self, cmd: &'a str) -> Self {
+        self.pre_start = Some(hook(cmd));
+        self
+    }
+
+    /// Kill the [pre-start hook] if it runs for longer than the given time, after which the
+    /// process is spawned anyway. Defaults to 90 seconds.
+    ///
+    /// [pre-start hook]: #method.pre_start
+    pub fn pre_start_timeout(mut self, timeout: Duration) -> Self {
+        self.pre_start_timeout = timeout;
+        self
+    }
+
+    /// Run a command, with its arguments separated by whitespace, whenever a process of the
+    /// command exits successfully on its own. The hook is not restarted, and the service does
+    /// not wait for it.
+    pub fn on_success_run(mut self, cmd: &'a str) -> Self {
+        self.on_success = Some(hook(cmd));
+        self
+    }
+
+    /// Run a command, with its arguments separated by whitespace, whenever a process of the
+    /// command fails, e.g. to send an alert when a service flaps. Stopping the service doesn't
+    /// count as a failure. The hook is not restarted, and the service does not wait for it.
+    pub fn on_failure_run(mut self, cmd: &'a str) -> Self {
+        self.on_failure = Some(hook(cmd));
+        self
+    }
+
+    /// Set what happens once the command failed for good, i.e. it is not restarted anymore after
+    /// a failure, because it used up its spawn limit or its restart policy gave up on it.
+    pub fn on_permanent_failure(mut self, action: FailureAction<'a>) -> Self {
+        self.failure_action = action;
+        self
+    }
+
+    /// Treat an exit with one of the given codes as a successful exit, for daemons which exit
+    /// with e.g. code 1 as part of their normal operation. The children of the process are
+    /// left alone, and the command is only restarted if `restart_on_success` is set.
+    pub fn success_exit_status(mut self, codes: &[i32]) -> Self {
+        self.success_codes.extend_from_slice(codes);
+        self
+    }
+
+    /// Treat being killed by one of the given signals as a successful exit, e.g. SIGTERM for
+    /// daemons which are expected to be stopped that way.
+    pub fn success_exit_signal(mut self, signals: &[Signal]) -> Self {
+        self.success_signals.extend_from_slice(signals);
+        self
+    }
+
+    /// Classify how a process of the command exited, taking the exits which are considered
+    /// successful, and the start grace period, into account.
+    pub(crate) fn classify(&self, event: Event) -> Event {
+        let event = match event {
+            Event::ExitCode(code) if self.success_codes.contains(&code) => Event::ExitSuccess,
+            Event::ExitSignal(signal) if self.success_signals.contains(&signal) => {
+                Event::ExitSuccess
+            }
+            event => event,
+        };
+        // the process is reaped right away, so it ran for as long as since its spawn
+        let early = self.start_grace.is_some_and(|grace| {
+            self.spawn_times
+                .back()
+                .is_some_and(|spawned| spawned.elapsed() < grace)
+        });
+        match event {
+            Event::ExitCode(code) if early => Event::StartFailed {
+                code: Some(code),
+                signal: None,
+            },
+            Event::ExitSignal(signal) if early => Event::StartFailed {
+                code: None,
+                signal: Some(signal),
+            },
+            event => event,
+        }
+    }
+
+    /// Treat a process which exits with an error or is killed within the given time after it
+    /// was spawned as having failed to start, e.g. a misconfigured daemon which exits right
+    /// away. Its restarts are then delayed, starting at a second and doubling with every start
+    /// failure in a row up to a minute, so it can't burn through its spawn limit, and start
+    /// failures are counted separately in the status of its service.
+    pub fn start_grace(mut self, grace: Duration) -> Self {
+        self.start_grace = Some(grace);
+        self
+    }
+
+    /// Set what happens to the children a process of the command leaves behind. Restarted
+    /// forking daemons which pick up the workers of their previous process should use
+    /// `OrphanPolicy::Adopt`.
+    pub fn orphan_policy(mut self, policy: OrphanPolicy) -> Self {
+        self.orphan_policy = policy;
+        self
+    }
+
+    /// Set which processes of the service are signaled when it is stopped, and terminated once
+    /// its process exited. Without a kill mode, stopping signals the process group, and
+    /// everything the process left behind is terminated.
+    pub fn kill_mode(mut self, mode: KillMode) -> Self {
+        self.kill_mode = Some(mode);
+        self
+    }
+
+    pub(crate) fn kill_handling(&self) -> Option<KillMode> {
+        self.kill_mode
+    }
+
+    pub(crate) fn failure_action(&self) -> FailureAction<'a> {
+        self.failure_action
+    }
+
+    pub(crate) fn orphan_handling(&self) -> OrphanPolicy {
+        self.orphan_policy
+    }
+
+    pub(crate) fn restarts_on_success(&self) -> bool {
+        self.restart_policy.is_none() && self.restart_flags.on_success
+    }
+
+    /// Decide whether the command is restarted with a policy of its own, instead of the
+    /// `restart_on_*` flags.
+    pub fn restart_policy(mut self, policy: impl RestartPolicy + 'a) -> Self {
+        self.restart_policy = Some(Box::new(policy));
+        self
+    }
+
+    pub fn spawn_limit(mut self, limit: usize) -> Self {
+        self.spawn_limit = Some(limit);
+        self
+    }
+
+    pub(crate) fn has_spawn_limit(&self) -> bool {
+        self.spawn_limit.is_some()
+    }
+
+    /// Check if restarts are spread out over time, rather than done back to back.
+    pub(crate) fn is_paced(&self) -> bool {
+        self.spawn_limit_window.is_some() || self.restart_policy.is_some()
+    }
+
+    /// Allow the command to be spawned at most count times within a rolling window. If the
+    /// limit is hit, the next spawn is delayed until the window allows it again.
+    pub fn spawn_limit_window(mut self, count: usize, window: Duration) -> Self {
+        self.spawn_limit_window = Some((count, window));
+        self
+    }
+
+    /// Forget about previous spawns once a process of the command has been running for the
+    /// given duration, so a service which has been stable for a while gets its full spawn
+    /// budget back.
+    pub fn reset_spawns_after(mut self, stable: Duration) -> Self {
+        self.reset_spawns_after = Some(stable);
+        self
+    }
+
+    /// Give up on the command once its processes failed the given amount of times in a row,
+    /// each within the given time after it was spawned, instead of restarting it again. The
+    /// service is then failed until it is started again.
+    pub fn crash_loop_limit(mut self, failures: usize, within: Duration) -> Self {
+        self.crash_loop_limit = Some((failures, within));
+        self
+    }
+
+    /// Set the security context the command is executed with. The label is only applied if the
+    /// targeted LSM is active on the system.
+    pub fn security_label(mut self, label: SecurityLabel<'a>) -> Self {
+        self.security_label = Some(label);
+        self
+    }
+
+    /// Give every spawned process its own, new session keyring instead of sharing the one of
+    /// init.
+    pub fn session_keyring(mut self, new_keyring: bool) -> Self {
+        self.session_keyring = new_keyring;
+        self
+    }
+
+    /// Set the audit login uid of the spawned process, so audit records are attributed to
+    /// this user.
+    pub fn login_uid(mut self, uid: u32) -> Self {
+        self.login_uid = Some(uid);
+        self
+    }
+
+    /// Limit the number of open file descriptors of the process. Processes started by init
+    /// otherwise get the kernel default, which is often too low for e.g. databases.
+    pub fn limit_nofile(self, soft: u64, hard: u64) -> Self {
+        self.rlimit(libc::RLIMIT_NOFILE as _, soft, hard)
+    }
+
+    /// Limit the size of core dumps of the process, `u64::MAX` allows any size.
+    pub fn limit_core(self, soft: u64, hard: u64) -> Self {
+        self.rlimit(libc::RLIMIT_CORE as _, soft, hard)
+    }
+
+    /// Limit the number of processes the user of the process can have.
+    pub fn limit_nproc(self, soft: u64, hard: u64) -> Self {
+        self.rlimit(libc::RLIMIT_NPROC as _, soft, hard)
+    }
+
+    fn rlimit(mut self, resource: libc::c_int, soft: u64, hard: u64) -> Self {
+        let limit = libc::rlimit {
+            rlim_cur: soft as _,
+            rlim_max: hard as _,
+        };
+        self.rlimits.retain(|(r, _)| *r != resource);
+        self.rlimits.push((resource, limit));
+        self
+    }
+
+    /// Set the nice value of the process, from -20 for the highest priority to 19 for the lowest.
+    pub fn nice(mut self, nice: i32) -> Self {
+        self.nice = Some(nice);
+        self
+    }
+
+    /// Adjust the likelihood of the process being killed when the system runs out of memory,
+    /// from -1000, which exempts it, to 1000, which makes it the first victim.
+    pub fn oom_score_adj(mut self, adj: i32) -> Self {
+        self.oom_score_adj = Some(adj);
+        self
+    }
+
+    /// Limit the memory all processes of the service use together to the given amount of
+    /// bytes, through the `memory.max` control of its cgroup. The kernel reclaims memory from
+    /// the service, and kills a process of it once it can't. Only enforced if the reaper uses
+    /// [cgroups].
+    ///
+    /// [cgroups]: ../struct.Reaper.html#method.cgroups
+    pub fn memory_max(mut self, bytes: u64) -> Self {
+        self.cgroup_limits.memory_max = Some(bytes);
+        self
+    }
+
+    /// Limit the CPU time all processes of the service use together to the given percentage of
+    /// a single CPU, e.g. 200 for two CPUs, through the `cpu.max` control of its cgroup. The
+    /// service is throttled once it used up its quota of a period. Only enforced if the reaper
+    /// uses [cgroups].
+    ///
+    /// [cgroups]: ../struct.Reaper.html#method.cgroups
+    pub fn cpu_quota(mut self, percent: u32) -> Self {
+        self.cgroup_limits.cpu_quota = Some(percent);
+        self
+    }
+
+    /// Limit the amount of processes and threads of the service to the given number, through
+    /// the `pids.max` control of its cgroup, so a fork bomb can't take the system down. Only
+    /// enforced if the reaper uses [cgroups].
+    ///
+    /// [cgroups]: ../struct.Reaper.html#method.cgroups
+    pub fn tasks_max(mut self, tasks: u64) -> Self {
+        self.cgroup_limits.tasks_max = Some(tasks);
+        self
+    }
+
+    pub(crate) fn cgroup_limits(&self) -> &CgroupLimits {
+        &self.cgroup_limits
+    }
+
+    /// Set the io scheduling class and priority of the process.
+    pub fn io_priority(mut self, priority: IoPriority) -> Self {
+        self.io_priority = Some(priority);
+        self
+    }
+
+    /// Only run the process, and its threads and children, on the given CPUs, numbered from 0,
+    /// e.g. to pin a control loop to a core of its own, or to keep housekeeping off it.
+    pub fn cpu_affinity(mut self, cpus: &[usize]) -> Self {
+        self.cpu_affinity = cpus.to_vec();
+        self
+    }
+
+    /// Set the scheduling policy of the process, which its threads and children inherit.
+    pub fn sched_policy(mut self, policy: SchedPolicy) -> Self {
+        self.sched_policy = Some(policy);
+        self
+    }
+
+    /// Run the command with the given user id.
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Run the command with the given group id.
+    pub fn gid(mut self, gid: u32) -> Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Keep only the given capabilities, e.g. `NetBindService` for a web server. They are kept
+    /// when running as another user than root, as ambient capabilities, so the service needs
+    /// no setuid binary or file capabilities. The others are dropped for good: neither the
+    /// process nor anything it executes can gain them.
+    pub fn capabilities(mut self, caps: &[Capability]) -> Self {
+        self.capabilities = Some(capabilities::mask(caps));
+        self
+    }
+
+    /// Drop all capabilities for good, so not even a process running as root, nor anything it
+    /// executes, can gain them.
+    pub fn drop_all_capabilities(mut self) -> Self {
+        self.capabilities = Some(0);
+        self
+    }
+
+    /// Keep the process, and everything it executes, from gaining privileges, e.g. through
+    /// setuid binaries such as `su`, or file capabilities.
+    pub fn no_new_privs(mut self, no_new_privs: bool) -> Self {
+        self.no_new_privs = no_new_privs;
+        self
+    }
+
+    /// Restrict the system calls the process, and everything it executes, can make. A denied
+    /// call fails with `EPERM`. This implies [`no_new_privs`], as the kernel requires it.
+    ///
+    /// [`no_new_privs`]: struct.PersistentCommand.html#method.no_new_privs
+    pub fn syscall_filter(mut self, filter: SyscallFilter) -> Self {
+        self.syscall_filter = Some(filter);
+        self
+    }
+
+    /// Run the command as the named user. The user is looked up every time the command is
+    /// spawned, and also determines the group, supplementary groups, and the `HOME`, `USER`,
+    /// `LOGNAME` and `SHELL` environment variables, unless overridden by [`uid`] or [`gid`].
+    ///
+    /// [`uid`]: struct.PersistentCommand.html#method.uid
+    /// [`gid`]: struct.PersistentCommand.html#method.gid
+    pub fn user(mut self, name: &'a str) -> Self {
+        self.user = Some(name);
+        self
+    }
+
+    /// Set an environment variable for the command.
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.update_env(&[(key.to_string(), Some(value.to_string()))]);
         self
     }
 
-    pub fn restart_on_signal(mut self, restart: bool) -> Self {
-        self.restart_on_signal = restart;
-        self
+    /// Write the environment variables of the command to the file at the given path, as
+    /// `KEY=VALUE` lines, whenever they are changed through the control socket. A service which
+    /// reads the file when it gets its [`reload_signal`] can so pick up changes without a restart.
+    ///
+    /// [`reload_signal`]: struct.PersistentCommand.html#method.reload_signal
+    pub fn env_file(mut self, path: &'a str) -> Self {
+        self.env_file = Some(path);
+        self
+    }
+
+    /// Read environment variables for the command from the file at the given path, such as
+    /// `/etc/default/sshd`, with `KEY=VALUE` lines. The file is read every time the command is
+    /// spawned, so a respawn picks up changes, and spawning fails if it can't be read. Variables
+    /// set with [`env`] take precedence, as do those of files which were added later.
+    ///
+    /// Unlike the [`env_file`], the file is never written to.
+    ///
+    /// [`env`]: struct.PersistentCommand.html#method.env
+    /// [`env_file`]: struct.PersistentCommand.html#method.env_file
+    pub fn environment_file(mut self, path: &'a str) -> Self {
+        self.environment_files.push((path, true));
+        self
+    }
+
+    /// Read environment variables from a file, like [`environment_file`], but spawn the command
+    /// without them if the file does not exist.
+    ///
+    /// [`environment_file`]: struct.PersistentCommand.html#method.environment_file
+    pub fn optional_environment_file(mut self, path: &'a str) -> Self {
+        self.environment_files.push((path, false));
+        self
+    }
+
+    /// Set, or with a value of `None` remove, environment variables of the command.
+    pub(crate) fn update_env(&mut self, changes: &[(String, Option<String>)]) {
+        for (key, value) in changes {
+            self.env.retain(|(k, _)| k != key);
+            if let Some(value) = value {
+                self.env.push((key.clone(), value.clone()));
+            }
+        }
+    }
+
+    /// Write the environment variables to the env file, if there is one. Returns whether the
+    /// file was written.
+    pub(crate) fn write_env_file(&self) -> io::Result<bool> {
+        let path = match self.env_file {
+            Some(path) => Path::new(path),
+            None => return Ok(false),
+        };
+        let content: String = self
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}\n", key, value))
+            .collect();
+        // replace the file at once, so the service never reads it half written
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(true)
+    }
+
+    /// Run the command in the given directory, instead of in `/`. With [`chroot`], the directory
+    /// is relative to the new root.
+    ///
+    /// [`chroot`]: struct.PersistentCommand.html#method.chroot
+    pub fn current_dir(mut self, dir: &'a str) -> Self {
+        self.current_dir = Some(dir);
+        self
+    }
+
+    /// Create the directory with the given mode before the command is spawned, owned by the
+    /// user and group the command runs as, e.g. `/run/sshd`, without which sshd refuses to
+    /// start. The mode and ownership of an existing directory are adjusted.
+    pub fn directory(mut self, path: &'a str, mode: u32) -> Self {
+        self.directories.push((path, mode));
+        self
+    }
+
+    /// Set the file mode creation mask of the process, instead of inheriting the one of init.
+    pub fn umask(mut self, mask: u32) -> Self {
+        self.umask = Some(mask);
+        self
+    }
+
+    pub(crate) fn root(&self) -> Option<&'a str> {
+        self.chroot
+    }
+
+    /// Change the root directory of the process to the given path before executing the command,
+    /// so the path of the command is relative to the new root as well.
+    pub fn chroot(mut self, root: &'a str) -> Self {
+        self.chroot = Some(root);
+        self
+    }
+
+    /// Give the process a private /dev, which only contains basic pseudo devices such as
+    /// /dev/null and /dev/urandom, and the devices allowed with [`allow_device`]. The /dev of the
+    /// [`chroot`] is used if there is one.
+    ///
+    /// [`allow_device`]: struct.PersistentCommand.html#method.allow_device
+    /// [`chroot`]: struct.PersistentCommand.html#method.chroot
+    pub fn private_devices(mut self, private: bool) -> Self {
+        self.private_devices = private;
+        self
+    }
+
+    /// Allow the device at the given path, e.g. `/dev/net/tun`, in the private /dev of the
+    /// process. This implies [`private_devices`]. Devices which don't exist when the command is
+    /// spawned are left out.
+    ///
+    /// [`private_devices`]: struct.PersistentCommand.html#method.private_devices
+    pub fn allow_device(mut self, path: &'a str) -> Self {
+        self.private_devices = true;
+        self.allowed_devices.push(path);
+        self
+    }
+
+    /// Run the process in new instances of the given namespaces, e.g. with a network of its own,
+    /// or as PID 1 of its own PID namespace, with a private /proc. For a PID namespace, the
+    /// process the reaper supervises stays outside of it, forwarding signals to the service.
+    pub fn unshare(mut self, namespaces: &[Namespace]) -> Self {
+        self.namespaces = namespaces.to_vec();
+        self
+    }
+
+    /// Run the process in an existing network namespace, by the name it was created with by
+    /// `ip netns add`, e.g. by a one-shot command, or by a path with a slash in it. This puts
+    /// a service behind e.g. the VPN client which set up the namespace. Spawning fails if the
+    /// namespace does not exist.
+    pub fn netns(mut self, netns: &'a str) -> Self {
+        self.netns = Some(netns);
+        self
+    }
+
+    pub(crate) fn joined_netns(&self) -> Option<&'a str> {
+        self.netns
+    }
+
+    pub(crate) fn unshares(&self, namespace: Namespace) -> bool {
+        self.namespaces.contains(&namespace)
+    }
+
+    /// Read the standard input of the command from somewhere else than the reaper does, e.g.
+    /// from /dev/null for a daemon which behaves differently when its input is the console. This
+    /// takes precedence over the terminal set with [`tty`] for the input.
+    ///
+    /// [`tty`]: #method.tty
+    pub fn stdin(mut self, input: Input<'a>) -> Self {
+        self.stdin = Some(input);
+        self
+    }
+
+    /// Redirect the standard output of the command to the file at the given path. The file is
+    /// created if needed, and appended to. It is reopened every time the command is spawned.
+    pub fn stdout_path(mut self, path: &'a str) -> Self {
+        self.stdout_path = Some(path);
+        self
+    }
+
+    /// Redirect the standard error of the command to the file at the given path. The file is
+    /// created if needed, and appended to. It is reopened every time the command is spawned.
+    pub fn stderr_path(mut self, path: &'a str) -> Self {
+        self.stderr_path = Some(path);
+        self
+    }
+
+    /// The files the standard output and error of the command are redirected to.
+    pub(crate) fn output_paths(&self) -> impl Iterator<Item = &'a str> {
+        self.stdout_path.into_iter().chain(self.stderr_path)
+    }
+
+    /// Open a PAM session for the given PAM service around every spawned process, as is done
+    /// for user logins. This requires the command to run as a named [`user`]. The session is
+    /// closed once the process has exited.
+    ///
+    /// [`user`]: struct.PersistentCommand.html#method.user
+    #[cfg(feature = "pam")]
+    pub fn pam_session(mut self, service: &'a str) -> Self {
+        self.pam_service = Some(service);
+        self
+    }
+
+    /// Run the command on the terminal at the given path. The terminal is used for stdin,
+    /// stdout and stderr, and becomes the controlling terminal of a new session for the command.
+    pub fn tty(mut self, path: &'a str) -> Self {
+        self.tty = Some(path);
+        self
+    }
+
+    /// Run the command on a pseudo-terminal of its own, for commands which buffer their output
+    /// or refuse to run unless they are on a terminal. The terminal is used for stdin, stdout and
+    /// stderr, and becomes the controlling terminal of a new session for the command, with
+    /// `TERM` set to `dumb`. What the command writes to it is captured like the output of any
+    /// other command, so this requires the output to be captured, see
+    /// [`Reaper::multiplex_output`]. A terminal set with [`tty`] takes precedence, and the
+    /// command is not run on a pseudo-terminal if its output is piped, see [`pipe_to`]. Input
+    /// set with [`stdin`] replaces the terminal as stdin, except for another terminal, which is
+    /// ignored.
+    ///
+    /// [`Reaper::multiplex_output`]: ../struct.Reaper.html#method.multiplex_output
+    /// [`tty`]: #method.tty
+    /// [`pipe_to`]: #method.pipe_to
+    /// [`stdin`]: #method.stdin
+    pub fn pty(mut self, pty: bool) -> Self {
+        self.pty = pty;
+        self
+    }
+
+    /// Only start the command once Enter is pressed on its terminal, see [`tty`], like the
+    /// `askfirst` entries of busybox. This is asked again whenever it is respawned, so a shell on
+    /// a console nobody looks at doesn't keep running.
+    ///
+    /// [`tty`]: #method.tty
+    pub fn ask_first(mut self, ask: bool) -> Self {
+        self.ask_first = ask;
+        self
+    }
+
+    /// Pipe the output of the command into another command, with its arguments separated by
+    /// whitespace, like `/usr/bin/logger -t daemon`. Calling this again extends the pipeline.
+    /// The pipeline is supervised as a whole: if any of its later stages exits, the service is
+    /// restarted, and they are stopped once the command exits. The command itself is the
+    /// process of the service, e.g. to notify readiness. The later stages run as the same user,
+    /// with the same environment and working directory, and the last one writes where the
+    /// command would.
+    pub fn pipe_to(mut self, cmd: &'a str) -> Self {
+        self.pipe_to.push(cmd);
+        self
+    }
+
+    /// Ask for a password with the given prompt before the command is first spawned, on the
+    /// password console of the [`Reaper`] or through its control socket. The command is not
+    /// spawned until the password is given, after which it is written to the standard input of
+    /// every spawned process, followed by a newline.
+    ///
+    /// [`Reaper`]: struct.Reaper.html
+    pub fn ask_password(mut self, prompt: &'a str) -> Self {
+        self.password_prompt = Some(prompt);
+        self
+    }
+
+    /// Leave the process running when the reaper exits, instead of killing it. By default the
+    /// process is stopped when the reaper shuts down, and killed by the kernel if the thread
+    /// running the reaper exits in any other way.
+    pub fn leave_running(mut self, leave: bool) -> Self {
+        self.leave_running = leave;
+        self
+    }
+
+    pub(crate) fn leaves_running(&self) -> bool {
+        self.leave_running
+    }
+
+    /// Mark the service as a supervisor of its own, like s6 or a user session manager, which
+    /// owns all of its descendants. When another process exits, the descendants of the service
+    /// are never taken for the orphans of that process, and when one of its descendants exits,
+    /// what it leaves behind is left to the service. A supervisor which exits successfully is
+    /// not assumed to have forked a daemon either. Its descendants are still terminated when
+    /// the service itself fails, as its orphan policy says.
+    ///
+    /// The descendants are found in the lineage of processes, which is exact when it is
+    /// followed through process events, and read from /proc every tick otherwise.
+    pub fn supervisor(mut self, supervisor: bool) -> Self {
+        self.supervisor = supervisor;
+        self
+    }
+
+    pub(crate) fn is_supervisor(&self) -> bool {
+        self.supervisor
+    }
+
+    /// Make the service the primary one, which the signals sent to the reaper are forwarded to
+    /// if they mean nothing to the reaper itself: SIGWINCH, SIGHUP unless the reaper reloads
+    /// its configuration on it, and the [extra signals] it traps. The signal reaches the
+    /// process of the service the way stopping it would, along with its process group unless
+    /// its kill mode says otherwise. SIGTERM, SIGUSR1 and SIGUSR2 still shut the reaper down,
+    /// like `docker stop` expects. Only one service should be the primary one.
+    ///
+    /// [extra signals]: ../struct.ReaperBuilder.html#method.extra_signals
+    pub fn primary(mut self, primary: bool) -> Self {
+        self.primary = primary;
+        self
+    }
+
+    pub(crate) fn is_primary(&self) -> bool {
+        self.primary
+    }
+
+    /// Find the process of a daemon which forks, after which the process the reaper started
+    /// exits successfully, in the given pid file. Without a pid file, the first child left
+    /// behind by the exited process is assumed to be the daemon, unless the command
+    /// [does not daemonize].
+    ///
+    /// [does not daemonize]: #method.daemonizes
+    pub fn pid_file(mut self, path: &'a str) -> Self {
+        self.pid_file = Some(path);
+        self
+    }
+
+    pub(crate) fn pid_file_path(&self) -> Option<&'a str> {
+        self.pid_file
+    }
+
+    /// Tell whether the command forks a daemon and exits, instead of leaving the reaper to
+    /// guess. A daemon is tracked as the first child left behind once the command exited
+    /// successfully, or through its [pid file]. A command which does not daemonize has the
+    /// processes it leaves behind when exiting successfully taken for leaked: they are
+    /// reported and terminated like orphans, unless they are adopted. Without this, the first
+    /// child left behind is assumed to be a daemon.
+    ///
+    /// [pid file]: #method.pid_file
+    pub fn daemonizes(mut self, daemonizes: bool) -> Self {
+        self.daemonizes = Some(daemonizes);
+        self
+    }
+
+    /// Whether the command daemonizes, if that was told.
+    pub(crate) fn daemonizing(&self) -> Option<bool> {
+        self.daemonizes
+    }
+
+    /// Pass the command a socket in `NOTIFY_SOCKET` through which it notifies when it finished
+    /// starting up, like `sd_notify(3)`. Until it sends `READY=1`, services which depend on it
+    /// are not started.
+    pub fn notify(mut self, notify: bool) -> Self {
+        self.notify = notify;
+        self
+    }
+
+    pub(crate) fn notifies(&self) -> bool {
+        self.notify
+    }
+
+    /// Kill the process of the command if it doesn't send `WATCHDOG=1` to its notification
+    /// socket within the given interval, since it was spawned or last did so, as it is then
+    /// considered hung. The process is passed the interval in `WATCHDOG_USEC`. Whether it is
+    /// restarted afterwards is up to the restart policy, as for any process killed by a signal.
+    pub fn watchdog(mut self, interval: Duration) -> Self {
+        self.watchdog = Some(interval);
+        self
+    }
+
+    pub(crate) fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog
+    }
+
+    /// Probe the service every interval, and restart it once the given amount of consecutive
+    /// probes failed, as it then likely wedged without exiting. The first probe is done an
+    /// interval after the process was spawned, and a probe which takes longer than an interval
+    /// fails.
+    pub fn health_check(mut self, probe: HealthProbe, interval: Duration, retries: u32) -> Self {
+        self.health_check = Some(HealthCheck {
+            probe,
+            interval,
+            retries,
+        });
+        self
+    }
+
+    /// Check the health of the service by running a command, with its arguments separated by
+    /// whitespace, which must exit with code 0. See [`health_check`].
+    ///
+    /// [`health_check`]: #method.health_check
+    pub fn health_check_cmd(self, cmd: &str, interval: Duration, retries: u32) -> Self {
+        self.health_check(HealthProbe::Command(cmd.to_string()), interval, retries)
+    }
+
+    /// Check the health of the service by connecting to an address, e.g. `127.0.0.1:22`, over
+    /// TCP. See [`health_check`].
+    ///
+    /// [`health_check`]: #method.health_check
+    pub fn health_check_tcp(self, addr: &str, interval: Duration, retries: u32) -> Self {
+        self.health_check(HealthProbe::Tcp(addr.to_string()), interval, retries)
+    }
+
+    /// Check the health of the service by getting an `http://` URL, which must be answered with
+    /// a 2xx or 3xx status. See [`health_check`].
+    ///
+    /// [`health_check`]: #method.health_check
+    pub fn health_check_http(self, url: &str, interval: Duration, retries: u32) -> Self {
+        self.health_check(HealthProbe::Http(url.to_string()), interval, retries)
+    }
+
+    pub(crate) fn health(&self) -> Option<&HealthCheck> {
+        self.health_check.as_ref()
+    }
+
+    /// Probe the service every interval while it starts, until a probe passes, after which the
+    /// [`health_check`], if any, takes over. The service is restarted once the given amount of
+    /// consecutive probes failed, so it gets about interval times retries to start. This lets a
+    /// service which is slow to start have a strict health check, which would otherwise kill it
+    /// before it is up. As with the health check, a probe which takes longer than an interval
+    /// fails.
+    ///
+    /// [`health_check`]: #method.health_check
+    pub fn startup_check(mut self, probe: HealthProbe, interval: Duration, retries: u32) -> Self {
+        self.startup_check = Some(HealthCheck {
+            probe,
+            interval,
+            retries,
+        });
+        self
+    }
+
+    pub(crate) fn startup(&self) -> Option<&HealthCheck> {
+        self.startup_check.as_ref()
+    }
+
+    /// Check if the command needs a notification socket, to notify readiness or to ping its
+    /// watchdog.
+    pub(crate) fn needs_notify_socket(&self) -> bool {
+        self.notify || self.watchdog.is_some()
+    }
+
+    /// Set the path of the notification socket which is passed to spawned processes.
+    pub(crate) fn set_notify_socket(&mut self, path: &Path) {
+        self.notify_socket = Some(path.to_string_lossy().into_owned());
+    }
+
+    /// The prompt to ask for a password with, if the command still needs one.
+    pub(crate) fn password_prompt(&self) -> Option<&'a str> {
+        self.password_prompt.filter(|_| self.password.is_none())
+    }
+
+    /// Set the password which is passed to spawned processes.
+    pub(crate) fn set_password(&mut self, password: Vec<u8>) {
+        self.password = Some(password);
+    }
+
+    /// The executable of the command.
+    pub(crate) fn program(&self) -> &'a str {
+        self.cmd
+    }
+
+    /// The path of the executable of the command as seen by init, within the root of the command
+    /// if it is chrooted. Programs without an absolute path are looked up at spawn, so they have
+    /// none.
+    pub(crate) fn executable(&self) -> Option<PathBuf> {
+        if !self.cmd.starts_with('/') {
+            return None;
+        }
+        Some(match self.chroot {
+            Some(root) => Path::new(root).join(&self.cmd[1..]),
+            None => PathBuf::from(self.cmd),
+        })
+    }
+
+    /// The name of the service provided by the command.
+    pub fn service_name(&self) -> &str {
+        if let Some(name) = self.name {
+            return name;
+        }
+        if let Some(name) = &self.derived_name {
+            return name;
+        }
+        if self.login_shell {
+            return "autologin";
+        }
+        if self.shell {
+            return basename(split_command(self.args).0);
+        }
+        basename(self.cmd)
+    }
+
+    /// The arguments the executable is run with.
+    fn arguments(&self) -> Vec<&'a str> {
+        if self.shell {
+            vec!["-c", self.args]
+        } else {
+            self.args.split_whitespace().collect()
+        }
+    }
+
+    /// Check if a process with the given arguments, starting with its program, runs the
+    /// command. The executable is compared by its name, as it might have been started through
+    /// another path.
+    pub(crate) fn runs_in(&self, cmdline: &[String]) -> bool {
+        match cmdline.split_first() {
+            Some((program, args)) => {
+                basename(program) == basename(self.cmd)
+                    && args.iter().map(String::as_str).eq(self.arguments())
+            }
+            None => false,
+        }
+    }
+
+    /// The hook to run now a process of the command exited as described by event, if any.
+    pub(crate) fn exit_hook(&self, event: Event) -> Option<&OneShotCommand<'a>> {
+        match event {
+            Event::ExitSuccess => self.on_success.as_ref(),
+            Event::ExitCode(_) | Event::ExitSignal(_) | Event::StartFailed { .. } => {
+                self.on_failure.as_ref()
+            }
+        }
+    }
+
+    /// Start the pre-start hook, if any, for the next spawn, which was [admitted] already. The
+    /// reaper reaps the hook, and spawns the command once it exited, see [`finish_pre_start`].
+    /// A hook which fails to start is reported, and the command is spawned right away.
+    ///
+    /// [admitted]: #method.admit
+    /// [`finish_pre_start`]: #method.finish_pre_start
+    pub(crate) fn start_pre_start(&mut self) -> Option<Child> {
+        let hook = self.pre_start.as_ref().filter(|_| !self.pre_started)?;
+        debug!(
+            "Running pre-start hook of {}: {}",
+            self.service_name(),
+            hook
+        );
+        // it is killed along with the reaper, which would never spawn the command
+        match hook.spawn(false, Signal::SIGKILL) {
+            Ok(child) => {
+                self.pre_started = true;
+                Some(child)
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to run pre-start hook of {} ({}), starting it anyway",
+                    self.service_name(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// How long the pre-start hook may run.
+    pub(crate) fn pre_start_limit(&self) -> Duration {
+        self.pre_start_timeout
+    }
+
+    /// Forget that the pre-start hook ran, as the spawn it ran for was cancelled.
+    pub(crate) fn cancel_pre_start(&mut self) {
+        self.pre_started = false;
+    }
+
+    /// Report how the pre-start hook went. A failed hook does not keep the service from
+    /// starting.
+    pub(crate) fn finish_pre_start(&self, status: Option<i32>, signal: Option<Signal>) {
+        match (status, signal) {
+            (Some(0), _) => (),
+            (Some(code), _) => warn!(
+                "Pre-start hook of {} exited with code {}, starting it anyway",
+                self.service_name(),
+                code
+            ),
+            (_, signal) => warn!(
+                "Pre-start hook of {} exited with signal {:?}, starting it anyway",
+                self.service_name(),
+                signal
+            ),
+        }
+    }
+
+    /// Forget about previous spawns, e.g. when the command is started explicitly.
+    pub(crate) fn reset_spawns(&mut self) {
+        self.spawns = 0;
+        self.spawn_times.clear();
+        self.exit_signals.clear();
+        self.fast_failures = 0;
+        self.start_failures = 0;
     }
 
-    pub fn spawn_limit(mut self, limit: usize) -> Self {
-        self.spawn_limit = Some(limit);
-        self
+    /// Take over what is tracked about the processes of another definition of the command, which
+    /// it replaces, e.g. after the configuration was reloaded: its spawn history, password and
+    /// notification socket.
+    pub(crate) fn carry_over(&mut self, old: PersistentCommand<'a>) {
+        self.spawns = old.spawns;
+        self.spawn_times = old.spawn_times;
+        self.exit_signals = old.exit_signals;
+        self.fast_failures = old.fast_failures;
+        self.start_failures = old.start_failures;
+        self.password = old.password;
+        self.notify_socket = old.notify_socket;
+        #[cfg(feature = "pam")]
+        {
+            self.pam_session = old.pam_session;
+        }
     }
 
-    pub(crate) fn spawn(
+    /// Decide whether a new process is spawned for the command now, after the given exit of the
+    /// previous one, if any, as its restart policy and spawn limits say, and count the spawn if
+    /// it is. A spawn which was admitted before its pre-start hook ran is let through.
+    pub(crate) fn admit(
         &mut self,
         previous_exit_reason: Option<Event>,
-    ) -> Result<u32, PersistentCommandError> {
-        debug!("Creating command from persistent command");
+    ) -> Result<(), PersistentCommandError> {
+        if self.pre_started {
+            return Ok(());
+        }
+
+        // the session of the previous process, if any, ends now that it exited
+        #[cfg(feature = "pam")]
+        {
+            self.pam_session = None;
+        }
 
         // In case there is an exit from a previous process, check if we need to respawn
         if let Some(reason) = previous_exit_reason {
             match reason {
-                Event::ExitSuccess if !self.restart_on_success => {
-                    debug!("Not respawning successful command");
-                    return Err(PersistentCommandError::MustNotRespawn(reason));
+                Event::ExitSignal(signal)
+                | Event::StartFailed {
+                    signal: Some(signal),
+                    ..
+                } => *self.exit_signals.entry(signal).or_insert(0) += 1,
+                _ => (),
+            }
+            self.start_failures = match reason {
+                Event::StartFailed { .. } => self.start_failures + 1,
+                _ => 0,
+            };
+            if let Some((_, within)) = self.crash_loop_limit {
+                // the process is reaped right away, so it ran for as long as since its spawn
+                let fast = reason != Event::ExitSuccess
+                    && self
+                        .spawn_times
+                        .back()
+                        .is_some_and(|spawned| spawned.elapsed() < within);
+                self.fast_failures = if fast { self.fast_failures + 1 } else { 0 };
+            }
+            let history = SpawnHistory::new(self.spawns, &self.spawn_times, &self.exit_signals);
+            let mapped = match reason {
+                Event::ExitCode(code) => self
+                    .exit_reasons
+                    .iter()
+                    .find(|(c, _, _)| *c == code)
+                    .and_then(|(_, _, action)| *action),
+                _ => None,
+            };
+            let action = match (mapped, &mut self.restart_policy) {
+                (Some(action), _) => action,
+                (None, Some(policy)) => policy.decide(reason, &history),
+                (None, None) => self.restart_flags.decide(reason, &history),
+            };
+            match action {
+                RestartAction::Restart(_)
+                    if self
+                        .crash_loop_limit
+                        .is_some_and(|(failures, _)| self.fast_failures >= failures) =>
+                {
+                    debug!(
+                        "Command failed {} times in a row right after spawning, giving up",
+                        self.fast_failures
+                    );
+                    return Err(PersistentCommandError::CrashLooping(self.fast_failures));
                 }
-                Event::ExitCode if !self.restart_on_error => {
-                    debug!("Not respawning errored command");
-                    return Err(PersistentCommandError::MustNotRespawn(reason));
+                RestartAction::Restart(delay) if self.start_failures > 0 => {
+                    let backoff = START_FAILURE_BACKOFF
+                        .checked_mul(1 << (self.start_failures - 1).min(16))
+                        .map_or(MAX_START_FAILURE_BACKOFF, |backoff| {
+                            backoff.min(MAX_START_FAILURE_BACKOFF)
+                        });
+                    let delay = delay.max(backoff);
+                    debug!("Command failed to start, respawning it in {:?}", delay);
+                    return Err(PersistentCommandError::RestartDelayed(delay));
                 }
-                Event::ExitSignal if !self.restart_on_signal => {
-                    debug!("Not respawning signaled command");
+                RestartAction::Restart(delay) if delay > Duration::from_secs(0) => {
+                    debug!("Respawning command in {:?}", delay);
+                    return Err(PersistentCommandError::RestartDelayed(delay));
+                }
+                RestartAction::Restart(_) => (),
+                RestartAction::GiveUp => {
+                    match reason {
+                        Event::ExitSuccess => debug!("Not respawning successful command"),
+                        Event::ExitCode(code) => {
+                            debug!("Not respawning command which exited with code {}", code)
+                        }
+                        Event::ExitSignal(signal) => {
+                            debug!("Not respawning command killed by {:?}", signal)
+                        }
+                        Event::StartFailed { .. } => {
+                            debug!("Not respawning command which failed to start")
+                        }
+                    }
                     return Err(PersistentCommandError::MustNotRespawn(reason));
                 }
-                _ => (),
+                RestartAction::Escalate => {
+                    return Err(PersistentCommandError::Escalated(reason));
+                }
+            }
+        }
+
+        // e.g. on a filesystem which is not mounted yet. Waiting for it doesn't count as a spawn.
+        if let Some(path) = self.executable().filter(|path| !path.exists()) {
+            return Err(PersistentCommandError::ExecutableMissing(path));
+        }
+
+        let now = Instant::now();
+        if let (Some(stable), Some(last)) = (self.reset_spawns_after, self.spawn_times.back()) {
+            if previous_exit_reason.is_some() && now.duration_since(*last) >= stable {
+                debug!(
+                    "Command ran stable for at least {:?}, resetting spawn count",
+                    stable
+                );
+                self.spawns = 0;
+                self.spawn_times.clear();
+                self.exit_signals.clear();
             }
         }
 
@@ -82,58 +1602,891 @@ impl<'a> PersistentCommand<'a> {
             }
         }
 
+        if let Some((count, window)) = self.spawn_limit_window {
+            while let Some(first) = self.spawn_times.front() {
+                if now.duration_since(*first) < window {
+                    break;
+                }
+                self.spawn_times.pop_front();
+            }
+            if self.spawn_times.len() >= count {
+                // the window is full, so there is a first entry
+                let wait = window.saturating_sub(now.duration_since(self.spawn_times[0]));
+                debug!(
+                    "Command spawned {} times in the last {:?}, delaying spawn for {:?}",
+                    count, window, wait
+                );
+                return Err(PersistentCommandError::SpawnRateLimited(wait));
+            }
+        }
+
         self.spawns += 1;
+        self.spawn_times.push_back(now);
+        // only the last spawn is needed to know how long the process ran
+        if self.spawn_limit_window.is_none() && self.spawn_times.len() > 1 {
+            self.spawn_times.pop_front();
+        }
         trace!("Command has been spawned {} times now", self.spawns);
+        Ok(())
+    }
 
-        let mut cmd = Command::new(self.cmd);
-        cmd.args(self.args.split_whitespace());
+    /// Spawn a new process for the command, which was [admitted], along with the later stages
+    /// of its pipeline, if any. If output is captured, stdout and stderr are piped unless they
+    /// are explicitly redirected. The process joins the cgroup the given write places it in, if
+    /// any.
+    ///
+    /// [admitted]: #method.admit
+    pub(crate) fn spawn(
+        &mut self,
+        capture_output: bool,
+        cgroup: Option<PreparedWrite>,
+    ) -> Result<(Child, Vec<Child>), PersistentCommandError> {
+        debug!("Creating command from persistent command");
+        // the next spawn runs the pre-start hook again
+        self.pre_started = false;
+
+        let passwd = match self.user {
+            Some(name) => Some(Passwd::from_name(name)?),
+            None => None,
+        };
+
+        for &(path, mode) in &self.directories {
+            let uid = self.uid.or_else(|| passwd.as_ref().map(|pw| pw.uid));
+            let gid = self.gid.or_else(|| passwd.as_ref().map(|pw| pw.gid));
+            let entry = tmpfiles::Entry {
+                kind: EntryType::CreateDirectory,
+                path: PathBuf::from(path),
+                mode: Some(mode),
+                user: uid.map(|uid| uid.to_string()),
+                group: gid.map(|gid| gid.to_string()),
+                age: None,
+                argument: None,
+            };
+            entry.create(false).map_err(|e| {
+                io::Error::new(e.kind(), format!("failed to create {}: {}", path, e))
+            })?;
+        }
+
+        let mut cmd = if self.login_shell {
+            let pw = passwd.as_ref().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "a login shell requires a user")
+            })?;
+            // login shells are recognized by a leading dash in argv[0]
+            let shell_name = Path::new(&pw.shell)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let mut cmd = Command::new(&pw.shell);
+            cmd.arg0(format!("-{}", shell_name)).current_dir(&pw.home);
+            cmd
+        } else if self.ask_first {
+            // the shell asks, and is replaced by the command once Enter is pressed
+            let mut cmd = Command::new(SHELL);
+            cmd.args(["-c", ASK_FIRST_SCRIPT, basename(self.cmd), self.cmd])
+                .args(self.arguments());
+            cmd
+        } else {
+            let mut cmd = Command::new(self.cmd);
+            cmd.args(self.arguments());
+            cmd
+        };
+
+        if capture_output {
+            cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+
+        unsafe {
+            cmd.pre_exec(sys::unblock_signals);
+        }
+
+        if let Some(mask) = self.umask {
+            unsafe {
+                cmd.pre_exec(move || {
+                    libc::umask(mask as libc::mode_t);
+                    Ok(())
+                });
+            }
+        }
+        // the working directory is changed before any pre_exec hook runs, so with a chroot it is
+        // changed by hand, or the process would be left outside of the new root
+        if let (None, Some(dir)) = (self.chroot, self.current_dir) {
+            cmd.current_dir(dir);
+        }
+        if let Some(join) = cgroup {
+            unsafe {
+                cmd.pre_exec(move || join.apply());
+            }
+        }
+        // the process leads its own session so it, and everything it forks, can be signaled as
+        // a group. A terminal needs a new session as well, which is set up along with it.
+        let input_tty = match self.stdin {
+            Some(Input::Tty(path)) => Some(path),
+            _ => None,
+        };
+        // without anything reading the master, a command would block once the pseudo-terminal
+        // is full
+        let pty = self.pty && self.tty.is_none();
+        if pty && !capture_output {
+            warn!(
+                "Not running {} on a pseudo-terminal, its output is not captured",
+                self.service_name()
+            );
+        }
+        // the output on the terminal can't be read by a pipeline as well
+        if pty && !self.pipe_to.is_empty() {
+            warn!(
+                "Not running {} on a pseudo-terminal, its output is piped",
+                self.service_name()
+            );
+        }
+        let pty = pty && capture_output && self.pipe_to.is_empty();
+        let input_tty = match input_tty {
+            Some(path) if pty => {
+                warn!(
+                    "Not reading the input of {} from {}, it runs on a pseudo-terminal",
+                    self.service_name(),
+                    path
+                );
+                None
+            }
+            input_tty => input_tty,
+        };
+        if self.tty.is_none() && input_tty.is_none() && !pty {
+            unsafe {
+                cmd.pre_exec(sys::new_session);
+            }
+        }
+
+        let owner = self.uid.or_else(|| passwd.as_ref().map(|pw| pw.uid));
+        if let Some(path) = self.tty {
+            let tty = open_tty(path, owner)?;
+            // the terminal is set up on the descriptor it was opened as, as stdin might be
+            // replaced by the input of the command
+            let ctty = tty.try_clone()?;
+            cmd.stdin(Stdio::from(tty.try_clone()?))
+                .stdout(Stdio::from(tty.try_clone()?))
+                .stderr(Stdio::from(tty))
+                .env("TERM", term_for(path));
+            unsafe {
+                cmd.pre_exec(move || set_controlling_tty(ctty.as_raw_fd()));
+            }
+        }
+        let mut pty_master = None;
+        if pty {
+            let (master, slave) = open_pty(owner)?;
+            let ctty = slave.try_clone()?;
+            cmd.stdin(Stdio::from(slave.try_clone()?))
+                .stdout(Stdio::from(slave.try_clone()?))
+                .stderr(Stdio::from(slave))
+                .env("TERM", "dumb");
+            unsafe {
+                cmd.pre_exec(move || set_controlling_tty(ctty.as_raw_fd()));
+            }
+            pty_master = Some(master);
+        }
+
+        match self.stdin {
+            Some(Input::Null) => {
+                cmd.stdin(Stdio::null());
+            }
+            Some(Input::File(path)) => {
+                cmd.stdin(Stdio::from(File::open(path)?));
+            }
+            Some(Input::Tty(path)) if input_tty.is_some() => {
+                let tty = open_tty(path, owner)?;
+                // the controlling terminal is the one set with tty, if any, so it is only set up
+                // once
+                if self.tty.is_none() {
+                    let ctty = tty.try_clone()?;
+                    cmd.env("TERM", term_for(path));
+                    unsafe {
+                        cmd.pre_exec(move || set_controlling_tty(ctty.as_raw_fd()));
+                    }
+                }
+                cmd.stdin(Stdio::from(tty));
+            }
+            Some(Input::Tty(_)) | None => (),
+        }
+        if let Some(path) = self.stdout_path {
+            cmd.stdout(Stdio::from(open_log(path)?));
+        }
+        if let Some(path) = self.stderr_path {
+            cmd.stderr(Stdio::from(open_log(path)?));
+        }
+
+        if let Some(label) = self.security_label {
+            if label.lsm_active() {
+                let exec_label = label.prepare()?;
+                unsafe {
+                    cmd.pre_exec(move || exec_label.apply());
+                }
+            } else {
+                warn!(
+                    "Not applying security label {} to {}, LSM is not active",
+                    label, self
+                );
+            }
+        }
+
+        if self.session_keyring {
+            unsafe {
+                cmd.pre_exec(sys::join_session_keyring);
+            }
+        }
+
+        if let Some(uid) = self.login_uid {
+            let loginuid = PreparedWrite::new("/proc/self/loginuid", uid.to_string())?;
+            unsafe {
+                cmd.pre_exec(move || loginuid.apply());
+            }
+        }
+
+        if let Some(adj) = self.oom_score_adj {
+            let oom_score_adj = PreparedWrite::new("/proc/self/oom_score_adj", adj.to_string())?;
+            unsafe {
+                cmd.pre_exec(move || oom_score_adj.apply());
+            }
+        }
+
+        if let Some(nice) = self.nice {
+            unsafe {
+                cmd.pre_exec(move || sys::set_nice(nice));
+            }
+        }
+
+        if let Some(priority) = self.io_priority {
+            let ioprio = priority.value();
+            unsafe {
+                cmd.pre_exec(move || sys::set_ioprio(ioprio));
+            }
+        }
+
+        if !self.cpu_affinity.is_empty() {
+            let cpus = cpu_set(&self.cpu_affinity)?;
+            unsafe {
+                cmd.pre_exec(move || sys::set_affinity(&cpus));
+            }
+        }
+
+        if let Some(policy) = self.sched_policy {
+            let (policy, priority) = policy.value();
+            unsafe {
+                cmd.pre_exec(move || sys::set_scheduler(policy, priority, false));
+            }
+        }
+
+        // joined before any new namespace is created, which might be one the reaper can't see
+        if let Some(netns) = self.netns {
+            let join = NetnsJoin::open(netns)?;
+            unsafe {
+                cmd.pre_exec(move || join.apply());
+            }
+        }
+
+        if !self.namespaces.is_empty() {
+            let parent = Some(i32::from(getpid())).filter(|_| !self.leave_running);
+            let namespaces = Namespaces::prepare(&self.namespaces, self.chroot, parent)?;
+            unsafe {
+                cmd.pre_exec(move || namespaces.apply());
+            }
+        }
+
+        if self.private_devices {
+            let dev = PrivateDev::prepare(self.chroot, &self.allowed_devices)?;
+            unsafe {
+                cmd.pre_exec(move || dev.apply());
+            }
+        }
+
+        if let Some(root) = self.chroot {
+            let root = CString::new(root).map_err(io::Error::from)?;
+            let dir = CString::new(self.current_dir.unwrap_or("/")).map_err(io::Error::from)?;
+            unsafe {
+                cmd.pre_exec(move || sys::chroot(&root, &dir));
+            }
+        }
+
+        if !self.rlimits.is_empty() {
+            let rlimits = self.rlimits.clone();
+            unsafe {
+                cmd.pre_exec(move || sys::set_rlimits(&rlimits));
+            }
+        }
+
+        // Dropping privileges must be the last step before exec, since everything above might
+        // require the privileges of init.
+        if let Some(pw) = &passwd {
+            cmd.env("HOME", &pw.home)
+                .env("USER", &pw.name)
+                .env("LOGNAME", &pw.name)
+                .env("SHELL", &pw.shell);
+        }
+        if let Some(path) = &self.notify_socket {
+            cmd.env("NOTIFY_SOCKET", path);
+        }
+        if let Some(interval) = self.watchdog {
+            cmd.env("WATCHDOG_USEC", interval.as_micros().to_string());
+        }
+        let mut env: Vec<(String, String)> = Vec::new();
+        for (path, required) in &self.environment_files {
+            match read_environment_file(path) {
+                Ok(vars) => env.extend(vars),
+                Err(ref e) if !required && e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => {
+                    return Err(PersistentCommandError::SpawnFailed(io::Error::new(
+                        e.kind(),
+                        format!("failed to read environment file {}: {}", path, e),
+                    )))
+                }
+            }
+        }
+        env.extend(self.env.iter().cloned());
+        cmd.envs(env.iter().map(|(key, value)| (key, value)));
+        let credentials = Credentials::resolve(passwd.as_ref(), self.uid, self.gid)?
+            .with_capabilities(self.capabilities);
+        let stage_credentials = credentials.clone();
+        unsafe {
+            cmd.pre_exec(move || credentials.apply());
+        }
+        // changing credentials clears the parent death signal, so it is set up afterwards
+        if !self.leave_running {
+            let parent = i32::from(getpid());
+            unsafe {
+                cmd.pre_exec(move || sys::signal_on_parent_death(libc::SIGKILL, parent));
+            }
+        }
+        let lockdown = Lockdown {
+            no_new_privs: self.no_new_privs || self.syscall_filter.is_some(),
+            filter: self
+                .syscall_filter
+                .as_ref()
+                .map(SyscallFilter::compile)
+                .transpose()?,
+        };
+        let stage_lockdown = lockdown.clone();
+        unsafe {
+            cmd.pre_exec(move || lockdown.apply());
+        }
+
+        #[cfg(feature = "pam")]
+        let pam_session = match (self.pam_service, &passwd) {
+            (Some(service), Some(pw)) => {
+                let session = PamSession::open(service, &pw.name, self.tty)?;
+                cmd.envs(session.env());
+                Some(session)
+            }
+            (Some(_), None) => {
+                return Err(PersistentCommandError::SpawnFailed(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a PAM session requires a user",
+                )));
+            }
+            _ => None,
+        };
+
+        if self.password.is_some() {
+            cmd.stdin(Stdio::piped());
+        }
+        if !self.pipe_to.is_empty() {
+            cmd.stdout(Stdio::piped());
+        }
+
+        let spawned = cmd.spawn();
+
+        #[cfg(feature = "pam")]
+        {
+            if let Some(session) = &pam_session {
+                session.restore_limits();
+            }
+            // on failure this drops, and thus closes, the session again
+            if spawned.is_ok() {
+                self.pam_session = pam_session;
+            }
+        }
+
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) if is_resource_exhaustion(&e) => {
+                // the command itself is fine, so this spawn doesn't count
+                self.spawns -= 1;
+                self.spawn_times.pop_back();
+                return Err(PersistentCommandError::ResourcesExhausted(e));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let (Some(password), Some(mut stdin)) = (&self.password, child.stdin.take()) {
+            // a password easily fits in the pipe buffer, so this doesn't block. Dropping stdin
+            // closes the pipe afterwards.
+            if let Err(e) = stdin
+                .write_all(password)
+                .and_then(|_| stdin.write_all(b"\n"))
+            {
+                warn!("Failed to pass password to {}: {}", self.service_name(), e);
+            }
+        }
+
+        let stages = match self.spawn_stages(
+            &mut child,
+            capture_output,
+            &env,
+            stage_credentials,
+            stage_lockdown,
+        ) {
+            Ok(stages) => stages,
+            Err(e) => {
+                // the pipeline runs as a whole or not at all
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e.into());
+            }
+        };
+        // everything written to the terminal is read from the master, which is captured in
+        // place of stdout. Commands with a pipeline don't run on a pseudo-terminal.
+        if let Some(master) = pty_master {
+            child.stdout = Some(ChildStdout::from(OwnedFd::from(master)));
+        }
+        Ok((child, stages))
+    }
+
+    /// Spawn the later stages of the pipeline, the first of which reads the output of the
+    /// process of the command.
+    fn spawn_stages(
+        &self,
+        first: &mut Child,
+        capture_output: bool,
+        env: &[(String, String)],
+        credentials: Credentials,
+        lockdown: Lockdown,
+    ) -> io::Result<Vec<Child>> {
+        let mut stages: Vec<Child> = Vec::with_capacity(self.pipe_to.len());
+        if self.pipe_to.is_empty() {
+            // the output of a single command is left for the caller to capture
+            return Ok(stages);
+        }
+        let mut input = first.stdout.take().map(Stdio::from);
+        for (idx, stage) in self.pipe_to.iter().enumerate() {
+            let last = idx + 1 == self.pipe_to.len();
+            let (program, args) = split_command(stage);
+            let mut cmd = Command::new(program);
+            cmd.args(args.split_whitespace())
+                .envs(env.iter().map(|(key, value)| (key, value)));
+            if let Some(input) = input.take() {
+                cmd.stdin(input);
+            }
+            match (last, self.stdout_path) {
+                (false, _) => {
+                    cmd.stdout(Stdio::piped());
+                }
+                (true, Some(path)) => {
+                    cmd.stdout(Stdio::from(open_log(path)?));
+                }
+                (true, None) if capture_output => {
+                    cmd.stdout(Stdio::piped());
+                }
+                (true, None) => (),
+            }
+            if let Some(path) = self.stderr_path {
+                cmd.stderr(Stdio::from(open_log(path)?));
+            } else if capture_output {
+                cmd.stderr(Stdio::piped());
+            }
+            if let (None, Some(dir)) = (self.chroot, self.current_dir) {
+                cmd.current_dir(dir);
+            }
+            let credentials = credentials.clone();
+            let parent = i32::from(getpid());
+            let leave_running = self.leave_running;
+            unsafe {
+                cmd.pre_exec(sys::unblock_signals);
+                cmd.pre_exec(sys::new_session);
+                cmd.pre_exec(move || credentials.apply());
+                if !leave_running {
+                    cmd.pre_exec(move || sys::signal_on_parent_death(libc::SIGKILL, parent));
+                }
+                let lockdown = lockdown.clone();
+                cmd.pre_exec(move || lockdown.apply());
+            }
+            let mut child = match cmd.spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    for mut stage in stages {
+                        let _ = stage.kill();
+                        let _ = stage.wait();
+                    }
+                    return Err(e);
+                }
+            };
+            if !last {
+                input = child.stdout.take().map(Stdio::from);
+            }
+            stages.push(child);
+        }
+        Ok(stages)
+    }
+}
 
-        let id = cmd.spawn().map(|child| child.id())?;
+/// Restrictions on a process which are applied right before it execs, so they don't get in the
+/// way of setting it up.
+#[derive(Clone)]
+struct Lockdown {
+    no_new_privs: bool,
+    filter: Option<CompiledFilter>,
+}
+
+impl Lockdown {
+    fn apply(&self) -> io::Result<()> {
+        if self.no_new_privs {
+            seccomp::set_no_new_privs()?;
+        }
+        if let Some(filter) = &self.filter {
+            filter.apply()?;
+        }
+        Ok(())
+    }
+}
 
-        Ok(id)
+/// Read the variables in an environment file, with a `KEY=VALUE` assignment on every line.
+/// Empty lines and comments starting with `#` are skipped, as is an `export` in front of an
+/// assignment, and quotes around a value are removed, so the file can be sourced by a shell as
+/// well.
+fn read_environment_file(path: &str) -> io::Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(path)?;
+    let mut vars = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (key, value) = match line.find('=') {
+            Some(eq) if eq > 0 => (line[..eq].trim_end(), line[eq + 1..].trim_start()),
+            _ => {
+                warn!("Ignoring line {} of {}: expected KEY=VALUE", idx + 1, path);
+                continue;
+            }
+        };
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| {
+                value
+                    .strip_prefix(*quote)
+                    .and_then(|value| value.strip_suffix(*quote))
+            })
+            .unwrap_or(value);
+        vars.push((key.to_string(), value.to_string()));
     }
+    Ok(vars)
+}
+
+/// Open a file to capture output of a command in.
+fn open_log(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
 }
 
 impl<'a> std::fmt::Display for PersistentCommand<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{} {}", self.cmd, self.args)
+        match (self.login_shell, self.user, self.tty) {
+            (true, Some(user), Some(tty)) => write!(f, "autologin {} on {}", user, tty),
+            _ => {
+                if self.shell {
+                    write!(f, "{} -c {:?}", self.cmd, self.args)?;
+                } else {
+                    write!(f, "{} {}", self.cmd, self.args)?;
+                }
+                for stage in &self.pipe_to {
+                    write!(f, " | {}", stage)?;
+                }
+                Ok(())
+            }
+        }
     }
 }
 
-#[derive(Debug)]
-pub enum PersistentCommandError {
-    SpawnLimitReached(usize),
-    SpawnFailed(std::io::Error),
-    MustNotRespawn(Event),
+/// A command which is run once at boot, before the persistent commands are spawned, e.g. to
+/// mount filesystems or bring up the loopback interface.
+#[derive(Clone)]
+pub struct OneShotCommand<'a> {
+    cmd: &'a str,
+    args: &'a str,
+    name: Option<&'a str>,
+    required: bool,
+    wait: bool,
+    timeout: Option<Duration>,
+}
+
+impl<'a> OneShotCommand<'a> {
+    pub const fn new(cmd: &'a str, args: &'a str) -> Self {
+        OneShotCommand {
+            cmd,
+            args,
+            name: None,
+            required: false,
+            wait: true,
+            timeout: None,
+        }
+    }
+
+    /// Set the name the command is known by. If no name is set, the file name of the executable
+    /// is used.
+    pub fn name(mut self, name: &'a str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// The name the command is known by.
+    pub fn service_name(&self) -> &str {
+        self.name.unwrap_or_else(|| basename(self.cmd))
+    }
+
+    /// Require the command to succeed for the system to be usable, e.g. to set up the storage
+    /// everything else relies on. If it fails, the one-shot commands after it are not run and
+    /// nothing is started, see [`ReaperError::RequiredFailed`].
+    ///
+    /// [`ReaperError::RequiredFailed`]: ../enum.ReaperError.html#variant.RequiredFailed
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    pub(crate) fn is_required(&self) -> bool {
+        self.required
+    }
+
+    /// Start the commands and services after this command only once it finished, which is the
+    /// default, like the `wait` action of an inittab. A command which does not wait runs
+    /// alongside the ones after it, and the services which depend on it are held until it
+    /// finished, which keeps booting short. A required command always waits.
+    pub fn wait(mut self, wait: bool) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    pub(crate) fn waits(&self) -> bool {
+        self.wait || self.required
+    }
+
+    /// Kill the command if it runs for longer than the given time, so a command which hangs
+    /// doesn't hold up booting or shutting down. A command which is killed failed.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub(crate) fn max_runtime(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// The executable of the command.
+    pub(crate) fn program(&self) -> &'a str {
+        self.cmd
+    }
+
+    /// Spawn the process of the command. If output is captured, stdout and stderr are piped. The
+    /// process is sent death_signal if the reaper dies before it exited.
+    pub(crate) fn spawn(&self, capture_output: bool, death_signal: Signal) -> io::Result<Child> {
+        let mut cmd = Command::new(self.cmd);
+        cmd.args(self.args.split_whitespace());
+        if capture_output {
+            cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        let parent = i32::from(getpid());
+        unsafe {
+            cmd.pre_exec(sys::unblock_signals);
+            cmd.pre_exec(move || sys::signal_on_parent_death(death_signal as libc::c_int, parent));
+        }
+        cmd.spawn()
+    }
 }
 
-impl std::fmt::Display for PersistentCommandError {
+impl<'a> std::fmt::Display for OneShotCommand<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.cmd, self.args)
+    }
+}
+
+/// Split a command line into the executable and its arguments.
+pub(crate) fn split_command(cmd: &str) -> (&str, &str) {
+    let cmd = cmd.trim();
+    match cmd.find(char::is_whitespace) {
+        Some(idx) => (&cmd[..idx], cmd[idx..].trim_start()),
+        None => (cmd, ""),
+    }
+}
+
+/// Split a hook command into the executable and its arguments.
+pub(crate) fn hook(cmd: &str) -> OneShotCommand<'_> {
+    let (program, args) = split_command(cmd);
+    OneShotCommand::new(program, args)
+}
+
+/// Give commands without an explicit name which share their default name with another service a
+/// name of their own, derived from their arguments, as `basename@hash`. Commands with the same
+/// arguments as well are told apart by their position, as `basename@hash-N`. The names only
+/// depend on the commands, so they are the same every time init starts.
+pub(crate) fn derive_names(commands: &mut [PersistentCommand]) {
+    let mut taken: HashMap<String, usize> = HashMap::new();
+    for cmd in commands.iter() {
+        *taken.entry(cmd.service_name().to_string()).or_default() += 1;
+    }
+    let mut derived: HashMap<String, usize> = HashMap::new();
+    for cmd in commands.iter_mut() {
+        if cmd.name.is_some() || taken[cmd.service_name()] < 2 {
+            continue;
+        }
+        let name = format!("{}@{:08x}", cmd.service_name(), fnv1a(cmd.args.as_bytes()));
+        let seen = derived.entry(name.clone()).or_default();
+        *seen += 1;
+        cmd.derived_name = Some(match *seen {
+            1 => name,
+            n => format!("{}-{}", name, n),
+        });
+    }
+}
+
+/// The 32 bit FNV-1a hash of data, which, unlike the hasher of the standard library, is stable.
+fn fnv1a(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Check if spawning failed because the system, rather than the command, is out of resources,
+/// e.g. when the process limit is hit. Trying again later can succeed.
+fn is_resource_exhaustion(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::EAGAIN) | Some(libc::ENOMEM))
+}
+
+/// The file name of an executable.
+fn basename(cmd: &str) -> &str {
+    Path::new(cmd)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(cmd)
+}
+
+/// The io scheduling class of a process, along with the priority within the class where it has
+/// one, from 0 for the highest to 7 for the lowest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Always served first, use with care as it can starve other processes.
+    Realtime(u8),
+    /// The default class.
+    BestEffort(u8),
+    /// Only served when nothing else needs the disk.
+    Idle,
+}
+
+impl IoPriority {
+    /// The value as passed to `ioprio_set`.
+    fn value(self) -> libc::c_int {
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        let (class, level) = match self {
+            IoPriority::Realtime(level) => (1, level.min(7)),
+            IoPriority::BestEffort(level) => (2, level.min(7)),
+            IoPriority::Idle => (3, 0),
+        };
+        (class << IOPRIO_CLASS_SHIFT) | libc::c_int::from(level)
+    }
+}
+
+/// The scheduling policy of a process, along with the realtime priority for the realtime
+/// policies, from 1 for the lowest to 99 for the highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// Realtime, running until it blocks or a process with a higher priority is runnable. Use
+    /// with care, a busy loop starves everything with a lower priority.
+    Fifo(u8),
+    /// Realtime like `Fifo`, but taking turns with processes of the same priority.
+    RoundRobin(u8),
+    /// Only run when nothing else wants the CPU.
+    Idle,
+}
+
+impl SchedPolicy {
+    /// The policy and priority as passed to `sched_setscheduler`.
+    fn value(self) -> (libc::c_int, libc::c_int) {
         match self {
-            PersistentCommandError::SpawnLimitReached(x) => {
-                write!(f, "Spawn limit ({}) reached", x)
-            }
-            PersistentCommandError::SpawnFailed(e) => write!(f, "Spawning command failed: {}", e),
-            PersistentCommandError::MustNotRespawn(e) => write!(
-                f,
-                "Previous command died due to {:?}, no need to respawn",
-                e
-            ),
+            SchedPolicy::Fifo(priority) => {
+                (libc::SCHED_FIFO, libc::c_int::from(priority.clamp(1, 99)))
+            }
+            SchedPolicy::RoundRobin(priority) => {
+                (libc::SCHED_RR, libc::c_int::from(priority.clamp(1, 99)))
+            }
+            SchedPolicy::Idle => (libc::SCHED_IDLE, 0),
         }
     }
 }
 
-impl std::error::Error for PersistentCommandError {}
+/// Where a command reads its standard input from, see [`PersistentCommand::stdin`].
+///
+/// [`PersistentCommand::stdin`]: struct.PersistentCommand.html#method.stdin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input<'a> {
+    /// `/dev/null`, so reading gives end of file right away.
+    Null,
+    /// The file at the path, which must exist.
+    File(&'a str),
+    /// The terminal at the path, which becomes the controlling terminal of a new session for
+    /// the command, as for a getty. Its output goes where it would otherwise.
+    Tty(&'a str),
+}
 
-impl From<std::io::Error> for PersistentCommandError {
-    fn from(e: std::io::Error) -> Self {
-        PersistentCommandError::SpawnFailed(e)
+/// The set of the given CPUs, as passed to `sched_setaffinity`.
+fn cpu_set(cpus: &[usize]) -> io::Result<libc::cpu_set_t> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    let max = 8 * std::mem::size_of::<libc::cpu_set_t>();
+    for &cpu in cpus {
+        if cpu >= max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("CPU {} is out of range", cpu),
+            ));
+        }
+        unsafe { libc::CPU_SET(cpu, &mut set) };
     }
+    Ok(set)
 }
 
+/// How a process exited.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum Event {
+    /// The process exited with code 0.
     ExitSuccess,
-    ExitCode,
-    ExitSignal,
+    /// The process exited with the given, non zero, code.
+    ExitCode(i32),
+    /// The process was killed by the given signal.
+    ExitSignal(
+        #[cfg_attr(
+            feature = "serde",
+            serde(serialize_with = "crate::events::ser::signal")
+        )]
+        Signal,
+    ),
+    /// The process exited with an error or was killed within the start grace period of its
+    /// command, with either its exit code or the signal which killed it.
+    StartFailed {
+        code: Option<i32>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(serialize_with = "crate::events::ser::optional_signal")
+        )]
+        signal: Option<Signal>,
+    },
+}
+
+impl Event {
+    /// A stable name for how the process exited, like `signal`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ExitSuccess => "success",
+            Event::ExitCode(_) => "error",
+            Event::ExitSignal(_) => "signal",
+            Event::StartFailed { .. } => "start_failed",
+        }
+    }
 }
@@ -0,0 +1,174 @@
+//! A human oriented account of booting on the console: a line per service once it started, or
+//! failed to, with how long that took. A service which takes a while to become ready gets a
+//! spinner, as long as the console can redraw a line.
+//!
+//! Every service is only reported on the first time it is started, restarts are left to the log.
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How long a service must be starting before the spinner is shown.
+const SPINNER_DELAY: Duration = Duration::from_secs(1);
+
+/// How often the spinner is redrawn.
+const SPINNER_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The frames of the spinner, as wide as the tags of the status lines.
+const SPINNER_FRAMES: [&str; 10] = [
+    "*     ", " *    ", "  *   ", "   *  ", "    * ", "     *", "    * ", "   *  ", "  *   ",
+    " *    ",
+];
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// How the status of services is shown on the console while booting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConsoleMode {
+    /// Don't show it, e.g. when the kernel command line has `quiet`. This is the default.
+    #[default]
+    Off,
+    /// Plain lines without colors or a spinner, e.g. for serial consoles which log everything.
+    Plain,
+    /// Colored lines, with a spinner for services which take a while to start.
+    Color,
+}
+
+impl ConsoleMode {
+    /// Use colors if stdout is a terminal, or plain lines otherwise.
+    pub fn detect() -> Self {
+        if unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1 {
+            ConsoleMode::Color
+        } else {
+            ConsoleMode::Plain
+        }
+    }
+}
+
+/// Parse `off`, `plain` or `color`.
+impl FromStr for ConsoleMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "off" => Ok(ConsoleMode::Off),
+            "plain" => Ok(ConsoleMode::Plain),
+            "color" => Ok(ConsoleMode::Color),
+            _ => Err(format!("invalid console mode {:?}", mode)),
+        }
+    }
+}
+
+pub(crate) struct ConsoleStatus {
+    mode: ConsoleMode,
+    reported: HashSet<String>,
+    // the services which were spawned but are not ready yet, in the order they were spawned
+    starting: Vec<(String, Instant)>,
+    frame: usize,
+    last_draw: Option<Instant>,
+}
+
+impl ConsoleStatus {
+    pub(crate) fn new(mode: ConsoleMode) -> Self {
+        ConsoleStatus {
+            mode,
+            reported: HashSet::new(),
+            starting: Vec::new(),
+            frame: 0,
+            last_draw: None,
+        }
+    }
+
+    /// Record that a process of the service was spawned. Services which are ready right away
+    /// are reported at once.
+    pub(crate) fn spawned(&mut self, name: &str, ready: bool, now: Instant) {
+        if self.mode == ConsoleMode::Off || self.reported.contains(name) {
+            return;
+        }
+        if !self.starting.iter().any(|(starting, _)| starting == name) {
+            self.starting.push((name.to_string(), now));
+        }
+        if ready {
+            self.ready(name, now);
+        }
+    }
+
+    /// Report that the service finished starting up.
+    pub(crate) fn ready(&mut self, name: &str, now: Instant) {
+        if let Some(since) = self.take(name) {
+            let took = now.duration_since(since).as_secs_f64();
+            self.print("  OK  ", GREEN, &format!("Started {} ({:.2}s)", name, took));
+        }
+    }
+
+    /// Report that the service failed before it finished starting up.
+    pub(crate) fn failed(&mut self, name: &str, why: &str) {
+        if self.mode == ConsoleMode::Off || self.reported.contains(name) {
+            return;
+        }
+        self.take(name);
+        self.reported.insert(name.to_string());
+        self.print(" FAIL ", RED, &format!("Failed to start {}: {}", name, why));
+    }
+
+    /// When the spinner is due to be drawn again, if it is shown.
+    pub(crate) fn next_draw(&self) -> Option<Instant> {
+        if self.mode != ConsoleMode::Color {
+            return None;
+        }
+        let (_, since) = self.starting.first()?;
+        Some(match self.last_draw {
+            Some(last) => last + SPINNER_INTERVAL,
+            None => *since + SPINNER_DELAY,
+        })
+    }
+
+    /// Draw the spinner for the service which has been starting the longest, if it is due.
+    pub(crate) fn draw_spinner(&mut self, now: Instant) {
+        if self.next_draw().is_none_or(|next| next > now) {
+            return;
+        }
+        let (name, since) = &self.starting[0];
+        let line = format!(
+            "\r\x1b[K[{}{}{}] Starting {} ({}s)",
+            YELLOW,
+            SPINNER_FRAMES[self.frame % SPINNER_FRAMES.len()],
+            RESET,
+            name,
+            now.duration_since(*since).as_secs()
+        );
+        self.frame += 1;
+        self.last_draw = Some(now);
+        let mut stdout = io::stdout();
+        let _ = stdout
+            .write_all(line.as_bytes())
+            .and_then(|_| stdout.flush());
+    }
+
+    fn take(&mut self, name: &str) -> Option<Instant> {
+        let idx = self
+            .starting
+            .iter()
+            .position(|(starting, _)| starting == name)?;
+        self.reported.insert(name.to_string());
+        Some(self.starting.remove(idx).1)
+    }
+
+    fn print(&mut self, tag: &str, color: &str, message: &str) {
+        let line = match self.mode {
+            ConsoleMode::Off => return,
+            ConsoleMode::Plain => format!("[{}] {}\n", tag, message),
+            // the spinner, if any, is overwritten
+            ConsoleMode::Color => format!("\r\x1b[K[{}{}{}] {}\n", color, tag, RESET, message),
+        };
+        self.last_draw = None;
+        let mut stdout = io::stdout();
+        let _ = stdout
+            .write_all(line.as_bytes())
+            .and_then(|_| stdout.flush());
+    }
+}
@@ -0,0 +1,553 @@
+//! A Unix domain socket through which services can be inspected and managed at runtime.
+//!
+//! Every connection carries a single request: a JSON object on one line, such as
+//! `{"command": "restart", "name": "sshd"}`. The reply is a JSON object on one line as well,
+//! with an `ok` field indicating success and either the requested data or an `error` message.
+//! Supported commands are `list`, `status`, `start`, `stop` and `restart`, where all but `list`
+//! require the `name` of a service, as well as `stop-all` and `stats`. Pending password queries
+//! are listed with `passwords`, and answered with `answer`, which requires the `id` of the query
+//! and the `password`. Both a query and the `status` of a single service list the services which
+//! wait for it as `blocking`. Finally `ping` checks that the reaper is alive: it is answered
+//! from the main loop, so a reply proves the loop is making progress. Services which are on hold
+//! are started with `release`, which takes an optional `name`, and releases all services without
+//! it.
+//! The environment of a service is changed with `set-env`, which requires the `name` of the
+//! service and an `env` object, in which a `null` value removes a variable. With `reexec`, the
+//! reaper executes its own binary again once it replied, e.g. after it was upgraded, and the new
+//! instance adopts the running services. The last output of a service is retrieved with `logs`,
+//! which requires the `name` of the service, if the reaper keeps the output in memory. With
+//! `boot-times`, the reaper reports when booting finished its phases, and when the services
+//! were first spawned and became ready. A process which must never be terminated as an orphan,
+//! like a debug shell, is protected with `protect`, which requires its `pid`.
+//!
+//! Instead of a single service, `status`, `start`, `stop` and `restart` also accept a group of
+//! services as `@group`, or a pattern of service names with `*` and `?` wildcards. The services
+//! are then handled in dependency order, and the reply lists the ones which were handled, along
+//! with the `errors` for the ones which weren't.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use nix::unistd::Pid;
+
+use crate::command::Event;
+use crate::json::Value;
+use crate::password::Query;
+use crate::stats::ReaperStats;
+use crate::status::{ServiceState, ServiceStatus};
+
+/// Default path of the control socket.
+pub const CONTROL_SOCKET: &str = "/run/rsinit.sock";
+
+/// How long a client gets to send its request before it is hung up on.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a client gets to take in its reply, so a stuck client can't stall the reaper.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The longest request a client can send, so it can't make the reaper buffer without bound.
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+/// A request received on the control socket.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) enum Request {
+    List,
+    Status(String),
+    Inspect(String),
+    Logs(String),
+    Start(String),
+    Stop(String),
+    Restart(String),
+    Reset(String),
+    StopAll,
+    Stats,
+    BootTimes,
+    Passwords,
+    Answer(u64, String),
+    Ping,
+    Release(Option<String>),
+    SwitchTarget(String),
+    Protect(Pid),
+    SetEnv(String, Vec<(String, Option<String>)>),
+    Reexec,
+}
+
+// passwords must not end up in the logs
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Request::List => write!(f, "List"),
+            Request::Status(name) => write!(f, "Status({:?})", name),
+            Request::Inspect(name) => write!(f, "Inspect({:?})", name),
+            Request::Logs(name) => write!(f, "Logs({:?})", name),
+            Request::Start(name) => write!(f, "Start({:?})", name),
+            Request::Stop(name) => write!(f, "Stop({:?})", name),
+            Request::Restart(name) => write!(f, "Restart({:?})", name),
+            Request::Reset(name) => write!(f, "Reset({:?})", name),
+            Request::StopAll => write!(f, "StopAll"),
+            Request::Stats => write!(f, "Stats"),
+            Request::BootTimes => write!(f, "BootTimes"),
+            Request::Passwords => write!(f, "Passwords"),
+            Request::Answer(id, _) => write!(f, "Answer({}, <redacted>)", id),
+            Request::Ping => write!(f, "Ping"),
+            Request::Release(name) => write!(f, "Release({:?})", name),
+            Request::SwitchTarget(name) => write!(f, "SwitchTarget({:?})", name),
+            Request::Protect(pid) => write!(f, "Protect({})", pid),
+            Request::Reexec => write!(f, "Reexec"),
+            // values can be sensitive as well
+            Request::SetEnv(name, env) => {
+                let keys: Vec<&String> = env.iter().map(|(key, _)| key).collect();
+                write!(f, "SetEnv({:?}, {:?})", name, keys)
+            }
+        }
+    }
+}
+
+impl Request {
+    fn parse(line: &str) -> Result<Request, String> {
+        let value = Value::parse(line)?;
+        let name = || {
+            value
+                .get("name")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| "missing service name".to_string())
+        };
+        match value.get("command").and_then(Value::as_str) {
+            Some("list") => Ok(Request::List),
+            Some("status") => Ok(Request::Status(name()?)),
+            Some("inspect") => Ok(Request::Inspect(name()?)),
+            Some("logs") => Ok(Request::Logs(name()?)),
+            Some("start") => Ok(Request::Start(name()?)),
+            Some("stop") => Ok(Request::Stop(name()?)),
+            Some("restart") => Ok(Request::Restart(name()?)),
+            Some("reset") => Ok(Request::Reset(name()?)),
+            Some("stop-all") => Ok(Request::StopAll),
+            Some("stats") => Ok(Request::Stats),
+            Some("boot-times") => Ok(Request::BootTimes),
+            Some("passwords") => Ok(Request::Passwords),
+            Some("answer") => {
+                let id = value
+                    .get("id")
+                    .and_then(Value::as_f64)
+                    .ok_or_else(|| "missing query id".to_string())?;
+                let password = value
+                    .get("password")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| "missing password".to_string())?;
+                Ok(Request::Answer(id as u64, password.to_string()))
+            }
+            Some("ping") => Ok(Request::Ping),
+            Some("release") => Ok(Request::Release(name().ok())),
+            Some("switch-target") => Ok(Request::SwitchTarget(name()?)),
+            Some("reexec") => Ok(Request::Reexec),
+            Some("protect") => {
+                let pid = value
+                    .get("pid")
+                    .and_then(Value::as_f64)
+                    .filter(|pid| *pid > 0.0)
+                    .ok_or_else(|| "missing pid".to_string())?;
+                Ok(Request::Protect(Pid::from_raw(pid as i32)))
+            }
+            Some("set-env") => {
+                let env = match value.get("env") {
+                    Some(Value::Object(fields)) => fields
+                        .iter()
+                        .map(|(key, value)| match value {
+                            Value::Null => Ok((key.clone(), None)),
+                            Value::String(s) => Ok((key.clone(), Some(s.clone()))),
+                            _ => Err(format!("invalid value for {}", key)),
+                        })
+                        .collect::<Result<_, _>>()?,
+                    _ => return Err("missing env".to_string()),
+                };
+                Ok(Request::SetEnv(name()?, env))
+            }
+            Some(cmd) => Err(format!("unknown command {}", cmd)),
+            None => Err("missing command".to_string()),
+        }
+    }
+}
+
+/// The services a request applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Target {
+    Service(String),
+    Group(String),
+    Pattern(String),
+}
+
+impl Target {
+    pub(crate) fn parse(target: &str) -> Target {
+        if let Some(group) = target.strip_prefix('@') {
+            Target::Group(group.to_string())
+        } else if target.contains(['*', '?']) {
+            Target::Pattern(target.to_string())
+        } else {
+            Target::Service(target.to_string())
+        }
+    }
+
+    /// Check if the target applies to a service with the given name and groups.
+    pub(crate) fn matches(&self, name: &str, groups: &[&str]) -> bool {
+        match self {
+            Target::Service(service) => service == name,
+            Target::Group(group) => groups.contains(&group.as_str()),
+            Target::Pattern(pattern) => wildcard_match(pattern.as_bytes(), name.as_bytes()),
+        }
+    }
+}
+
+/// Match a name against a pattern in which `*` matches any number of characters, and `?`
+/// matches a single one.
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_match(&pattern[1..], name)
+                || (!name.is_empty() && wildcard_match(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => wildcard_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// A client connection whose request is still arriving.
+struct Incoming {
+    stream: UnixStream,
+    request: Vec<u8>,
+    deadline: Instant,
+}
+
+impl Incoming {
+    /// Read what arrived of the request. Returns whether the request is complete, which it also
+    /// is when the client hung up or sent too much.
+    fn read(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Ok(true),
+                Ok(n) => {
+                    self.request.extend_from_slice(&buf[..n]);
+                    if buf[..n].contains(&b'\n') || self.request.len() > MAX_REQUEST_LEN {
+                        return Ok(true);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Parse the first line of the complete request.
+    fn parse(&self) -> Result<Request, String> {
+        if self.request.len() > MAX_REQUEST_LEN {
+            return Err("request too long".to_string());
+        }
+        let line = self
+            .request
+            .split(|b| *b == b'\n')
+            .next()
+            .unwrap_or_default();
+        let line = std::str::from_utf8(line).map_err(|_| "request is not valid UTF-8")?;
+        Request::parse(line.trim())
+    }
+}
+
+/// A client connection with a pending request.
+pub(crate) struct Client {
+    stream: UnixStream,
+}
+
+impl Client {
+    /// Send the reply to the request of the client. This blocks until the reply is written, for
+    /// at most [`REPLY_TIMEOUT`].
+    ///
+    /// [`REPLY_TIMEOUT`]: constant.REPLY_TIMEOUT.html
+    pub(crate) fn reply(mut self, reply: Result<Value, String>) {
+        let reply = match reply {
+            Ok(Value::Object(mut fields)) => {
+                fields.insert(0, ("ok".to_string(), Value::Bool(true)));
+                Value::Object(fields)
+            }
+            Ok(value) => Value::object().with("ok", true).with("result", value),
+            Err(e) => Value::object().with("ok", false).with("error", e),
+        };
+        let res = self
+            .stream
+            .set_nonblocking(false)
+            .and_then(|_| self.stream.set_write_timeout(Some(REPLY_TIMEOUT)))
+            .and_then(|_| writeln!(self.stream, "{}", reply));
+        if let Err(e) = res {
+            debug!("Failed to reply to control client: {}", e);
+        }
+    }
+}
+
+/// The listening control socket. The socket becomes readable when a connection comes in, so it
+/// is watched by the same loop which waits for SIGCHLD, and so are the connections, until their
+/// request arrived.
+pub(crate) struct ControlSocket {
+    listener: UnixListener,
+    /// The connections whose request did not fully arrive yet, by their file descriptor.
+    incoming: HashMap<RawFd, Incoming>,
+}
+
+impl ControlSocket {
+    /// Bind the control socket at path, replacing a stale socket left by a previous instance.
+    /// Only root can connect to it.
+    pub(crate) fn bind(path: &str) -> io::Result<Self> {
+        match fs::remove_file(path) {
+            Err(ref e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("failed to remove stale socket: {}", e),
+                ))
+            }
+            _ => (),
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+        Ok(ControlSocket {
+            listener,
+            incoming: HashMap::new(),
+        })
+    }
+
+    /// Accept all pending connections. Returns their file descriptors, which become readable as
+    /// their requests arrive. Closing a connection stops watching it.
+    pub(crate) fn accept(&mut self) -> Vec<RawFd> {
+        let mut accepted = Vec::new();
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("Failed to accept control connection: {}", e);
+                    break;
+                }
+            };
+            if let Err(e) = stream.set_nonblocking(true) {
+                warn!("Failed to accept control connection: {}", e);
+                continue;
+            }
+            let fd = stream.as_raw_fd();
+            self.incoming.insert(
+                fd,
+                Incoming {
+                    stream,
+                    request: Vec::new(),
+                    deadline: Instant::now() + REQUEST_TIMEOUT,
+                },
+            );
+            accepted.push(fd);
+        }
+        accepted
+    }
+
+    /// Check if the file descriptor is one of a connection whose request is still arriving.
+    pub(crate) fn is_incoming(&self, fd: RawFd) -> bool {
+        self.incoming.contains_key(&fd)
+    }
+
+    /// Read what arrived on the connection, and return its request once it is complete.
+    /// Malformed requests are answered right away.
+    pub(crate) fn read_request(&mut self, fd: RawFd) -> Option<(Request, Client)> {
+        match self.incoming.get_mut(&fd)?.read() {
+            Ok(false) => return None,
+            Ok(true) => (),
+            Err(e) => {
+                debug!("Failed to read control request: {}", e);
+                self.incoming.remove(&fd);
+                return None;
+            }
+        }
+        let incoming = self.incoming.remove(&fd)?;
+        let request = incoming.parse();
+        let client = Client {
+            stream: incoming.stream,
+        };
+        match request {
+            Ok(request) => Some((request, client)),
+            Err(e) => {
+                client.reply(Err(e));
+                None
+            }
+        }
+    }
+
+    /// Hang up on the clients which did not send their request in time.
+    pub(crate) fn expire(&mut self, now: Instant) {
+        self.incoming.retain(|_, incoming| {
+            if incoming.deadline > now {
+                return true;
+            }
+            debug!("Control client did not send its request in time");
+            false
+        });
+    }
+
+    /// When the next client runs out of time to send its request.
+    pub(crate) fn next_expiry(&self) -> Option<Instant> {
+        self.incoming
+            .values()
+            .map(|incoming| incoming.deadline)
+            .min()
+    }
+}
+
+impl AsRawFd for ControlSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+/// Describe what a service is doing.
+fn state_name(state: ServiceState) -> &'static str {
+    match state {
+        ServiceState::Stopped => "stopped",
+        ServiceState::Starting => "starting",
+        ServiceState::Ready => "ready",
+        ServiceState::Unhealthy => "unhealthy",
+        ServiceState::Restarting => "restarting",
+        ServiceState::Failed => "failed",
+    }
+}
+
+/// Describe the status of a service.
+pub(crate) fn status_json(name: &str, status: &ServiceStatus) -> Value {
+    Value::object()
+        .with("name", name)
+        .with("state", state_name(status.state()))
+        .with("running", status.is_running())
+        .with("ready", status.is_ready())
+        .with("healthy", status.healthy)
+        .with("status", status.status_text.as_deref())
+        .with("pid", status.pid.map(i32::from))
+        .with("cpu_time", status.usage.cpu_time.as_secs_f64())
+        .with("max_rss_bytes", status.usage.max_rss_bytes)
+        .with(
+            "stopped_for",
+            status.stopped_since.map(|since| since.elapsed().as_secs()),
+        )
+        .with("restarts", status.restarts)
+        .with("start_failures", status.start_failures)
+        .with("last_exit", status.last_exit.as_ref().map(Event::name))
+        .with(
+            "exit_code",
+            match status.last_exit {
+                Some(Event::ExitCode(code)) => Some(code),
+                Some(Event::StartFailed { code, .. }) => code,
+                _ => None,
+            },
+        )
+        .with("exit_reason", status.exit_reason.as_deref())
+        .with("failure", status.failure.as_deref())
+        .with(
+            "exit_signal",
+            match status.last_exit {
+                Some(Event::ExitSignal(signal))
+                | Some(Event::StartFailed {
+                    signal: Some(signal),
+                    ..
+                }) => Some(format!("{:?}", signal)),
+                _ => None,
+            },
+        )
+        .with(
+            "exit_signals",
+            status
+                .exit_signals
+                .iter()
+                .fold(Value::object(), |value, (signal, count)| {
+                    value.with(&format!("{:?}", signal), *count)
+                }),
+        )
+        .with("uptime", status.uptime().map(|u| u.as_secs()))
+        .with(
+            "waiting_for_executable",
+            status
+                .waiting_for_executable
+                .as_ref()
+                .map(|path| path.display().to_string()),
+        )
+}
+
+/// Describe how the last processes of a service ended, oldest first.
+pub(crate) fn history_json(status: &ServiceStatus) -> Value {
+    let terminations: Vec<Value> = status
+        .history
+        .iter()
+        .map(|termination| {
+            Value::object()
+                .with(
+                    "at",
+                    termination
+                        .at
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                )
+                .with("exit", termination.event.name())
+                .with(
+                    "code",
+                    match termination.event {
+                        Event::ExitCode(code) => Some(code),
+                        Event::StartFailed { code, .. } => code,
+                        _ => None,
+                    },
+                )
+                .with(
+                    "signal",
+                    match termination.event {
+                        Event::ExitSignal(signal)
+                        | Event::StartFailed {
+                            signal: Some(signal),
+                            ..
+                        } => Some(format!("{:?}", signal)),
+                        _ => None,
+                    },
+                )
+                .with("runtime", termination.runtime.map(|r| r.as_secs_f64()))
+        })
+        .collect();
+    terminations.into()
+}
+
+/// Describe a pending password query.
+pub(crate) fn query_json(query: &Query) -> Value {
+    Value::object()
+        .with("id", query.id)
+        .with("service", query.service.as_str())
+        .with("prompt", query.prompt.as_str())
+}
+
+/// Describe the statistics of the reaper.
+pub(crate) fn stats_json(stats: &ReaperStats) -> Value {
+    Value::object()
+        .with("zombies_reaped", stats.zombies_reaped)
+        .with("orphans_terminated", stats.orphans_terminated)
+        .with("orphans_expired", stats.orphans_expired)
+        .with("signals_handled", stats.signals_handled)
+        .with("spawn_pauses", stats.spawn_pauses)
+        .with("clock_jumps", stats.clock_jumps)
+        .with("reaps_deferred", stats.reaps_deferred)
+        .with("loop_iterations", stats.loop_iterations)
+        .with("tracked_children", stats.tracked_children)
+        .with("tracked_orphans", stats.tracked_orphans)
+        .with("unkillable_processes", stats.unkillable_processes)
+        .with("unhealthy", stats.unhealthy)
+        .with("rss_bytes", stats.rss_bytes)
+        .with("open_fds", stats.open_fds)
+        .with("threads", stats.threads)
+}
@@ -0,0 +1,173 @@
+use std::ffi::{CStr, CString};
+use std::io;
+
+use nix::unistd::{getgrouplist, Gid};
+
+use crate::capabilities;
+
+/// An entry from the user database.
+#[derive(Debug, Clone)]
+pub(crate) struct Passwd {
+    pub(crate) name: String,
+    pub(crate) uid: libc::uid_t,
+    pub(crate) gid: libc::gid_t,
+    pub(crate) home: String,
+    pub(crate) shell: String,
+}
+
+impl Passwd {
+    /// Look up a user by name in the user database.
+    pub(crate) fn from_name(name: &str) -> io::Result<Passwd> {
+        let c_name = CString::new(name)?;
+        let mut buf = vec![0 as libc::c_char; 4096];
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+
+        loop {
+            let res = unsafe {
+                libc::getpwnam_r(
+                    c_name.as_ptr(),
+                    &mut pwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                )
+            };
+            match res {
+                0 => break,
+                libc::ERANGE => buf.resize(buf.len() * 2, 0),
+                e => return Err(io::Error::from_raw_os_error(e)),
+            }
+        }
+
+        if result.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("user {} does not exist", name),
+            ));
+        }
+
+        unsafe {
+            Ok(Passwd {
+                name: name.to_string(),
+                uid: pwd.pw_uid,
+                gid: pwd.pw_gid,
+                home: CStr::from_ptr(pwd.pw_dir).to_string_lossy().into_owned(),
+                shell: CStr::from_ptr(pwd.pw_shell).to_string_lossy().into_owned(),
+            })
+        }
+    }
+}
+
+/// Look up the id of a group by name. Returns `None` if there is no such group.
+pub(crate) fn group_id(name: &str) -> io::Result<Option<libc::gid_t>> {
+    let c_name = CString::new(name)?;
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::group = std::ptr::null_mut();
+
+    loop {
+        let res = unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match res {
+            0 => break,
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            e => return Err(io::Error::from_raw_os_error(e)),
+        }
+    }
+
+    if result.is_null() {
+        return Ok(None);
+    }
+
+    Ok(Some(grp.gr_gid))
+}
+
+/// The credentials a process switches to before it execs.
+#[derive(Debug, Clone)]
+pub(crate) struct Credentials {
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    groups: Vec<libc::gid_t>,
+    /// The capabilities to keep, as a mask, or none to leave them to the kernel.
+    capabilities: Option<u64>,
+}
+
+impl Credentials {
+    /// Resolve the credentials to switch to. If a user name is given, the uid, primary group and
+    /// supplementary groups are taken from the user database, though an explicit uid or gid
+    /// takes precedence.
+    pub(crate) fn resolve(
+        user: Option<&Passwd>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> io::Result<Credentials> {
+        let mut creds = Credentials {
+            uid,
+            gid,
+            groups: Vec::new(),
+            capabilities: None,
+        };
+
+        if let Some(pw) = user {
+            creds.uid = creds.uid.or(Some(pw.uid));
+            creds.gid = creds.gid.or(Some(pw.gid));
+            let name = CString::new(pw.name.as_str())?;
+            creds.groups = getgrouplist(&name, Gid::from_raw(creds.gid.unwrap_or(pw.gid)))
+                .map_err(io::Error::other)?
+                .into_iter()
+                .map(libc::gid_t::from)
+                .collect();
+        } else if let Some(gid) = creds.gid {
+            creds.groups.push(gid);
+        }
+
+        Ok(creds)
+    }
+
+    /// Keep only the capabilities in the mask, also after changing to another user than root.
+    pub(crate) fn with_capabilities(mut self, mask: Option<u64>) -> Self {
+        self.capabilities = mask;
+        self
+    }
+
+    /// Switch to the credentials. Supplementary groups are always replaced when changing user,
+    /// so the process does not keep any of the groups of init. This only performs raw syscalls,
+    /// so it is safe to call in between fork and exec.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        if let Some(mask) = self.capabilities {
+            capabilities::limit_bounding_set(mask)?;
+            if self.uid.is_some() {
+                capabilities::keep_on_setuid()?;
+            }
+        }
+        unsafe {
+            if (self.uid.is_some() || self.gid.is_some())
+                && libc::setgroups(self.groups.len(), self.groups.as_ptr()) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(gid) = self.gid {
+                if libc::setgid(gid) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(uid) = self.uid {
+                if libc::setuid(uid) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        if let Some(mask) = self.capabilities {
+            capabilities::set(mask)?;
+        }
+        Ok(())
+    }
+}
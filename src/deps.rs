@@ -0,0 +1,45 @@
+//! Ordering of services by their dependencies.
+
+use std::collections::HashMap;
+
+/// Order nodes, given as a name and the names they depend on, so every node comes after its
+/// dependencies. Dependencies on names which are not in the list are ignored. Nodes keep their
+/// relative order where the dependencies allow it.
+///
+/// Returns the indices of the nodes in order, followed by the indices of the nodes which are
+/// part of, or depend on, a cycle.
+pub(crate) fn order(nodes: &[(&str, Vec<&str>)]) -> (Vec<usize>, Vec<usize>) {
+    let index: HashMap<&str, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(idx, (name, _))| (*name, idx))
+        .collect();
+
+    let mut pending: Vec<usize> = nodes
+        .iter()
+        .map(|(_, deps)| deps.iter().filter(|d| index.contains_key(*d)).count())
+        .collect();
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+    for (idx, (_, deps)) in nodes.iter().enumerate() {
+        for dep in deps {
+            if let Some(&dep_idx) = index.get(dep) {
+                dependents[dep_idx].push(idx);
+            }
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(nodes.len());
+    let mut done = vec![false; nodes.len()];
+    // repeatedly take the first node which has no pending dependencies left, to keep the
+    // original order as much as possible
+    while let Some(idx) = (0..nodes.len()).find(|&idx| !done[idx] && pending[idx] == 0) {
+        done[idx] = true;
+        ordered.push(idx);
+        for &dependent in &dependents[idx] {
+            pending[dependent] -= 1;
+        }
+    }
+
+    let cyclic = (0..nodes.len()).filter(|&idx| !done[idx]).collect();
+    (ordered, cyclic)
+}
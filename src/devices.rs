@@ -0,0 +1,164 @@
+//! Private `/dev` directories, so a service only sees the device nodes it needs.
+//!
+//! The service gets a mount namespace of its own, in which a fresh tmpfs is mounted over `/dev`
+//! and populated with copies of the allowed device nodes. Everything is looked up before fork,
+//! so only raw syscalls are left to do in the child.
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+/// The pseudo devices which are always available, as almost every program expects them.
+const BASIC_DEVICES: [&str; 6] = [
+    "/dev/null",
+    "/dev/zero",
+    "/dev/full",
+    "/dev/random",
+    "/dev/urandom",
+    "/dev/tty",
+];
+
+/// Links into /proc which are expected in /dev, by name.
+const LINKS: [(&str, &str); 4] = [
+    ("fd", "/proc/self/fd"),
+    ("stdin", "/proc/self/fd/0"),
+    ("stdout", "/proc/self/fd/1"),
+    ("stderr", "/proc/self/fd/2"),
+];
+
+#[derive(Debug)]
+struct DeviceNode {
+    path: CString,
+    mode: libc::mode_t,
+    rdev: libc::dev_t,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+/// A private /dev, prepared up front so it can be set up after fork.
+#[derive(Debug)]
+pub(crate) struct PrivateDev {
+    dev: CString,
+    shm: CString,
+    dirs: Vec<CString>,
+    nodes: Vec<DeviceNode>,
+    links: Vec<(CString, CString)>,
+}
+
+impl PrivateDev {
+    /// Prepare a private /dev below root, containing the basic pseudo devices and the allowed
+    /// devices, given by their path in the /dev of init. Allowed devices which don't exist are
+    /// left out.
+    pub(crate) fn prepare(root: Option<&str>, allowed: &[&str]) -> io::Result<Self> {
+        let dev = Path::new(root.unwrap_or("/")).join("dev");
+        let cpath = |path: &Path| CString::new(path.to_string_lossy().as_bytes());
+
+        let mut dirs = Vec::new();
+        let mut nodes = Vec::new();
+        for (idx, path) in BASIC_DEVICES.iter().chain(allowed).enumerate() {
+            let basic = idx < BASIC_DEVICES.len();
+            let name = match Path::new(path).strip_prefix("/dev") {
+                Ok(name) if name.components().count() > 0 => name,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} is not in /dev", path),
+                    ))
+                }
+            };
+            let meta = match fs::metadata(path) {
+                Ok(meta) => meta,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                    if !basic {
+                        warn!("Allowed device {} does not exist, leaving it out", path);
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if !meta.file_type().is_char_device() && !meta.file_type().is_block_device() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a device", path),
+                ));
+            }
+            // directories in between, e.g. /dev/net for /dev/net/tun, parents first
+            let mut parents: Vec<&Path> = name.ancestors().skip(1).collect();
+            parents.pop(); // the empty path, which is /dev itself
+            for parent in parents.into_iter().rev() {
+                let dir = cpath(&dev.join(parent))?;
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+            }
+            nodes.push(DeviceNode {
+                path: cpath(&dev.join(name))?,
+                mode: meta.mode(),
+                rdev: meta.rdev(),
+                uid: meta.uid(),
+                gid: meta.gid(),
+            });
+        }
+
+        let mut links = Vec::new();
+        for (name, target) in LINKS.iter() {
+            links.push((CString::new(*target)?, cpath(&dev.join(name))?));
+        }
+
+        Ok(PrivateDev {
+            shm: cpath(&dev.join("shm"))?,
+            dev: cpath(&dev)?,
+            dirs,
+            nodes,
+            links,
+        })
+    }
+
+    /// Move the calling process into a mount namespace of its own, and set up the private /dev
+    /// in it. Only raw syscalls are used, so this can be done in between fork and exec.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        let check = |res: libc::c_int| {
+            if res < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        };
+        unsafe {
+            check(libc::unshare(libc::CLONE_NEWNS))?;
+            // keep the mounts made below from propagating back to init
+            check(libc::mount(
+                std::ptr::null(),
+                b"/\0".as_ptr() as *const libc::c_char,
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_SLAVE,
+                std::ptr::null(),
+            ))?;
+            check(libc::mount(
+                b"tmpfs\0".as_ptr() as *const libc::c_char,
+                self.dev.as_ptr(),
+                b"tmpfs\0".as_ptr() as *const libc::c_char,
+                libc::MS_NOSUID | libc::MS_NOEXEC,
+                b"mode=755\0".as_ptr() as *const libc::c_void,
+            ))?;
+            // the umask of the service applies to everything created, so modes are set after
+            for dir in &self.dirs {
+                check(libc::mkdir(dir.as_ptr(), 0o755))?;
+                check(libc::chmod(dir.as_ptr(), 0o755))?;
+            }
+            for node in &self.nodes {
+                check(libc::mknod(node.path.as_ptr(), node.mode, node.rdev))?;
+                check(libc::chown(node.path.as_ptr(), node.uid, node.gid))?;
+                check(libc::chmod(node.path.as_ptr(), node.mode & 0o7777))?;
+            }
+            for (target, link) in &self.links {
+                check(libc::symlink(target.as_ptr(), link.as_ptr()))?;
+            }
+            check(libc::mkdir(self.shm.as_ptr(), 0o1777))?;
+            check(libc::chmod(self.shm.as_ptr(), 0o1777))?;
+        }
+        Ok(())
+    }
+}
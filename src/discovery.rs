@@ -0,0 +1,138 @@
+//! Finding the processes an exited process left behind, so they can be terminated, or adopted
+//! as the new process of a forking daemon.
+//!
+//! By default, the processes in the cgroup of the service are used if it has one, and otherwise
+//! the processes which were reparented to the reaper. A [`ChildDiscovery`] strategy can be
+//! chosen instead, to match the features of the kernel and the amount of processes.
+//!
+//! [`ChildDiscovery`]: trait.ChildDiscovery.html
+
+use std::fmt;
+
+use nix::unistd::Pid;
+
+use crate::cgroup::Cgroup;
+use crate::process::ProcessOps;
+
+/// A process which exited, along with what is known about where its descendants could be.
+pub struct ExitedProcess<'e> {
+    pid: Pid,
+    pgid: Option<Pid>,
+    cgroup: Option<&'e Cgroup>,
+    reparented: &'e [Pid],
+    processes: &'e dyn ProcessOps,
+}
+
+impl<'e> ExitedProcess<'e> {
+    pub(crate) fn new(
+        pid: Pid,
+        pgid: Option<Pid>,
+        cgroup: Option<&'e Cgroup>,
+        reparented: &'e [Pid],
+        processes: &'e dyn ProcessOps,
+    ) -> Self {
+        ExitedProcess {
+            pid,
+            pgid,
+            cgroup,
+            reparented,
+            processes,
+        }
+    }
+
+    /// The pid of the exited process.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// The process group the process led, if it was a service.
+    pub fn pgid(&self) -> Option<Pid> {
+        self.pgid
+    }
+
+    /// The processes which became children of the reaper since the last time this was checked,
+    /// which includes the orphans of the exited process if the reaper is PID 1 or a subreaper.
+    pub fn reparented(&self) -> &[Pid] {
+        self.reparented
+    }
+
+    /// The operations the reaper lists processes with, see [`Reaper::process_ops`].
+    ///
+    /// [`Reaper::process_ops`]: ../struct.Reaper.html#method.process_ops
+    pub fn processes(&self) -> &dyn ProcessOps {
+        self.processes
+    }
+}
+
+impl<'e> fmt::Debug for ExitedProcess<'e> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExitedProcess")
+            .field("pid", &self.pid)
+            .field("pgid", &self.pgid)
+            .field("cgroup", &self.cgroup)
+            .field("reparented", &self.reparented)
+            .finish()
+    }
+}
+
+/// A strategy to find the processes an exited process left behind.
+pub trait ChildDiscovery {
+    /// Find the descendants of the exited process which are still around.
+    fn descendants(&mut self, exited: &ExitedProcess) -> Vec<Pid>;
+}
+
+/// Use the processes which were reparented to the reaper, found by scanning /proc. This works
+/// on any kernel, but only finds orphans if the reaper is PID 1 or a child subreaper.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcScan;
+
+impl ChildDiscovery for ProcScan {
+    fn descendants(&mut self, exited: &ExitedProcess) -> Vec<Pid> {
+        exited.reparented().to_vec()
+    }
+}
+
+/// Use the processes in the process group the exited process led, along with the reparented
+/// processes. This also finds descendants which were reparented to another process, unless
+/// they moved to a process group of their own.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GroupScan;
+
+impl ChildDiscovery for GroupScan {
+    fn descendants(&mut self, exited: &ExitedProcess) -> Vec<Pid> {
+        let mut children = exited.reparented().to_vec();
+        let members = match exited.pgid().map(|pgid| exited.processes().group(pgid)) {
+            Some(Ok(members)) => members,
+            Some(Err(e)) => {
+                warn!(
+                    "Failed to list the process group of {}: {}",
+                    exited.pid(),
+                    e
+                );
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        for pid in members {
+            if !children.contains(&pid) {
+                children.push(pid);
+            }
+        }
+        children
+    }
+}
+
+/// Use the processes in the cgroup of the service, which no process can escape from. Requires
+/// services to be placed in cgroups, processes which are not a service fall back to the
+/// reparented processes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CgroupScan;
+
+impl ChildDiscovery for CgroupScan {
+    fn descendants(&mut self, exited: &ExitedProcess) -> Vec<Pid> {
+        match exited.cgroup {
+            Some(cgroup) => cgroup.procs(),
+            None => exited.reparented().to_vec(),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! A shell on the console for when booting fails, so there is a way in to fix things rather than
+//! a dead machine, or when a rescue shell is asked for with `rescue` on the kernel command line.
+//!
+//! The shell runs as root. If sulogin is installed, it is started through sulogin, which asks
+//! for the password of root first. Otherwise anyone at the console gets a root shell without a
+//! password, so on machines where the console is not trusted, the mode should be `off`.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+use nix::errno::Errno;
+use nix::sys::wait::waitpid;
+use nix::unistd::Pid;
+
+use crate::sys;
+use crate::tty;
+
+/// The shell which is started.
+pub const SHELL: &str = "/bin/sh";
+
+/// The terminal the shell is started on.
+pub const CONSOLE: &str = "/dev/console";
+
+/// Where sulogin is looked for, to ask for the password of root before the shell is started.
+const SULOGIN_PATHS: [&str; 3] = ["/sbin/sulogin", "/usr/sbin/sulogin", "/bin/sulogin"];
+
+/// Whether a shell is started when booting fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmergencyMode {
+    /// Don't start a shell.
+    Off,
+    /// Start a shell right away.
+    Shell,
+    /// Only start a shell once Enter is pressed on the console. This is the default, so a
+    /// machine which fails to boot unattended doesn't sit at a root shell.
+    #[default]
+    Confirm,
+}
+
+/// Parse `off`, `shell` or `confirm`.
+impl FromStr for EmergencyMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "off" => Ok(EmergencyMode::Off),
+            "shell" => Ok(EmergencyMode::Shell),
+            "confirm" => Ok(EmergencyMode::Confirm),
+            _ => Err(format!("invalid emergency mode {:?}", mode)),
+        }
+    }
+}
+
+/// Tell why on the console and start a shell there, as mode says, waiting for it to exit. The
+/// shell is started through sulogin if it is installed. Every other child which exits in the
+/// meantime is reaped as well, as nothing else is reaping. Returns whether a shell was started,
+/// which it is not if mode is `Off`, or if it is `Confirm` and the console is closed instead.
+pub fn run_shell(reason: &str, mode: EmergencyMode) -> io::Result<bool> {
+    if mode == EmergencyMode::Off {
+        return Ok(false);
+    }
+    let mut console = tty::open_tty(CONSOLE, None)?;
+    writeln!(console, "\n{}", reason)?;
+    if mode == EmergencyMode::Confirm {
+        write!(console, "Press Enter for a shell, or Ctrl-D to skip it: ")?;
+        let mut line = String::new();
+        if BufReader::new(&console).read_line(&mut line)? == 0 {
+            return Ok(false);
+        }
+    }
+    let sulogin = SULOGIN_PATHS
+        .iter()
+        .find(|path| Path::new(path).exists())
+        .copied();
+    writeln!(console, "Starting {}, exit it to carry on", SHELL)?;
+
+    let mut cmd = Command::new(sulogin.unwrap_or(SHELL));
+    cmd.stdin(console.try_clone()?)
+        .stdout(console.try_clone()?)
+        .stderr(console)
+        .env("TERM", tty::term_for(CONSOLE))
+        .env("HOME", "/")
+        .env("SUSHELL", SHELL)
+        .current_dir("/");
+    unsafe {
+        cmd.pre_exec(sys::unblock_signals);
+        cmd.pre_exec(|| tty::set_controlling_tty(libc::STDIN_FILENO));
+    }
+    let shell = Pid::from_raw(cmd.spawn()?.id() as i32);
+    loop {
+        match waitpid(None, None) {
+            Ok(status) if status.pid() == Some(shell) => return Ok(true),
+            Ok(_) | Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(e) => return Err(io::Error::other(e.to_string())),
+        }
+    }
+}
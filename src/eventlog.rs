@@ -0,0 +1,174 @@
+//! A machine readable record of what happens to services, alongside the log. Log messages are
+//! meant for humans and change between releases, the codes and fields of events don't, so
+//! tooling can rely on them.
+//!
+//! Every event is appended to the file as a JSON line like
+//! `{"time":1700000000.25,"code":"service.failed","service":"sshd","reason":"spawn_limit"}`.
+//! Instead of to a file, events can be written to a file descriptor which was passed to the
+//! reaper, e.g. a pipe to a monitoring agent. The entries are the [`ReaperEvent`]s of services,
+//! orphans, the clock and shutting down, each under a code of its own.
+//!
+//! [`ReaperEvent`]: ../events/enum.ReaperEvent.html
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+use crate::command::Event;
+use crate::events::ReaperEvent;
+use crate::json::Value;
+
+/// Default location of the event log.
+pub const EVENT_LOG_PATH: &str = "/run/rsinit/events";
+
+/// The stable code an event is recorded with, if it is recorded at all, along with its fields.
+/// Events of processes which don't belong to a service are not recorded.
+fn entry(event: &ReaperEvent) -> Option<(&'static str, Value)> {
+    let entry = match event {
+        ReaperEvent::Spawned { service, pid } => (
+            "service.started",
+            Value::object()
+                .with("service", service.as_str())
+                .with("pid", i32::from(*pid)),
+        ),
+        ReaperEvent::Restarted {
+            service,
+            pid,
+            restarts,
+        } => (
+            "service.restarted",
+            Value::object()
+                .with("service", service.as_str())
+                .with("pid", i32::from(*pid))
+                .with("restarts", *restarts),
+        ),
+        ReaperEvent::Ready(service) => (
+            "service.ready",
+            Value::object().with("service", service.as_str()),
+        ),
+        ReaperEvent::Exited {
+            service,
+            pid,
+            exit,
+            reason,
+        } => {
+            let fields = Value::object()
+                .with("service", service.as_str())
+                .with("pid", i32::from(*pid))
+                .with("exit", exit.name());
+            let fields = match *exit {
+                Event::ExitSuccess => fields,
+                Event::ExitCode(code) => fields.with("code", code),
+                Event::ExitSignal(signal) => fields.with("signal", format!("{:?}", signal)),
+                Event::StartFailed { code, signal } => fields
+                    .with("code", code)
+                    .with("signal", signal.map(|signal| format!("{:?}", signal))),
+            };
+            (
+                "service.exited",
+                fields.with("exit_reason", reason.as_deref()),
+            )
+        }
+        ReaperEvent::Failed { service, reason } => (
+            "service.failed",
+            Value::object()
+                .with("service", service.as_str())
+                .with("reason", reason.as_str()),
+        ),
+        ReaperEvent::Unhealthy(service) => (
+            "service.unhealthy",
+            Value::object().with("service", service.as_str()),
+        ),
+        ReaperEvent::WatchdogExpired(service) => (
+            "service.watchdog",
+            Value::object().with("service", service.as_str()),
+        ),
+        ReaperEvent::Stopped {
+            service: Some(service),
+            pid,
+            signal,
+        } => (
+            "service.suspended",
+            Value::object()
+                .with("service", service.as_str())
+                .with("pid", i32::from(*pid))
+                .with("signal", format!("{:?}", signal)),
+        ),
+        ReaperEvent::Continued {
+            service: Some(service),
+            pid,
+        } => (
+            "service.resumed",
+            Value::object()
+                .with("service", service.as_str())
+                .with("pid", i32::from(*pid)),
+        ),
+        ReaperEvent::OrphanKilled(carcass) => {
+            let fields = Value::object().with("pid", i32::from(carcass.pid));
+            let fields = match (carcass.status, carcass.signal) {
+                (Some(code), _) => fields.with("code", code),
+                (_, signal) => fields.with("signal", signal.map(|s| format!("{:?}", s))),
+            };
+            ("orphan.killed", fields)
+        }
+        ReaperEvent::ClockJumped { backward, by } => (
+            "clock.jumped",
+            Value::object()
+                .with("direction", if *backward { "backward" } else { "forward" })
+                .with("seconds", by.as_secs_f64()),
+        ),
+        ReaperEvent::ShutdownStarted => ("shutdown", Value::object()),
+        _ => return None,
+    };
+    Some(entry)
+}
+
+pub(crate) struct EventLog {
+    file: File,
+}
+
+impl EventLog {
+    /// Open the event log at path to append to it, creating it if needed.
+    pub(crate) fn open(path: &str) -> io::Result<Self> {
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(EventLog { file })
+    }
+
+    /// Write events to an open file descriptor, which the event log takes ownership of. It is
+    /// made non-blocking, so a reader which falls behind loses events instead of holding up the
+    /// reaper.
+    pub(crate) fn from_fd(fd: RawFd) -> io::Result<Self> {
+        let flags = fcntl(fd, FcntlArg::F_GETFL).map_err(io::Error::other)?;
+        let flags = OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK;
+        fcntl(fd, FcntlArg::F_SETFL(flags)).map_err(io::Error::other)?;
+        let file = unsafe { File::from_raw_fd(fd) };
+        Ok(EventLog { file })
+    }
+
+    /// Append an event, if it is one which is recorded.
+    pub(crate) fn record(&mut self, event: &ReaperEvent) {
+        let (code, fields) = match entry(event) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs_f64())
+            .unwrap_or_default();
+        let mut line = Value::object().with("time", time).with("code", code);
+        if let (Value::Object(line), Value::Object(fields)) = (&mut line, fields) {
+            line.extend(fields);
+        }
+        // a single write, so concurrent readers never see half an event
+        if let Err(e) = self.file.write_all(format!("{}\n", line).as_bytes()) {
+            debug!("Failed to write event {}: {}", code, e);
+        }
+    }
+}
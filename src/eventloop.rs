@@ -0,0 +1,205 @@
+//! The event loop of the reaper. Signals are received through a signalfd, which is multiplexed
+//! with other file descriptors, such as the control socket, through epoll, so the reaper waits
+//! for all of them at once, with an exact timeout.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use nix::sys::epoll::{
+    epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::{signalfd, SfdFlags, SIGNALFD_NEW};
+
+/// Something the reaper was woken up for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Wakeup {
+    Signal(Signal),
+    /// A watched file descriptor became readable, or changed.
+    Readable(RawFd),
+}
+
+pub(crate) struct EventLoop {
+    epoll: RawFd,
+    signals: RawFd,
+    mask: SigSet,
+    /// The read end of the pipe signals caught elsewhere are passed on through.
+    injected: Option<File>,
+    pending: VecDeque<Wakeup>,
+}
+
+impl EventLoop {
+    /// Block the given signals in the calling thread, and receive them through the loop instead.
+    /// Threads spawned afterwards inherit the blocked signals, so they are never delivered to
+    /// those either.
+    pub(crate) fn new(signals: &[Signal]) -> io::Result<Self> {
+        let mut mask = SigSet::empty();
+        for signal in signals {
+            mask.add(*signal);
+        }
+        mask.thread_block().map_err(nix_error)?;
+        let signals = signalfd(
+            SIGNALFD_NEW,
+            &mask,
+            SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC,
+        )
+        .map_err(nix_error)?;
+        let epoll = match epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC) {
+            Ok(epoll) => epoll,
+            Err(e) => {
+                unsafe { libc::close(signals) };
+                return Err(nix_error(e));
+            }
+        };
+        let event_loop = EventLoop {
+            epoll,
+            signals,
+            mask,
+            injected: None,
+            pending: VecDeque::new(),
+        };
+        event_loop.watch(signals)?;
+        Ok(event_loop)
+    }
+
+    /// Receive more signals through the loop, blocking them in the calling thread. Threads which
+    /// were spawned before do not block them.
+    pub(crate) fn trap(&mut self, signals: &[Signal]) -> io::Result<()> {
+        let mut mask = self.mask;
+        for signal in signals {
+            mask.add(*signal);
+        }
+        mask.thread_block().map_err(nix_error)?;
+        signalfd(self.signals, &mask, SfdFlags::empty()).map_err(nix_error)?;
+        self.mask = mask;
+        Ok(())
+    }
+
+    /// Receive the signals written to the pipe, as the number of every signal in a byte,
+    /// as if they were caught by the loop. This is for signals which are caught elsewhere, e.g.
+    /// by an async runtime whose threads don't block them.
+    #[cfg(feature = "async")]
+    pub(crate) fn inject_from(&mut self, pipe: File) -> io::Result<()> {
+        self.watch(pipe.as_raw_fd())?;
+        self.injected = Some(pipe);
+        Ok(())
+    }
+
+    /// Wake up when the file descriptor becomes readable. Closing the file descriptor stops
+    /// watching it.
+    pub(crate) fn watch(&self, fd: RawFd) -> io::Result<()> {
+        let mut event = EpollEvent::new(EpollFlags::EPOLLIN, fd as u64);
+        epoll_ctl(self.epoll, EpollOp::EpollCtlAdd, fd, &mut event).map_err(nix_error)
+    }
+
+    /// Wake up when the kernel flags the file descriptor with an exceptional condition, which
+    /// some files in /proc use to report changes, e.g. `/proc/self/mountinfo`.
+    pub(crate) fn watch_changes(&self, fd: RawFd) -> io::Result<()> {
+        let mut event = EpollEvent::new(EpollFlags::EPOLLPRI, fd as u64);
+        epoll_ctl(self.epoll, EpollOp::EpollCtlAdd, fd, &mut event).map_err(nix_error)
+    }
+
+    /// Wait for the next wakeup, or until the deadline passed, in which case `None` is
+    /// returned. The file descriptor of the loop becomes readable when a wakeup is pending.
+    pub(crate) fn wait(&mut self, deadline: Instant) -> Option<Wakeup> {
+        while self.pending.is_empty() {
+            let now = Instant::now();
+            // once the deadline has passed, what is pending is still picked up
+            let expired = now >= deadline;
+            // round up, so the deadline has passed when the wait times out
+            let timeout_ms = match deadline.checked_duration_since(now) {
+                Some(left) => (left + Duration::from_nanos(999_999)).as_millis(),
+                None => 0,
+            };
+
+            let mut events = [EpollEvent::empty(); 8];
+            let ready = match epoll_wait(self.epoll, &mut events, timeout_ms as isize) {
+                Ok(ready) => ready,
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+                Err(e) => {
+                    error!("Failed to wait for events: {}", e);
+                    return None;
+                }
+            };
+            for event in &events[..ready] {
+                let fd = event.data() as RawFd;
+                if fd == self.signals {
+                    self.read_signals();
+                } else if self.injected.as_ref().map(AsRawFd::as_raw_fd) == Some(fd) {
+                    self.read_injected();
+                } else {
+                    self.pending.push_back(Wakeup::Readable(fd));
+                }
+            }
+            if expired {
+                break;
+            }
+        }
+        self.pending.pop_front()
+    }
+
+    /// Queue all signals which are pending on the signalfd.
+    fn read_signals(&mut self) {
+        loop {
+            let mut info: libc::signalfd_siginfo = unsafe { std::mem::zeroed() };
+            let size = std::mem::size_of::<libc::signalfd_siginfo>();
+            let res = unsafe {
+                libc::read(
+                    self.signals,
+                    &mut info as *mut libc::signalfd_siginfo as *mut libc::c_void,
+                    size,
+                )
+            };
+            if res != size as isize {
+                return;
+            }
+            match Signal::from_c_int(info.ssi_signo as libc::c_int) {
+                Ok(signal) => self.pending.push_back(Wakeup::Signal(signal)),
+                Err(_) => debug!("Received unknown signal {}", info.ssi_signo),
+            }
+        }
+    }
+
+    /// Queue all signals which were passed on through the pipe.
+    fn read_injected(&mut self) {
+        let mut buf = [0u8; 64];
+        while let Some(Ok(n)) = self.injected.as_mut().map(|pipe| pipe.read(&mut buf)) {
+            if n == 0 {
+                // nothing is passed on anymore, closing the pipe stops watching it
+                self.injected = None;
+                return;
+            }
+            for &signo in &buf[..n] {
+                match Signal::from_c_int(libc::c_int::from(signo)) {
+                    Ok(signal) => self.pending.push_back(Wakeup::Signal(signal)),
+                    Err(_) => debug!("Received unknown signal {}", signo),
+                }
+            }
+        }
+    }
+}
+
+impl AsRawFd for EventLoop {
+    fn as_raw_fd(&self) -> RawFd {
+        self.epoll
+    }
+}
+
+impl Drop for EventLoop {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.epoll);
+            libc::close(self.signals);
+        }
+    }
+}
+
+fn nix_error(e: nix::Error) -> io::Error {
+    match e {
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        e => io::Error::other(e.to_string()),
+    }
+}
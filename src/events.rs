@@ -0,0 +1,356 @@
+//! The typed model of what the reaper does and of what goes wrong: the [`ReaperEvent`]s which
+//! are told to observers and returned by [`Reaper::step`], the [`ReaperError`] which stops the
+//! reaper, and the [`PersistentCommandError`] the process of a service is not spawned for.
+//! Events and errors have stable names, which don't change along with their messages, for
+//! programs which pass them on, e.g. as metrics or over a socket.
+//!
+//! With the `serde` feature they can be serialized: an event as an object with its `event`
+//! name and its `data`, like `{"event": "spawned", "data": {"service": "sshd", "pid": 42}}`,
+//! and an error as an object with its `kind` and its `message`. Processes are serialized as
+//! their pid, and signals by their name.
+//!
+//! [`ReaperEvent`]: enum.ReaperEvent.html
+//! [`Reaper::step`]: ../struct.Reaper.html#method.step
+//! [`ReaperError`]: enum.ReaperError.html
+//! [`PersistentCommandError`]: enum.PersistentCommandError.html
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+
+use crate::command::Event;
+use crate::power::PowerAction;
+use crate::Carcass;
+
+/// Something which happened to the services, as told to a [`ReaperObserver`], for programs
+/// which take the events rather than being called back, see [`Reaper::step`]. The same events
+/// are written to the event log and counted in the metrics, by their [`name`].
+///
+/// [`ReaperObserver`]: ../observer/trait.ReaperObserver.html
+/// [`Reaper::step`]: ../struct.Reaper.html#method.step
+/// [`name`]: #method.name
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(tag = "event", content = "data", rename_all = "snake_case")
+)]
+pub enum ReaperEvent {
+    /// A process was spawned for the named service.
+    Spawned {
+        service: String,
+        #[cfg_attr(feature = "serde", serde(serialize_with = "ser::pid"))]
+        pid: Pid,
+    },
+    /// A child of the reaper was reaped, whether it belonged to a service or not.
+    Reaped(Carcass),
+    /// The process of the named service exited, with what its exit code means, if the service
+    /// explains it.
+    Exited {
+        service: String,
+        #[cfg_attr(feature = "serde", serde(serialize_with = "ser::pid"))]
+        pid: Pid,
+        exit: Event,
+        reason: Option<String>,
+    },
+    /// The named service was spawned again, for the given amount of times in total. This
+    /// follows the event it was spawned with.
+    Restarted {
+        service: String,
+        #[cfg_attr(feature = "serde", serde(serialize_with = "ser::pid"))]
+        pid: Pid,
+        restarts: usize,
+    },
+    /// The named service notified it finished starting up.
+    Ready(String),
+    /// The named service failed its health checks, and is restarted.
+    Unhealthy(String),
+    /// The named service did not ping its watchdog in time, and is killed.
+    WatchdogExpired(String),
+    /// The named service is not spawned again, for the given reason, like `spawn_limit`.
+    Failed { service: String, reason: String },
+    /// An orphan was sent a signal, as the process it was left behind by failed. A negative
+    /// pid is a process group.
+    OrphanSignaled {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "ser::pid"))]
+        pid: Pid,
+        #[cfg_attr(feature = "serde", serde(serialize_with = "ser::signal"))]
+        signal: Signal,
+    },
+    /// An orphan which was being terminated exited.
+    OrphanKilled(Carcass),
+    /// The named service is no longer spawned, as it was spawned too often.
+    SpawnLimit(String),
+    /// A child of the reaper was stopped by a signal, with the service it belongs to, if any.
+    Stopped {
+        service: Option<String>,
+        #[cfg_attr(feature = "serde", serde(serialize_with = "ser::pid"))]
+        pid: Pid,
+        #[cfg_attr(feature = "serde", serde(serialize_with = "ser::signal"))]
+        signal: Signal,
+    },
+    /// A child of the reaper which was stopped was continued, with the service it belongs to,
+    /// if any.
+    Continued {
+        service: Option<String>,
+        #[cfg_attr(feature = "serde", serde(serialize_with = "ser::pid"))]
+        pid: Pid,
+    },
+    /// The wall clock was set, forward or backward by the given time.
+    ClockJumped { backward: bool, by: Duration },
+    /// The services are being stopped, to shut down.
+    ShutdownStarted,
+    /// All services were stopped, and the system is to be brought down with the action. This
+    /// is the last event, as nothing is supervised anymore.
+    ShutDown(PowerAction),
+}
+
+impl ReaperEvent {
+    /// A stable name for the event, like `spawned`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReaperEvent::Spawned { .. } => "spawned",
+            ReaperEvent::Reaped(_) => "reaped",
+            ReaperEvent::Exited { .. } => "exited",
+            ReaperEvent::Restarted { .. } => "restarted",
+            ReaperEvent::Ready(_) => "ready",
+            ReaperEvent::Unhealthy(_) => "unhealthy",
+            ReaperEvent::WatchdogExpired(_) => "watchdog_expired",
+            ReaperEvent::Failed { .. } => "failed",
+            ReaperEvent::OrphanSignaled { .. } => "orphan_signaled",
+            ReaperEvent::OrphanKilled(_) => "orphan_killed",
+            ReaperEvent::SpawnLimit(_) => "spawn_limit",
+            ReaperEvent::Stopped { .. } => "stopped",
+            ReaperEvent::Continued { .. } => "continued",
+            ReaperEvent::ClockJumped { .. } => "clock_jumped",
+            ReaperEvent::ShutdownStarted => "shutdown_started",
+            ReaperEvent::ShutDown(_) => "shut_down",
+        }
+    }
+}
+
+/// A failure the reaper can't recover from, returned by [`Reaper::spawn`] instead of aborting,
+/// so the application embedding the reaper decides what happens next.
+///
+/// [`Reaper::spawn`]: ../struct.Reaper.html#method.spawn
+#[derive(Debug)]
+pub enum ReaperError {
+    /// Setting up the event loop the reaper waits on, e.g. the signalfd, failed.
+    EventLoop(io::Error),
+    /// Waiting for exited children failed.
+    Wait(nix::Error),
+    /// Listing the processes in /proc failed.
+    ListProcesses(io::Error),
+    /// The restart policy of the named service escalated its failure.
+    Escalated(String),
+    /// The named service failed permanently, and its failure action asks for the system to be
+    /// powered off or restarted. All services are stopped.
+    PermanentFailure(String, PowerAction),
+    /// The named one-shot command, which is required, failed. Nothing was started.
+    RequiredFailed(String),
+    /// The given processes could not be killed for as many ticks as the reaper tolerates, see
+    /// [`UnkillableAction::Reboot`]. All services are stopped.
+    ///
+    /// [`UnkillableAction::Reboot`]: ../enum.UnkillableAction.html#variant.Reboot
+    Unkillable(Vec<Pid>),
+}
+
+impl ReaperError {
+    /// A stable name for the kind of error, like `escalated`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ReaperError::EventLoop(_) => "event_loop",
+            ReaperError::Wait(_) => "wait",
+            ReaperError::ListProcesses(_) => "list_processes",
+            ReaperError::Escalated(_) => "escalated",
+            ReaperError::PermanentFailure(..) => "permanent_failure",
+            ReaperError::RequiredFailed(_) => "required_failed",
+            ReaperError::Unkillable(_) => "unkillable",
+        }
+    }
+}
+
+impl fmt::Display for ReaperError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReaperError::EventLoop(e) => write!(f, "Failed to set up the event loop: {}", e),
+            ReaperError::Wait(e) => write!(f, "Failed to wait for children: {}", e),
+            ReaperError::ListProcesses(e) => write!(f, "Failed to list processes: {}", e),
+            ReaperError::Escalated(name) => write!(f, "Service {} failed, giving up", name),
+            ReaperError::PermanentFailure(name, action) => write!(
+                f,
+                "Service {} failed permanently, requesting {:?}",
+                name, action
+            ),
+            ReaperError::RequiredFailed(name) => {
+                write!(f, "Required one-shot command {} failed", name)
+            }
+            ReaperError::Unkillable(pids) => {
+                let pids: Vec<String> = pids.iter().map(Pid::to_string).collect();
+                write!(
+                    f,
+                    "Processes {} can't be killed, rebooting",
+                    pids.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReaperError {}
+
+/// Why the process of a service was not spawned.
+#[derive(Debug)]
+pub enum PersistentCommandError {
+    /// The service was spawned as often as its spawn limit allows.
+    SpawnLimitReached(usize),
+    /// The service was spawned too often within its window, and is retried after the delay.
+    SpawnRateLimited(Duration),
+    /// The service failed the given amount of times in a row right after it was spawned.
+    CrashLooping(usize),
+    /// Spawning the process failed.
+    SpawnFailed(io::Error),
+    /// The restart policy of the service does not restart it after how it exited.
+    MustNotRespawn(Event),
+    /// The service is restarted after the delay.
+    RestartDelayed(Duration),
+    /// The restart policy of the service escalates how it exited.
+    Escalated(Event),
+    /// The system ran out of resources, like memory or processes, to spawn the process.
+    ResourcesExhausted(io::Error),
+    /// Spawning is paused for the given time, as the system ran out of resources.
+    SpawnPaused(Duration),
+    /// The executable of the service does not exist, and is waited for.
+    ExecutableMissing(PathBuf),
+}
+
+impl PersistentCommandError {
+    /// A stable name for the kind of error, like `spawn_limit_reached`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PersistentCommandError::SpawnLimitReached(_) => "spawn_limit_reached",
+            PersistentCommandError::SpawnRateLimited(_) => "spawn_rate_limited",
+            PersistentCommandError::CrashLooping(_) => "crash_looping",
+            PersistentCommandError::SpawnFailed(_) => "spawn_failed",
+            PersistentCommandError::MustNotRespawn(_) => "must_not_respawn",
+            PersistentCommandError::RestartDelayed(_) => "restart_delayed",
+            PersistentCommandError::Escalated(_) => "escalated",
+            PersistentCommandError::ResourcesExhausted(_) => "resources_exhausted",
+            PersistentCommandError::SpawnPaused(_) => "spawn_paused",
+            PersistentCommandError::ExecutableMissing(_) => "executable_missing",
+        }
+    }
+}
+
+impl fmt::Display for PersistentCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PersistentCommandError::SpawnLimitReached(x) => {
+                write!(f, "Spawn limit ({}) reached", x)
+            }
+            PersistentCommandError::SpawnRateLimited(d) => {
+                write!(f, "Spawn rate limit reached, retrying in {:?}", d)
+            }
+            PersistentCommandError::CrashLooping(x) => write!(
+                f,
+                "Failed {} times in a row right after spawning, giving up",
+                x
+            ),
+            PersistentCommandError::SpawnFailed(e) => write!(f, "Spawning command failed: {}", e),
+            PersistentCommandError::MustNotRespawn(e) => write!(
+                f,
+                "Previous command died due to {:?}, no need to respawn",
+                e
+            ),
+            PersistentCommandError::RestartDelayed(d) => write!(f, "Respawning in {:?}", d),
+            PersistentCommandError::Escalated(e) => write!(
+                f,
+                "Previous command died due to {:?}, escalating the failure",
+                e
+            ),
+            PersistentCommandError::ResourcesExhausted(e) => {
+                write!(f, "Out of resources while spawning command: {}", e)
+            }
+            PersistentCommandError::SpawnPaused(d) => {
+                write!(f, "Spawning is paused, retrying in {:?}", d)
+            }
+            PersistentCommandError::ExecutableMissing(path) => write!(
+                f,
+                "Executable {} does not exist, waiting for it",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PersistentCommandError {}
+
+impl From<io::Error> for PersistentCommandError {
+    fn from(e: io::Error) -> Self {
+        PersistentCommandError::SpawnFailed(e)
+    }
+}
+
+/// Serialize an error as its kind and its message.
+#[cfg(feature = "serde")]
+fn serialize_error<S: serde::Serializer>(
+    name: &'static str,
+    kind: &str,
+    message: String,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeStruct;
+    let mut error = serializer.serialize_struct(name, 2)?;
+    error.serialize_field("kind", kind)?;
+    error.serialize_field("message", &message)?;
+    error.end()
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for ReaperError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_error("ReaperError", self.kind(), self.to_string(), serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PersistentCommandError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_error(
+            "PersistentCommandError",
+            self.kind(),
+            self.to_string(),
+            serializer,
+        )
+    }
+}
+
+/// Serializing the types of nix, which don't implement serde themselves.
+#[cfg(feature = "serde")]
+pub(crate) mod ser {
+    use nix::sys::signal::Signal;
+    use nix::unistd::Pid;
+    use serde::Serializer;
+
+    pub(crate) fn pid<S: Serializer>(pid: &Pid, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(i32::from(*pid))
+    }
+
+    pub(crate) fn signal<S: Serializer>(signal: &Signal, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:?}", signal))
+    }
+
+    pub(crate) fn optional_signal<S: Serializer>(
+        signal: &Option<Signal>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match signal {
+            Some(signal) => self::signal(signal, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+}
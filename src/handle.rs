@@ -0,0 +1,228 @@
+//! A handle on the services, for the application which embeds the reaper, e.g. to build a
+//! dashboard, or to start and stop services, from another thread while [`Reaper::spawn`]
+//! supervises the services. Unlike the control socket, it needs no round trip through a socket.
+//!
+//! [`Reaper::spawn`]: ../struct.Reaper.html#method.spawn
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use nix::fcntl::OFlag;
+use nix::unistd::pipe2;
+
+use crate::stats::ReaperStats;
+use crate::status::{ServiceState, ServiceStatus};
+
+/// The error of a request which the reaper can't handle anymore.
+pub(crate) const NOT_RUNNING: &str = "the reaper is not running";
+
+/// What a handle asks the reaper to do with a service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Start,
+    Stop,
+    Restart,
+}
+
+/// Called with the outcome of a request.
+pub(crate) type Reply = Box<dyn FnOnce(Result<(), String>) + Send>;
+
+/// An operation on a service, with where to send the outcome to.
+pub(crate) struct HandleRequest {
+    pub(crate) op: Operation,
+    pub(crate) name: String,
+    reply: Reply,
+}
+
+impl HandleRequest {
+    pub(crate) fn reply(self, outcome: Result<(), String>) {
+        (self.reply)(outcome)
+    }
+}
+
+/// A handle on the services of a [`Reaper`], see [`Reaper::control_handle`]. The status and the
+/// statistics are updated whenever the reaper wakes up. The handle can be cloned freely.
+///
+/// [`Reaper`]: ../struct.Reaper.html
+/// [`Reaper::control_handle`]: ../struct.Reaper.html#method.control_handle
+#[derive(Debug, Clone)]
+pub struct ControlHandle {
+    statuses: Arc<Mutex<BTreeMap<String, ServiceStatus>>>,
+    stats: Arc<Mutex<ReaperStats>>,
+    requests: Sender<HandleRequest>,
+    /// Wakes up the reaper when written to, if the pipe could be created.
+    wake: Option<Arc<File>>,
+}
+
+/// The receiving end of the requests of the handles.
+pub(crate) struct HandleRequests {
+    requests: Receiver<HandleRequest>,
+    wake: Option<File>,
+}
+
+impl ControlHandle {
+    /// Create a handle, along with the end the reaper receives its requests on.
+    pub(crate) fn new() -> (Self, HandleRequests) {
+        let (tx, rx) = channel();
+        let wake = match pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK) {
+            Ok((rx, tx)) => unsafe { Some((File::from_raw_fd(rx), File::from_raw_fd(tx))) },
+            Err(e) => {
+                // the requests are still picked up, at the next tick of the reaper
+                warn!(
+                    "Failed to create the wake up pipe of the control handle: {}",
+                    e
+                );
+                None
+            }
+        };
+        let (wake_rx, wake_tx) = match wake {
+            Some((rx, tx)) => (Some(rx), Some(Arc::new(tx))),
+            None => (None, None),
+        };
+        let handle = ControlHandle {
+            statuses: Arc::default(),
+            stats: Arc::default(),
+            requests: tx,
+            wake: wake_tx,
+        };
+        let requests = HandleRequests {
+            requests: rx,
+            wake: wake_rx,
+        };
+        (handle, requests)
+    }
+
+    /// Start a stopped service, like `rsinitctl start`. This blocks until the reaper handled
+    /// the request, so it must not be called on the thread running the reaper.
+    pub fn start(&self, name: &str) -> Result<(), String> {
+        self.request(Operation::Start, name)
+    }
+
+    /// Stop a service, which is not respawned until it is started again. This blocks until the
+    /// reaper sent the service its stop signal, not until it exited.
+    pub fn stop(&self, name: &str) -> Result<(), String> {
+        self.request(Operation::Stop, name)
+    }
+
+    /// Stop a service and start it again once it exited. This blocks until the reaper sent the
+    /// service its stop signal.
+    pub fn restart(&self, name: &str) -> Result<(), String> {
+        self.request(Operation::Restart, name)
+    }
+
+    fn request(&self, op: Operation, name: &str) -> Result<(), String> {
+        let (tx, rx) = channel();
+        self.submit(
+            op,
+            name,
+            Box::new(move |outcome| {
+                // the handle might have given up waiting
+                let _ = tx.send(outcome);
+            }),
+        )?;
+        rx.recv().map_err(|_| NOT_RUNNING.to_string())?
+    }
+
+    /// Pass a request on to the reaper, which calls reply once it handled it.
+    pub(crate) fn submit(&self, op: Operation, name: &str, reply: Reply) -> Result<(), String> {
+        let req = HandleRequest {
+            op,
+            name: name.to_string(),
+            reply,
+        };
+        self.requests
+            .send(req)
+            .map_err(|_| NOT_RUNNING.to_string())?;
+        if let Some(wake) = &self.wake {
+            // if the pipe is full, a wake up is pending anyway
+            let _ = (&**wake).write(&[0]);
+        }
+        Ok(())
+    }
+
+    /// List the status of all services, by name.
+    pub fn list(&self) -> Vec<(String, ServiceStatus)> {
+        self.with_statuses(|statuses| {
+            statuses
+                .iter()
+                .map(|(name, status)| (name.clone(), status.clone()))
+                .collect()
+        })
+    }
+
+    /// List the status of the services in the given state, by name.
+    pub fn list_in(&self, state: ServiceState) -> Vec<(String, ServiceStatus)> {
+        self.with_statuses(|statuses| {
+            statuses
+                .iter()
+                .filter(|(_, status)| status.state() == state)
+                .map(|(name, status)| (name.clone(), status.clone()))
+                .collect()
+        })
+    }
+
+    /// Get the status of the service with the given name.
+    pub fn status(&self, name: &str) -> Option<ServiceStatus> {
+        self.with_statuses(|statuses| statuses.get(name).cloned())
+    }
+
+    /// Get the statistics of the reaper, like [`Reaper::stats`].
+    ///
+    /// [`Reaper::stats`]: ../struct.Reaper.html#method.stats
+    pub fn stats(&self) -> ReaperStats {
+        // like the statuses, the statistics are only ever replaced as a whole
+        match self.stats.lock() {
+            Ok(stats) => *stats,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+
+    /// Replace the statuses and the statistics with the current ones of the reaper.
+    pub(crate) fn publish<'s>(
+        &self,
+        statuses: impl Iterator<Item = (&'s String, &'s ServiceStatus)>,
+        stats: ReaperStats,
+    ) {
+        self.with_statuses(|published| {
+            published.clear();
+            published.extend(statuses.map(|(name, status)| (name.clone(), status.clone())));
+        });
+        match self.stats.lock() {
+            Ok(mut published) => *published = stats,
+            Err(poisoned) => *poisoned.into_inner() = stats,
+        }
+    }
+
+    fn with_statuses<T>(&self, f: impl FnOnce(&mut BTreeMap<String, ServiceStatus>) -> T) -> T {
+        // the statuses are only ever replaced as a whole, so they are fine after a panic
+        let mut statuses = match self.statuses.lock() {
+            Ok(statuses) => statuses,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        f(&mut statuses)
+    }
+}
+
+impl HandleRequests {
+    /// Take all pending requests.
+    pub(crate) fn take(&self) -> Vec<HandleRequest> {
+        if let Some(mut wake) = self.wake.as_ref() {
+            let mut buf = [0; 64];
+            while let Ok(n) = wake.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+            }
+        }
+        self.requests.try_iter().collect()
+    }
+
+    /// The file descriptor which becomes readable when there are requests, if there is one.
+    pub(crate) fn wake_fd(&self) -> Option<RawFd> {
+        self.wake.as_ref().map(AsRawFd::as_raw_fd)
+    }
+}
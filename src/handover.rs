@@ -0,0 +1,180 @@
+//! The state a reaper hands over to the new instance of itself when it re-executes its own
+//! binary, e.g. after init was upgraded, so the running services are adopted instead of being
+//! restarted. The same state can be snapshotted periodically, so a reaper which is not PID 1 can
+//! recover its services after it crashed or was restarted.
+//!
+//! The state is written as a single JSON object to a file on a tmpfs, which the new instance
+//! reads and removes again before it supervises anything. Only what can't be found out
+//! otherwise is handed over: which process belongs to which service, when that process started,
+//! how often the services were restarted, for how long they have been running and whether they
+//! are ready, and the orphans which are being terminated. The services themselves are defined
+//! by the new instance, like when booting.
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStringExt;
+use std::path::Path;
+use std::time::Duration;
+
+use nix::unistd::{execv, Pid};
+
+use crate::json::Value;
+
+/// Default location of the handover. It lives on a tmpfs, so a handover which is left behind
+/// never survives a reboot.
+pub const HANDOVER_PATH: &str = "/run/rsinit/handover";
+
+/// Default location of the snapshots of the state.
+pub const STATE_PATH: &str = "/run/rsinit.state";
+
+/// What the previous reaper knew about a service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Handed {
+    /// The running process of the service, or none if it was stopped.
+    pub(crate) pid: Option<Pid>,
+    /// When the process started, in clock ticks since boot, so a recycled pid is not adopted.
+    pub(crate) start_time: Option<u64>,
+    /// The later stages of its pipeline, if it has one.
+    pub(crate) stages: Vec<Pid>,
+    pub(crate) restarts: usize,
+    pub(crate) uptime: Duration,
+    pub(crate) ready: bool,
+}
+
+/// The state of a reaper, as handed over to the next one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Handover {
+    pub(crate) target: Option<String>,
+    pub(crate) services: HashMap<String, Handed>,
+    /// The orphans which were sent SIGTERM, and are killed if they linger.
+    pub(crate) orphans: Vec<Pid>,
+}
+
+impl Handover {
+    /// Read the handover at path, and remove it, so a crash of the new instance is not followed
+    /// by a second adoption of the same processes.
+    pub(crate) fn take(path: &str) -> io::Result<Handover> {
+        let content = fs::read_to_string(path)?;
+        if let Err(e) = fs::remove_file(path) {
+            warn!("Failed to remove handover {}: {}", path, e);
+        }
+        let value = Value::parse(&content).map_err(io::Error::other)?;
+        let pid = |value: &Value| value.as_f64().map(|pid| Pid::from_raw(pid as i32));
+        let services = value
+            .get("services")
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|service| {
+                let name = service.get("name").and_then(Value::as_str)?;
+                let handed = Handed {
+                    pid: service.get("pid").and_then(pid),
+                    start_time: service
+                        .get("start_time")
+                        .and_then(Value::as_f64)
+                        .map(|ticks| ticks as u64),
+                    stages: service
+                        .get("stages")
+                        .and_then(Value::as_array)
+                        .unwrap_or_default()
+                        .iter()
+                        .filter_map(pid)
+                        .collect(),
+                    restarts: service
+                        .get("restarts")
+                        .and_then(Value::as_f64)
+                        .unwrap_or(0.0) as usize,
+                    uptime: Duration::from_millis(
+                        service
+                            .get("uptime_ms")
+                            .and_then(Value::as_f64)
+                            .unwrap_or(0.0) as u64,
+                    ),
+                    ready: service
+                        .get("ready")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(true),
+                };
+                Some((name.to_string(), handed))
+            })
+            .collect();
+        Ok(Handover {
+            target: value
+                .get("target")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            services,
+            orphans: value
+                .get("orphans")
+                .and_then(Value::as_array)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(pid)
+                .collect(),
+        })
+    }
+
+    /// Write the handover to path, at once, so it is never read half written.
+    pub(crate) fn write(&self, path: &str) -> io::Result<()> {
+        let mut names: Vec<&String> = self.services.keys().collect();
+        names.sort();
+        let services: Vec<Value> = names
+            .into_iter()
+            .map(|name| {
+                let handed = &self.services[name];
+                Value::object()
+                    .with("name", name.as_str())
+                    .with("pid", handed.pid.map(i32::from))
+                    .with("start_time", handed.start_time)
+                    .with(
+                        "stages",
+                        handed
+                            .stages
+                            .iter()
+                            .map(|pid| i32::from(*pid))
+                            .collect::<Vec<_>>(),
+                    )
+                    .with("restarts", handed.restarts)
+                    .with("uptime_ms", handed.uptime.as_millis() as u64)
+                    .with("ready", handed.ready)
+            })
+            .collect();
+        let value = Value::object()
+            .with("target", self.target.as_deref())
+            .with("services", services)
+            .with(
+                "orphans",
+                self.orphans
+                    .iter()
+                    .map(|pid| i32::from(*pid))
+                    .collect::<Vec<_>>(),
+            );
+
+        let path = Path::new(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, format!("{}\n", value))?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// Replace the running process with a fresh instance of its own binary, with the same
+/// arguments. This only returns if that fails.
+pub(crate) fn exec_self() -> io::Error {
+    let to_cstring = |arg: OsString| CString::new(arg.into_vec()).map_err(io::Error::from);
+    let args: Result<Vec<CString>, io::Error> = std::env::args_os().map(to_cstring).collect();
+    let args = match args {
+        Ok(args) => args,
+        Err(e) => return e,
+    };
+    let exe = CString::new("/proc/self/exe").expect("no nul byte in path");
+    match execv(&exe, &args) {
+        Ok(void) => match void {},
+        Err(nix::Error::Sys(errno)) => io::Error::from(errno),
+        Err(e) => io::Error::other(e.to_string()),
+    }
+}
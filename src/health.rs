@@ -0,0 +1,376 @@
+//! Health checks, to notice services which wedged without exiting. Every check probes the service
+//! periodically, and the service is considered unhealthy once a number of consecutive probes
+//! failed.
+//!
+//! A service which takes a while to start can have a startup check as well, which probes it
+//! until it passes once, after which the health check takes over. The startup check can be more
+//! lenient, so the health check can be strict without killing the service while it starts.
+//!
+//! Network probes run on a thread of their own, so a slow peer does not hold up reaping. While the
+//! thread of a probe which timed out is still running, no new probe is started. Command probes
+//! are processes, which are reaped by the reaper like any other child, so they report back
+//! through it.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{getpid, Pid};
+
+use crate::sys;
+
+/// How a service is probed.
+#[derive(Debug, Clone)]
+pub enum HealthProbe {
+    /// Run a command, with its arguments separated by whitespace, which must exit with code 0.
+    Command(String),
+    /// Connect to an address, as `host:port`, over TCP.
+    Tcp(String),
+    /// Get an `http://` URL, which must be answered with a 2xx or 3xx status.
+    Http(String),
+}
+
+impl std::fmt::Display for HealthProbe {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HealthProbe::Command(cmd) => write!(f, "command {}", cmd),
+            HealthProbe::Tcp(addr) => write!(f, "connect to {}", addr),
+            HealthProbe::Http(url) => write!(f, "GET {}", url),
+        }
+    }
+}
+
+/// A probe which is run every interval, and fails the check after the given amount of
+/// consecutive failures.
+#[derive(Debug, Clone)]
+pub(crate) struct HealthCheck {
+    pub(crate) probe: HealthProbe,
+    pub(crate) interval: Duration,
+    pub(crate) retries: u32,
+}
+
+enum Running {
+    Process(Pid),
+    Thread(Arc<Mutex<Option<bool>>>),
+}
+
+/// The state of the health check of a single running process of a service.
+pub(crate) struct HealthMonitor {
+    check: HealthCheck,
+    // the health check which takes over once the startup check passed, while starting
+    liveness: Option<HealthCheck>,
+    starting: bool,
+    // the startup check passed, and there is no health check to take over
+    done: bool,
+    next_run: Instant,
+    failures: u32,
+    // the probe in progress, with when it times out
+    running: Option<(Running, Instant)>,
+    // the result of a network probe which timed out, while its thread has yet to give up
+    abandoned: Option<Arc<Mutex<Option<bool>>>>,
+}
+
+impl HealthMonitor {
+    /// Monitor a process which was just spawned. It gets an interval to start up before it is
+    /// probed for the first time.
+    pub(crate) fn new(check: HealthCheck, now: Instant) -> Self {
+        HealthMonitor {
+            next_run: now + check.interval,
+            check,
+            liveness: None,
+            starting: false,
+            done: false,
+            failures: 0,
+            running: None,
+            abandoned: None,
+        }
+    }
+
+    /// Monitor a process which was just spawned with the startup check, until it passes once,
+    /// and with the health check, if any, from then on.
+    pub(crate) fn starting(
+        startup: HealthCheck,
+        liveness: Option<HealthCheck>,
+        now: Instant,
+    ) -> Self {
+        let mut monitor = HealthMonitor::new(startup, now);
+        monitor.liveness = liveness;
+        monitor.starting = true;
+        monitor
+    }
+
+    /// Check if the startup check has yet to pass.
+    pub(crate) fn is_starting(&self) -> bool {
+        self.starting
+    }
+
+    /// Check if there is nothing left to probe, as the startup check passed and there is no
+    /// health check.
+    pub(crate) fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// When the monitor must be acted upon next, to start or to time out a probe.
+    pub(crate) fn next_due(&self) -> Instant {
+        match &self.running {
+            Some((_, timeout)) => *timeout,
+            None => self.next_run,
+        }
+    }
+
+    /// The process of the probe in progress, if it is a command.
+    pub(crate) fn probe_pid(&self) -> Option<Pid> {
+        match &self.running {
+            Some((Running::Process(pid), _)) => Some(*pid),
+            _ => None,
+        }
+    }
+
+    /// Start a probe if one is due. Returns the process of a command probe, which must be
+    /// reported back through `finished` once it is reaped.
+    pub(crate) fn start_if_due(&mut self, now: Instant, death_signal: Signal) -> Option<Pid> {
+        if self.done || self.running.is_some() || self.next_run > now {
+            return None;
+        }
+        self.next_run = now + self.check.interval;
+        // e.g. resolving the address can take longer than the timeout, and the probes of a
+        // service which hangs must not pile up. The thread holds on to the result until it ends.
+        if let Some(abandoned) = &self.abandoned {
+            if Arc::strong_count(abandoned) > 1 {
+                debug!(
+                    "{} check ({}) is still in flight, skipping a probe",
+                    self.kind(),
+                    self.check.probe
+                );
+                return None;
+            }
+            self.abandoned = None;
+        }
+        // a probe may take up to an interval, so it never overlaps with the next one
+        let timeout = now + self.check.interval;
+        let started = match &self.check.probe {
+            HealthProbe::Command(cmd) => spawn_command(cmd, death_signal).map(Running::Process),
+            probe => spawn_thread(probe.clone(), self.check.interval).map(Running::Thread),
+        };
+        match started {
+            Ok(running) => {
+                let pid = match running {
+                    Running::Process(pid) => Some(pid),
+                    Running::Thread(_) => None,
+                };
+                self.running = Some((running, timeout));
+                pid
+            }
+            Err(e) => {
+                // which says nothing about the service, so it is just tried again later
+                warn!(
+                    "Failed to start {} check ({}): {}",
+                    self.kind(),
+                    self.check.probe,
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Collect the result of a network probe which finished, and time out a probe which takes
+    /// too long. Returns whether the service became unhealthy.
+    pub(crate) fn poll(&mut self, now: Instant) -> bool {
+        let kind = self.kind();
+        let (running, timeout) = match &mut self.running {
+            Some(running) => running,
+            None => return false,
+        };
+        let finished = match running {
+            Running::Thread(result) => *result.lock().unwrap(),
+            Running::Process(_) => None,
+        };
+        if let Some(healthy) = finished {
+            self.running = None;
+            return self.record(healthy);
+        }
+        if *timeout > now {
+            return false;
+        }
+        debug!("{} check ({}) timed out", kind, self.check.probe);
+        match running {
+            Running::Process(pid) => {
+                // reported back once it is reaped
+                let _ = kill(*pid, Signal::SIGKILL);
+                *timeout = now + self.check.interval;
+                false
+            }
+            Running::Thread(result) => {
+                // the thread gives up on its own, its result is ignored
+                self.abandoned = Some(result.clone());
+                self.running = None;
+                self.record(false)
+            }
+        }
+    }
+
+    /// Record the result of a command probe, once its process is reaped. Returns whether the
+    /// service became unhealthy.
+    pub(crate) fn finished(&mut self, healthy: bool) -> bool {
+        self.running = None;
+        self.record(healthy)
+    }
+
+    /// Check if the latest probes succeeded, or not enough failed yet.
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.failures < self.check.retries.max(1)
+    }
+
+    /// What the current check is called in the log.
+    fn kind(&self) -> &'static str {
+        if self.starting {
+            "Startup"
+        } else {
+            "Health"
+        }
+    }
+
+    fn record(&mut self, healthy: bool) -> bool {
+        if healthy {
+            self.failures = 0;
+            if self.starting {
+                debug!("Startup check ({}) passed", self.check.probe);
+                self.starting = false;
+                match self.liveness.take() {
+                    Some(liveness) => {
+                        self.next_run = Instant::now() + liveness.interval;
+                        self.check = liveness;
+                    }
+                    None => self.done = true,
+                }
+            }
+            return false;
+        }
+        self.failures += 1;
+        debug!(
+            "{} check ({}) failed {} time(s) in a row",
+            self.kind(),
+            self.check.probe,
+            self.failures
+        );
+        // only reported once, when the failures add up
+        self.failures == self.check.retries.max(1)
+    }
+}
+
+impl Drop for HealthMonitor {
+    fn drop(&mut self) {
+        // the service is gone, and so is the reason to probe it
+        if let Some(pid) = self.probe_pid() {
+            let _ = kill(pid, Signal::SIGKILL);
+        }
+    }
+}
+
+fn spawn_command(cmd: &str, death_signal: Signal) -> io::Result<Pid> {
+    let mut args = cmd.split_whitespace();
+    let program = args
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+    let mut cmd = Command::new(program);
+    cmd.args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let parent = i32::from(getpid());
+    unsafe {
+        cmd.pre_exec(sys::unblock_signals);
+        cmd.pre_exec(move || sys::signal_on_parent_death(death_signal as libc::c_int, parent));
+    }
+    // the process is reaped by the reaper, not through the handle
+    Ok(Pid::from_raw(cmd.spawn()?.id() as i32))
+}
+
+fn spawn_thread(probe: HealthProbe, timeout: Duration) -> io::Result<Arc<Mutex<Option<bool>>>> {
+    let result = Arc::new(Mutex::new(None));
+    let shared = result.clone();
+    thread::Builder::new()
+        .name("health".to_string())
+        .spawn(move || {
+            let res = match &probe {
+                HealthProbe::Tcp(addr) => connect(addr, timeout).map(drop),
+                HealthProbe::Http(url) => http_get(url, timeout),
+                HealthProbe::Command(_) => unreachable!(),
+            };
+            if let Err(e) = &res {
+                debug!("Health check ({}) failed: {}", probe, e);
+            }
+            *shared.lock().unwrap() = Some(res.is_ok());
+        })?;
+    Ok(result)
+}
+
+fn connect(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no address to connect to");
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+/// Get the URL, and check that the status of the response is 2xx or 3xx.
+fn http_get(url: &str, timeout: Duration) -> io::Result<()> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid(format!("{} is not an http:// URL", url)))?;
+    let (host, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+    let addr = if host.contains(':') && !host.ends_with(']') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let mut stream = connect(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    // in a single write, as some servers answer as soon as they read anything
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes())?;
+    // only the status line is of interest, e.g. `HTTP/1.1 200 OK`
+    let mut buf = [0; 64];
+    let mut len = 0;
+    while len < buf.len() {
+        match stream.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+        if buf[..len].contains(&b'\n') {
+            break;
+        }
+    }
+    let line = String::from_utf8_lossy(&buf[..len]);
+    match line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+    {
+        Some(status) if (200..400).contains(&status) => Ok(()),
+        Some(status) => Err(io::Error::other(format!("status {}", status))),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid HTTP response",
+        )),
+    }
+}
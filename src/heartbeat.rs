@@ -0,0 +1,47 @@
+//! A file which the reaper rewrites on every pass of its loop, so external watchdogs can check
+//! that init is still making progress, without having to talk to it.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default location of the heartbeat file.
+pub const HEARTBEAT_PATH: &str = "/run/rsinit/alive";
+
+pub(crate) struct Heartbeat {
+    path: PathBuf,
+    tmp: PathBuf,
+}
+
+impl Heartbeat {
+    pub(crate) fn create(path: &str) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let heartbeat = Heartbeat {
+            tmp: path.with_extension("tmp"),
+            path,
+        };
+        heartbeat.write()?;
+        Ok(heartbeat)
+    }
+
+    /// Write the current time, in seconds since the epoch, to the file.
+    pub(crate) fn beat(&self) {
+        if let Err(e) = self.write() {
+            warn!("Failed to update heartbeat {:?}: {}", self.path, e);
+        }
+    }
+
+    fn write(&self) -> io::Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        // replace the file at once, so it is never read half written
+        fs::write(&self.tmp, format!("{}\n", now))?;
+        fs::rename(&self.tmp, &self.path)
+    }
+}
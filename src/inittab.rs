@@ -0,0 +1,348 @@
+//! The classic `/etc/inittab` of sysvinit and busybox, so images which boot with either can
+//! be booted with rsinit without rewriting their boot configuration.
+//!
+//! Every line has the form `id:runlevels:action:process`. Runlevels are ignored, as rsinit has
+//! none. Supported actions are:
+//!
+//! - `sysinit`: run the process to completion at boot, before anything else
+//! - `wait`: run the process to completion at boot, after the `sysinit` entries
+//! - `once`: start the process, without restarting it when it exits
+//! - `respawn`: start the process, and restart it whenever it exits
+//! - `askfirst`: like `respawn`, but the process only starts once Enter is pressed on its
+//!   terminal
+//! - `shutdown`: run the process to completion when shutting down, once all services are
+//!   stopped
+//!
+//! Other actions, like `ctrlaltdel`, are left out. As with busybox, an id which
+//! names a terminal, like `ttyS0`, runs the process on that terminal. The process is run
+//! without a shell, so its arguments are separated by whitespace, and the leading `-` busybox
+//! uses to start a login shell is dropped.
+//!
+//! As an extension, an id like `worker@1-8` or `getty@tty1,ttyS0` makes the line a template:
+//! it stands for a line for every instance, with ids like `worker@1`, and with `%i` in the
+//! process replaced by the instance. An instance which names a terminal runs the process on it.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::time::Duration;
+
+use crate::command::{split_command, OneShotCommand, PersistentCommand};
+use crate::emergency::CONSOLE;
+use crate::template::{parse_instances, substitute};
+
+/// The inittab which is read at boot.
+pub const INITTAB_PATH: &str = "/etc/inittab";
+
+/// When an inittab entry runs its process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    SysInit,
+    Wait,
+    Once,
+    Respawn,
+    AskFirst,
+    Shutdown,
+    /// An action rsinit has no equivalent for, which is left out.
+    Other(String),
+}
+
+/// A single line of an inittab.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub id: String,
+    pub action: Action,
+    pub process: String,
+    /// The terminal the id names, if it names one.
+    pub tty: Option<String>,
+}
+
+impl Entry {
+    /// Parse a line of the form `id:runlevels:action:process`.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut fields = line.splitn(4, ':');
+        let (id, _runlevels, action, process) =
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some(id), Some(runlevels), Some(action), Some(process)) => {
+                    (id.trim(), runlevels, action.trim(), process.trim())
+                }
+                _ => return Err("expected id:runlevels:action:process".to_string()),
+            };
+        let action = match action {
+            "sysinit" => Action::SysInit,
+            "wait" => Action::Wait,
+            "once" => Action::Once,
+            "respawn" => Action::Respawn,
+            "askfirst" => Action::AskFirst,
+            "shutdown" => Action::Shutdown,
+            "" => return Err("missing action".to_string()),
+            other => Action::Other(other.to_string()),
+        };
+        let process = process.strip_prefix('-').unwrap_or(process).trim_start();
+        if process.is_empty() && !matches!(action, Action::Other(_)) {
+            return Err("missing process".to_string());
+        }
+        Ok(Entry {
+            id: id.to_string(),
+            action,
+            process: process.to_string(),
+            tty: terminal(id),
+        })
+    }
+
+    /// The entries for every instance of a template, or just the entry itself if it is not
+    /// a template.
+    fn instances(self) -> Result<Vec<Entry>, String> {
+        let (name, spec) = match self.id.find('@') {
+            Some(idx) if idx + 1 < self.id.len() => (&self.id[..idx], &self.id[idx + 1..]),
+            _ => return Ok(vec![self]),
+        };
+        Ok(parse_instances(spec)?
+            .into_iter()
+            .map(|instance| Entry {
+                id: format!("{}@{}", name, instance),
+                action: self.action.clone(),
+                process: substitute(&self.process, &instance),
+                tty: terminal(&instance),
+            })
+            .collect())
+    }
+
+    /// The process of the entry as a one-shot command.
+    fn one_shot(&self) -> OneShotCommand<'_> {
+        let (cmd, args) = split_command(&self.process);
+        let one_shot = OneShotCommand::new(cmd, args);
+        match self.name() {
+            Some(name) => one_shot.name(name),
+            None => one_shot,
+        }
+    }
+
+    /// The name of the service of the entry: its id, unless it has none.
+    fn name(&self) -> Option<&str> {
+        Some(self.id.as_str()).filter(|id| !id.is_empty())
+    }
+}
+
+/// The path of the terminal an id names, like `/dev/ttyS0` for `ttyS0`.
+fn terminal(id: &str) -> Option<String> {
+    if id.is_empty() || id.contains('/') {
+        return None;
+    }
+    let path = format!("/dev/{}", id);
+    fs::metadata(&path)
+        .ok()
+        .filter(|meta| meta.file_type().is_char_device())
+        .map(|_| path)
+}
+
+/// The entries of an inittab.
+#[derive(Debug, Clone, Default)]
+pub struct Inittab {
+    entries: Vec<Entry>,
+}
+
+impl Inittab {
+    /// Read an inittab. Lines which can't be parsed are logged and left out, so a typo does
+    /// not keep the system from booting.
+    pub fn read(path: &str) -> io::Result<Self> {
+        Self::parse(path, |problem, supported| {
+            if supported {
+                error!("Ignoring {}", problem)
+            } else {
+                info!("Ignoring {}", problem)
+            }
+        })
+    }
+
+    /// Read an inittab like [`read`], returning what is wrong with the lines which are left
+    /// out, like `line 3 of /etc/inittab: missing process`, instead of logging it.
+    ///
+    /// [`read`]: #method.read
+    pub fn validate(path: &str) -> io::Result<(Self, Vec<String>)> {
+        let mut problems = Vec::new();
+        let inittab = Self::parse(path, |problem, _| problems.push(problem))?;
+        Ok((inittab, problems))
+    }
+
+    /// Read an inittab, passing what is wrong with the lines which are left out to report,
+    /// along with whether their action is supported.
+    fn parse(path: &str, mut report: impl FnMut(String, bool)) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let mut entries = Vec::new();
+        for (idx, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let at = format!("line {} of {}", idx + 1, path);
+            match Entry::parse(line) {
+                Ok(Entry {
+                    action: Action::Other(action),
+                    ..
+                }) => report(format!("{}: {} is not supported", at, action), false),
+                Ok(entry) => match entry.instances() {
+                    Ok(instances) => entries.extend(instances),
+                    Err(e) => report(format!("{}: {}", at, e), true),
+                },
+                Err(e) => report(format!("{}: {}", at, e), true),
+            }
+        }
+        Ok(Inittab { entries })
+    }
+
+    /// The entries which are supported, in the order they appear in.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The `sysinit` and `wait` entries as one-shot commands, in the order they run in.
+    pub fn one_shots(&self) -> Vec<OneShotCommand<'_>> {
+        let sysinit = self.with_action(Action::SysInit);
+        let wait = self.with_action(Action::Wait);
+        sysinit.chain(wait).map(Entry::one_shot).collect()
+    }
+
+    /// The `shutdown` entries as one-shot commands, in the order they run in.
+    pub fn shutdown_commands(&self) -> Vec<OneShotCommand<'_>> {
+        self.with_action(Action::Shutdown)
+            .map(Entry::one_shot)
+            .collect()
+    }
+
+    /// The `once`, `respawn` and `askfirst` entries as persistent commands. Like a getty, a
+    /// process which is respawned is restarted at most 10 times in 2 minutes.
+    pub fn services(&self) -> Vec<PersistentCommand<'_>> {
+        self.entries
+            .iter()
+            .filter_map(|entry| {
+                let (cmd, args) = split_command(&entry.process);
+                let mut pcmd = PersistentCommand::new(cmd, args);
+                match entry.action {
+                    Action::Once => (),
+                    Action::Respawn | Action::AskFirst => {
+                        pcmd = pcmd
+                            .restart_on_success(true)
+                            .restart_on_error(true)
+                            .restart_on_signal(true)
+                            .spawn_limit_window(10, Duration::from_secs(120))
+                            .ask_first(entry.action == Action::AskFirst);
+                    }
+                    _ => return None,
+                }
+                // asking needs a terminal, which is the console unless the id names another
+                match (&entry.tty, &entry.action) {
+                    (Some(tty), _) => pcmd = pcmd.tty(tty),
+                    (None, Action::AskFirst) => pcmd = pcmd.tty(CONSOLE),
+                    (None, _) => (),
+                }
+                if let Some(name) = entry.name() {
+                    pcmd = pcmd.name(name);
+                }
+                Some(pcmd)
+            })
+            .collect()
+    }
+
+    fn with_action(&self, action: Action) -> impl Iterator<Item = &Entry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.action == action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_entries() {
+        let cases: &[(&str, &str, Action, &str)] = &[
+            (
+                "si::sysinit:/etc/init.d/rcS",
+                "si",
+                Action::SysInit,
+                "/etc/init.d/rcS",
+            ),
+            (
+                "rc:2345:wait:/etc/rc boot",
+                "rc",
+                Action::Wait,
+                "/etc/rc boot",
+            ),
+            ("x::once:/bin/true", "x", Action::Once, "/bin/true"),
+            ("::respawn:-/bin/sh", "", Action::Respawn, "/bin/sh"),
+            (
+                "::askfirst:- /bin/sh -l",
+                "",
+                Action::AskFirst,
+                "/bin/sh -l",
+            ),
+            (
+                "::shutdown:/bin/umount -a -r",
+                "",
+                Action::Shutdown,
+                "/bin/umount -a -r",
+            ),
+            (" x :: once : /bin/a:b ", "x", Action::Once, "/bin/a:b"),
+            (
+                "ca::ctrlaltdel:",
+                "ca",
+                Action::Other("ctrlaltdel".to_string()),
+                "",
+            ),
+        ];
+        for (line, id, action, process) in cases {
+            let entry = Entry::parse(line).unwrap_or_else(|e| panic!("{:?}: {}", line, e));
+            assert_eq!(entry.id, *id, "{:?}", line);
+            assert_eq!(entry.action, *action, "{:?}", line);
+            assert_eq!(entry.process, *process, "{:?}", line);
+            assert_eq!(entry.tty, None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = &[
+            ("", "expected id:runlevels:action:process"),
+            ("x::respawn", "expected id:runlevels:action:process"),
+            ("x:::/bin/sh", "missing action"),
+            ("x::respawn:", "missing process"),
+            ("x::once:-", "missing process"),
+        ];
+        for (line, error) in cases {
+            assert_eq!(Entry::parse(line), Err(error.to_string()), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn read_templates_and_problems() {
+        let path = std::env::temp_dir().join(format!("rsinit-inittab-{}", std::process::id()));
+        fs::write(
+            &path,
+            "# comment\n\
+             \n\
+             worker@1-3::respawn:/bin/worker --id %i\n\
+             bad@3-1::respawn:/bin/worker\n\
+             x::respawn\n\
+             ca::ctrlaltdel:/sbin/reboot\n\
+             plain@::once:/bin/true\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+        let (inittab, problems) = Inittab::validate(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let ids: Vec<_> = inittab.entries().iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, ["worker@1", "worker@2", "worker@3", "plain@"]);
+        assert_eq!(inittab.entries()[1].process, "/bin/worker --id 2");
+        assert_eq!(
+            problems,
+            [
+                format!("line 4 of {}: empty range 3-1", path),
+                format!("line 5 of {}: expected id:runlevels:action:process", path),
+                format!("line 6 of {}: ctrlaltdel is not supported", path),
+            ]
+        );
+    }
+}
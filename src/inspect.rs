@@ -0,0 +1,152 @@
+//! Low level details of the process of a service, for tooling which needs to attach to it, such
+//! as monitoring agents or network plugins. Everything is read from /proc.
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use nix::unistd::Pid;
+
+use crate::json::Value;
+
+/// The namespaces which are reported, by their name in `/proc/<pid>/ns`.
+const NAMESPACES: [&str; 8] = ["cgroup", "ipc", "mnt", "net", "pid", "time", "user", "uts"];
+
+/// `__SO_ACCEPTCON`, the flag of a listening unix socket in `/proc/net/unix`.
+const SO_ACCEPTCON: u32 = 1 << 16;
+
+/// Describe the process of a service: its cgroup, namespaces and listening sockets.
+pub(crate) fn inspect_json(name: &str, pid: Option<Pid>) -> Value {
+    let value = Value::object()
+        .with("name", name)
+        .with("pid", pid.map(i32::from));
+    let pid = match pid {
+        Some(pid) => pid,
+        None => return value,
+    };
+    value
+        .with("cgroup", cgroup(pid))
+        .with("namespaces", namespaces(pid))
+        .with("listen_fds", listen_fds(pid))
+}
+
+/// The path of the cgroup v2 the process is in, relative to the cgroup2 mount.
+fn cgroup(pid: Pid) -> Option<String> {
+    let content = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(str::to_string)
+}
+
+/// The inode numbers of the namespaces of the process, which tell whether two processes share
+/// a namespace.
+fn namespaces(pid: Pid) -> Value {
+    NAMESPACES.iter().fold(Value::object(), |value, ns| {
+        // the link reads like `net:[4026531840]`
+        let inode = fs::read_link(format!("/proc/{}/ns/{}", pid, ns))
+            .ok()
+            .and_then(|link| {
+                let link = link.to_string_lossy().into_owned();
+                let start = link.find('[')? + 1;
+                link[start..].trim_end_matches(']').parse::<u64>().ok()
+            });
+        value.with(ns, inode)
+    })
+}
+
+/// The file descriptors of the process which are listening sockets, with their address.
+fn listen_fds(pid: Pid) -> Vec<Value> {
+    // the sockets are looked up in the network namespace of the process
+    let mut listening: HashMap<u64, (&str, String)> = HashMap::new();
+    for (proto, v6) in [("tcp", false), ("tcp6", true)].iter() {
+        let table = fs::read_to_string(format!("/proc/{}/net/{}", pid, proto)).unwrap_or_default();
+        for line in table.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout
+            // inode
+            if fields.len() < 10 || fields[3] != "0A" {
+                continue;
+            }
+            if let (Some(addr), Ok(inode)) = (parse_addr(fields[1], *v6), fields[9].parse()) {
+                listening.insert(inode, (proto, addr));
+            }
+        }
+    }
+    let unix = fs::read_to_string(format!("/proc/{}/net/unix", pid)).unwrap_or_default();
+    for line in unix.lines().skip(1) {
+        // Num RefCount Protocol Flags Type St Inode Path
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let flags = fields
+            .get(3)
+            .and_then(|flags| u32::from_str_radix(flags, 16).ok());
+        let inode = fields.get(6).and_then(|inode| inode.parse().ok());
+        if let (Some(flags), Some(inode)) = (flags, inode) {
+            if flags & SO_ACCEPTCON != 0 {
+                let path = fields.get(7).copied().unwrap_or("");
+                listening.insert(inode, ("unix", path.to_string()));
+            }
+        }
+    }
+
+    let mut fds: Vec<(i32, u64)> = fs::read_dir(format!("/proc/{}/fd", pid))
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter_map(|entry| {
+                    let fd = entry.file_name().to_str()?.parse().ok()?;
+                    // the link reads like `socket:[12345]`
+                    let link = fs::read_link(entry.path()).ok()?;
+                    let inode = link
+                        .to_str()?
+                        .strip_prefix("socket:[")?
+                        .trim_end_matches(']')
+                        .parse()
+                        .ok()?;
+                    Some((fd, inode))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    fds.sort();
+    fds.into_iter()
+        .filter_map(|(fd, inode)| {
+            let (proto, addr) = listening.get(&inode)?;
+            Some(
+                Value::object()
+                    .with("fd", fd)
+                    .with("protocol", *proto)
+                    .with("address", addr.as_str())
+                    .with("inode", inode),
+            )
+        })
+        .collect()
+}
+
+/// Parse an address like `0100007F:0016` from `/proc/net/tcp`, in which the address is in host
+/// byte order, word by word, and the port in hex.
+fn parse_addr(field: &str, v6: bool) -> Option<String> {
+    let (addr, port) = field.split_at(field.find(':')?);
+    let port = u16::from_str_radix(&port[1..], 16).ok()?;
+    let words: Vec<u32> = (0..addr.len() / 8)
+        .map(|idx| u32::from_str_radix(&addr[idx * 8..idx * 8 + 8], 16))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    let mut octets = Vec::with_capacity(16);
+    for word in words {
+        octets.extend_from_slice(&word.to_ne_bytes());
+    }
+    match (v6, octets.len()) {
+        (false, 4) => Some(format!(
+            "{}:{}",
+            Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]),
+            port
+        )),
+        (true, 16) => {
+            let mut bytes = [0; 16];
+            bytes.copy_from_slice(&octets);
+            Some(format!("[{}]:{}", Ipv6Addr::from(bytes), port))
+        }
+        _ => None,
+    }
+}
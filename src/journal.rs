@@ -0,0 +1,193 @@
+//! A journal of the start, stop and restart intents issued for services, so a restarted reaper
+//! can pick up where the previous one left off.
+//!
+//! Every intent is appended as a JSON line when it is issued, and a second line marks it as done
+//! once it completed. When the journal is opened again, intents are rolled forward: a service
+//! which was last stopped stays stopped, even if the stop did not complete, and any other service
+//! is started as usual. The journal is then compacted to just the services which are stopped.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use nix::unistd::Pid;
+
+use crate::json::Value;
+
+/// Default location of the journal. It lives on a tmpfs, so it only survives restarts of init
+/// itself, not reboots.
+pub const JOURNAL_PATH: &str = "/run/rsinit/journal";
+
+/// A state mutation of a service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Intent {
+    Start,
+    Stop,
+    Restart,
+}
+
+impl Intent {
+    fn as_str(self) -> &'static str {
+        match self {
+            Intent::Start => "start",
+            Intent::Stop => "stop",
+            Intent::Restart => "restart",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Intent> {
+        match s {
+            "start" => Some(Intent::Start),
+            "stop" => Some(Intent::Stop),
+            "restart" => Some(Intent::Restart),
+            _ => None,
+        }
+    }
+}
+
+/// The last intent issued for a service, as recovered from the journal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Recovered {
+    pub(crate) intent: Intent,
+    /// The process the intent applied to.
+    pub(crate) pid: Option<Pid>,
+    pub(crate) done: bool,
+}
+
+pub(crate) struct Journal {
+    path: PathBuf,
+    file: File,
+    next_seq: u64,
+}
+
+impl Journal {
+    /// Open the journal at path, returning the last intent for every service found in it. The
+    /// journal is compacted afterwards, so only stopped services are remembered.
+    pub(crate) fn open(path: &str) -> io::Result<(Journal, HashMap<String, Recovered>)> {
+        let path = PathBuf::from(path);
+        let recovered = match fs::read_to_string(&path) {
+            Ok(content) => replay(&content),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let (file, next_seq) = compact(&path, &recovered)?;
+        Ok((
+            Journal {
+                path,
+                file,
+                next_seq,
+            },
+            recovered,
+        ))
+    }
+
+    /// Record an intent, returning the sequence number to complete it with.
+    pub(crate) fn begin(&mut self, intent: Intent, service: &str, pid: Option<Pid>) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.append(
+            Value::object()
+                .with("seq", seq)
+                .with("intent", intent.as_str())
+                .with("service", service)
+                .with("pid", pid.map(i32::from)),
+        );
+        seq
+    }
+
+    /// Record that an intent completed.
+    pub(crate) fn complete(&mut self, seq: u64) {
+        self.append(Value::object().with("seq", seq).with("done", true));
+    }
+
+    fn append(&mut self, entry: Value) {
+        let res = writeln!(self.file, "{}", entry).and_then(|_| self.file.sync_data());
+        if let Err(e) = res {
+            warn!("Failed to write to journal {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Work out the last intent per service.
+fn replay(content: &str) -> HashMap<String, Recovered> {
+    let mut intents: BTreeMap<u64, (String, Recovered)> = BTreeMap::new();
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        // a crash can leave a partially written last line
+        let entry = match Value::parse(line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Ignoring malformed journal entry: {}", e);
+                continue;
+            }
+        };
+        let seq = match entry.get("seq").and_then(Value::as_f64) {
+            Some(seq) => seq as u64,
+            None => continue,
+        };
+        if entry.get("done").and_then(Value::as_bool) == Some(true) {
+            if let Some((_, recovered)) = intents.get_mut(&seq) {
+                recovered.done = true;
+            }
+            continue;
+        }
+        let intent = entry
+            .get("intent")
+            .and_then(Value::as_str)
+            .and_then(Intent::from_str);
+        let service = entry.get("service").and_then(Value::as_str);
+        if let (Some(intent), Some(service)) = (intent, service) {
+            let pid = entry
+                .get("pid")
+                .and_then(Value::as_f64)
+                .map(|p| Pid::from_raw(p as i32));
+            intents.insert(
+                seq,
+                (
+                    service.to_string(),
+                    Recovered {
+                        intent,
+                        pid,
+                        done: false,
+                    },
+                ),
+            );
+        }
+    }
+
+    // later intents override earlier ones
+    intents.into_values().collect()
+}
+
+/// Rewrite the journal with only the stopped services, and open it for appending. Also returns
+/// the next free sequence number.
+fn compact(path: &Path, recovered: &HashMap<String, Recovered>) -> io::Result<(File, u64)> {
+    let tmp = path.with_extension("tmp");
+    let mut next_seq = 1;
+    {
+        let mut file = File::create(&tmp)?;
+        let mut stopped: Vec<&String> = recovered
+            .iter()
+            .filter(|(_, r)| r.intent == Intent::Stop)
+            .map(|(name, _)| name)
+            .collect();
+        stopped.sort();
+        next_seq += stopped.len() as u64;
+        for (seq, name) in stopped.into_iter().enumerate() {
+            let seq = seq as u64 + 1;
+            let intent = Value::object()
+                .with("seq", seq)
+                .with("intent", Intent::Stop.as_str())
+                .with("service", name.as_str());
+            let done = Value::object().with("seq", seq).with("done", true);
+            writeln!(file, "{}\n{}", intent, done)?;
+        }
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok((OpenOptions::new().append(true).open(path)?, next_seq))
+}
@@ -0,0 +1,465 @@
+//! A minimal JSON value, with just enough parsing and serialization for the line based
+//! protocols and event streams of rsinit.
+
+use std::fmt;
+
+/// How deep arrays and objects may be nested in a parsed document, so a hostile one can't run
+/// the parser out of stack.
+const MAX_DEPTH: usize = 64;
+
+/// A JSON value. Objects keep the order in which their fields were inserted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Create an empty object.
+    pub fn object() -> Value {
+        Value::Object(Vec::new())
+    }
+
+    /// Add a field to an object. Does nothing if the value is not an object.
+    pub fn with<V: Into<Value>>(mut self, key: &str, value: V) -> Value {
+        if let Value::Object(fields) = &mut self {
+            fields.push((key.to_string(), value.into()));
+        }
+        self
+    }
+
+    /// Get a field of an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a string, if it is one.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a number, if it is one.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a boolean, if it is one.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Get the value as an array, if it is one.
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Parse a JSON document.
+    pub fn parse(input: &str) -> Result<Value, String> {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.pos != parser.input.len() {
+            return Err(format!("trailing data at offset {}", parser.pos));
+        }
+        Ok(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+macro_rules! from_number {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Value {
+                fn from(n: $t) -> Value {
+                    Value::Number(n as f64)
+                }
+            }
+        )*
+    };
+}
+
+from_number!(i32, i64, u32, u64, usize, f64);
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(o: Option<T>) -> Value {
+        match o {
+            Some(v) => v.into(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(v: Vec<T>) -> Value {
+        Value::Array(v.into_iter().map(Into::into).collect())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Value::Number(_) => write!(f, "null"),
+            Value::String(s) => write_string(f, s),
+            Value::Array(a) => {
+                write!(f, "[")?;
+                for (i, v) in a.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(o) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in o.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.lookahead(token) {
+            self.pos += token.len();
+            Ok(())
+        } else {
+            Err(format!("expected {} at offset {}", token, self.pos))
+        }
+    }
+
+    /// Check if the input continues with token.
+    fn lookahead(&self, token: &str) -> bool {
+        self.input
+            .get(self.pos..)
+            .is_some_and(|rest| rest.starts_with(token.as_bytes()))
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'n') => self.expect("null").map(|_| Value::Null),
+            Some(b't') => self.expect("true").map(|_| Value::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') | Some(b'{') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(format!("nested too deeply at offset {}", self.pos));
+                }
+                self.depth += 1;
+                let value = match self.peek() {
+                    Some(b'[') => self.array(),
+                    _ => self.object(),
+                };
+                self.depth -= 1;
+                value
+            }
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(c) => Err(format!(
+                "unexpected character {} at offset {}",
+                c as char, self.pos
+            )),
+            None => Err("unexpected end of input".to_string()),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while let Some(b'-') | Some(b'+') | Some(b'.') | Some(b'e') | Some(b'E')
+        | Some(b'0'..=b'9') = self.peek()
+        {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.input[start..self.pos]).map_err(|e| e.to_string())?;
+        text.parse()
+            .map(Value::Number)
+            .map_err(|_| format!("invalid number {}", text))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut out = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err("unterminated string".to_string()),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = self.peek().ok_or("unterminated string")?;
+                    self.pos += 1;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        c => return Err(format!("invalid escape \\{}", c as char)),
+                    };
+                    let mut buf = [0; 4];
+                    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(c) => {
+                    out.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        String::from_utf8(out).map_err(|e| e.to_string())
+    }
+
+    /// Decode the rest of a `\u` escape. Characters outside of the basic plane are escaped as
+    /// a pair of UTF-16 surrogates, which are combined, and a surrogate which is not part of a
+    /// pair is refused.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let start = self.pos - 2;
+        let unpaired = || format!("unpaired surrogate at offset {}", start);
+        let code = match self.hex4()? {
+            high @ 0xd800..=0xdbff => {
+                if !self.lookahead("\\u") {
+                    return Err(unpaired());
+                }
+                self.pos += 2;
+                match self.hex4()? {
+                    low @ 0xdc00..=0xdfff => 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                    _ => return Err(unpaired()),
+                }
+            }
+            0xdc00..=0xdfff => return Err(unpaired()),
+            code => code,
+        };
+        std::char::from_u32(code).ok_or_else(unpaired)
+    }
+
+    /// Read the four hex digits of a `\u` escape.
+    fn hex4(&mut self) -> Result<u32, String> {
+        let hex = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .ok_or_else(|| format!("invalid unicode escape at offset {}", self.pos))?;
+        self.pos += 4;
+        Ok(hex.iter().fold(0, |code, &digit| {
+            code * 16 + (digit as char).to_digit(16).unwrap_or(0)
+        }))
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect("[")?;
+        let mut values = Vec::new();
+        self.whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return Err(format!("expected , or ] at offset {}", self.pos)),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect("{")?;
+        let mut fields = Vec::new();
+        self.whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.whitespace();
+            let key = self.string()?;
+            self.whitespace();
+            self.expect(":")?;
+            let value = self.value()?;
+            fields.push((key, value));
+            self.whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                _ => return Err(format!("expected , or }} at offset {}", self.pos)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_values() {
+        for (input, value) in [
+            ("null", Value::Null),
+            (" true ", Value::Bool(true)),
+            ("false", Value::Bool(false)),
+            ("-1.5e3", Value::Number(-1500.0)),
+            ("\"a\\\"b\\\\c\\/\\n\\t\"", Value::from("a\"b\\c/\n\t")),
+            ("\"\\u00e9\\u20AC\"", Value::from("é€")),
+            ("\"\\ud83d\\ude00\"", Value::from("\u{1f600}")),
+            ("\"\u{1f600}\"", Value::from("\u{1f600}")),
+            ("[]", Value::Array(vec![])),
+            (
+                "[1, [2]]",
+                Value::from(vec![Value::from(1), Value::from(vec![2])]),
+            ),
+            (
+                "{\"a\": 1, \"b\": {}}",
+                Value::object().with("a", 1).with("b", Value::object()),
+            ),
+        ] {
+            assert_eq!(Value::parse(input), Ok(value), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (input, error) in [
+            ("", "unexpected end of input"),
+            ("tru", "expected true at offset 0"),
+            ("[1,", "unexpected end of input"),
+            ("[1 2]", "expected , or ] at offset 3"),
+            ("{\"a\" 1}", "expected : at offset 5"),
+            ("{\"a\": 1", "expected , or } at offset 7"),
+            ("{1: 2}", "expected \" at offset 1"),
+            ("\"abc", "unterminated string"),
+            ("\"\\", "unterminated string"),
+            ("\"\\x\"", "invalid escape \\x"),
+            ("\"\\u12\"", "invalid unicode escape at offset 3"),
+            ("\"\\u+123\"", "invalid unicode escape at offset 3"),
+            ("\"\\ud83d\"", "unpaired surrogate at offset 1"),
+            ("\"\\ud83dx\"", "unpaired surrogate at offset 1"),
+            ("\"\\ud83d\\u0041\"", "unpaired surrogate at offset 1"),
+            ("\"\\ude00\"", "unpaired surrogate at offset 1"),
+            ("\"\\ud83d\\ud83d\"", "unpaired surrogate at offset 1"),
+            ("1x", "trailing data at offset 1"),
+            ("1-", "invalid number 1-"),
+            ("@", "unexpected character @ at offset 0"),
+        ] {
+            assert_eq!(Value::parse(input), Err(error.to_string()), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parse_limits_nesting() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(Value::parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            Value::parse(&nested(MAX_DEPTH + 1)),
+            Err(format!("nested too deeply at offset {}", MAX_DEPTH))
+        );
+    }
+
+    #[test]
+    fn serialize_round_trips() {
+        let value = Value::object()
+            .with("s", "quote \" backslash \\ newline \n bell \u{7} \u{1f600}")
+            .with("n", 42)
+            .with("f", 0.5)
+            .with("none", None::<u32>)
+            .with("list", vec![true, false]);
+        let text = value.to_string();
+        assert!(text.contains("\\u0007"), "{}", text);
+        assert_eq!(Value::parse(&text), Ok(value));
+        assert_eq!(Value::from(f64::NAN).to_string(), "null");
+    }
+}
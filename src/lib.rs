@@ -1,28 +1,256 @@
 #[macro_use]
 extern crate log;
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
-use std::fs::{read_dir, File};
-use std::io::Read;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::time::Instant;
 
+use nix::errno::Errno;
 use nix::sys::signal::Signal;
-use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
-use nix::unistd::{getpid, Pid};
+use nix::sys::wait::{WaitPidFlag, WaitStatus};
+use nix::unistd::{getpgid, getpid, Pid};
 
-use signal::trap::Trap;
-use signal::Signal::*;
+use boottimes::BootTimes;
+#[cfg(feature = "bpf")]
+use bpf::ProcessEvents;
+use cgroup::{Cgroup, CgroupLimits, CgroupRoot};
+use clock::{ClockJump, ClockWatch};
+use console::ConsoleStatus;
+use control::{history_json, query_json, stats_json, status_json, ControlSocket, Request, Target};
+use discovery::{ChildDiscovery, ExitedProcess};
+use eventlog::EventLog;
+use eventloop::{EventLoop, Wakeup};
+use handle::{HandleRequests, Operation};
+use handover::{Handed, Handover};
+use health::HealthMonitor;
+use heartbeat::Heartbeat;
+use inspect::inspect_json;
+use journal::{Intent, Journal, Recovered};
+use json::Value;
+use lineage::{ProcessEvent, ProcessTree};
+use logmux::{LineSink, LogMultiplexer, Prefixed};
+use logrotate::LogRotation;
+use logsink::Syslog;
+use metrics::MetricsFile;
+use mounts::MountTable;
+use notify::{Notification, NotifySocket};
+use observer::EventForwarder;
+use orphan::OrphanState;
+use password::PasswordAgent;
+use power::PowerAction;
+use proc_connector::ProcConnector;
+use process::{ProcessOps, ProcessStat, SystemProcesses};
+use readiness::PseudoService;
+use scrollback::Scrollback;
+use sys::{PidFd, PreparedWrite};
+use usage::UsageMonitor;
+use watch::FileWatcher;
 
+#[cfg(feature = "async")]
+mod async_reaper;
+#[cfg(feature = "async")]
+pub use async_reaper::{AsyncReaper, Frontend};
+pub mod boot;
+pub mod boottimes;
+#[cfg(feature = "bpf")]
+mod bpf;
+mod capabilities;
+mod cgroup;
+pub use capabilities::Capability;
+mod clock;
+mod console;
+pub use console::ConsoleMode;
+pub mod cmdline;
 pub mod command;
 pub use command::*;
+mod control;
+pub use control::CONTROL_SOCKET;
+mod credentials;
+mod deps;
+mod devices;
+pub mod discovery;
+pub mod emergency;
+mod eventlog;
+pub use eventlog::EVENT_LOG_PATH;
+mod eventloop;
+pub mod events;
+pub use events::{ReaperError, ReaperEvent};
+mod health;
+pub use health::HealthProbe;
+mod handle;
+pub use handle::ControlHandle;
+mod handover;
+pub use handover::{HANDOVER_PATH, STATE_PATH};
+mod heartbeat;
+pub use heartbeat::HEARTBEAT_PATH;
+pub mod inittab;
+mod inspect;
+mod journal;
+pub use journal::JOURNAL_PATH;
+pub mod json;
+mod lineage;
+pub mod lint;
+pub mod logfile;
+mod logmux;
+pub mod logrotate;
+pub mod logsink;
+pub mod lsm;
+pub use lsm::SecurityLabel;
+pub mod maintenance;
+pub use maintenance::{MaintenanceJob, MaintenanceTask};
+mod metrics;
+pub use metrics::METRICS_PATH;
+mod mounts;
+pub use mounts::Automount;
+mod namespaces;
+pub use namespaces::Namespace;
+pub mod notice;
+pub use notice::{NoticeFile, ShutdownNotice, SHUTDOWN_NOTICE_PATH};
+mod notify;
+pub use notify::NOTIFY_DIR;
+pub mod observer;
+pub use observer::ReaperObserver;
+mod orphan;
+pub use orphan::{KillMode, OrphanExemption, OrphanPolicy, UnkillableAction};
+#[cfg(feature = "pam")]
+mod pam;
+mod password;
+pub mod power;
+mod proc_connector;
+pub mod process;
+pub mod profile;
+pub mod readiness;
+mod restart;
+mod scrollback;
+mod seccomp;
+pub mod session;
+pub use restart::{FailureAction, RestartAction, RestartPolicy, SpawnHistory};
+pub use seccomp::SyscallFilter;
+pub mod signalmap;
+pub use signalmap::SignalAction;
+mod stats;
+pub use stats::ReaperStats;
+mod status;
+pub use status::{ResourceUsage, ServiceState, ServiceStatus, StopPolicy, Termination};
+mod sys;
+mod template;
+pub use template::ServiceTemplate;
+pub mod timer;
+pub use timer::{Schedule, TimerCommand};
+pub mod tmpfiles;
+mod tty;
+mod usage;
+pub use usage::UsageLimits;
+mod watch;
 
+/// A command which waits for its dependencies.
+struct HeldCommand<'a> {
+    cmd: PersistentCommand<'a>,
+    // not started until released by the operator, even if its dependencies are met
+    on_hold: bool,
+    // the unmet dependency which was last reported, and whether it failed
+    waiting_on: Option<(String, bool)>,
+    // the missing mount which was last reported
+    waiting_for_mount: Option<String>,
+    // the missing path which was last reported
+    waiting_for_path: Option<String>,
+}
+
+/// A command which is spawned once its pre-start hook exited.
+struct PreStarting<'a> {
+    cmd: PersistentCommand<'a>,
+    // how the previous process of the command exited, if it ran before
+    exit_reason: Option<Event>,
+    // when the hook is killed, until it was
+    deadline: Option<Instant>,
+}
+
+/// How long a service gets to exit after SIGTERM when shutting down, by default.
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long orphans get to exit after SIGTERM, before they are sent SIGKILL, by default.
+const DEFAULT_ORPHAN_KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many processes are reaped at most in one pass of the main loop, by default.
+const DEFAULT_REAP_BUDGET: usize = 256;
+
+/// How long reaping may take at most in one pass of the main loop, by default.
+const DEFAULT_REAP_TIME_BUDGET: Duration = Duration::from_millis(50);
+
+/// How long an orphan which lingers after SIGKILL, or which could not be signaled, is tracked
+/// before it is given up on.
+const ORPHAN_EXPIRY: Duration = Duration::from_secs(60);
+
+/// How many orphans are tracked at most. Beyond that the ones which are lingering the longest are
+/// given up on, so a storm of processes can't grow the memory of the reaper without limit.
+const MAX_TRACKED_ORPHANS: usize = 4096;
+
+/// How long a forking daemon gets to write its pid file after its parent exited.
+const PID_FILE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the pid file of a forking daemon is read again, while it is not written yet.
+const PID_FILE_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often processes which were recovered, but are not children of the reaper, are checked
+/// for having exited.
+const UNPARENTED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a one-shot command with a timeout is checked for having exited.
+const ONE_SHOT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How often the paths which held commands require are checked for having appeared.
+const REQUIRED_PATH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long spawning is paused after the system ran out of resources to spawn a process.
+const RESOURCE_PAUSE: Duration = Duration::from_secs(1);
+
+/// How often a service whose executable is missing checks whether it appeared.
+const EXECUTABLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the main loop waits at most before passing again, by default.
+const DEFAULT_TICK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The signals which are forwarded to the command supervised by [`Reaper::supervise_single`].
+///
+/// [`Reaper::supervise_single`]: struct.Reaper.html#method.supervise_single
+const FORWARDED_SIGNALS: [Signal; 5] = [
+    Signal::SIGTERM,
+    Signal::SIGINT,
+    Signal::SIGHUP,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+];
+
+/// How often the log files are checked for whether they are due to be rotated.
+const ROTATION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The signals which are trapped to be forwarded to the primary service, if there is one,
+/// besides the extra signals.
+const PRIMARY_SIGNALS: [Signal; 2] = [Signal::SIGHUP, Signal::SIGWINCH];
+
+/// A child process which exited and was reaped, with how it exited. Exactly one of the exit
+/// code and the signal is set.
 #[derive(Clone, Debug)]
-struct Carcass {
-    pid: Pid,
-    status: Option<i32>,
-    signal: Option<Signal>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Carcass {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "events::ser::pid"))]
+    pub pid: Pid,
+    /// The exit code, if the process exited by itself.
+    pub status: Option<i32>,
+    /// The signal which killed the process, if it was killed.
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "events::ser::optional_signal")
+    )]
+    pub signal: Option<Signal>,
 }
 
 impl fmt::Display for Carcass {
@@ -35,272 +263,5288 @@ impl fmt::Display for Carcass {
     }
 }
 
+/// What is left of a reaper once it is taken apart with [`Reaper::into_parts`].
+///
+/// [`Reaper::into_parts`]: struct.Reaper.html#method.into_parts
+#[derive(Debug, Clone)]
+pub struct ReaperParts {
+    /// The status of every service, by name.
+    pub services: HashMap<String, ServiceStatus>,
+    /// The pid of the process of every running service, by name.
+    pub pids: HashMap<String, Pid>,
+    /// The children the reaper knows of.
+    pub children: Vec<Pid>,
+    /// The orphans which are being terminated, or which linger.
+    pub orphans: Vec<Pid>,
+    pub stats: ReaperStats,
+}
+
+/// A change in the state of a child, as reported by waitpid.
+enum ChildEvent {
+    Exited(Carcass),
+    Stopped(Pid, Signal),
+    Continued(Pid),
+}
+
 /// reap executes waitpid, returning a zombie process ready to be reaped. This means it can't be
 /// used to wait for a specific pid to exit. If there is currently no zombie process, None is returned,
 /// else it returns a Carcass with information on how the process was terminated.
-fn reap() -> Option<Carcass> {
-    match waitpid(None, Some(WaitPidFlag::WNOHANG)).unwrap() {
-        WaitStatus::Exited(pid, st) => Some(Carcass {
+///
+/// This is for programs which reap their own children without a [`Reaper`], as a reaper which
+/// runs reaps everything itself.
+///
+/// [`Reaper`]: struct.Reaper.html
+pub fn reap() -> Result<Option<Carcass>, ReaperError> {
+    Ok(match wait_child(&SystemProcesses, WaitPidFlag::empty())? {
+        Some(ChildEvent::Exited(carcass)) => Some(carcass),
+        _ => None,
+    })
+}
+
+/// Like [`reap`], but children which were stopped or continued are reported as well if the
+/// flags ask for them, with WUNTRACED and WCONTINUED.
+///
+/// [`reap`]: fn.reap.html
+fn wait_child(
+    processes: &dyn ProcessOps,
+    flags: WaitPidFlag,
+) -> Result<Option<ChildEvent>, ReaperError> {
+    let status = match processes.wait(flags) {
+        // no children at all, e.g. because all commands are waiting to be respawned
+        Err(nix::Error::Sys(Errno::ECHILD)) => return Ok(None),
+        res => res.map_err(ReaperError::Wait)?,
+    };
+    Ok(match status {
+        WaitStatus::Exited(pid, st) => Some(ChildEvent::Exited(Carcass {
             pid,
             status: Some(st),
             signal: None,
-        }),
-        WaitStatus::Signaled(pid, sig, _) => Some(Carcass {
+        })),
+        WaitStatus::Signaled(pid, sig, _) => Some(ChildEvent::Exited(Carcass {
             pid,
             status: None,
             signal: Some(sig),
-        }),
+        })),
+        WaitStatus::Stopped(pid, sig) => Some(ChildEvent::Stopped(pid, sig)),
+        WaitStatus::Continued(pid) => Some(ChildEvent::Continued(pid)),
         WaitStatus::StillAlive => None,
         ws => {
             debug!("uninterpreted waitpid status: {:?}", ws);
             None
         }
+    })
+}
+
+/// The pid which addresses a whole process group when signaling it.
+fn group(pgid: Pid) -> Pid {
+    Pid::from_raw(-i32::from(pgid))
+}
+
+/// Verify that a process which was sent SIGKILL a while ago is gone, and remember it as
+/// unkillable if it is not. A zombie is as good as gone, it only waits to be reaped.
+fn verify_killed(unkillable: &mut HashMap<Pid, u64>, pid: Pid, stat: &ProcessStat) {
+    if stat.state == 'Z' || unkillable.contains_key(&pid) {
+        return;
     }
+    if stat.state == 'D' {
+        warn!(
+            "Process {} can't be killed, it is stuck in uninterruptible sleep (D state)",
+            pid
+        );
+    } else {
+        warn!(
+            "Process {} can't be killed, it is in state {}",
+            pid, stat.state
+        );
+    }
+    unkillable.insert(pid, stat.start_time);
 }
 
-/// List all children of the process by looping over the /proc directory and reading the stat
-/// entry. A child is identified as a process which has the given PID as 4th entry in the stat file
-/// in the process id directory.
-fn list_children(parent: Pid) -> Vec<Pid> {
-    read_dir("/proc")
-        .expect("unable to list /proc")
-        .filter_map(|rde| {
-            rde.ok().and_then(|de| {
-                de.file_name()
-                    .to_str()
-                    .and_then(|fname| str::parse(fname).ok())
-                    .map(|p| (de, Pid::from_raw(p)))
-            })
-        })
-        .filter_map(|(de, pid)| {
-            let mut path_buf = de.path();
-            path_buf.push("stat");
+/// Wait for the child to exit until the deadline, returning `None` if it didn't.
+fn wait_until(child: &mut Child, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        std::thread::sleep(ONE_SHOT_POLL_INTERVAL);
+    }
+}
 
-            let mut s = String::new();
-            let path = path_buf.as_path();
-            match File::open(path).and_then(|mut f| f.read_to_string(&mut s)) {
-                Ok(_) => {
-                    if let Some(r) = s.split_whitespace().nth(3) {
-                        match str::parse(r) {
-                            Ok(p) => Some((pid, Pid::from_raw(p))),
-                            _ => {
-                                warn!("unable to interpret field 4 in {:?}", path);
-                                None
-                            }
-                        }
-                    } else {
-                        warn!("unable to interpret {:?}", path);
-                        None
-                    }
-                }
-                Err(e) => {
-                    warn!("unable to read {:?}: {}", path, e);
-                    None
-                }
+/// The first path the command requires which does not exist yet.
+fn missing_path<'c>(cmd: &'c PersistentCommand) -> Option<&'c str> {
+    cmd.required_paths()
+        .iter()
+        .copied()
+        .find(|path| !std::path::Path::new(path).exists())
+}
+
+/// Take the lineage of processes from the parents of the processes in /proc, which misses the
+/// processes which were reparented already.
+fn scan_lineage(processes: &dyn ProcessOps) -> ProcessTree {
+    let mut tree = ProcessTree::default();
+    match processes.processes() {
+        Ok(pids) => {
+            for (pid, stat) in pids
+                .into_iter()
+                .filter_map(|pid| Some((pid, processes.stat(pid)?)))
+            {
+                tree.forked(stat.ppid, pid);
             }
-        })
-        .filter_map(|(pid, ppid)| if ppid == parent { Some(pid) } else { None })
-        .collect()
+        }
+        Err(e) => warn!("Failed to list processes: {}", e),
+    }
+    tree
+}
+
+/// Read the arguments of a single process, starting with its program, if it exists and is not
+/// a zombie.
+fn process_cmdline(pid: Pid) -> Option<Vec<String>> {
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args: Vec<String> = cmdline
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    (!args.is_empty()).then_some(args)
 }
 
 /// A process reaper
 ///
 /// # Use
 ///
-/// The `Reaper` traps SIGCHLD signals and uses these as an indicator that it potentially needs
+/// The `Reaper` receives SIGCHLD signals and uses these as an indicator that it potentially needs
 /// to reap a zombie. Upon reaping a zombie, the `Reaper` attempts to identify the children of the
 /// zombie and, based on the reason the zombie died, decides whether or not the orphans should be
-/// exterminated or not.
+/// exterminated or not. Persistent commands lead their own session, so a failed one is cleaned up
+/// by signaling its whole process group at once, which also catches processes forked meanwhile.
 ///
 /// It is possible to start the `Reaper` with a list of processes which should be kept alive,
 /// and revive them if necessary. A protected process' pid is tracked accross forks.
+///
+/// The `Reaper` does not have to run as PID 1, e.g. to supervise the services of a container
+/// or a session, but it then needs to be a [`child_subreaper`] to adopt orphans.
+///
+/// [`child_subreaper`]: struct.Reaper.html#method.child_subreaper
 pub struct Reaper<'a> {
     children: Vec<Pid>,
-    trap: Trap,
+    orphans: HashMap<Pid, OrphanState>,
+    // process groups of failed services which are being terminated, by process group id
+    orphan_groups: HashMap<Pid, OrphanState>,
+    // the process group led by the process of every persistent command
+    process_groups: HashMap<Pid, Pid>,
+    // the pidfds of the processes the reaper spawned for services, until they are reaped
+    pidfds: HashMap<Pid, PidFd>,
+    // the later stages of running pipelines, with the process of the command they belong to
+    pipeline_stages: HashMap<Pid, Pid>,
+    cgroups: Option<CgroupRoot>,
+    child_discovery: Option<Box<dyn ChildDiscovery>>,
+    // waits for, signals and lists processes, which are those of the system unless faked
+    processes: Box<dyn ProcessOps + 'a>,
+    // loads the services again on SIGHUP
+    config_loader: Option<Box<dyn FnMut() -> Vec<PersistentCommand<'a>> + 'a>>,
+    control_handle: Option<ControlHandle>,
+    // the start, stop and restart requests of the control handles
+    handle_requests: Option<HandleRequests>,
+    shutdown_notice: Option<Box<dyn ShutdownNotice + 'a>>,
+    observers: Vec<Box<dyn ReaperObserver + 'a>>,
+    shutdown_drain: Duration,
+    // set once shutting down, after which nothing is respawned anymore
+    shutting_down: bool,
+    notify_sockets: HashMap<String, NotifySocket>,
+    // the health checks of the running services, by name
+    health_monitors: HashMap<String, HealthMonitor>,
+    // the cgroup of every service, once it has been spawned
+    service_cgroups: HashMap<String, Cgroup>,
+    orphan_exemptions: Vec<OrphanExemption>,
+    events: EventLoop,
 
+    one_shots: Vec<OneShotCommand<'a>>,
+    shutdown_commands: Vec<OneShotCommand<'a>>,
+    // whether each one-shot command succeeded, by name
+    one_shot_results: HashMap<String, bool>,
+    // one-shot commands which run alongside the ones after them, with their name and when
+    // they are killed if they run for too long
+    background_one_shots: HashMap<Pid, (String, Option<Instant>)>,
+    // commands which are not spawned until their dependencies are running, in spawn order
+    held_commands: Vec<HeldCommand<'a>>,
+    // states of the system services can be ordered after, like network-online
+    pseudo_services: Vec<PseudoService<'a>>,
     persistent_commands_map: HashMap<Pid, PersistentCommand<'a>>,
+    delayed_commands: Vec<(Instant, PersistentCommand<'a>)>,
+    /// The service whose restart policy escalated its failure, if any.
+    escalated: Option<String>,
+    /// the service which failed permanently and asks for the system to go down, and how
+    power_request: Option<(String, PowerAction)>,
+    ctrl_alt_del: Option<PowerAction>,
+    // where the notification sockets and the handover are created, instead of /run/rsinit
+    runtime_dir: Option<String>,
+    // what to do on the signals which are mapped to an action, instead of the usual
+    signal_actions: Vec<(Signal, SignalAction<'a>)>,
+    /// Until when spawning is paused, after the system ran out of resources.
+    spawn_paused_until: Option<Instant>,
+    /// Forking daemons whose pid file is not written yet, with when their parent exited.
+    pid_file_waits: Vec<(Instant, PersistentCommand<'a>)>,
+    // commands without a running process, which can be started through the control socket
+    inactive_commands: HashMap<String, PersistentCommand<'a>>,
+    // commands which are not spawned until the password they asked for is given, by query id
+    awaiting_password: HashMap<u64, PersistentCommand<'a>>,
+    // commands which are spawned once their pre-start hook exited, by the pid of the hook
+    pre_starting: HashMap<Pid, PreStarting<'a>>,
+    // services which are being stopped, and must be started again once they exited
+    pending_starts: Vec<String>,
+    // journaled intents which complete once the process of the service exited
+    awaiting_exit: HashMap<String, Vec<u64>>,
+    services: HashMap<String, ServiceStatus>,
+    log_mux: Option<LogMultiplexer>,
+    scrollback: Option<Arc<Mutex<Scrollback>>>,
+    maintenance_jobs: Vec<MaintenanceJob>,
+    log_rotation: Option<LogRotation>,
+    // when the log files were last checked for rotation
+    rotation_checked: Option<Instant>,
+    timers: Vec<TimerCommand<'a>>,
+    /// the running hooks of services, with what they are run for
+    hooks: HashMap<Pid, String>,
+    control: Option<ControlSocket>,
+    passwords: PasswordAgent,
+    journal: Option<Journal>,
+    event_log: Option<EventLog>,
+    recovered: HashMap<String, Recovered>,
+    // the state handed over by the previous instance, which is adopted when spawning
+    handover: Handover,
+    // recovered processes which are not children of the reaper, with when they started
+    unparented: HashMap<Pid, u64>,
+    // the descendants of services which supervise their own processes, with the service
+    delegated: HashMap<Pid, String>,
+    boot_times: BootTimes,
+    // set once a re-exec is requested, which happens after the request is answered
+    reexec_requested: bool,
+    // where the state is snapshotted to, and how often
+    state_snapshots: Option<(String, Duration)>,
+    next_snapshot: Option<Instant>,
+    heartbeat: Option<Heartbeat>,
+    metrics: Option<MetricsFile>,
+    // how often each event happened, by its name
+    event_counts: BTreeMap<&'static str, u64>,
+    clock: Option<ClockWatch>,
+    stats: ReaperStats,
+    // when the main loop passes next, regardless of what wakes it up before
+    next_tick: Instant,
+    // what happened since the reaper was last stepped, once it is started for stepping
+    stepped_events: Option<Rc<RefCell<Vec<ReaperEvent>>>>,
+    usage: UsageMonitor,
+    console: ConsoleStatus,
+    shutdown_timeout: Duration,
+    helper_death_signal: Signal,
+    hold_all: bool,
+    // the boot target, if only the services which are part of it are started
+    target: Option<String>,
+    // the amount of services of a resource class which may be starting at the same time
+    start_limits: HashMap<String, usize>,
+    // the amount of services which may be starting at the same time, regardless of their class
+    max_starting: Option<usize>,
+    watcher: Option<FileWatcher>,
+    mounts: Option<MountTable>,
+    automounts: Vec<Automount>,
+    subreaper: bool,
+    nice: Option<i32>,
+    realtime_priority: Option<i32>,
+    lock_memory: bool,
+    tick_interval: Duration,
+    orphan_kill_timeout: Duration,
+    kill_orphans_on_failure: bool,
+    adopt_existing: bool,
+    reap_budget: usize,
+    reap_time_budget: Duration,
+    // reaping was cut short, and carries on in the next pass of the main loop
+    reap_deferred: bool,
+    unkillable_actions: Vec<UnkillableAction<'a>>,
+    // the processes which outlived SIGKILL, with their start time, as their pid may be reused
+    unkillable: HashMap<Pid, u64>,
+    // the amount of ticks in a row there have been unkillable processes for
+    unkillable_ticks: u32,
+    // what happens to stopped services, if stopped children are followed at all
+    stop_policy: Option<StopPolicy>,
+    #[cfg(feature = "bpf")]
+    process_events: Option<ProcessEvents>,
+    proc_connector: Option<ProcConnector>,
+    // the lineage of processes, if followed through process events
+    process_tree: ProcessTree,
 
     pid: Pid, // own process id
 }
 
+/// Builds a [`Reaper`] with non-default timing or signals. [`Reaper::new`] builds one with the
+/// defaults.
+///
+/// [`Reaper`]: struct.Reaper.html
+/// [`Reaper::new`]: struct.Reaper.html#method.new
+#[derive(Debug, Clone)]
+pub struct ReaperBuilder {
+    tick_interval: Duration,
+    orphan_kill_timeout: Duration,
+    extra_signals: Vec<Signal>,
+    kill_orphans_on_failure: bool,
+    adopt_existing: bool,
+    reap_budget: usize,
+    reap_time_budget: Duration,
+}
+
+impl Default for ReaperBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReaperBuilder {
+    pub fn new() -> Self {
+        ReaperBuilder {
+            tick_interval: DEFAULT_TICK_INTERVAL,
+            orphan_kill_timeout: DEFAULT_ORPHAN_KILL_TIMEOUT,
+            extra_signals: Vec::new(),
+            kill_orphans_on_failure: true,
+            adopt_existing: false,
+            reap_budget: DEFAULT_REAP_BUDGET,
+            reap_time_budget: DEFAULT_REAP_TIME_BUDGET,
+        }
+    }
+
+    /// Set how long the main loop waits at most before passing again, to e.g. write the
+    /// heartbeat. Defaults to 5 seconds.
+    pub fn tick_interval(mut self, interval: Duration) -> Self {
+        self.tick_interval = interval;
+        self
+    }
+
+    /// Set how long orphans get to exit after SIGTERM, before they are sent SIGKILL. Defaults
+    /// to 5 seconds.
+    pub fn term_to_kill_timeout(mut self, timeout: Duration) -> Self {
+        self.orphan_kill_timeout = timeout;
+        self
+    }
+
+    /// Trap the given signals as well, besides SIGCHLD, SIGINT, SIGTERM, SIGUSR1, SIGUSR2 and
+    /// SIGIO, so they don't terminate the reaper. They are ignored otherwise.
+    pub fn extra_signals(mut self, signals: &[Signal]) -> Self {
+        self.extra_signals.extend_from_slice(signals);
+        self
+    }
+
+    /// Set whether the orphans a failed process leaves behind are terminated. Defaults to true.
+    pub fn kill_orphans_on_failure(mut self, kill: bool) -> Self {
+        self.kill_orphans_on_failure = kill;
+        self
+    }
+
+    /// Set whether children which were running before the reaper spawned its services, e.g.
+    /// the processes started by the entrypoint of a container, are supervised as the process of
+    /// the service whose command they run, instead of starting another one. A process matches
+    /// if its arguments are those of the command, and its executable has the same name.
+    /// Defaults to false.
+    pub fn adopt_existing(mut self, adopt: bool) -> Self {
+        self.adopt_existing = adopt;
+        self
+    }
+
+    /// Set how many processes are reaped at most, and for how long, before the main loop gets
+    /// to the rest of its work, like escalating orphans and answering the control socket. What
+    /// is left is reaped in the next pass, so a fork bomb can't keep the reaper from doing
+    /// anything else. Defaults to 256 processes and 50 milliseconds.
+    pub fn reap_budget(mut self, processes: usize, time: Duration) -> Self {
+        self.reap_budget = processes.max(1);
+        self.reap_time_budget = time;
+        self
+    }
+
+    /// Create the [`Reaper`], which traps its signals right away. This must be called on the
+    /// main thread of the process, see [`Reaper::new`]. This fails if the signals can't be
+    /// trapped.
+    ///
+    /// [`Reaper`]: struct.Reaper.html
+    /// [`Reaper::new`]: struct.Reaper.html#method.new
+    pub fn build<'a>(self) -> Result<Reaper<'a>, ReaperError> {
+        let mut signals = vec![
+            Signal::SIGCHLD,
+            Signal::SIGINT,
+            Signal::SIGTERM,
+            Signal::SIGUSR1,
+            Signal::SIGUSR2,
+            Signal::SIGIO,
+        ];
+        signals.extend(self.extra_signals);
+        let events = EventLoop::new(&signals).map_err(ReaperError::EventLoop)?;
+        Ok(Reaper {
+            children: Vec::new(),
+            orphans: HashMap::new(),
+            orphan_groups: HashMap::new(),
+            process_groups: HashMap::new(),
+            pidfds: HashMap::new(),
+            pipeline_stages: HashMap::new(),
+            cgroups: None,
+            child_discovery: None,
+            processes: Box::new(SystemProcesses),
+            config_loader: None,
+            control_handle: None,
+            handle_requests: None,
+            shutdown_notice: None,
+            observers: Vec::new(),
+            shutdown_drain: Duration::from_secs(0),
+            shutting_down: false,
+            notify_sockets: HashMap::new(),
+            health_monitors: HashMap::new(),
+            service_cgroups: HashMap::new(),
+            orphan_exemptions: Vec::new(),
+            events,
+
+            one_shots: Vec::new(),
+            shutdown_commands: Vec::new(),
+            one_shot_results: HashMap::new(),
+            background_one_shots: HashMap::new(),
+            held_commands: Vec::new(),
+            pseudo_services: PseudoService::builtin(),
+            persistent_commands_map: HashMap::new(),
+            delayed_commands: Vec::new(),
+            escalated: None,
+            power_request: None,
+            ctrl_alt_del: None,
+            runtime_dir: None,
+            signal_actions: Vec::new(),
+            spawn_paused_until: None,
+            pid_file_waits: Vec::new(),
+            inactive_commands: HashMap::new(),
+            awaiting_password: HashMap::new(),
+            pre_starting: HashMap::new(),
+            pending_starts: Vec::new(),
+            awaiting_exit: HashMap::new(),
+            services: HashMap::new(),
+            log_mux: None,
+            scrollback: None,
+            maintenance_jobs: Vec::new(),
+            log_rotation: None,
+            rotation_checked: None,
+            timers: Vec::new(),
+            hooks: HashMap::new(),
+            control: None,
+            passwords: PasswordAgent::new(),
+            journal: None,
+            event_log: None,
+            recovered: HashMap::new(),
+            handover: Handover::default(),
+            unparented: HashMap::new(),
+            delegated: HashMap::new(),
+            boot_times: BootTimes::default(),
+            reexec_requested: false,
+            state_snapshots: None,
+            next_snapshot: None,
+            heartbeat: None,
+            metrics: None,
+            event_counts: BTreeMap::new(),
+            clock: None,
+            stats: ReaperStats::default(),
+            next_tick: Instant::now(),
+            stepped_events: None,
+            usage: UsageMonitor::new(UsageLimits::default()),
+            console: ConsoleStatus::new(ConsoleMode::Off),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            helper_death_signal: Signal::SIGTERM,
+            hold_all: false,
+            target: None,
+            start_limits: HashMap::new(),
+            max_starting: None,
+            watcher: None,
+            mounts: None,
+            automounts: Vec::new(),
+            subreaper: false,
+            nice: None,
+            realtime_priority: None,
+            lock_memory: false,
+            tick_interval: self.tick_interval,
+            orphan_kill_timeout: self.orphan_kill_timeout,
+            kill_orphans_on_failure: self.kill_orphans_on_failure,
+            adopt_existing: self.adopt_existing,
+            reap_budget: self.reap_budget,
+            reap_time_budget: self.reap_time_budget,
+            reap_deferred: false,
+            unkillable_actions: Vec::new(),
+            unkillable: HashMap::new(),
+            unkillable_ticks: 0,
+            stop_policy: None,
+            #[cfg(feature = "bpf")]
+            process_events: None,
+            proc_connector: None,
+            process_tree: ProcessTree::default(),
+
+            pid: getpid(),
+        })
+    }
+}
+
+/// Error of a builder method of the [`Reaper`] which failed, like [`journal`]. The reaper is
+/// handed back, so it can be used without what failed to be set up. It converts into the
+/// `io::Error` it holds, for `?`.
+///
+/// [`Reaper`]: struct.Reaper.html
+/// [`journal`]: struct.Reaper.html#method.journal
+pub struct SetupError<'a> {
+    pub reaper: Box<Reaper<'a>>,
+    pub error: io::Error,
+}
+
+impl<'a> fmt::Debug for SetupError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SetupError")
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> fmt::Display for SetupError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<'a> std::error::Error for SetupError<'a> {}
+
+impl<'a> From<SetupError<'a>> for io::Error {
+    fn from(e: SetupError<'a>) -> Self {
+        e.error
+    }
+}
+
+/// The file descriptor which becomes readable when the reaper has something to act on, for a
+/// loop which [steps] it.
+///
+/// [steps]: struct.Reaper.html#method.step
+impl<'a> AsRawFd for Reaper<'a> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.events.as_raw_fd()
+    }
+}
+
 impl<'a> Reaper<'a> {
     /// Create a new [`Reaper`].
     ///
     /// It is required that this method is called on the main thread of the process, as it
-    /// sets up a Trap which captures the SIGCHLD signal. The signal is captured as soon
-    /// as this function is called, even before the [`Reaper`] is [`spawned`].
+    /// blocks the SIGCHLD signal, to receive it through a signalfd instead. The signal is
+    /// captured as soon as this function is called, even before the [`Reaper`] is [`spawned`].
+    ///
+    /// This fails if the signals can't be trapped, e.g. when no signalfd can be created.
     ///
     /// [`Reaper`]: struct.Reaper.html
     /// [`spawned`]: struct.Reaper.html#method.spawn
-    pub fn new() -> Self {
-        Reaper {
-            children: Vec::new(),
-            trap: Trap::trap(&[SIGCHLD, SIGINT, SIGTERM]),
+    pub fn new() -> Result<Self, ReaperError> {
+        ReaperBuilder::new().build()
+    }
 
-            persistent_commands_map: HashMap::new(),
+    /// Start building a [`Reaper`] with non-default timing or signals.
+    ///
+    /// [`Reaper`]: struct.Reaper.html
+    pub fn builder() -> ReaperBuilder {
+        ReaperBuilder::new()
+    }
 
-            pid: getpid(),
+    /// Capture the output of all persistent commands, and write it to a single stream, with
+    /// every line prefixed by a timestamp and the name of the command. Commands which redirect
+    /// their output to a file or a terminal are not affected.
+    pub fn multiplex_output<W: Write + Send + 'static>(
+        self,
+        out: W,
+    ) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| reaper.output_to(Box::new(Prefixed(Box::new(out)))))
+    }
+
+    /// Capture the output of all persistent commands, like [`multiplex_output`], but send every
+    /// line to syslog, tagged with the name of the command and the pid of its process.
+    ///
+    /// [`multiplex_output`]: struct.Reaper.html#method.multiplex_output
+    pub fn syslog_output(self, syslog: Syslog) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| reaper.output_to(Box::new(syslog)))
+    }
+
+    /// Capture the output of all persistent commands, like [`multiplex_output`], and keep the
+    /// last `kib` KiB of output of every service in memory, to be queried with the `logs`
+    /// request on the control socket. This can be combined with other output.
+    ///
+    /// [`multiplex_output`]: struct.Reaper.html#method.multiplex_output
+    pub fn keep_output(self, kib: usize) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| {
+            let scrollback = Scrollback::shared(kib * 1024);
+            reaper.output_to(Box::new(scrollback.clone()))?;
+            reaper.scrollback = Some(scrollback);
+            Ok(())
+        })
+    }
+
+    /// Write the captured output to another sink, starting the multiplexer if this is the
+    /// first one.
+    fn output_to(&mut self, sink: Box<dyn LineSink>) -> io::Result<()> {
+        match &self.log_mux {
+            Some(mux) => mux.add_sink(sink),
+            None => self.log_mux = Some(LogMultiplexer::start(sink)?),
         }
+        Ok(())
     }
 
-    pub fn spawn(mut self, persistent_commands: Vec<PersistentCommand<'a>>) {
-        let _ = self.new_children(); // make sure we know children we obtained before spawning the reaper
-        for cmd in persistent_commands {
-            // rememmber name in case shit blows up
-            let cmd_name = format!("{}", cmd);
-            match self.spawn_persistent_command(cmd, None) {
-                Ok(_) => (),
-                Err(e) => {
-                    error!("Failed to spawn persistent command ({}): {}", cmd_name, e);
-                    // command is not inserted so its not remembered
-                }
-            }
+    /// Apply a part of the setup which can fail, handing the reaper back along with the error
+    /// if it does.
+    fn try_setup(
+        mut self,
+        setup: impl FnOnce(&mut Self) -> io::Result<()>,
+    ) -> Result<Self, SetupError<'a>> {
+        match setup(&mut self) {
+            Ok(()) => Ok(self),
+            Err(error) => Err(SetupError {
+                reaper: Box::new(self),
+                error,
+            }),
         }
-        let _ = self.new_children(); // make sure we know about these processes
+    }
 
-        loop {
-            // keep the outer loop for now, might want to move some runtime addition of cmds in
-            // here at a later stage
-            let deadline = Instant::now() + Duration::from_secs(5);
+    /// Run a command once at boot, before the persistent commands are spawned. One-shot
+    /// commands are run one after the other, in the order in which they were added, and a
+    /// failing one does not prevent the next ones from running.
+    pub fn one_shot(mut self, cmd: OneShotCommand<'a>) -> Self {
+        self.one_shots.push(cmd);
+        self
+    }
 
-            while let Some(signal) = self.trap.wait(deadline) {
-                trace!("Caught signal {:?}", signal);
-                match signal {
-                    SIGCHLD => {
-                        // received sigchld, try to get a carcass
-                        // a single signal can be used for multiple dead children, so keep reaping
-                        // untill we got them all. If this captures dead children from a subsequent
-                        // signal, then reaping will fail on that signal so no more action will be
-                        // taken.
-                        while let Some(carcass) = reap() {
-                            // got a dead process
-                            let event = match carcass {
-                                // if the process exited normally, i.e. exit code 0, everything is fine
-                                // if the process did not exit with 0, or it was signaled, kill all of its
-                                // children
-                                Carcass {
-                                    pid,
-                                    status: Some(0),
-                                    signal: _,
-                                } => {
-                                    info!(
-                                    "Reaped carcass of {}, exited with code 0, children can live",
-                                    pid
-                                );
-                                    Event::ExitSuccess
-                                }
-                                Carcass {
-                                    pid,
-                                    status: Some(code),
-                                    signal: _,
-                                } => {
-                                    info!(
-                                    "Reaped carcass of {}, exited with code {}, killing children",
-                                    pid, code
-                                );
-                                    Event::ExitCode
-                                }
-                                Carcass {
-                                    pid,
-                                    status: _,
-                                    signal: Some(sig),
-                                } => {
-                                    info!(
-                                        "Reaped {}, exited with signal {:?}, killing children",
-                                        pid, sig
-                                    );
-                                    Event::ExitSignal
-                                }
-                                _ => unreachable!(), // we always have either signal or status set
-                            };
-
-                            // get a list of children for this process
-                            // this also forgets the current carcass pid as a child
-                            let children = self.new_children();
-                            debug!("Reaped process has {} children", children.len());
-
-                            // see if the children need to be marked
-                            match event {
-                                Event::ExitCode | Event::ExitSignal => {
-                                    // Do we need to update the tracked processes here?
-                                }
-                                Event::ExitSuccess => {
-                                    // make sure forked processes have their pid updated
-                                    if children.len() > 0 {
-                                        self.update_ensured_process_pid(&carcass.pid, &children[0]);
-                                    }
-                                }
-                            }
+    /// Run a command when shutting down, once all services are stopped, e.g. to tear down
+    /// storage with `vgchange -an`. Shutdown commands are run one after the other, in the order
+    /// in which they were added, before the reaper returns and the filesystems are unmounted.
+    pub fn shutdown_command(mut self, cmd: OneShotCommand<'a>) -> Self {
+        self.shutdown_commands.push(cmd);
+        self
+    }
 
-                            if let Err(e) = self.ensure_process(&carcass.pid, Some(event)) {
-                                // for now just log failures
-                                match e {
-                                    PersistentCommandError::SpawnFailed(_) => {
-                                        error!("{}", e);
-                                    }
-                                    PersistentCommandError::SpawnLimitReached(_) => {
-                                        warn!("{}", e);
-                                    }
-                                    PersistentCommandError::MustNotRespawn(_) => {
-                                        info!("{}", e);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    s => debug!("Ignoring signal {:?}", s),
-                }
+    /// Follow children which are stopped by a signal, e.g. SIGSTOP or SIGTSTP, and continued
+    /// again, which are told to the observers. What happens to the process of a service which
+    /// is stopped is up to the policy, by default it is left stopped.
+    pub fn stopped_processes(mut self, policy: StopPolicy) -> Self {
+        self.stop_policy = Some(policy);
+        self
+    }
+
+    /// Never terminate matching processes when their parent fails.
+    pub fn exempt_orphans(mut self, exemption: OrphanExemption) -> Self {
+        self.orphan_exemptions.push(exemption);
+        self
+    }
+
+    /// Act on processes which are still around once they were given the
+    /// [`term_to_kill_timeout`] to exit after SIGKILL as well, besides logging them and counting
+    /// them in the stats. Processes which are stuck in uninterruptible sleep are told apart in
+    /// the log.
+    ///
+    /// [`term_to_kill_timeout`]: struct.ReaperBuilder.html#method.term_to_kill_timeout
+    pub fn on_unkillable(mut self, action: UnkillableAction<'a>) -> Self {
+        self.unkillable_actions.push(action);
+        self
+    }
+
+    /// Act on a signal the reaper receives, e.g. power off on SIGPWR, which a UPS daemon sends
+    /// when the power fails. This replaces what the reaper does on the signal otherwise, like
+    /// halting on SIGUSR1, and the action which was set for the signal before. SIGCHLD can't be
+    /// mapped, as the reaper reaps on it.
+    pub fn on_signal(mut self, signal: Signal, action: SignalAction<'a>) -> Self {
+        if signal == Signal::SIGCHLD {
+            warn!("Ignoring the action for SIGCHLD, which can't be mapped");
+            return self;
+        }
+        if let Err(e) = self.events.trap(&[signal]) {
+            warn!(
+                "Failed to trap {:?}, {:?} won't be acted on: {}",
+                signal, action, e
+            );
+            return self;
+        }
+        self.signal_actions.retain(|(mapped, _)| *mapped != signal);
+        self.signal_actions.push((signal, action));
+        self
+    }
+
+    /// Set how long every service gets to exit after SIGTERM when shutting down, before it is
+    /// sent SIGKILL.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Give the services the given time to finish their work when shutting down, after they
+    /// were warned and before they are sent SIGTERM, see [`shutdown_notice`]. Services which
+    /// exit on their own in the meantime are not restarted. Only services which were warned
+    /// are waited for. Defaults to no drain period.
+    ///
+    /// [`shutdown_notice`]: #method.shutdown_notice
+    pub fn shutdown_drain(mut self, drain: Duration) -> Self {
+        self.shutdown_drain = drain;
+        self
+    }
+
+    /// Warn every running service in the given way when shutting down, besides the signal a
+    /// service might ask for with [`PersistentCommand::shutdown_notice`], e.g. with a
+    /// [`NoticeFile`].
+    ///
+    /// [`PersistentCommand::shutdown_notice`]: struct.PersistentCommand.html#method.shutdown_notice
+    /// [`NoticeFile`]: notice/struct.NoticeFile.html
+    pub fn shutdown_notice(mut self, notice: impl ShutdownNotice + 'a) -> Self {
+        self.shutdown_notice = Some(Box::new(notice));
+        self
+    }
+
+    /// Tell the observer about the lifecycle of the services. Multiple observers can be added,
+    /// which are told in the order they were added in.
+    pub fn observer(mut self, observer: impl ReaperObserver + 'a) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Set the signal helper processes, such as one-shot commands, are sent if the reaper dies
+    /// while they are still running, so they never outlive it. Defaults to SIGTERM.
+    pub fn helper_death_signal(mut self, signal: Signal) -> Self {
+        self.helper_death_signal = signal;
+        self
+    }
+
+    /// Hold all persistent commands, as if they were all put on [`hold`], so nothing is started
+    /// until released through the control socket.
+    ///
+    /// [`hold`]: struct.PersistentCommand.html#method.hold
+    pub fn hold_all(mut self, hold: bool) -> Self {
+        self.hold_all = hold;
+        self
+    }
+
+    /// Only start the services which are part of the given boot target, see [`target`], and the
+    /// services they depend on. The other services can still be started through the control
+    /// socket, which can also switch to another target. Without a target, all services are
+    /// started.
+    ///
+    /// [`target`]: struct.PersistentCommand.html#method.target
+    pub fn target(mut self, name: &str) -> Self {
+        self.target = Some(name.to_string());
+        self
+    }
+
+    /// Add a pseudo-service with the given name, which services can depend on like on any other
+    /// service, and which is ready once the probe returns true. The probe runs every second
+    /// while a service waits for it. This replaces the probe of a pseudo-service with the same
+    /// name, like the built in [`NETWORK_ONLINE`], e.g. to wait for a specific interface. A
+    /// service with the same name takes precedence.
+    ///
+    /// [`NETWORK_ONLINE`]: readiness/constant.NETWORK_ONLINE.html
+    pub fn pseudo_service(mut self, name: &str, probe: impl Fn() -> bool + 'a) -> Self {
+        self.pseudo_services.retain(|pseudo| pseudo.name != name);
+        self.pseudo_services.push(PseudoService::new(name, probe));
+        self
+    }
+
+    /// Start at most limit services of the given [`resource_class`] at the same time, e.g. so
+    /// services which replay their journals at boot don't all compete for the same disk. A
+    /// service is starting from when it is spawned until it is ready. Services of the class are
+    /// held until one of the starting ones is ready or exits. Restarts are not limited.
+    ///
+    /// [`resource_class`]: struct.PersistentCommand.html#method.resource_class
+    pub fn start_limit(mut self, class: &str, limit: usize) -> Self {
+        self.start_limits.insert(class.to_string(), limit);
+        self
+    }
+
+    /// Start at most limit services at the same time, regardless of their [`resource_class`].
+    /// Like with [`start_limit`], a service is starting from when it is spawned until it is
+    /// ready, so this only holds back services while others which notify are starting.
+    /// Services which are ready to start together are spawned at once, with their pre-start
+    /// hooks running alongside each other.
+    ///
+    /// [`resource_class`]: struct.PersistentCommand.html#method.resource_class
+    /// [`start_limit`]: #method.start_limit
+    pub fn max_starting(mut self, limit: usize) -> Self {
+        self.max_starting = Some(limit.max(1));
+        self
+    }
+
+    /// Run the reaper at the given nice value, e.g. -10, so it keeps reaping and escalating when
+    /// the services keep the CPU busy. Children don't inherit a negative nice value, they start
+    /// at 0 unless they set their own.
+    pub fn nice(mut self, nice: i32) -> Self {
+        self.nice = Some(nice);
+        self
+    }
+
+    /// Run the reaper with the `SCHED_FIFO` realtime policy at the given priority, from 1 to
+    /// 99, so it always runs before the services when it has something to do. Children don't
+    /// inherit the policy.
+    pub fn realtime_priority(mut self, priority: i32) -> Self {
+        self.realtime_priority = Some(priority);
+        self
+    }
+
+    /// Lock the memory of the reaper, so it is never swapped out, and the reaper keeps
+    /// responding while the system is swapping heavily. Children don't inherit the lock.
+    pub fn lock_memory(mut self, lock: bool) -> Self {
+        self.lock_memory = lock;
+        self
+    }
+
+    /// Become a child subreaper, so orphaned descendants are reparented to the reaper instead
+    /// of to init, and can be reaped and terminated as usual. Only needed when the reaper is not
+    /// PID 1.
+    pub fn child_subreaper(self) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| {
+            sys::set_child_subreaper()?;
+            reaper.subreaper = true;
+            Ok(())
+        })
+    }
+
+    /// Follow every fork and exit in the system through BPF programs attached to the scheduler
+    /// tracepoints, so the orphans of a failed process are found however deep they are in its
+    /// process tree, even if they were reparented or started a session of their own.
+    /// Experimental, this requires Linux 5.8 or newer and a mounted tracefs.
+    #[cfg(feature = "bpf")]
+    pub fn process_events(self) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| {
+            let events = ProcessEvents::open()?;
+            reaper.events.watch(events.as_raw_fd())?;
+            reaper.process_events = Some(events);
+            Ok(())
+        })
+    }
+
+    /// Follow every fork and exit in the system through the proc connector of netlink, so the
+    /// processes an exited process left behind are known from its lineage, however deep they
+    /// are in its process tree, instead of scanning /proc for new children of the reaper. This
+    /// requires CAP_NET_ADMIN and the initial PID namespace; without them, /proc is scanned as
+    /// before.
+    pub fn proc_connector(mut self) -> Self {
+        let connector = ProcConnector::open().and_then(|connector| {
+            self.events.watch(connector.as_raw_fd())?;
+            Ok(connector)
+        });
+        match connector {
+            Ok(connector) => {
+                self.proc_connector = Some(connector);
+                // the processes which are forked from now on are reported, so none is missed
+                self.read_lineage();
             }
+            Err(e) => warn!(
+                "Failed to subscribe to the proc connector, scanning /proc: {}",
+                e
+            ),
         }
+        self
     }
 
-    /// get a list of all new children since the last time this method is called, and remember
-    /// all current children
-    fn new_children(&mut self) -> Vec<Pid> {
-        trace!("Finding children we don't know about yet");
+    /// Set what happens when Ctrl-Alt-Del is pressed, or SIGINT is received otherwise: all
+    /// services are stopped and `spawn` returns the action, or nothing happens if there is no
+    /// action. Defaults to no action. With an action, when the reaper is PID 1, it has the
+    /// kernel send SIGINT on Ctrl-Alt-Del, instead of restarting the system at once.
+    pub fn ctrl_alt_del(mut self, action: Option<PowerAction>) -> Self {
+        self.ctrl_alt_del = action;
+        self
+    }
 
-        let all_children = list_children(self.pid);
+    /// Warn when the resources the reaper itself uses cross the given thresholds. The usage is
+    /// recorded in the statistics every minute either way.
+    pub fn usage_limits(mut self, limits: UsageLimits) -> Self {
+        self.usage.set_limits(limits);
+        self
+    }
 
-        let new_children = all_children
-            .iter()
-            .filter(|p| !self.children.contains(p))
-            .map(|p| *p)
-            .collect();
+    /// Show a line per service on the console once it started while booting, or failed to,
+    /// like `[  OK  ] Started sshd (0.02s)`. Services are ready once they are spawned, unless
+    /// they notify when they are.
+    pub fn console_status(mut self, mode: ConsoleMode) -> Self {
+        self.console = ConsoleStatus::new(mode);
+        self
+    }
 
-        // remember the new children
-        self.children = all_children;
+    /// Periodically run a housekeeping job.
+    pub fn maintenance(mut self, job: MaintenanceJob) -> Self {
+        self.maintenance_jobs.push(job);
+        self
+    }
 
-        new_children
+    /// Rotate the files the output of the services is redirected to, and the log files of init
+    /// given to the rotation, once they grew too large or too old. They are checked every
+    /// minute.
+    pub fn rotate_logs(mut self, rotation: LogRotation) -> Self {
+        self.log_rotation = Some(rotation);
+        self
     }
 
-    fn spawn_persistent_command(
-        &mut self,
-        mut pcmd: PersistentCommand<'a>,
-        exit_reason: Option<Event>,
-    ) -> Result<(), PersistentCommandError> {
-        debug!("Spawning persistent command");
+    /// Run a command on a schedule, once the persistent commands are spawned.
+    pub fn timer(mut self, timer: TimerCommand<'a>) -> Self {
+        self.timers.push(timer);
+        self
+    }
 
-        let id = pcmd.spawn(exit_reason)?;
-        self.persistent_commands_map
-            .insert(Pid::from_raw(id as i32), pcmd);
+    /// Mount a filesystem only once a service which requires it is started.
+    pub fn automount(mut self, automount: Automount) -> Self {
+        self.automounts.push(automount);
+        self
+    }
 
-        Ok(())
+    /// Accept requests to list, start, stop and restart services on a Unix domain socket at the
+    /// given path, usually [`CONTROL_SOCKET`].
+    ///
+    /// [`CONTROL_SOCKET`]: constant.CONTROL_SOCKET.html
+    pub fn control_socket(self, path: &str) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| {
+            let control = ControlSocket::bind(path)?;
+            reaper.events.watch(control.as_raw_fd())?;
+            reaper.control = Some(control);
+            Ok(())
+        })
     }
 
-    fn ensure_process(
-        &mut self,
-        pid: &Pid,
-        event: Option<Event>,
-    ) -> Result<(), PersistentCommandError> {
-        if let Some(cmd) = self.persistent_commands_map.remove(pid) {
-            self.spawn_persistent_command(cmd, event)?;
+    /// Create the notification sockets of the services, and the handover when re-executing, in
+    /// the given directory instead of in `/run/rsinit`, e.g. in `$XDG_RUNTIME_DIR/rsinit` when
+    /// supervising a user session. The reaper should then [`resume`] from `handover` in it.
+    ///
+    /// [`resume`]: #method.resume
+    pub fn runtime_dir(mut self, dir: &str) -> Self {
+        self.runtime_dir = Some(dir.to_string());
+        self
+    }
+
+    /// The directory the notification sockets of the services are created in.
+    fn notify_dir(&self) -> String {
+        match &self.runtime_dir {
+            Some(dir) => format!("{}/notify", dir),
+            None => NOTIFY_DIR.to_string(),
         }
-        Ok(())
     }
 
-    fn update_ensured_process_pid(&mut self, pid: &Pid, new_pid: &Pid) {
-        if let Some(cmd) = self.persistent_commands_map.remove(pid) {
-            let _ = self.persistent_commands_map.insert(*new_pid, cmd);
+    /// Where the handover is written when re-executing.
+    fn handover_path(&self) -> String {
+        match &self.runtime_dir {
+            Some(dir) => format!("{}/handover", dir),
+            None => HANDOVER_PATH.to_string(),
+        }
+    }
+
+    /// Prompt for the passwords commands ask for on the console at the given path, e.g.
+    /// `/dev/console`. Passwords can always be given through the control socket as well.
+    pub fn password_console(self, path: &str) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| reaper.passwords.prompt_on(path))
+    }
+
+    /// Journal the start, stop and restart requests made through the control socket to the file
+    /// at the given path, usually [`JOURNAL_PATH`]. Requests recorded by a previous reaper are
+    /// rolled forward when the reaper is spawned, so services which were stopped stay stopped.
+    ///
+    /// [`JOURNAL_PATH`]: constant.JOURNAL_PATH.html
+    pub fn journal(self, path: &str) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| {
+            let (journal, recovered) = Journal::open(path)?;
+            reaper.journal = Some(journal);
+            reaper.recovered = recovered;
+            Ok(())
+        })
+    }
+
+    /// Adopt the running services from the handover at the given path, usually
+    /// [`HANDOVER_PATH`], which the previous reaper wrote before it re-executed itself, or from
+    /// a snapshot of its state, see [`snapshot_state`]. The processes which are still running
+    /// are supervised as if they were spawned by this reaper, and services which were stopped
+    /// stay stopped. The boot target is taken over as well. The handover is removed once it is
+    /// read. If there is none, or it can't be read, the services are started as usual.
+    ///
+    /// Processes which are no longer children of the reaper, e.g. because it was restarted
+    /// while not being PID 1, can't be reaped by it. Their exit is noticed by polling them, and
+    /// is handled as if they exited with code 0, as only their parent learns how they exited.
+    ///
+    /// [`HANDOVER_PATH`]: constant.HANDOVER_PATH.html
+    /// [`snapshot_state`]: struct.Reaper.html#method.snapshot_state
+    pub fn resume(mut self, path: &str) -> Self {
+        match Handover::take(path) {
+            Ok(handover) => {
+                if handover.target.is_some() {
+                    self.target = handover.target.clone();
+                }
+                self.handover = handover;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => error!("Failed to read the handover {}: {}", path, e),
+        }
+        self
+    }
+
+    /// Write the state of the reaper to the file at path, usually [`STATE_PATH`], every
+    /// interval, so a reaper which replaces it after a crash can adopt the running services with
+    /// [`resume`]. Only services which are [left running] outlive a crash of the reaper.
+    ///
+    /// [`STATE_PATH`]: constant.STATE_PATH.html
+    /// [`resume`]: struct.Reaper.html#method.resume
+    /// [left running]: struct.PersistentCommand.html#method.leave_running
+    pub fn snapshot_state(mut self, path: &str, interval: Duration) -> Self {
+        self.state_snapshots = Some((path.to_string(), interval));
+        self
+    }
+
+    /// Record what happens to services as JSON lines in the file at path, e.g. [`EVENT_LOG_PATH`],
+    /// with stable codes and fields for tooling, instead of the log messages meant for humans.
+    ///
+    /// [`EVENT_LOG_PATH`]: constant.EVENT_LOG_PATH.html
+    pub fn event_log(self, path: &str) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| {
+            reaper.event_log = Some(EventLog::open(path)?);
+            Ok(())
+        })
+    }
+
+    /// Record what happens to services as JSON lines, like [`event_log`], but on the given file
+    /// descriptor, e.g. a pipe set up by the program which started the reaper. The reaper takes
+    /// ownership of the file descriptor.
+    ///
+    /// [`event_log`]: struct.Reaper.html#method.event_log
+    pub fn event_log_fd(self, fd: RawFd) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| {
+            reaper.event_log = Some(EventLog::from_fd(fd)?);
+            Ok(())
+        })
+    }
+
+    /// Place every persistent command in a cgroup of its own, below the directory at the given
+    /// path on a cgroup v2 filesystem, e.g. `/sys/fs/cgroup/rsinit`. The processes of a service
+    /// are then found through its cgroup instead of by scanning /proc, and whatever a failed
+    /// service leaves behind is killed at once through `cgroup.kill`, without SIGTERM first.
+    pub fn cgroups(self, root: &str) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| {
+            reaper.cgroups = Some(CgroupRoot::create(root)?);
+            Ok(())
+        })
+    }
+
+    /// Find the processes an exited process left behind with the given strategy, instead of
+    /// through the cgroup of its service if it has one, or by scanning /proc otherwise.
+    pub fn child_discovery(mut self, discovery: impl ChildDiscovery + 'static) -> Self {
+        self.child_discovery = Some(Box::new(discovery));
+        self
+    }
+
+    /// Wait for, signal and list processes through the given operations instead of the system
+    /// calls and /proc, e.g. those of a [`FakeProcesses`] which a test acts out the processes
+    /// with. The processes of services are still spawned for real.
+    ///
+    /// [`FakeProcesses`]: process/struct.FakeProcesses.html
+    pub fn process_ops(mut self, processes: impl ProcessOps + 'a) -> Self {
+        self.processes = Box::new(processes);
+        self
+    }
+
+    /// Load the services again with the given function on SIGHUP, e.g. by reading their
+    /// configuration again. Services which were added are started as if they were passed to
+    /// [`spawn`], and services which were removed are stopped. Other changes to a service, like
+    /// its restart policy, apply from its next spawn on. Services are matched by name.
+    ///
+    /// [`spawn`]: #method.spawn
+    pub fn reload_with(mut self, loader: impl FnMut() -> Vec<PersistentCommand<'a>> + 'a) -> Self {
+        if let Err(e) = self.events.trap(&[Signal::SIGHUP]) {
+            warn!(
+                "Failed to trap SIGHUP, the configuration can't be reloaded: {}",
+                e
+            );
+        }
+        self.config_loader = Some(Box::new(loader));
+        self
+    }
+
+    /// Write the current time to the file at the given path, usually [`HEARTBEAT_PATH`], on
+    /// every pass of the main loop. The loop passes at least every tick interval, 5 seconds by
+    /// default, so a timestamp which is much older than that means the reaper is stuck.
+    ///
+    /// [`HEARTBEAT_PATH`]: constant.HEARTBEAT_PATH.html
+    pub fn heartbeat(self, path: &str) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| {
+            reaper.heartbeat = Some(Heartbeat::create(path)?);
+            Ok(())
+        })
+    }
+
+    /// Write the restarts, last exit code, uptime and whether it runs of every service, in the
+    /// text format of Prometheus, to the file at the given path, usually [`METRICS_PATH`], on
+    /// every pass of the main loop.
+    ///
+    /// [`METRICS_PATH`]: constant.METRICS_PATH.html
+    pub fn metrics_file(self, path: &str) -> Result<Self, SetupError<'a>> {
+        self.try_setup(|reaper| {
+            reaper.metrics = Some(MetricsFile::create(path)?);
+            Ok(())
+        })
+    }
+
+    /// Get the totals of the work done by the reaper so far.
+    pub fn stats(&self) -> ReaperStats {
+        ReaperStats {
+            tracked_children: self.children.len(),
+            tracked_orphans: self.orphans.len(),
+            ..self.stats
+        }
+    }
+
+    /// Get the status of the service with the given name.
+    pub fn status(&self, name: &str) -> Option<&ServiceStatus> {
+        self.services.get(name)
+    }
+
+    /// Get the status of all services, by name.
+    pub fn statuses(&self) -> &HashMap<String, ServiceStatus> {
+        &self.services
+    }
+
+    /// List the status of all services, ordered by name.
+    pub fn services(&self) -> Vec<(&str, &ServiceStatus)> {
+        let mut services: Vec<(&str, &ServiceStatus)> = self
+            .services
+            .iter()
+            .map(|(name, status)| (name.as_str(), status))
+            .collect();
+        services.sort_by_key(|(name, _)| *name);
+        services
+    }
+
+    /// List the status of the services in the given state, ordered by name.
+    pub fn services_in(&self, state: ServiceState) -> Vec<(&str, &ServiceStatus)> {
+        let mut services = self.services();
+        services.retain(|(_, status)| status.state() == state);
+        services
+    }
+
+    /// Get a handle on the status of the services and the statistics of the reaper, which stay
+    /// up to date while the services are supervised, e.g. for another thread to report on them.
+    /// The handle can also start, stop and restart services while the reaper runs.
+    pub fn control_handle(&mut self) -> ControlHandle {
+        if self.control_handle.is_none() {
+            let (handle, requests) = ControlHandle::new();
+            if let Some(fd) = requests.wake_fd() {
+                if let Err(e) = self.events.watch(fd) {
+                    warn!("Failed to watch the requests of the control handle: {}", e);
+                }
+            }
+            self.control_handle = Some(handle);
+            self.handle_requests = Some(requests);
+        }
+        let handle = self.control_handle.as_ref().unwrap();
+        handle.publish(self.services.iter(), self.stats());
+        handle.clone()
+    }
+
+    /// Run the one-shot commands, spawn the persistent commands and supervise them. Once SIGTERM,
+    /// SIGUSR1 or SIGUSR2 is received, all services are stopped, dependents before their
+    /// dependencies, after which this returns what the signal asks to do with the system, like
+    /// busybox init does: SIGTERM asks for a reboot, SIGUSR1 to halt and SIGUSR2 to power off.
+    /// SIGINT, which is what Ctrl-Alt-Del sends, asks for the [`ctrl_alt_del`] action.
+    /// Services which are left running are not stopped.
+    ///
+    /// Commands without an explicit name which would share their name with another service are
+    /// named `basename@hash` instead, where the hash is taken over their arguments, so they
+    /// can be told apart in the logs and through the control socket.
+    ///
+    /// An error is returned if supervising fails, e.g. because waiting for children fails. The
+    /// services are left as they are in that case.
+    ///
+    /// [`ctrl_alt_del`]: struct.Reaper.html#method.ctrl_alt_del
+    pub fn spawn(
+        mut self,
+        persistent_commands: Vec<PersistentCommand<'a>>,
+    ) -> Result<PowerAction, ReaperError> {
+        self.set_up(persistent_commands)?;
+        loop {
+            if let Some(action) = self.run_until(None)? {
+                return Ok(action);
+            }
+        }
+    }
+
+    /// Run the one-shot commands and spawn the persistent commands like [`spawn`] does, but
+    /// return once they are spawned, for a program with a loop of its own to supervise them
+    /// with [`step`].
+    ///
+    /// [`spawn`]: struct.Reaper.html#method.spawn
+    /// [`step`]: struct.Reaper.html#method.step
+    pub fn start(
+        &mut self,
+        persistent_commands: Vec<PersistentCommand<'a>>,
+    ) -> Result<(), ReaperError> {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let queue = Rc::clone(&events);
+        self.observers.push(Box::new(EventForwarder(move |event| {
+            queue.borrow_mut().push(event)
+        })));
+        self.stepped_events = Some(events);
+        self.set_up(persistent_commands)
+    }
+
+    /// Supervise the services which were [started] for the given time, and return what
+    /// happened to them meanwhile. A zero timeout only handles what is pending, so the reaper
+    /// can be stepped whenever its file descriptor becomes readable, and once [`next_step`] has
+    /// come. The signals which ask to shut down are handled like [`spawn`] handles them, after
+    /// which the last event tells what to do with the system.
+    ///
+    /// [started]: struct.Reaper.html#method.start
+    /// [`next_step`]: struct.Reaper.html#method.next_step
+    /// [`spawn`]: struct.Reaper.html#method.spawn
+    pub fn step(&mut self, timeout: Duration) -> Result<Vec<ReaperEvent>, ReaperError> {
+        let until = Instant::now() + timeout;
+        let mut action = None;
+        while action.is_none() {
+            action = self.run_until(Some(until))?;
+            if Instant::now() >= until {
+                break;
+            }
+        }
+        let mut events: Vec<ReaperEvent> = match &self.stepped_events {
+            Some(events) => events.borrow_mut().drain(..).collect(),
+            None => Vec::new(),
+        };
+        events.extend(action.map(ReaperEvent::ShutDown));
+        Ok(events)
+    }
+
+    /// When the reaper has to be [stepped] next at the latest, e.g. to kill an orphan which
+    /// ignored SIGTERM, if nothing wakes it up before.
+    ///
+    /// [stepped]: struct.Reaper.html#method.step
+    pub fn next_step(&self) -> Instant {
+        self.next_tick
+    }
+
+    /// Take the reaper apart, e.g. for a test to inspect what it did.
+    pub fn into_parts(self) -> ReaperParts {
+        ReaperParts {
+            stats: self.stats(),
+            pids: self
+                .persistent_commands_map
+                .iter()
+                .map(|(pid, cmd)| (cmd.service_name().to_string(), *pid))
+                .collect(),
+            services: self.services,
+            children: self.children,
+            orphans: self.orphans.keys().copied().collect(),
+        }
+    }
+
+    /// Run the one-shot commands and spawn the persistent commands.
+    fn set_up(
+        &mut self,
+        mut persistent_commands: Vec<PersistentCommand<'a>>,
+    ) -> Result<(), ReaperError> {
+        let startup_span = profile::span("reaper startup");
+        if i32::from(self.pid) != 1 && !self.subreaper {
+            warn!("Not running as PID 1 or as a child subreaper, orphans are adopted by init");
+        }
+        if i32::from(self.pid) == 1 && self.ctrl_alt_del.is_some() {
+            if let Err(e) = sys::disable_ctrl_alt_del() {
+                warn!("Failed to have Ctrl-Alt-Del handled by the reaper: {}", e);
+            }
+        }
+        self.apply_scheduling();
+        command::derive_names(&mut persistent_commands);
+        for lint in lint::check(&persistent_commands, &self.one_shots) {
+            warn!("{}", lint);
+        }
+        self.trap_primary_signals(&persistent_commands);
+        self.new_children()?; // make sure we know children we obtained before spawning the reaper
+        for pid in std::mem::take(&mut self.handover.orphans) {
+            if self.children.contains(&pid) {
+                self.track_orphan(pid, OrphanState::HasBeenSentSIGTERM(Instant::now()));
+            }
+        }
+        let one_shots = self.run_one_shots();
+        // the early mounts are done by now
+        logfile::release();
+        one_shots?;
+        boottimes::phase("one-shots finished");
+
+        let members = self.target.clone().map(|target| {
+            if !persistent_commands
+                .iter()
+                .any(|cmd| cmd.targets().contains(&target.as_str()))
+            {
+                warn!("No service is part of the {} target", target);
+            }
+            let members = target_members(persistent_commands.iter(), &target);
+            (target, members)
+        });
+
+        let (ordered, cyclic) = {
+            let nodes: Vec<(&str, Vec<&str>)> = persistent_commands
+                .iter()
+                .map(|cmd| {
+                    let deps = cmd.dependencies().map(|(name, _)| name).collect();
+                    (cmd.service_name(), deps)
+                })
+                .collect();
+            deps::order(&nodes)
+        };
+        let mut persistent_commands: Vec<Option<PersistentCommand<'a>>> =
+            persistent_commands.into_iter().map(Some).collect();
+        for idx in cyclic {
+            if let Some(cmd) = persistent_commands[idx].take() {
+                error!(
+                    "{} is part of, or depends on, a dependency cycle, not starting it",
+                    cmd.service_name()
+                );
+                self.services
+                    .insert(cmd.service_name().to_string(), ServiceStatus::default());
+                self.inactive_commands
+                    .insert(cmd.service_name().to_string(), cmd);
+            }
+        }
+
+        for cmd in ordered
+            .into_iter()
+            .filter_map(|idx| persistent_commands[idx].take())
+        {
+            if self.services.contains_key(cmd.service_name()) {
+                warn!(
+                    "Multiple services are named {}, their status will be mixed up",
+                    cmd.service_name()
+                );
+            }
+            self.services
+                .insert(cmd.service_name().to_string(), ServiceStatus::default());
+
+            self.watch_files(&cmd);
+            if !cmd.required_mounts().is_empty() {
+                self.track_mounts();
+            }
+
+            if let Some(recovered) = self.recovered.remove(cmd.service_name()) {
+                if recovered.intent == Intent::Stop {
+                    self.resume_stop(cmd, recovered);
+                    continue;
+                }
+            }
+
+            let cmd = match self.handover.services.remove(cmd.service_name()) {
+                Some(handed) => match self.adopt(cmd, handed) {
+                    Some(cmd) => cmd,
+                    None => continue,
+                },
+                None if self.adopt_existing => match self.adopt_existing(cmd) {
+                    Some(cmd) => cmd,
+                    None => continue,
+                },
+                None => cmd,
+            };
+
+            if let Some((target, members)) = &members {
+                if !members.contains(cmd.service_name()) {
+                    info!(
+                        "Not starting {}, it is not part of the {} target",
+                        cmd.service_name(),
+                        target
+                    );
+                    self.inactive_commands
+                        .insert(cmd.service_name().to_string(), cmd);
+                    continue;
+                }
+            }
+
+            let on_hold = self.hold_all || cmd.is_held();
+            if on_hold {
+                info!("Holding {} until it is released", cmd.service_name());
+            }
+            self.held_commands.push(HeldCommand {
+                cmd,
+                on_hold,
+                waiting_on: None,
+                waiting_for_mount: None,
+                waiting_for_path: None,
+            });
+        }
+        self.release_held_commands();
+        self.new_children()?; // make sure we know about these processes
+
+        let clock = ClockWatch::new().and_then(|clock| {
+            self.events.watch(clock.as_raw_fd())?;
+            Ok(clock)
+        });
+        match clock {
+            Ok(clock) => self.clock = Some(clock),
+            Err(e) => warn!("Failed to watch the system clock for jumps: {}", e),
+        }
+
+        drop(startup_span);
+        profile::log_report();
+        boottimes::phase("services spawned");
+
+        let now = Instant::now();
+        for job in &mut self.maintenance_jobs {
+            job.schedule(now);
+        }
+        for timer in &mut self.timers {
+            timer.schedule(now);
+        }
+        self.next_tick = now;
+        Ok(())
+    }
+
+    /// Supervise the services until the given time, or until the next tick if there is no
+    /// time given, which ends early once the services were stopped to shut down.
+    fn run_until(&mut self, until: Option<Instant>) -> Result<Option<PowerAction>, ReaperError> {
+        let now = Instant::now();
+        if now >= self.next_tick {
+            self.tick(now)?;
+            self.next_tick = self.next_deadline(now);
+        }
+        let limit = |deadline: Instant| until.map_or(deadline, |until| until.min(deadline));
+        while let Some(wakeup) = self.events.wait(limit(self.next_tick)) {
+            if let Some(action) = self.handle_wakeup(wakeup)? {
+                return Ok(Some(action));
+            }
+            // only ever wake up earlier, e.g. to kill an orphan which was just signaled
+            self.next_tick = self.next_tick.min(self.next_deadline(Instant::now()));
+            // the rest of the loop gets its turn before reaping carries on
+            if self.reap_deferred {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Do what is due every time the main loop passes.
+    fn tick(&mut self, now: Instant) -> Result<(), ReaperError> {
+        self.stats.loop_iterations += 1;
+        // no SIGCHLD is coming for what the last pass left to reap
+        if self.reap_deferred {
+            self.reap_zombies()?;
+            if let Some(name) = self.escalated.take() {
+                self.shutdown();
+                return Err(ReaperError::Escalated(name));
+            }
+        }
+        if let Some(heartbeat) = &self.heartbeat {
+            heartbeat.beat();
+        }
+        for job in &mut self.maintenance_jobs {
+            job.run_if_due(now);
+        }
+        self.run_due_timers(now);
+        self.rotate_logs_if_due(now);
+        self.kill_overdue_one_shots(now);
+        self.kill_overdue_pre_starts(now);
+        if let Some(control) = &mut self.control {
+            control.expire(now);
+        }
+        self.spawn_delayed_commands(now);
+        self.read_pid_files(now);
+        self.check_watchdogs(now);
+        self.check_stopped(now);
+        self.check_unparented();
+        self.track_delegated();
+        self.record_lineage();
+        self.check_health(now);
+        self.sample_usage();
+        self.escalate_orphans(now);
+        let reboot = self.check_unkillable();
+        self.unmount_idle(now);
+        self.release_held_commands();
+        self.handle_password_answers();
+        self.handle_file_changes(now);
+        self.answer_handle_requests();
+        self.usage.check_if_due(now, &mut self.stats);
+        self.console.draw_spinner(now);
+        self.publish_statuses();
+        if let Some(metrics) = &self.metrics {
+            metrics.write(&self.services, &self.stats, &self.event_counts);
+        }
+        self.snapshot_if_due(now);
+        if let Some((name, action)) = self.power_request.take() {
+            self.shutdown();
+            return Err(ReaperError::PermanentFailure(name, action));
+        }
+        if reboot {
+            let mut pids: Vec<Pid> = self.unkillable.keys().cloned().collect();
+            pids.sort_by_key(|pid| i32::from(*pid));
+            self.shutdown();
+            return Err(ReaperError::Unkillable(pids));
+        }
+        Ok(())
+    }
+
+    /// Act on what the main loop was woken up for. Returns what to do with the system once
+    /// the services were stopped to shut down.
+    fn handle_wakeup(&mut self, wakeup: Wakeup) -> Result<Option<PowerAction>, ReaperError> {
+        match wakeup {
+            Wakeup::Signal(signal) => {
+                trace!("Caught signal {:?}", signal);
+                self.stats.signals_handled += 1;
+                // log files are rotated with SIGHUP, like those of syslog daemons
+                if signal == Signal::SIGHUP {
+                    logfile::reopen();
+                }
+                let mapped = self
+                    .signal_actions
+                    .iter()
+                    .find(|(mapped, _)| *mapped == signal)
+                    .map(|(_, action)| *action);
+                if let Some(action) = mapped {
+                    return Ok(self.act_on_signal(signal, action));
+                }
+                match signal {
+                    Signal::SIGCHLD => {
+                        self.reap_zombies()?;
+                        if let Some(name) = self.escalated.take() {
+                            self.shutdown();
+                            return Err(ReaperError::Escalated(name));
+                        }
+                    }
+                    // how shutdown is requested follows the convention of busybox
+                    Signal::SIGTERM => {
+                        self.shutdown();
+                        return Ok(Some(PowerAction::Reboot));
+                    }
+                    Signal::SIGUSR1 => {
+                        self.shutdown();
+                        return Ok(Some(PowerAction::Halt));
+                    }
+                    Signal::SIGUSR2 => {
+                        self.shutdown();
+                        return Ok(Some(PowerAction::PowerOff));
+                    }
+                    // Ctrl-Alt-Del, once the kernel is told to send it
+                    Signal::SIGINT => match self.ctrl_alt_del {
+                        Some(action) => {
+                            info!("Ctrl-Alt-Del pressed, performing {:?}", action);
+                            self.shutdown();
+                            return Ok(Some(action));
+                        }
+                        None => debug!("Ignoring Ctrl-Alt-Del"),
+                    },
+                    // raised by the password prompt when a password was entered
+                    Signal::SIGIO => self.handle_password_answers(),
+                    Signal::SIGHUP if self.config_loader.is_some() => self.reload_config(),
+                    s => self.forward_to_primary(s),
+                }
+            }
+            Wakeup::Readable(fd) => {
+                if self.control.as_ref().map(AsRawFd::as_raw_fd) == Some(fd) {
+                    self.accept_control_clients();
+                } else if self
+                    .control
+                    .as_ref()
+                    .is_some_and(|control| control.is_incoming(fd))
+                {
+                    self.handle_control_request(fd);
+                } else if self.watcher.as_ref().map(AsRawFd::as_raw_fd) == Some(fd) {
+                    self.handle_file_changes(Instant::now());
+                } else if self.mounts.as_ref().map(AsRawFd::as_raw_fd) == Some(fd) {
+                    if let Err(e) = self.mounts.as_mut().unwrap().refresh() {
+                        warn!("Failed to read the mount table: {}", e);
+                    }
+                } else if self.clock.as_ref().map(AsRawFd::as_raw_fd) == Some(fd) {
+                    self.handle_clock_jump();
+                } else if self
+                    .notify_sockets
+                    .values()
+                    .any(|socket| socket.as_raw_fd() == fd)
+                {
+                    self.handle_notifications(fd);
+                } else if self
+                    .handle_requests
+                    .as_ref()
+                    .and_then(HandleRequests::wake_fd)
+                    == Some(fd)
+                {
+                    self.answer_handle_requests();
+                } else {
+                    self.handle_process_events();
+                }
+            }
+        }
+        if let Some((name, action)) = self.power_request.take() {
+            self.shutdown();
+            return Err(ReaperError::PermanentFailure(name, action));
+        }
+        if self.reexec_requested {
+            self.reexec_requested = false;
+            self.reexec();
+        }
+        // a dependency might have been started
+        self.release_held_commands();
+        // the last service using an automount might have exited
+        self.unmount_idle(Instant::now());
+        self.publish_statuses();
+        Ok(None)
+    }
+
+    /// Run a single command as the only service, like the init of a container: SIGTERM, SIGINT,
+    /// SIGHUP, SIGUSR1 and SIGUSR2 are forwarded to it, and the zombies of any process are
+    /// reaped. Once the command exited, this returns its exit code, or 128 plus the number of the
+    /// signal which killed it, to be passed on as the exit code of the reaper. An error is
+    /// returned if waiting for the command fails.
+    ///
+    /// The one-shot commands are run before the command is spawned. The control socket is not
+    /// used, as there are no services to control.
+    pub fn supervise_single(mut self, mut cmd: Command) -> Result<i32, ReaperError> {
+        self.control = None;
+        self.apply_scheduling();
+        if let Err(e) = self.events.trap(&FORWARDED_SIGNALS) {
+            warn!("Failed to trap the signals to forward: {}", e);
+        }
+        let one_shots = self.run_one_shots();
+        logfile::release();
+        one_shots?;
+
+        let parent = i32::from(self.pid);
+        unsafe {
+            cmd.pre_exec(sys::unblock_signals);
+            cmd.pre_exec(move || sys::signal_on_parent_death(libc::SIGKILL, parent));
+        }
+        let pid = match cmd.spawn() {
+            Ok(child) => Pid::from_raw(child.id() as i32),
+            Err(e) => {
+                error!("Failed to spawn {:?}: {}", cmd, e);
+                // what shells exit with when a command can't be run
+                return Ok(127);
+            }
+        };
+
+        loop {
+            self.stats.loop_iterations += 1;
+            if let Some(heartbeat) = &self.heartbeat {
+                heartbeat.beat();
+            }
+            let deadline = Instant::now() + self.tick_interval;
+            while let Some(wakeup) = self.events.wait(deadline) {
+                let signal = match wakeup {
+                    Wakeup::Signal(signal) => signal,
+                    Wakeup::Readable(_) => continue,
+                };
+                trace!("Caught signal {:?}", signal);
+                self.stats.signals_handled += 1;
+                if signal == Signal::SIGCHLD {
+                    while let Some(carcass) = reap()? {
+                        self.stats.zombies_reaped += 1;
+                        if carcass.pid != pid {
+                            continue;
+                        }
+                        info!("{:?} exited {}", cmd, carcass);
+                        return Ok(match (carcass.status, carcass.signal) {
+                            (Some(code), _) => code,
+                            (None, Some(signal)) => 128 + signal as i32,
+                            (None, None) => unreachable!(),
+                        });
+                    }
+                } else if FORWARDED_SIGNALS.contains(&signal) {
+                    debug!("Forwarding {:?} to {}", signal, pid);
+                    if let Err(e) = self.processes.kill(pid, Some(signal)) {
+                        warn!("Failed to forward {:?} to {}: {}", signal, pid, e);
+                    }
+                } else {
+                    debug!("Ignoring signal {:?}", signal);
+                }
+            }
+        }
+    }
+
+    /// When the main loop must act next: to run a maintenance job, spawn a delayed command,
+    /// kill an orphan which ignored SIGTERM or a service which missed its watchdog, act on
+    /// changed files, unmount an idle automount or record its own resource usage. The loop
+    /// passes at least every tick interval regardless, to keep the heartbeat going.
+    fn next_deadline(&self, now: Instant) -> Instant {
+        let orphan_kills = self
+            .orphans
+            .values()
+            .chain(self.orphan_groups.values())
+            .map(|state| match state {
+                OrphanState::HasBeenSentSIGTERM(at) => *at + self.orphan_kill_timeout,
+                // lingering orphans are given up on after a while
+                state => state.since() + ORPHAN_EXPIRY,
+            });
+        self.maintenance_jobs
+            .iter()
+            .filter_map(MaintenanceJob::next_run)
+            .chain(self.timers.iter().filter_map(TimerCommand::next_run))
+            .chain(self.delayed_commands.iter().map(|(at, _)| *at))
+            .chain(self.background_one_shots.values().filter_map(|(_, at)| *at))
+            .chain(self.pre_starting.values().filter_map(|pre| pre.deadline))
+            .chain(self.control.as_ref().and_then(ControlSocket::next_expiry))
+            .chain(
+                self.pid_file_waits
+                    .first()
+                    .map(|_| now + PID_FILE_RETRY_INTERVAL),
+            )
+            .chain(orphan_kills)
+            .chain(self.watchdog_expiries().into_iter().map(|(_, at)| at))
+            .chain(self.stop_expiries().into_iter().map(|(_, at)| at))
+            .chain(self.health_monitors.values().map(HealthMonitor::next_due))
+            .chain(self.watcher.as_ref().and_then(FileWatcher::next_due))
+            .chain(self.automounts.iter().filter_map(Automount::next_unmount))
+            .chain(self.usage.next_check())
+            .chain(self.console.next_draw())
+            .chain(self.next_snapshot)
+            .chain(Some(now + UNPARENTED_POLL_INTERVAL).filter(|_| !self.unparented.is_empty()))
+            .chain(Some(now).filter(|_| self.reap_deferred))
+            .chain(Some(now + REQUIRED_PATH_POLL_INTERVAL).filter(|_| {
+                self.held_commands
+                    .iter()
+                    .any(|held| held.waiting_for_path.is_some())
+            }))
+            .chain(
+                self.pseudo_services
+                    .iter()
+                    .filter(|pseudo| {
+                        self.held_commands.iter().any(|held| {
+                            held.waiting_on
+                                .as_ref()
+                                .is_some_and(|(dep, _)| *dep == pseudo.name)
+                        })
+                    })
+                    .filter_map(PseudoService::next_probe),
+            )
+            .fold(now + self.tick_interval, Instant::min)
+    }
+
+    /// Stop all running services, dependents before their dependencies. Services which were
+    /// not started yet are never started.
+    fn shutdown(&mut self) {
+        info!("Shutting down");
+        self.shutting_down = true;
+        self.record(ReaperEvent::ShutdownStarted);
+        let _span = profile::span("shutdown");
+
+        // control requests and file changes are no longer acted upon
+        self.control = None;
+        self.watcher = None;
+
+        self.delayed_commands.clear();
+        self.pid_file_waits.clear();
+        self.health_monitors.clear();
+        self.held_commands.clear();
+        for pid in self.timers.iter().filter_map(TimerCommand::running) {
+            if let Err(e) = self.processes.kill(pid, Some(Signal::SIGTERM)) {
+                warn!("Failed to stop timer command {}: {}", pid, e);
+            }
+        }
+        self.timers.clear();
+        self.awaiting_password.clear();
+        for (pid, pre) in self.pre_starting.drain() {
+            if let Err(e) = self.processes.kill(pid, Some(Signal::SIGTERM)) {
+                warn!(
+                    "Failed to stop pre-start hook of {}: {}",
+                    pre.cmd.service_name(),
+                    e
+                );
+            }
+        }
+        self.pending_starts.clear();
+
+        let names: Vec<String> = {
+            let running: Vec<&PersistentCommand> = self
+                .persistent_commands_map
+                .values()
+                .filter(|cmd| !cmd.leaves_running())
+                .collect();
+            let nodes: Vec<(&str, Vec<&str>)> = running
+                .iter()
+                .map(|cmd| {
+                    let deps = cmd.dependencies().map(|(name, _)| name).collect();
+                    (cmd.service_name(), deps)
+                })
+                .collect();
+            let (mut ordered, cyclic) = deps::order(&nodes);
+            ordered.extend(cyclic);
+            ordered
+                .into_iter()
+                .rev()
+                .map(|idx| running[idx].service_name().to_string())
+                .collect()
+        };
+
+        self.warn_of_shutdown(&names);
+        for name in names {
+            self.stop_for_shutdown(&name);
+        }
+        info!("All services are stopped");
+        self.publish_statuses();
+        // a later reaper has nothing to adopt
+        if let Some((path, _)) = &self.state_snapshots {
+            if let Err(e) = std::fs::remove_file(path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Failed to remove the state snapshot {}: {}", path, e);
+                }
+            }
+        }
+        self.notify_sockets.clear();
+        for automount in &mut self.automounts {
+            automount.unmount();
+        }
+        for cmd in std::mem::take(&mut self.shutdown_commands) {
+            self.run_one_shot(&cmd);
+        }
+    }
+
+    /// Update the status of the services for their control handles.
+    fn publish_statuses(&self) {
+        if let Some(handle) = &self.control_handle {
+            handle.publish(self.services.iter(), self.stats());
+        }
+    }
+
+    /// Warn the services that they are about to be stopped, and give those which were warned
+    /// the drain period to finish their work and exit.
+    fn warn_of_shutdown(&mut self, names: &[String]) {
+        let drain = self.shutdown_drain;
+        let mut warned = Vec::new();
+        for name in names {
+            let pid = match self.service_pid(name) {
+                Some(pid) => pid,
+                None => continue,
+            };
+            let mut notified = false;
+            // only the process itself, the rest of its group might not expect the signal
+            if let Some(signal) = self.persistent_commands_map[&pid].notice_signal() {
+                match self.signal_process(pid, signal) {
+                    Ok(_) => notified = true,
+                    Err(e) => warn!("Failed to send {:?} to {}: {}", signal, name, e),
+                }
+            }
+            if let Some(notice) = &mut self.shutdown_notice {
+                match notice.notify(name, pid, drain) {
+                    Ok(_) => notified = true,
+                    Err(e) => warn!("Failed to warn {} of the shutdown: {}", name, e),
+                }
+            }
+            if notified {
+                warned.push(name.as_str());
+            }
+        }
+        if warned.is_empty() || drain == Duration::from_secs(0) {
+            return;
+        }
+
+        info!(
+            "Giving {} services {:?} to finish their work",
+            warned.len(),
+            drain
+        );
+        let deadline = Instant::now() + drain;
+        for name in warned {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if !self.wait_for_exit(name, left) {
+                debug!("{} is still running after the drain period", name);
+            }
+        }
+    }
+
+    /// Stop a service and wait for it to exit, sending SIGKILL if it takes too long.
+    fn stop_for_shutdown(&mut self, name: &str) {
+        let pid = match self.service_pid(name) {
+            Some(pid) => pid,
+            None => return,
+        };
+        if let Some(cmd) = self.persistent_commands_map.remove(&pid) {
+            self.inactive_commands.insert(name.to_string(), cmd);
+        }
+
+        info!("Stopping {}", name);
+        if let Err(e) = self.signal_service(pid, Signal::SIGTERM) {
+            warn!("Failed to send SIGTERM to {}: {}", name, e);
+            return;
+        }
+        if self.wait_for_exit(name, self.shutdown_timeout) {
+            return;
+        }
+        warn!("{} did not stop in time, sending SIGKILL", name);
+        if let Err(e) = self.signal_service(pid, Signal::SIGKILL) {
+            warn!("Failed to send SIGKILL to {}: {}", name, e);
+            return;
+        }
+        if !self.wait_for_exit(name, self.shutdown_timeout) {
+            warn!("{} is lingering after SIGKILL", name);
+        }
+    }
+
+    /// Perform the action a signal is mapped to, returning what to do with the system if the
+    /// action shuts it down.
+    fn act_on_signal(&mut self, signal: Signal, action: SignalAction<'a>) -> Option<PowerAction> {
+        match action {
+            SignalAction::Restart(name) => {
+                info!("Caught {:?}, restarting {}", signal, name);
+                if let Err(e) = self.restart_service(name) {
+                    warn!("Failed to restart {} on {:?}: {}", name, signal, e);
+                }
+            }
+            SignalAction::RunCommand(action) => {
+                let hook = command::hook(action);
+                info!("Caught {:?}, running {}", signal, hook);
+                self.spawn_hook(&hook, format!("action on {:?}", signal));
+            }
+            SignalAction::Power(action) => {
+                info!("Caught {:?}, performing {:?}", signal, action);
+                self.shutdown();
+                return Some(action);
+            }
+            SignalAction::Reload => self.reload_config(),
+        }
+        None
+    }
+
+    /// Trap the signals which are forwarded to the primary service, if there is one among the
+    /// commands.
+    fn trap_primary_signals(&mut self, commands: &[PersistentCommand]) {
+        if !commands.iter().any(PersistentCommand::is_primary) {
+            return;
+        }
+        if let Err(e) = self.events.trap(&PRIMARY_SIGNALS) {
+            warn!(
+                "Failed to trap the signals forwarded to the primary service: {}",
+                e
+            );
+        }
+    }
+
+    /// Forward a signal which means nothing to the reaper to the primary service, if it runs.
+    fn forward_to_primary(&self, signal: Signal) {
+        let primary = self
+            .persistent_commands_map
+            .iter()
+            .find(|(_, cmd)| cmd.is_primary());
+        let (pid, name) = match primary {
+            Some((pid, cmd)) => (*pid, cmd.service_name()),
+            None => {
+                debug!("Ignoring signal {:?}", signal);
+                return;
+            }
+        };
+        debug!("Forwarding {:?} to {} ({})", signal, name, pid);
+        if let Err(e) = self.signal_service(pid, signal) {
+            warn!(
+                "Failed to forward {:?} to {} ({}): {}",
+                signal, name, pid, e
+            );
+        }
+    }
+
+    /// Signal the process of a service, along with the rest of its process group and, if its
+    /// kill mode says so, its other descendants.
+    fn signal_service(&self, pid: Pid, signal: Signal) -> nix::Result<()> {
+        let mode = self.kill_mode(pid);
+        let pgid = self.process_groups.get(&pid).copied();
+        // found first, as the descendants are reparented once their parent exits
+        let subtree = match mode {
+            Some(KillMode::Subtree) => self.subtree(pid),
+            _ => Vec::new(),
+        };
+        let res = match pgid {
+            Some(pgid) if mode != Some(KillMode::Process) => {
+                self.processes.kill(group(pgid), Some(signal))
+            }
+            _ => self.signal_process(pid, signal),
+        };
+        for descendant in subtree {
+            // the members of the group were signaled along with it
+            if pgid.is_some() && self.processes.stat(descendant).map(|stat| stat.pgrp) == pgid {
+                continue;
+            }
+            if let Err(e) = self.processes.kill(descendant, Some(signal)) {
+                debug!(
+                    "Failed to signal descendant {} of {}: {}",
+                    descendant, pid, e
+                );
+            }
+        }
+        res
+    }
+
+    /// The kill mode of the service the process belongs to, which is still known while the
+    /// service is being stopped.
+    fn kill_mode(&self, pid: Pid) -> Option<KillMode> {
+        if let Some(cmd) = self.persistent_commands_map.get(&pid) {
+            return cmd.kill_handling();
+        }
+        let (name, _) = self
+            .services
+            .iter()
+            .find(|(_, status)| status.pid == Some(pid))?;
+        self.inactive_commands.get(name)?.kill_handling()
+    }
+
+    /// All descendants of the process of a service, from its cgroup if it has one.
+    fn subtree(&self, pid: Pid) -> Vec<Pid> {
+        let cgroup = self
+            .services
+            .iter()
+            .find(|(_, status)| status.pid == Some(pid))
+            .and_then(|(name, _)| self.service_cgroups.get(name));
+        match cgroup {
+            Some(cgroup) => cgroup.procs().into_iter().filter(|p| *p != pid).collect(),
+            None if self.follows_lineage() => self.process_tree.descendants(pid),
+            None => scan_lineage(&*self.processes).descendants(pid),
+        }
+    }
+
+    /// Signal a single process, through its pidfd if the reaper spawned it, so the signal never
+    /// reaches another process which got the same pid.
+    fn signal_process(&self, pid: Pid, signal: Signal) -> nix::Result<()> {
+        match self.pidfds.get(&pid) {
+            Some(pidfd) => pidfd.signal(signal as libc::c_int).map_err(|e| {
+                nix::Error::Sys(Errno::from_i32(e.raw_os_error().unwrap_or_default()))
+            }),
+            None => self.processes.kill(pid, Some(signal)),
+        }
+    }
+
+    /// Reap zombies until the service exited, or the timeout expired. Returns whether the
+    /// service exited.
+    fn wait_for_exit(&mut self, name: &str, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.services.get(name).map(ServiceStatus::is_running) != Some(true) {
+                return true;
+            }
+            match self.events.wait(deadline) {
+                Some(Wakeup::Signal(Signal::SIGCHLD)) => {
+                    if let Err(e) = self.reap_zombies() {
+                        // the exit can't be noticed, so the service is considered stuck
+                        error!("{}", e);
+                        return false;
+                    }
+                }
+                Some(Wakeup::Signal(s)) => debug!("Ignoring signal {:?} while shutting down", s),
+                Some(Wakeup::Readable(_)) => (),
+                None => return false,
+            }
+        }
+    }
+
+    /// Reap all zombies, terminate the orphans of failed processes, and respawn persistent
+    /// commands as needed.
+    fn reap_zombies(&mut self) -> Result<(), ReaperError> {
+        // received sigchld, try to get a carcass
+        // a single signal can be used for multiple dead children, so keep reaping
+        // untill we got them all. If this captures dead children from a subsequent
+        // signal, then reaping will fail on that signal so no more action will be
+        // taken.
+        self.handle_process_events();
+        self.track_delegated();
+        let flags = match self.stop_policy {
+            Some(_) => WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED,
+            None => WaitPidFlag::empty(),
+        };
+        let started = Instant::now();
+        let mut reaped = 0;
+        self.reap_deferred = false;
+        loop {
+            if reaped >= self.reap_budget || started.elapsed() >= self.reap_time_budget {
+                debug!("Reaped {} processes, leaving the rest for later", reaped);
+                self.reap_deferred = true;
+                self.stats.reaps_deferred += 1;
+                break;
+            }
+            let event = match wait_child(&*self.processes, flags)? {
+                Some(event) => event,
+                None => break,
+            };
+            reaped += 1;
+            let carcass = match event {
+                ChildEvent::Exited(carcass) => carcass,
+                ChildEvent::Stopped(pid, signal) => {
+                    self.child_stopped(pid, signal);
+                    continue;
+                }
+                ChildEvent::Continued(pid) => {
+                    self.child_continued(pid);
+                    continue;
+                }
+            };
+            self.stats.zombies_reaped += 1;
+            self.record(ReaperEvent::Reaped(carcass.clone()));
+            // the pid may be reused by a process which is not protected
+            self.orphan_exemptions
+                .retain(|exemption| *exemption != OrphanExemption::Pid(carcass.pid));
+            // health check probes only report back to their check
+            let probed = self
+                .health_monitors
+                .iter_mut()
+                .find(|(_, monitor)| monitor.probe_pid() == Some(carcass.pid));
+            if let Some((name, monitor)) = probed {
+                let name = name.clone();
+                let unhealthy = monitor.finished(carcass.status == Some(0));
+                self.health_checked(&name, unhealthy);
+                continue;
+            }
+            if let Some(timer) = self
+                .timers
+                .iter_mut()
+                .find(|timer| timer.running() == Some(carcass.pid))
+            {
+                timer.finished();
+                match (carcass.status, carcass.signal) {
+                    (Some(0), _) => debug!("Timer command {} succeeded", timer.service_name()),
+                    (Some(code), _) => warn!(
+                        "Timer command {} exited with code {}",
+                        timer.service_name(),
+                        code
+                    ),
+                    (_, signal) => warn!(
+                        "Timer command {} exited with signal {:?}",
+                        timer.service_name(),
+                        signal
+                    ),
+                }
+                if let Some(mux) = &self.log_mux {
+                    mux.detach(carcass.pid);
+                }
+                continue;
+            }
+            if let Some(pre) = self.pre_starting.remove(&carcass.pid) {
+                pre.cmd.finish_pre_start(carcass.status, carcass.signal);
+                let cmd_name = format!("{}", pre.cmd);
+                if let Err(e) = self.spawn_persistent_command(pre.cmd, pre.exit_reason) {
+                    warn!("Failed to spawn persistent command ({}): {}", cmd_name, e);
+                }
+                continue;
+            }
+            if let Some(hook) = self.hooks.remove(&carcass.pid) {
+                match (carcass.status, carcass.signal) {
+                    (Some(0), _) => info!("The {} succeeded", hook),
+                    (Some(code), _) => warn!("The {} exited with code {}", hook, code),
+                    (_, signal) => warn!("The {} exited with signal {:?}", hook, signal),
+                }
+                if let Some(mux) = &self.log_mux {
+                    mux.detach(carcass.pid);
+                }
+                continue;
+            }
+            if let Some((name, _)) = self.background_one_shots.remove(&carcass.pid) {
+                self.one_shot_finished(name, &carcass);
+                continue;
+            }
+            if let Some(main) = self.pipeline_stages.remove(&carcass.pid) {
+                if let Some(mux) = &self.log_mux {
+                    mux.detach(carcass.pid);
+                }
+                // a broken pipeline is restarted as a whole, unless it is already going down
+                let name = self
+                    .persistent_commands_map
+                    .get(&main)
+                    .map(|cmd| cmd.service_name().to_string());
+                if let Some(name) = name {
+                    warn!(
+                        "Pipeline stage {} of {} exited, restarting it",
+                        carcass.pid, name
+                    );
+                    if let Err(e) = self.restart_service(&name) {
+                        warn!("Failed to restart {}: {}", name, e);
+                    }
+                }
+                continue;
+            }
+            if self.orphans.remove(&carcass.pid).is_some() {
+                self.stats.orphans_terminated += 1;
+                self.record(ReaperEvent::OrphanKilled(carcass.clone()));
+            }
+            // got a dead process, named after its service if it has one
+            let who = match self.persistent_commands_map.get(&carcass.pid) {
+                Some(cmd) => format!("{} ({})", cmd.service_name(), carcass.pid),
+                None => carcass.pid.to_string(),
+            };
+            // if the process exited normally, i.e. exit code 0, everything is fine
+            // if the process did not exit with 0, or it was signaled, kill all of its
+            // children, unless the service considers that a normal exit as well
+            let (event, how) = match carcass {
+                Carcass {
+                    status: Some(0), ..
+                } => (Event::ExitSuccess, "with code 0".to_string()),
+                Carcass {
+                    status: Some(code), ..
+                } => (Event::ExitCode(code), format!("with code {}", code)),
+                Carcass {
+                    signal: Some(sig), ..
+                } => (Event::ExitSignal(sig), format!("with signal {:?}", sig)),
+                _ => unreachable!(), // we always have either signal or status set
+            };
+            let event = match self.persistent_commands_map.get(&carcass.pid) {
+                Some(cmd) => cmd.classify(event),
+                None => event,
+            };
+            let how = match event {
+                Event::StartFailed { .. } => format!("{}, failing to start", how),
+                _ => how,
+            };
+            let exit_reason = match (
+                carcass.status,
+                self.persistent_commands_map.get(&carcass.pid),
+            ) {
+                (Some(code), Some(cmd)) => cmd.exit_reason(code),
+                _ => None,
+            };
+            let how = match exit_reason {
+                Some(reason) => format!("{} ({})", how, reason),
+                None => how,
+            };
+            info!("Reaped carcass of {}, exited {}", who, how);
+
+            // get a list of children for this process, which for a service in a cgroup are the
+            // processes left in it.
+            // this also forgets the current carcass pid as a child
+            let cgroup = self
+                .persistent_commands_map
+                .get(&carcass.pid)
+                .and_then(|cmd| self.service_cgroups.get(cmd.service_name()))
+                .cloned();
+            let children = if self.child_discovery.is_some() {
+                let reparented = self.left_behind(carcass.pid)?;
+                let exited = ExitedProcess::new(
+                    carcass.pid,
+                    self.process_groups.get(&carcass.pid).copied(),
+                    cgroup.as_ref(),
+                    &reparented,
+                    &*self.processes,
+                );
+                self.child_discovery
+                    .as_mut()
+                    .map(|discovery| discovery.descendants(&exited))
+                    .unwrap_or_default()
+            } else {
+                match &cgroup {
+                    Some(cgroup) => {
+                        self.children.retain(|pid| *pid != carcass.pid);
+                        cgroup.procs()
+                    }
+                    None => self.left_behind(carcass.pid)?,
+                }
+            };
+            debug!("Reaped process has {} children", children.len());
+
+            // see if the children need to be marked
+            let policy = self
+                .persistent_commands_map
+                .get(&carcass.pid)
+                .map_or_else(OrphanPolicy::default, PersistentCommand::orphan_handling);
+            // a command which does not daemonize leaked what it left behind
+            let leaked = event == Event::ExitSuccess
+                && policy != OrphanPolicy::Adopt
+                && !children.is_empty()
+                && self
+                    .persistent_commands_map
+                    .get(&carcass.pid)
+                    .and_then(PersistentCommand::daemonizing)
+                    == Some(false);
+            if leaked {
+                warn!(
+                    "{} does not daemonize, but left {} processes behind",
+                    who,
+                    children.len()
+                );
+            }
+            let kill = match policy {
+                OrphanPolicy::KillAlways => true,
+                OrphanPolicy::KillOnFailure => event != Event::ExitSuccess || leaked,
+                OrphanPolicy::Adopt => false,
+            };
+            let mode = self.kill_mode(carcass.pid);
+            let owner = self.delegated.remove(&carcass.pid);
+            let supervisor = self
+                .persistent_commands_map
+                .get(&carcass.pid)
+                .filter(|cmd| cmd.is_supervisor())
+                .map(|cmd| cmd.service_name().to_string());
+            match event {
+                // what a descendant of a supervisor leaves behind is the business of that
+                // supervisor
+                _ if owner.is_some() => {
+                    let owner = owner.unwrap();
+                    debug!(
+                        "Leaving the children of {} to {}, which supervises them",
+                        carcass.pid, owner
+                    );
+                    for child in &children {
+                        self.delegated.insert(*child, owner.clone());
+                    }
+                }
+                _ if kill && !self.kill_orphans_on_failure => {
+                    debug!("Leaving the children of {} alone", carcass.pid);
+                }
+                _ if kill && mode == Some(KillMode::Process) => {
+                    debug!(
+                        "Leaving the children of {} alone, only its process is killed",
+                        carcass.pid
+                    );
+                }
+                _ if kill && mode == Some(KillMode::Group) => {
+                    match self.process_groups.get(&carcass.pid).copied() {
+                        Some(pgid) => {
+                            debug!("Terminating the process group of {}", carcass.pid);
+                            self.terminate_group(pgid);
+                        }
+                        None => debug!("{} does not lead a process group", carcass.pid),
+                    }
+                }
+                _ if kill && cgroup.is_some() => {
+                    self.kill_cgroup(cgroup.as_ref().unwrap(), &children);
+                }
+                _ if kill => {
+                    debug!("Killing the children of {}", carcass.pid);
+                    if let Some(name) = &supervisor {
+                        self.delegated.retain(|_, owner| owner != name);
+                    }
+                    let children = self.with_descendants(carcass.pid, children);
+                    let swept = match self.process_groups.get(&carcass.pid).copied() {
+                        Some(pgid) if self.terminate_group(pgid) => Some(pgid),
+                        _ => None,
+                    };
+                    self.mark_orphans(&children, swept);
+                }
+                Event::ExitSuccess if policy != OrphanPolicy::Adopt => {
+                    let uses_pid_file = self
+                        .persistent_commands_map
+                        .get(&carcass.pid)
+                        .and_then(PersistentCommand::pid_file_path)
+                        .is_some();
+                    if uses_pid_file {
+                        // the daemon tells which process it is
+                        let cmd = self.persistent_commands_map.remove(&carcass.pid).unwrap();
+                        self.pid_file_waits.push((Instant::now(), cmd));
+                        self.read_pid_files(Instant::now());
+                    } else if !children.is_empty() && supervisor.is_none() {
+                        // make sure forked processes have their pid updated
+                        self.update_ensured_process_pid(&carcass.pid, &children[0]);
+                    } else if let Some(cmd) = self
+                        .persistent_commands_map
+                        .get(&carcass.pid)
+                        .filter(|cmd| cmd.daemonizing() == Some(true) && children.is_empty())
+                    {
+                        warn!(
+                            "{} should have daemonized, but left no process behind",
+                            cmd.service_name()
+                        );
+                    }
+                }
+                _ => debug!("Adopting the children of {}", carcass.pid),
+            }
+            self.process_groups.remove(&carcass.pid);
+            self.pidfds.remove(&carcass.pid);
+            self.stop_pipeline_stages(carcass.pid);
+            self.process_tree.forget(carcass.pid);
+
+            // the process is gone, so are its pipes, unless they moved to a fork
+            if let Some(mux) = &self.log_mux {
+                mux.detach(carcass.pid);
+            }
+            self.run_exit_hook(carcass.pid, event);
+            let service = self.service_exited(&carcass.pid, event, exit_reason);
+            if let Some(name) = &service {
+                // only reported if it was not ready yet
+                self.console.failed(name, &format!("exited {}", how));
+                self.record(ReaperEvent::Exited {
+                    service: name.clone(),
+                    pid: carcass.pid,
+                    exit: event,
+                    reason: exit_reason.map(str::to_string),
+                });
+            }
+
+            // only set if the process exited on its own, not on request
+            let respawned = self
+                .persistent_commands_map
+                .get(&carcass.pid)
+                .map(|cmd| cmd.service_name().to_string());
+            if let Err(e) = self.ensure_process(&carcass.pid, Some(event)) {
+                // for now just log failures
+                match e {
+                    PersistentCommandError::SpawnFailed(_) => {
+                        error!("{}", e);
+                    }
+                    PersistentCommandError::SpawnLimitReached(_)
+                    | PersistentCommandError::CrashLooping(_) => {
+                        warn!("{}", e);
+                    }
+                    PersistentCommandError::SpawnRateLimited(_)
+                    | PersistentCommandError::SpawnPaused(_) => {
+                        info!("{}", e);
+                    }
+                    PersistentCommandError::ResourcesExhausted(_)
+                    | PersistentCommandError::ExecutableMissing(_) => {
+                        warn!("{}", e);
+                    }
+                    PersistentCommandError::MustNotRespawn(_)
+                    | PersistentCommandError::RestartDelayed(_) => {
+                        info!("{}", e);
+                    }
+                    PersistentCommandError::Escalated(_) => {
+                        error!("{}", e);
+                        if let Some(name) = &service {
+                            self.record(ReaperEvent::Failed {
+                                service: name.clone(),
+                                reason: "escalated".to_string(),
+                            });
+                        }
+                        self.escalated = service.clone();
+                    }
+                }
+            }
+
+            if let Some(name) = respawned {
+                self.follow_bound(&name);
+            }
+            if let Some(name) = service {
+                self.finish_requests(&name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Restart the services which are bound to a service which exited, if it is started again,
+    /// or stop them if it is not.
+    fn follow_bound(&mut self, name: &str) {
+        if self.shutting_down {
+            return;
+        }
+        let mut bound: Vec<String> = self
+            .persistent_commands_map
+            .values()
+            .chain(self.delayed_commands.iter().map(|(_, cmd)| cmd))
+            .filter(|cmd| cmd.bound_to().contains(&name))
+            .map(|cmd| cmd.service_name().to_string())
+            .collect();
+        if bound.is_empty() {
+            return;
+        }
+        bound.sort();
+        bound.dedup();
+        let restarted = self.service_pid(name).is_some()
+            || self
+                .delayed_commands
+                .iter()
+                .any(|(_, cmd)| cmd.service_name() == name);
+        for service in bound {
+            let res = if restarted {
+                info!("{} restarted, restarting {} along with it", name, service);
+                self.restart_service(&service)
+            } else {
+                info!("{} stopped, stopping {} along with it", name, service);
+                self.stop_service(&service)
+            };
+            if let Err(e) = res {
+                warn!("Failed to follow {} with {}: {}", name, service, e);
+            }
+        }
+    }
+
+    /// Complete the requests made through the control socket which waited for the process of
+    /// a service to exit, and start it again if it is being restarted.
+    fn finish_requests(&mut self, name: &str) {
+        for seq in self.awaiting_exit.remove(name).unwrap_or_default() {
+            self.journal_complete(Some(seq));
+        }
+        if let Some(idx) = self.pending_starts.iter().position(|n| n == name) {
+            self.pending_starts.remove(idx);
+            match self.start_service(name) {
+                Ok(_) => self.follow_bound(name),
+                Err(e) => error!("Failed to restart {}: {}", name, e),
+            }
+        }
+    }
+
+    /// get a list of all new children since the last time this method is called, and remember
+    /// all current children
+    fn new_children(&mut self) -> Result<Vec<Pid>, ReaperError> {
+        trace!("Finding children we don't know about yet");
+
+        let all_children = self
+            .processes
+            .children(self.pid)
+            .map_err(ReaperError::ListProcesses)?;
+
+        let new_children = all_children
+            .iter()
+            .filter(|p| !self.children.contains(p))
+            .copied()
+            .collect();
+
+        // remember the new children
+        self.children = all_children;
+
+        Ok(new_children)
+    }
+
+    /// Run the hook of the service of which the process exited, if it has one for the exit. A
+    /// service which was stopped is no longer known by its pid, so its exit runs no hook.
+    fn run_exit_hook(&mut self, pid: Pid, event: Event) {
+        let (name, hook) = match self.persistent_commands_map.get(&pid).and_then(|cmd| {
+            Some((
+                cmd.service_name().to_string(),
+                cmd.exit_hook(event)?.clone(),
+            ))
+        }) {
+            Some(found) => found,
+            None => return,
+        };
+        let kind = match event {
+            Event::ExitSuccess => "success",
+            Event::ExitCode(_) | Event::ExitSignal(_) | Event::StartFailed { .. } => "failure",
+        };
+        info!("Running {} hook of {} ({})", kind, name, hook);
+        self.spawn_hook(&hook, format!("{} hook of {}", kind, name));
+    }
+
+    /// Act on a service which failed for good, as its failure action says.
+    fn failed_permanently(&mut self, cmd: &PersistentCommand<'a>) {
+        let name = cmd.service_name();
+        match cmd.failure_action() {
+            FailureAction::None => (),
+            FailureAction::RunCommand(action) => {
+                let hook = command::hook(action);
+                info!("{} failed permanently, running {}", name, hook);
+                self.spawn_hook(&hook, format!("failure action of {}", name));
+            }
+            FailureAction::Reboot => {
+                error!("{} failed permanently, rebooting", name);
+                self.power_request = Some((name.to_string(), PowerAction::Reboot));
+            }
+            FailureAction::PowerOff => {
+                error!("{} failed permanently, powering off", name);
+                self.power_request = Some((name.to_string(), PowerAction::PowerOff));
+            }
+        }
+    }
+
+    /// Rotate the log files which are due, if they were not checked for a while.
+    fn rotate_logs_if_due(&mut self, now: Instant) {
+        let rotation = match &self.log_rotation {
+            Some(rotation) => rotation.clone(),
+            None => return,
+        };
+        if self
+            .rotation_checked
+            .is_some_and(|at| now.duration_since(at) < ROTATION_INTERVAL)
+        {
+            return;
+        }
+        self.rotation_checked = Some(now);
+
+        // the files of the services are held open by their processes
+        let mut files: Vec<(&str, bool)> = rotation
+            .own_logs()
+            .iter()
+            .map(|path| (path.as_str(), false))
+            .collect();
+        for cmd in self.persistent_commands_map.values() {
+            for path in cmd.output_paths() {
+                if !files.iter().any(|(file, _)| *file == path) {
+                    files.push((path, true));
+                }
+            }
+        }
+        let mut rotated = Vec::new();
+        for (path, truncate) in files {
+            if !rotation.is_due(path) {
+                continue;
+            }
+            match rotation.rotate(path, truncate) {
+                Ok(to) => {
+                    info!("Rotated {}", path);
+                    if !truncate {
+                        logfile::reopen();
+                    }
+                    rotated.extend(to.filter(|_| rotation.compresses()));
+                }
+                Err(e) => warn!("Failed to rotate {}: {}", path, e),
+            }
+        }
+        for path in rotated {
+            let cmd = format!("gzip -f {}", path);
+            self.spawn_hook(&command::hook(&cmd), format!("compression of {}", path));
+        }
+    }
+
+    /// Spawn a hook, which is not waited for. Its outcome is logged once it is reaped, described
+    /// as what.
+    fn spawn_hook(&mut self, hook: &OneShotCommand, what: String) {
+        let mut child = match hook.spawn(self.log_mux.is_some(), self.helper_death_signal) {
+            Ok(child) => child,
+            Err(e) => {
+                error!("Failed to spawn the {} ({}): {}", what, hook, e);
+                return;
+            }
+        };
+        let pid = Pid::from_raw(child.id() as i32);
+        if let Some(mux) = &self.log_mux {
+            mux.attach(
+                pid,
+                hook.service_name(),
+                vec![
+                    child.stdout.take().map(IntoRawFd::into_raw_fd),
+                    child.stderr.take().map(IntoRawFd::into_raw_fd),
+                ],
+            );
+        }
+        // known right away, so it is never mistaken for an orphan
+        self.children.push(pid);
+        self.hooks.insert(pid, what);
+    }
+
+    /// Run all one-shot commands to completion, one after the other. An error is returned once
+    /// a required one fails, without running the rest.
+    fn run_one_shots(&mut self) -> Result<(), ReaperError> {
+        let _span = profile::span("one-shots");
+        for cmd in std::mem::take(&mut self.one_shots) {
+            if !cmd.waits() {
+                self.start_one_shot(&cmd);
+                continue;
+            }
+            if !self.run_one_shot(&cmd) && cmd.is_required() {
+                return Err(ReaperError::RequiredFailed(cmd.service_name().to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a one-shot command to completion, killing it if it runs for too long. Returns
+    /// whether it succeeded.
+    fn run_one_shot(&mut self, cmd: &OneShotCommand) -> bool {
+        info!("Running one-shot command {}", cmd);
+        let mut child = match self.spawn_one_shot(cmd) {
+            Some(child) => child,
+            None => return false,
+        };
+        let pid = Pid::from_raw(child.id() as i32);
+        // this only waits for this specific process, other zombies are reaped once the
+        // main loop runs
+        let res = match cmd.max_runtime() {
+            Some(timeout) => {
+                wait_until(&mut child, Instant::now() + timeout).and_then(|status| match status {
+                    Some(status) => Ok(status),
+                    None => {
+                        warn!(
+                            "One-shot command {} did not finish within {:?}, killing it",
+                            cmd, timeout
+                        );
+                        child.kill()?;
+                        child.wait()
+                    }
+                })
+            }
+            None => child.wait(),
+        };
+        let success = res.as_ref().map(|status| status.success()).unwrap_or(false);
+        self.one_shot_results
+            .insert(cmd.service_name().to_string(), success);
+        match res {
+            Ok(status) if status.success() => info!("One-shot command {} succeeded", cmd),
+            Ok(status) => match (status.code(), status.signal()) {
+                (Some(code), _) => warn!("One-shot command {} exited with code {}", cmd, code),
+                (_, Some(sig)) => warn!("One-shot command {} exited with signal {}", cmd, sig),
+                _ => warn!("One-shot command {} failed: {}", cmd, status),
+            },
+            Err(e) => error!("Failed to wait for one-shot command ({}): {}", cmd, e),
+        }
+        if let Some(mux) = &self.log_mux {
+            mux.detach(pid);
+        }
+        success
+    }
+
+    /// Start a one-shot command without waiting for it. It is reaped by the main loop, which
+    /// releases the services that wait for it.
+    fn start_one_shot(&mut self, cmd: &OneShotCommand) {
+        info!("Starting one-shot command {}", cmd);
+        let child = match self.spawn_one_shot(cmd) {
+            Some(child) => child,
+            None => {
+                self.one_shot_results
+                    .insert(cmd.service_name().to_string(), false);
+                return;
+            }
+        };
+        let pid = Pid::from_raw(child.id() as i32);
+        // known right away, so it is never mistaken for an orphan
+        self.children.push(pid);
+        let deadline = cmd.max_runtime().map(|timeout| Instant::now() + timeout);
+        self.background_one_shots
+            .insert(pid, (cmd.service_name().to_string(), deadline));
+    }
+
+    fn spawn_one_shot(&mut self, cmd: &OneShotCommand) -> Option<Child> {
+        let mut child = match cmd.spawn(self.log_mux.is_some(), self.helper_death_signal) {
+            Ok(child) => child,
+            Err(e) => {
+                error!("Failed to spawn one-shot command ({}): {}", cmd, e);
+                return None;
+            }
+        };
+        if let Some(mux) = &self.log_mux {
+            mux.attach(
+                Pid::from_raw(child.id() as i32),
+                cmd.service_name(),
+                vec![
+                    child.stdout.take().map(IntoRawFd::into_raw_fd),
+                    child.stderr.take().map(IntoRawFd::into_raw_fd),
+                ],
+            );
+        }
+        Some(child)
+    }
+
+    /// Record the outcome of a one-shot command which ran alongside the others.
+    fn one_shot_finished(&mut self, name: String, carcass: &Carcass) {
+        match (carcass.status, carcass.signal) {
+            (Some(0), _) => info!("One-shot command {} succeeded", name),
+            (Some(code), _) => warn!("One-shot command {} exited with code {}", name, code),
+            (_, signal) => warn!("One-shot command {} exited with signal {:?}", name, signal),
+        }
+        if let Some(mux) = &self.log_mux {
+            mux.detach(carcass.pid);
+        }
+        self.one_shot_results
+            .insert(name, carcass.status == Some(0));
+        self.release_held_commands();
+    }
+
+    /// Kill the one-shot commands running alongside the others which ran for too long.
+    fn kill_overdue_one_shots(&mut self, now: Instant) {
+        for (pid, (name, deadline)) in &mut self.background_one_shots {
+            if deadline.is_some_and(|deadline| deadline <= now) {
+                warn!("One-shot command {} ran for too long, killing it", name);
+                *deadline = None;
+                if let Err(e) = self.processes.kill(*pid, Some(Signal::SIGKILL)) {
+                    warn!("Failed to kill one-shot command {}: {}", name, e);
+                }
+            }
+        }
+    }
+
+    /// Kill the pre-start hooks which ran for too long. Their commands are spawned once they are
+    /// reaped.
+    fn kill_overdue_pre_starts(&mut self, now: Instant) {
+        for (pid, pre) in &mut self.pre_starting {
+            if pre.deadline.is_some_and(|deadline| deadline <= now) {
+                warn!(
+                    "Pre-start hook of {} ran for too long, killing it",
+                    pre.cmd.service_name()
+                );
+                pre.deadline = None;
+                if let Err(e) = self.processes.kill(*pid, Some(Signal::SIGKILL)) {
+                    warn!(
+                        "Failed to kill pre-start hook of {}: {}",
+                        pre.cmd.service_name(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Spawn the held commands of which all dependencies are satisfied, in order.
+    fn release_held_commands(&mut self) {
+        self.probe_pseudo_services();
+        self.mount_on_demand();
+        // the commands which can be started are started together, after which the commands
+        // which depend on those that are ready right away can be started
+        loop {
+            let mut batch: Vec<PersistentCommand<'a>> = Vec::new();
+            let mut idx = 0;
+            while idx < self.held_commands.len() {
+                let held = &self.held_commands[idx];
+                if !held.on_hold
+                    && self.missing_mount(&held.cmd).is_none()
+                    && missing_path(&held.cmd).is_none()
+                    && self.unmet_dependency(&held.cmd).is_none()
+                    && !self.at_start_limit(&held.cmd, &batch)
+                {
+                    batch.push(self.held_commands.remove(idx).cmd);
+                } else {
+                    idx += 1;
+                }
+            }
+            if batch.is_empty() {
+                break;
+            }
+            self.spawn_batch(batch);
+        }
+
+        // report what the remaining commands wait for, but only when that changes
+        let unmet: Vec<_> = self
+            .held_commands
+            .iter()
+            .map(|held| {
+                (
+                    self.unmet_dependency(&held.cmd),
+                    self.missing_mount(&held.cmd).map(str::to_string),
+                    missing_path(&held.cmd).map(str::to_string),
+                )
+            })
+            .collect();
+        for (held, (unmet, missing_mount, missing_path)) in self.held_commands.iter_mut().zip(unmet)
+        {
+            if held.on_hold {
+                continue;
+            }
+            if missing_mount != held.waiting_for_mount {
+                if let Some(path) = &missing_mount {
+                    info!(
+                        "Holding {} until {} is mounted",
+                        held.cmd.service_name(),
+                        path
+                    );
+                }
+                held.waiting_for_mount = missing_mount;
+            }
+            if missing_path != held.waiting_for_path {
+                if let Some(path) = &missing_path {
+                    info!("Holding {} until {} exists", held.cmd.service_name(), path);
+                }
+                held.waiting_for_path = missing_path;
+            }
+            if unmet == held.waiting_on {
+                continue;
+            }
+            if let Some((dep, failed)) = &unmet {
+                if *failed {
+                    warn!(
+                        "{} requires {}, which failed or does not exist, holding it",
+                        held.cmd.service_name(),
+                        dep
+                    );
+                } else {
+                    info!("Holding {} until {} is ready", held.cmd.service_name(), dep);
+                }
+            }
+            held.waiting_on = unmet;
+        }
+    }
+
+    /// Probe the pseudo-services held commands depend on which are not ready yet.
+    fn probe_pseudo_services(&mut self) {
+        let now = Instant::now();
+        for pseudo in &mut self.pseudo_services {
+            if self.services.contains_key(&pseudo.name) {
+                continue;
+            }
+            let waited_on = self.held_commands.iter().any(|held| {
+                !held.on_hold && held.cmd.dependencies().any(|(dep, _)| dep == pseudo.name)
+            });
+            if waited_on && pseudo.probe(now, REQUIRED_PATH_POLL_INTERVAL) {
+                info!("{} is ready", pseudo.name);
+            }
+        }
+    }
+
+    /// Spawn the commands which can be started together. Their pre-start hooks run alongside
+    /// each other, and the commands without one are spawned while the hooks run.
+    fn spawn_batch(&mut self, batch: Vec<PersistentCommand<'a>>) {
+        for cmd in batch {
+            self.spawn_released(cmd);
+        }
+    }
+
+    fn spawn_released(&mut self, cmd: PersistentCommand<'a>) {
+        // rememmber name in case shit blows up
+        let cmd_name = format!("{}", cmd);
+        if let Err(e) = self.spawn_persistent_command(cmd, None) {
+            error!("Failed to spawn persistent command ({}): {}", cmd_name, e);
+            // the command is kept as inactive, so it can still be started later on
+        }
+    }
+
+    /// Check if as many services are starting as may be starting at the same time, overall or
+    /// of the resource class of the command, counting the commands about to be spawned along
+    /// with it.
+    fn at_start_limit(&self, cmd: &PersistentCommand, spawning: &[PersistentCommand]) -> bool {
+        let class = cmd.class();
+        let class_limit = class
+            .and_then(|class| self.start_limits.get(class))
+            .copied();
+        if class_limit.is_none() && self.max_starting.is_none() {
+            return false;
+        }
+        let starting: Vec<Option<&str>> = self
+            .persistent_commands_map
+            .values()
+            .filter(|running| {
+                self.services
+                    .get(running.service_name())
+                    .is_some_and(|status| status.is_running() && !status.ready)
+            })
+            .chain(spawning.iter().filter(|cmd| cmd.notifies()))
+            .map(PersistentCommand::class)
+            .collect();
+        if self
+            .max_starting
+            .is_some_and(|limit| starting.len() >= limit)
+        {
+            return true;
+        }
+        class_limit
+            .is_some_and(|limit| starting.iter().filter(|other| **other == class).count() >= limit)
+    }
+
+    /// Find a mount the command requires which is not there yet. Without a mount table, the
+    /// command is not held back.
+    fn missing_mount<'c>(&self, cmd: &'c PersistentCommand) -> Option<&'c str> {
+        let mounts = self.mounts.as_ref()?;
+        cmd.required_mounts()
+            .iter()
+            .copied()
+            .find(|path| !mounts.is_mounted(path))
+    }
+
+    /// Mount the automounts required by held commands which are otherwise ready to start.
+    fn mount_on_demand(&mut self) {
+        let wanted: Vec<String> = self
+            .held_commands
+            .iter()
+            .filter(|held| !held.on_hold && self.unmet_dependency(&held.cmd).is_none())
+            .filter_map(|held| self.missing_mount(&held.cmd))
+            .map(str::to_string)
+            .collect();
+        if wanted.is_empty() {
+            return;
+        }
+        for path in wanted {
+            if let Some(automount) = self.automounts.iter_mut().find(|a| a.mounts_at(&path)) {
+                automount.mount();
+            }
+        }
+        // don't wait for the kernel to report the change to release the commands
+        if let Some(Err(e)) = self.mounts.as_mut().map(MountTable::refresh) {
+            warn!("Failed to read the mount table: {}", e);
+        }
+    }
+
+    /// Unmount the automounts which no running service requires anymore, once they have been
+    /// idle for long enough.
+    fn unmount_idle(&mut self, now: Instant) {
+        let commands = self
+            .persistent_commands_map
+            .values()
+            .chain(self.delayed_commands.iter().map(|(_, cmd)| cmd));
+        let in_use: Vec<&str> = commands
+            .flat_map(|cmd| cmd.required_mounts().iter().copied())
+            .collect();
+        for automount in &mut self.automounts {
+            let used = in_use.iter().any(|path| automount.mounts_at(path));
+            automount.unmount_if_idle(used, now);
+        }
+    }
+
+    /// Start tracking the mount table, once a command requires a mount.
+    fn track_mounts(&mut self) {
+        if self.mounts.is_some() {
+            return;
+        }
+        let mounts = MountTable::open().and_then(|mounts| {
+            self.events.watch_changes(mounts.as_raw_fd())?;
+            Ok(mounts)
+        });
+        match mounts {
+            Ok(mounts) => self.mounts = Some(mounts),
+            Err(e) => error!("Failed to track mounts, not waiting for any: {}", e),
+        }
+    }
+
+    /// The names of the held commands which wait for the service with the given name, sorted.
+    fn blocked_by(&self, name: &str) -> Vec<&str> {
+        let mut blocked: Vec<&str> = self
+            .held_commands
+            .iter()
+            .filter(|held| held.waiting_on.as_ref().is_some_and(|(dep, _)| dep == name))
+            .map(|held| held.cmd.service_name())
+            .collect();
+        blocked.sort_unstable();
+        blocked
+    }
+
+    /// Find a dependency of the command which is not satisfied, and whether it failed.
+    fn unmet_dependency(&self, cmd: &PersistentCommand) -> Option<(String, bool)> {
+        self.unmet_dependency_in(cmd, &mut HashSet::new())
+    }
+
+    /// The walk behind [`unmet_dependency`], which keeps track of the commands it went through,
+    /// so that commands which require each other don't send it around in circles.
+    ///
+    /// [`unmet_dependency`]: #method.unmet_dependency
+    fn unmet_dependency_in(
+        &self,
+        cmd: &PersistentCommand,
+        visited: &mut HashSet<String>,
+    ) -> Option<(String, bool)> {
+        visited.insert(cmd.service_name().to_string());
+        for (dep, required) in cmd.dependencies() {
+            if self
+                .background_one_shots
+                .values()
+                .any(|(name, _)| name == dep)
+            {
+                return Some((dep.to_string(), false));
+            }
+            if let Some(success) = self.one_shot_results.get(dep) {
+                if required && !success {
+                    return Some((dep.to_string(), true));
+                }
+                continue;
+            }
+            if self.services.get(dep).map(ServiceStatus::is_ready) == Some(true) {
+                continue;
+            }
+            if !self.services.contains_key(dep) {
+                if let Some(pseudo) = self.pseudo_services.iter().find(|p| p.name == dep) {
+                    if pseudo.ready {
+                        continue;
+                    }
+                    return Some((dep.to_string(), false));
+                }
+            }
+            let failed = self.inactive_commands.contains_key(dep)
+                || !self.services.contains_key(dep)
+                // held back because one of its own requirements failed
+                || (!visited.contains(dep)
+                    && self
+                        .held_commands
+                        .iter()
+                        .filter(|held| held.cmd.service_name() == dep)
+                        .any(|held| {
+                            matches!(
+                                self.unmet_dependency_in(&held.cmd, visited),
+                                Some((_, true))
+                            )
+                        }));
+            if !required && failed {
+                continue;
+            }
+            return Some((dep.to_string(), failed));
+        }
+        None
+    }
+
+    fn spawn_persistent_command(
+        &mut self,
+        mut pcmd: PersistentCommand<'a>,
+        exit_reason: Option<Event>,
+    ) -> Result<(), PersistentCommandError> {
+        debug!("Spawning persistent command");
+        let _span = profile::span("spawn");
+
+        if let Some(prompt) = pcmd.password_prompt() {
+            let id = self.passwords.ask(pcmd.service_name(), prompt);
+            self.awaiting_password.insert(id, pcmd);
+            return Ok(());
+        }
+
+        // the decision to restart is made when spawning, so only fresh spawns wait here
+        let now = Instant::now();
+        match self.spawn_paused_until {
+            Some(until) if until > now && exit_reason.is_none() => {
+                self.delay_spawn(until, pcmd);
+                return Err(PersistentCommandError::SpawnPaused(until - now));
+            }
+            _ => (),
+        }
+
+        let spawned = match pcmd.admit(exit_reason) {
+            Ok(()) => match pcmd.start_pre_start() {
+                // spawned once the hook exited, without waiting for it here
+                Some(hook) => {
+                    let pid = Pid::from_raw(hook.id() as i32);
+                    // known right away, so it is never mistaken for an orphan
+                    self.children.push(pid);
+                    let deadline = Instant::now().checked_add(pcmd.pre_start_limit());
+                    let pre = PreStarting {
+                        cmd: pcmd,
+                        exit_reason,
+                        deadline,
+                    };
+                    self.pre_starting.insert(pid, pre);
+                    return Ok(());
+                }
+                None => {
+                    if pcmd.needs_notify_socket() {
+                        self.set_up_notify_socket(&mut pcmd);
+                    }
+                    let cgroup = self.join_cgroup(pcmd.service_name(), pcmd.cgroup_limits());
+                    pcmd.spawn(self.log_mux.is_some(), cgroup)
+                }
+            },
+            Err(e) => Err(e),
+        };
+        let (mut child, stages) = match spawned {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                match &e {
+                    PersistentCommandError::SpawnRateLimited(wait)
+                    | PersistentCommandError::RestartDelayed(wait) => {
+                        match Instant::now().checked_add(*wait) {
+                            // keep the command around so it can be spawned once it is due
+                            Some(at) => self.delay_spawn(at, pcmd),
+                            // a delay no clock reaches is as good as not respawning at all
+                            None => {
+                                warn!(
+                                    "Not respawning {}, it is delayed for {:?}",
+                                    pcmd.service_name(),
+                                    wait
+                                );
+                                self.inactive_commands
+                                    .insert(pcmd.service_name().to_string(), pcmd);
+                            }
+                        }
+                    }
+                    PersistentCommandError::ResourcesExhausted(_) => {
+                        // likely to affect every spawn, so all of them are paused for a while
+                        let until = Instant::now() + RESOURCE_PAUSE;
+                        if self.spawn_paused_until.is_none_or(|paused| paused <= now) {
+                            warn!("Out of resources, pausing spawns for {:?}", RESOURCE_PAUSE);
+                            self.stats.spawn_pauses += 1;
+                            self.spawn_paused_until = Some(until);
+                        }
+                        self.delay_spawn(until, pcmd);
+                    }
+                    PersistentCommandError::ExecutableMissing(path) => {
+                        self.services
+                            .entry(pcmd.service_name().to_string())
+                            .or_default()
+                            .waiting_for_executable = Some(path.clone());
+                        self.delayed_commands
+                            .push((now + EXECUTABLE_POLL_INTERVAL, pcmd));
+                    }
+                    _ => {
+                        let permanent = match &e {
+                            PersistentCommandError::SpawnLimitReached(_)
+                            | PersistentCommandError::CrashLooping(_) => true,
+                            PersistentCommandError::MustNotRespawn(event) => {
+                                *event != Event::ExitSuccess
+                            }
+                            _ => false,
+                        };
+                        self.console.failed(pcmd.service_name(), &e.to_string());
+                        let reason = match &e {
+                            PersistentCommandError::SpawnLimitReached(_) => "spawn_limit",
+                            PersistentCommandError::CrashLooping(_) => "crash_loop",
+                            PersistentCommandError::MustNotRespawn(_) => "not_restarted",
+                            _ => "spawn_failed",
+                        };
+                        let status = self
+                            .services
+                            .entry(pcmd.service_name().to_string())
+                            .or_default();
+                        status.respawn_at = None;
+                        // a service which exited successfully and is not restarted is done
+                        if !matches!(
+                            e,
+                            PersistentCommandError::MustNotRespawn(Event::ExitSuccess)
+                        ) {
+                            status.failure = Some(reason.to_string());
+                        }
+                        self.record(ReaperEvent::Failed {
+                            service: pcmd.service_name().to_string(),
+                            reason: reason.to_string(),
+                        });
+                        if let PersistentCommandError::SpawnLimitReached(_) = e {
+                            self.record(ReaperEvent::SpawnLimit(pcmd.service_name().to_string()));
+                        }
+                        if permanent {
+                            self.failed_permanently(&pcmd);
+                        }
+                        self.inactive_commands
+                            .insert(pcmd.service_name().to_string(), pcmd);
+                    }
+                }
+                return Err(e);
+            }
+        };
+        let pid = Pid::from_raw(child.id() as i32);
+        let status = self
+            .services
+            .entry(pcmd.service_name().to_string())
+            .or_default();
+        // counted as a restart if the service ran before
+        let restarted = status.started_at.is_some();
+        status.spawned(pid, pcmd.notifies());
+        let restarts = status.restarts;
+        self.record(ReaperEvent::Spawned {
+            service: pcmd.service_name().to_string(),
+            pid,
+        });
+        if restarted {
+            self.record(ReaperEvent::Restarted {
+                service: pcmd.service_name().to_string(),
+                pid,
+                restarts,
+            });
+        }
+        self.console
+            .spawned(pcmd.service_name(), !pcmd.notifies(), Instant::now());
+        self.boot_times
+            .spawned(pcmd.service_name(), !pcmd.notifies());
+        if let Some(mux) = &self.log_mux {
+            mux.attach(
+                pid,
+                pcmd.service_name(),
+                vec![
+                    child.stdout.take().map(IntoRawFd::into_raw_fd),
+                    child.stderr.take().map(IntoRawFd::into_raw_fd),
+                ],
+            );
+        }
+        for mut stage in stages {
+            let stage_pid = Pid::from_raw(stage.id() as i32);
+            if let Some(mux) = &self.log_mux {
+                mux.attach(
+                    stage_pid,
+                    pcmd.service_name(),
+                    vec![
+                        stage.stdout.take().map(IntoRawFd::into_raw_fd),
+                        stage.stderr.take().map(IntoRawFd::into_raw_fd),
+                    ],
+                );
+            }
+            self.pipeline_stages.insert(stage_pid, pid);
+            self.children.push(stage_pid);
+        }
+        self.monitor_health(&pcmd);
+        self.persistent_commands_map.insert(pid, pcmd);
+        // the process leads its own session, and thus its own process group
+        self.process_groups.insert(pid, pid);
+        match PidFd::open(pid.into()) {
+            Ok(pidfd) => {
+                self.pidfds.insert(pid, pidfd);
+            }
+            // kernels before 5.3, the pid is used instead
+            Err(e) => debug!("Failed to open a pidfd for {}: {}", pid, e),
+        }
+        // known right away, so it is never mistaken for an orphan of another process
+        self.children.push(pid);
+
+        Ok(())
+    }
+
+    /// Start the health checks of a command of which the process was just started.
+    fn monitor_health(&mut self, pcmd: &PersistentCommand) {
+        let monitor = match (pcmd.startup(), pcmd.health()) {
+            (Some(startup), liveness) => Some(HealthMonitor::starting(
+                startup.clone(),
+                liveness.cloned(),
+                Instant::now(),
+            )),
+            (None, Some(check)) => Some(HealthMonitor::new(check.clone(), Instant::now())),
+            (None, None) => None,
+        };
+        if let Some(monitor) = monitor {
+            self.health_monitors
+                .insert(pcmd.service_name().to_string(), monitor);
+        }
+    }
+
+    /// Spawn all commands of which the spawn was delayed, and which are now due.
+    fn spawn_delayed_commands(&mut self, now: Instant) {
+        let (due, pending) = self
+            .delayed_commands
+            .drain(..)
+            .partition(|(at, _)| *at <= now);
+        self.delayed_commands = pending;
+
+        for (_, cmd) in due {
+            let cmd_name = format!("{}", cmd);
+            // the decision to respawn has already been made
+            match self.spawn_persistent_command(cmd, None) {
+                Ok(_) => (),
+                // reported when it went missing, this is polled until it appears
+                Err(PersistentCommandError::ExecutableMissing(_)) => (),
+                Err(e) => warn!("Failed to spawn delayed command ({}): {}", cmd_name, e),
+            }
+        }
+    }
+
+    fn ensure_process(
+        &mut self,
+        pid: &Pid,
+        event: Option<Event>,
+    ) -> Result<(), PersistentCommandError> {
+        if let Some(cmd) = self.persistent_commands_map.remove(pid) {
+            if self.shutting_down {
+                debug!("Not respawning {} while shutting down", cmd.service_name());
+                self.inactive_commands
+                    .insert(cmd.service_name().to_string(), cmd);
+                return Ok(());
+            }
+            self.spawn_persistent_command(cmd, event)?;
+        }
+        Ok(())
+    }
+
+    /// Add the descendants of a process to its children. If the lineage of processes is
+    /// followed, these include processes that were reparented to another process. Otherwise
+    /// only the descendants of its children are found, by the parents of the processes in
+    /// /proc.
+    fn with_descendants(&self, pid: Pid, mut children: Vec<Pid>) -> Vec<Pid> {
+        let descendants: Vec<Pid> = if self.follows_lineage() {
+            self.process_tree.descendants(pid)
+        } else {
+            let tree = scan_lineage(&*self.processes);
+            children
+                .iter()
+                .flat_map(|child| tree.descendants(*child))
+                .collect()
+        };
+        for descendant in descendants {
+            if !children.contains(&descendant) {
+                children.push(descendant);
+            }
+        }
+        children
+    }
+
+    /// Track the lineage of processes from the pending process events.
+    fn handle_process_events(&mut self) {
+        let mut events = Vec::new();
+        #[cfg(feature = "bpf")]
+        if let Some(source) = &mut self.process_events {
+            events.extend(source.read());
+        }
+        if let Some(connector) = &mut self.proc_connector {
+            events.extend(connector.read());
+            if connector.take_lost() {
+                warn!("Process events were lost, reading the processes from /proc again");
+                self.read_lineage();
+                return;
+            }
+        }
+        for event in events {
+            match event {
+                ProcessEvent::Fork { parent, child } => self.process_tree.forked(parent, child),
+                ProcessEvent::Exit(pid) => {
+                    // orphans which are not our children are reaped by someone else
+                    self.orphans.remove(&pid);
+                    // the lineage of a child is needed once it is reaped
+                    if !self.persistent_commands_map.contains_key(&pid)
+                        && !self.children.contains(&pid)
+                    {
+                        self.process_tree.forget(pid);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check if the lineage of processes is followed through process events.
+    fn follows_lineage(&self) -> bool {
+        #[cfg(feature = "bpf")]
+        if self.process_events.is_some() {
+            return true;
+        }
+        self.proc_connector.is_some()
+    }
+
+    /// Start the lineage of processes over from the parents of the processes in /proc, which
+    /// misses processes which were reparented already.
+    fn read_lineage(&mut self) {
+        self.process_tree = scan_lineage(&*self.processes);
+    }
+
+    /// Find the processes an exited process left behind, which become children of the reaper
+    /// if it is PID 1 or a child subreaper. These are known from the lineage of processes if
+    /// it is followed through the proc connector, and found by scanning /proc otherwise. The
+    /// exited process is forgotten as a child.
+    fn left_behind(&mut self, pid: Pid) -> Result<Vec<Pid>, ReaperError> {
+        if self.proc_connector.is_none() {
+            let reparented = self.new_children()?;
+            return Ok(self.orphans_of(pid, reparented));
+        }
+        // the exit of the process was reported before it could be reaped
+        self.handle_process_events();
+        self.children.retain(|child| *child != pid);
+        if i32::from(self.pid) != 1 && !self.subreaper {
+            return Ok(Vec::new());
+        }
+        let children = self.process_tree.children(pid);
+        for child in &children {
+            if !self.children.contains(child) {
+                self.children.push(*child);
+            }
+        }
+        Ok(children)
+    }
+
+    /// Pick the processes the exited process left behind from the processes which were just
+    /// reparented to the reaper, which may have been left behind by another process which
+    /// exited around the same time. A process is attributed to the process it is known to
+    /// descend from, or else to the service which leads its process group. The processes which
+    /// are left are attributed to the exited process, as nothing else is known about them. The
+    /// processes of another process are found again once that one is reaped.
+    fn orphans_of(&mut self, pid: Pid, reparented: Vec<Pid>) -> Vec<Pid> {
+        let mut orphans = Vec::new();
+        for child in reparented {
+            let ancestors = self.process_tree.ancestors(child);
+            let owner = if ancestors.contains(&pid) {
+                None
+            } else if let Some(ancestor) = ancestors.into_iter().find(|a| *a != self.pid) {
+                Some(ancestor)
+            } else {
+                let pgrp = self.processes.stat(child).map(|stat| stat.pgrp);
+                self.process_groups
+                    .iter()
+                    .find(|(leader, pgid)| **leader != pid && Some(**pgid) == pgrp)
+                    .map(|(leader, _)| *leader)
+            };
+            match owner {
+                Some(owner) => {
+                    debug!("{} was left behind by {}, not by {}", child, owner, pid);
+                    self.children.retain(|known| *known != child);
+                }
+                None => orphans.push(child),
+            }
+        }
+        orphans
+    }
+
+    /// Record which processes the processes of the services forked, while they are still
+    /// around, so what they leave behind is attributed to them once it is reparented. This is
+    /// only needed when the lineage is not followed through process events. Processes which
+    /// are gone are forgotten, and their children are attributed to their parent.
+    fn record_lineage(&mut self) {
+        if self.follows_lineage() {
+            return;
+        }
+        let mut seen: HashSet<Pid> = HashSet::new();
+        let mut parents: Vec<Pid> = self.persistent_commands_map.keys().copied().collect();
+        while let Some(parent) = parents.pop() {
+            if !seen.insert(parent) {
+                continue;
+            }
+            for child in self.processes.children(parent).unwrap_or_default() {
+                self.process_tree.record(parent, child);
+                parents.push(child);
+            }
+        }
+        for pid in self.process_tree.known() {
+            if !seen.contains(&pid) && self.processes.kill(pid, None).is_err() {
+                self.process_tree.forget(pid);
+            }
+        }
+    }
+
+    /// Send SIGTERM to the orphans a failed process left behind, unless they are exempted or
+    /// supervised themselves.
+    /// Orphans in the swept process group already received SIGTERM through their group.
+    fn mark_orphans(&mut self, children: &[Pid], swept: Option<Pid>) {
+        for pid in children {
+            if self.persistent_commands_map.contains_key(pid) || self.orphans.contains_key(pid) {
+                continue;
+            }
+            if let Some(owner) = self.delegated.get(pid) {
+                debug!("Leaving {} to {}, which supervises it", pid, owner);
+                continue;
+            }
+            // these are cleaned up along with the cgroup of their service
+            if self
+                .cgroups
+                .as_ref()
+                .is_some_and(|root| root.contains(*pid))
+            {
+                continue;
+            }
+            if swept.is_some() && getpgid(Some(*pid)).ok() == swept {
+                self.track_orphan(*pid, OrphanState::HasBeenSentSIGTERM(Instant::now()));
+                continue;
+            }
+            if self.is_exempted(*pid) {
+                info!("Orphan {} is exempted, letting it live", pid);
+                continue;
+            }
+            debug!("Sending SIGTERM to orphan {}", pid);
+            let state = match self.processes.kill(*pid, Some(Signal::SIGTERM)) {
+                Ok(_) => {
+                    self.orphan_signaled(*pid, Signal::SIGTERM);
+                    OrphanState::HasBeenSentSIGTERM(Instant::now())
+                }
+                Err(e) => {
+                    warn!("Failed to send SIGTERM to orphan {}: {}", pid, e);
+                    OrphanState::errored(e, Instant::now())
+                }
+            };
+            self.track_orphan(*pid, state);
+        }
+    }
+
+    /// Tell the observers an orphan, or a process group if the pid is negative, was signaled.
+    fn orphan_signaled(&mut self, pid: Pid, signal: Signal) {
+        self.record(ReaperEvent::OrphanSignaled { pid, signal });
+    }
+
+    /// Check if the process is exempted from being terminated as an orphan.
+    fn is_exempted(&self, pid: Pid) -> bool {
+        self.orphan_exemptions
+            .iter()
+            .any(|exemption| match exemption {
+                OrphanExemption::Service(name) => self
+                    .persistent_commands_map
+                    .iter()
+                    .filter(|(_, cmd)| cmd.service_name() == name)
+                    .any(|(main, _)| {
+                        *main == pid || self.process_tree.descendants(*main).contains(&pid)
+                    }),
+                exemption => exemption.matches(pid),
+            })
+    }
+
+    /// Exempt a running process from being terminated as an orphan until it is reaped, and stop
+    /// terminating it if that started already.
+    fn protect(&mut self, pid: Pid) -> Result<Value, String> {
+        if let Err(e) = self.processes.kill(pid, None) {
+            return Err(format!("no process {}: {}", pid, e));
+        }
+        if self.orphans.remove(&pid).is_some() {
+            warn!("Protecting orphan {}, which was being terminated", pid);
+        } else {
+            info!("Protecting {} from being terminated as an orphan", pid);
+        }
+        let exemption = OrphanExemption::Pid(pid);
+        if !self.orphan_exemptions.contains(&exemption) {
+            self.orphan_exemptions.push(exemption);
+        }
+        Ok(Value::object().with("protected", i32::from(pid)))
+    }
+
+    /// Track an orphan until it is reaped. If too many orphans are tracked already, the one
+    /// which lingers the longest, or else the one signaled first, is given up on.
+    fn track_orphan(&mut self, pid: Pid, state: OrphanState) {
+        if self.orphans.len() >= MAX_TRACKED_ORPHANS {
+            let oldest = self
+                .orphans
+                .iter()
+                .min_by_key(|(_, state)| (!state.is_lingering(), state.since()))
+                .map(|(pid, state)| (*pid, *state));
+            if let Some((oldest, old_state)) = oldest {
+                warn!(
+                    "Tracking too many orphans, giving up on orphan {} ({:?})",
+                    oldest, old_state
+                );
+                self.orphans.remove(&oldest);
+                self.stats.orphans_expired += 1;
+            }
+        }
+        self.orphans.insert(pid, state);
+    }
+
+    /// Create the notification socket of the service of the command, if it doesn't exist yet,
+    /// and pass it to the command.
+    fn set_up_notify_socket(&mut self, pcmd: &mut PersistentCommand) {
+        let name = pcmd.service_name().to_string();
+        if !self.notify_sockets.contains_key(&name) {
+            let dir = self.notify_dir();
+            let socket = NotifySocket::bind(&dir, &name).and_then(|socket| {
+                self.events.watch(socket.as_raw_fd())?;
+                Ok(socket)
+            });
+            match socket {
+                Ok(socket) => {
+                    self.notify_sockets.insert(name.clone(), socket);
+                }
+                Err(e) => {
+                    // the service never becomes ready, so it is noticed
+                    error!("Failed to create notification socket for {}: {}", name, e);
+                    return;
+                }
+            }
+        }
+        pcmd.set_notify_socket(self.notify_sockets[&name].path());
+    }
+
+    /// When the watchdog of every running service which has one expires, unless it is pinged
+    /// before then.
+    fn watchdog_expiries(&self) -> Vec<(Pid, Instant)> {
+        self.persistent_commands_map
+            .iter()
+            .filter_map(|(pid, cmd)| {
+                let interval = cmd.watchdog_interval()?;
+                let status = self.services.get(cmd.service_name())?;
+                let last = status.last_watchdog.or(status.started_at)?;
+                Some((*pid, last + interval))
+            })
+            .collect()
+    }
+
+    /// Record that a child was stopped by a signal, and continue it if it belongs to a service
+    /// and the policy says so.
+    fn child_stopped(&mut self, pid: Pid, signal: Signal) {
+        let service = self
+            .persistent_commands_map
+            .get(&pid)
+            .map(|cmd| cmd.service_name().to_string());
+        self.record(ReaperEvent::Stopped {
+            service: service.clone(),
+            pid,
+            signal,
+        });
+        let name = match service {
+            Some(name) => name,
+            None => {
+                debug!("Child {} was stopped by {:?}", pid, signal);
+                return;
+            }
+        };
+        info!("{} ({}) was stopped by {:?}", name, pid, signal);
+        if let Some(status) = self.services.get_mut(&name) {
+            status.stopped_since = Some(Instant::now());
+        }
+        if self.stop_policy == Some(StopPolicy::Continue) {
+            info!("Continuing {}", name);
+            if let Err(e) = self.signal_service(pid, Signal::SIGCONT) {
+                warn!("Failed to continue {}: {}", name, e);
+            }
+        }
+    }
+
+    /// Record that a child which was stopped was continued.
+    fn child_continued(&mut self, pid: Pid) {
+        let service = self
+            .persistent_commands_map
+            .get(&pid)
+            .map(|cmd| cmd.service_name().to_string());
+        self.record(ReaperEvent::Continued {
+            service: service.clone(),
+            pid,
+        });
+        let name = match service {
+            Some(name) => name,
+            None => {
+                debug!("Child {} was continued", pid);
+                return;
+            }
+        };
+        info!("{} ({}) was continued", name, pid);
+        if let Some(status) = self.services.get_mut(&name) {
+            status.stopped_since = None;
+        }
+    }
+
+    /// When every stopped service is killed for being stopped for too long, if the policy says
+    /// so.
+    fn stop_expiries(&self) -> Vec<(Pid, Instant)> {
+        let timeout = match self.stop_policy {
+            Some(StopPolicy::KillAfter(timeout)) => timeout,
+            _ => return Vec::new(),
+        };
+        self.persistent_commands_map
+            .iter()
+            .filter_map(|(pid, cmd)| {
+                let since = self.services.get(cmd.service_name())?.stopped_since?;
+                Some((*pid, since + timeout))
+            })
+            .collect()
+    }
+
+    /// Kill the services which have been stopped for too long. They are then handled like any
+    /// other service which got killed.
+    fn check_stopped(&mut self, now: Instant) {
+        for (pid, _) in self
+            .stop_expiries()
+            .into_iter()
+            .filter(|(_, at)| *at <= now)
+        {
+            let name = self.persistent_commands_map[&pid]
+                .service_name()
+                .to_string();
+            warn!("{} has been stopped for too long, killing it", name);
+            if let Err(e) = self.signal_service(pid, Signal::SIGKILL) {
+                warn!("Failed to kill {}: {}", name, e);
+            }
+            // a process which can't be killed right away is only killed again after another
+            // timeout
+            if let Some(status) = self.services.get_mut(&name) {
+                status.stopped_since = Some(now);
+            }
+        }
+    }
+
+    /// Kill the services which did not ping their watchdog in time. They are then handled like
+    /// any other service which got killed.
+    fn check_watchdogs(&mut self, now: Instant) {
+        for (pid, _) in self
+            .watchdog_expiries()
+            .into_iter()
+            .filter(|(_, at)| *at <= now)
+        {
+            let name = self.persistent_commands_map[&pid]
+                .service_name()
+                .to_string();
+            warn!("{} did not ping its watchdog in time, killing it", name);
+            self.record(ReaperEvent::WatchdogExpired(name.clone()));
+            if let Err(e) = self.signal_service(pid, Signal::SIGKILL) {
+                warn!("Failed to kill {}: {}", name, e);
+            }
+            // a process which can't be killed right away is only killed again after another
+            // interval
+            if let Some(status) = self.services.get_mut(&name) {
+                status.last_watchdog = Some(now);
+            }
+        }
+    }
+
+    /// Run the timer commands which are due.
+    fn run_due_timers(&mut self, now: Instant) {
+        for timer in &mut self.timers {
+            let capture = self.log_mux.is_some();
+            let mut child = match timer.run_if_due(now, capture, self.helper_death_signal) {
+                Some(child) => child,
+                None => continue,
+            };
+            let pid = Pid::from_raw(child.id() as i32);
+            if let Some(mux) = &self.log_mux {
+                mux.attach(
+                    pid,
+                    timer.service_name(),
+                    vec![
+                        child.stdout.take().map(IntoRawFd::into_raw_fd),
+                        child.stderr.take().map(IntoRawFd::into_raw_fd),
+                    ],
+                );
+            }
+            // known right away, so it is never mistaken for an orphan
+            self.children.push(pid);
+        }
+    }
+
+    /// Probe the running services which have a health check, and restart the ones which became
+    /// unhealthy.
+    fn check_health(&mut self, now: Instant) {
+        // the probes of services which are gone are stopped, as are the startup checks which
+        // passed without a health check to take over
+        let services = &self.services;
+        self.health_monitors.retain(|name, monitor| {
+            !monitor.is_done() && services.get(name).is_some_and(ServiceStatus::is_running)
+        });
+
+        let mut checked = Vec::new();
+        for (name, monitor) in &mut self.health_monitors {
+            let unhealthy = monitor.poll(now);
+            if let Some(pid) = monitor.start_if_due(now, self.helper_death_signal) {
+                // known right away, so it is never mistaken for an orphan
+                self.children.push(pid);
+            }
+            checked.push((name.clone(), unhealthy));
+        }
+        for (name, unhealthy) in checked {
+            self.health_checked(&name, unhealthy);
+        }
+    }
+
+    /// Update the health of a service after a probe, restarting it if it became unhealthy.
+    fn health_checked(&mut self, name: &str, unhealthy: bool) {
+        let healthy = self
+            .health_monitors
+            .get(name)
+            .map(HealthMonitor::is_healthy);
+        if let Some(status) = self.services.get_mut(name) {
+            status.healthy = healthy;
+        }
+        if !unhealthy {
+            return;
+        }
+        if self
+            .health_monitors
+            .get(name)
+            .is_some_and(HealthMonitor::is_starting)
+        {
+            warn!("{} failed its startup check, restarting it", name);
+        } else {
+            warn!("{} is unhealthy, restarting it", name);
+        }
+        self.record(ReaperEvent::Unhealthy(name.to_string()));
+        // probing starts over once it is spawned again
+        self.health_monitors.remove(name);
+        if let Err(e) = self.restart_service(name) {
+            warn!("Failed to restart {}: {}", name, e);
+        }
+    }
+
+    /// Report a jump of the wall clock. Timers run on the monotonic clock, so they are not
+    /// affected.
+    fn handle_clock_jump(&mut self) {
+        let jump = match self.clock.as_mut().and_then(ClockWatch::jump) {
+            Some(jump) => jump,
+            None => return,
+        };
+        self.stats.clock_jumps += 1;
+        let (backward, by) = match jump {
+            ClockJump::Forward(by) => {
+                info!("System clock jumped forward by {:?}", by);
+                (false, by)
+            }
+            ClockJump::Backward(by) => {
+                warn!("System clock jumped backward by {:?}", by);
+                (true, by)
+            }
+        };
+        self.record(ReaperEvent::ClockJumped { backward, by });
+        // the heartbeat holds the wall clock time, which would look stale or from the future
+        if let Some(heartbeat) = &self.heartbeat {
+            heartbeat.beat();
+        }
+        let now = Instant::now();
+        for timer in &mut self.timers {
+            timer.clock_changed(now);
+        }
+    }
+
+    /// Apply the notifications the service sent through the socket with the given fd.
+    fn handle_notifications(&mut self, fd: RawFd) {
+        let (name, socket) = match self
+            .notify_sockets
+            .iter()
+            .find(|(_, socket)| socket.as_raw_fd() == fd)
+        {
+            Some(found) => found,
+            None => return,
+        };
+        let status = match self.services.get_mut(name) {
+            Some(status) => status,
+            None => return,
+        };
+        let cgroup = self.service_cgroups.get(name);
+        let mut became_ready = false;
+        for (sender, notification) in socket.notifications() {
+            // anyone can send to the socket, only the service itself is listened to
+            if status.pid != Some(sender)
+                && !cgroup.is_some_and(|cgroup| cgroup.procs().contains(&sender))
+            {
+                warn!(
+                    "Dropping notification for {} from process {}, which is not part of it",
+                    name, sender
+                );
+                continue;
+            }
+            match notification {
+                Notification::Ready => {
+                    if !status.ready {
+                        info!("{} is ready", name);
+                        self.console.ready(name, Instant::now());
+                        self.boot_times.ready(name);
+                        became_ready = true;
+                    }
+                    status.ready = true;
+                }
+                Notification::Status(text) => {
+                    debug!("{}: {}", name, text);
+                    status.status_text = Some(text);
+                }
+                Notification::Watchdog => status.last_watchdog = Some(Instant::now()),
+            }
+        }
+        if became_ready {
+            let name = name.clone();
+            self.record(ReaperEvent::Ready(name));
+        }
+    }
+
+    /// Prepare placing a new process of a service in its cgroup, if cgroups are used, with the
+    /// limits of the service enforced. A service is spawned outside of a cgroup if its cgroup
+    /// can't be created, and without the limits which can't be enforced.
+    fn join_cgroup(&mut self, name: &str, limits: &CgroupLimits) -> Option<PreparedWrite> {
+        let root = match self.cgroups.as_ref() {
+            Some(root) => root,
+            None => {
+                if !limits.is_empty() {
+                    warn!(
+                        "Not limiting the resources of {}, cgroups are not used",
+                        name
+                    );
+                }
+                return None;
+            }
+        };
+        if !self.service_cgroups.contains_key(name) {
+            match root.service(name) {
+                Ok(cgroup) => {
+                    self.service_cgroups.insert(name.to_string(), cgroup);
+                }
+                Err(e) => {
+                    warn!("Failed to create cgroup for {}: {}", name, e);
+                    return None;
+                }
+            }
+        }
+        if let Err(e) = root.limit(&self.service_cgroups[name], limits) {
+            warn!("Failed to limit the resources of {}: {}", name, e);
+        }
+        match self.service_cgroups[name].join_on_exec() {
+            Ok(join) => Some(join),
+            Err(e) => {
+                warn!("Failed to prepare joining cgroup of {}: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Kill the processes a failed service left in its cgroup, after moving the exempted ones
+    /// out of it.
+    fn kill_cgroup(&mut self, cgroup: &Cgroup, procs: &[Pid]) {
+        let root = match &self.cgroups {
+            Some(root) => root,
+            None => return,
+        };
+        for pid in procs {
+            if self.is_exempted(*pid) {
+                info!("Orphan {} is exempted, letting it live", pid);
+                if let Err(e) = root.exempt(*pid) {
+                    warn!(
+                        "Failed to move exempted orphan {} out of its cgroup: {}",
+                        pid, e
+                    );
+                }
+            }
+        }
+        match cgroup.kill() {
+            Ok(killed) => {
+                debug!("Killed {} orphans through their cgroup", killed);
+                self.stats.orphans_terminated += killed as u64;
+            }
+            Err(e) => warn!("Failed to kill cgroup: {}", e),
+        }
+    }
+
+    /// Send SIGTERM to the process group a failed service led, in a single sweep, so processes
+    /// which fork in the meantime can't escape. A group with an exempted process in it is left
+    /// alone. Returns whether the group was signaled.
+    fn terminate_group(&mut self, pgid: Pid) -> bool {
+        if !self.orphan_exemptions.is_empty() {
+            let members = match self.processes.group(pgid) {
+                Ok(members) => members,
+                Err(e) => {
+                    // without the members, it is unknown whether any of them is exempted
+                    warn!("Failed to list process group {}: {}", pgid, e);
+                    return false;
+                }
+            };
+            let exempted = members.into_iter().find(|pid| self.is_exempted(*pid));
+            if let Some(pid) = exempted {
+                info!(
+                    "Process group {} contains exempted process {}, not signaling the group",
+                    pgid, pid
+                );
+                return false;
+            }
+        }
+        debug!("Sending SIGTERM to process group {}", pgid);
+        match self.processes.kill(group(pgid), Some(Signal::SIGTERM)) {
+            Ok(_) => {
+                self.orphan_groups
+                    .insert(pgid, OrphanState::HasBeenSentSIGTERM(Instant::now()));
+                self.orphan_signaled(group(pgid), Signal::SIGTERM);
+                true
+            }
+            // nothing is left in the group
+            Err(nix::Error::Sys(Errno::ESRCH)) => false,
+            Err(e) => {
+                warn!("Failed to send SIGTERM to process group {}: {}", pgid, e);
+                false
+            }
+        }
+    }
+
+    /// Send SIGKILL to orphans which ignored SIGTERM for too long, and report the ones which
+    /// are still around after that.
+    fn escalate_orphans(&mut self, now: Instant) {
+        let timeout = self.orphan_kill_timeout;
+        let processes = &self.processes;
+        let unkillable = &mut self.unkillable;
+        let mut killed = Vec::new();
+        self.orphan_groups.retain(|pgid, state| {
+            // a process group is gone once its last process is, which is only noticed by
+            // trying to signal it
+            let alive = processes.kill(group(*pgid), None).is_ok();
+            match *state {
+                OrphanState::HasBeenSentSIGTERM(at)
+                    if alive && now.duration_since(at) >= timeout =>
+                {
+                    info!(
+                        "Process group {} did not exit after SIGTERM, sending SIGKILL",
+                        pgid
+                    );
+                    *state = match processes.kill(group(*pgid), Some(Signal::SIGKILL)) {
+                        Ok(_) => {
+                            killed.push(group(*pgid));
+                            OrphanState::HasBeenSentSIGKILL(now)
+                        }
+                        Err(e) => {
+                            warn!("Failed to send SIGKILL to process group {}: {}", pgid, e);
+                            OrphanState::errored(e, now)
+                        }
+                    };
+                }
+                OrphanState::HasBeenSentSIGKILL(at)
+                    if alive && now.duration_since(at) >= ORPHAN_EXPIRY =>
+                {
+                    warn!(
+                        "Process group {} is still lingering {:?} after SIGKILL, giving up on it",
+                        pgid, ORPHAN_EXPIRY
+                    );
+                    return false;
+                }
+                OrphanState::HasBeenSentSIGKILL(at)
+                    if alive && now.duration_since(at) >= timeout =>
+                {
+                    for pid in processes.group(*pgid).unwrap_or_default() {
+                        if let Some(stat) = processes.stat(pid) {
+                            verify_killed(unkillable, pid, &stat);
+                        }
+                    }
+                }
+                OrphanState::HasBeenSentSIGKILL(_) if alive => {
+                    debug!("Process group {} is lingering after SIGKILL", pgid)
+                }
+                OrphanState::Errored(errno, _) if alive => {
+                    debug!("Process group {} could not be signaled: {}", pgid, errno)
+                }
+                _ => (),
+            }
+            alive && !matches!(state, OrphanState::Errored(..))
+        });
+
+        // orphans which are not our children are reaped by someone else, which is only noticed
+        // by them being gone from /proc
+        let mut gone = 0;
+        let processes = &self.processes;
+        self.orphans.retain(|pid, state| {
+            if !state.is_lingering() || processes.stat(*pid).is_some() {
+                return true;
+            }
+            debug!("Orphan {} is gone, no longer tracking it", pid);
+            if let OrphanState::HasBeenSentSIGKILL(_) = state {
+                gone += 1;
+            }
+            false
+        });
+        self.stats.orphans_terminated += gone;
+
+        for (pid, state) in self.orphans.iter_mut() {
+            match *state {
+                OrphanState::HasBeenSentSIGTERM(at) if now.duration_since(at) >= timeout => {
+                    info!("Orphan {} did not exit after SIGTERM, sending SIGKILL", pid);
+                    *state = match self.processes.kill(*pid, Some(Signal::SIGKILL)) {
+                        Ok(_) => {
+                            killed.push(*pid);
+                            OrphanState::HasBeenSentSIGKILL(now)
+                        }
+                        Err(e) => {
+                            warn!("Failed to send SIGKILL to orphan {}: {}", pid, e);
+                            OrphanState::errored(e, now)
+                        }
+                    };
+                }
+                OrphanState::HasBeenSentSIGTERM(_) => (),
+                OrphanState::HasBeenSentSIGKILL(at) if now.duration_since(at) >= timeout => {
+                    if let Some(stat) = self.processes.stat(*pid) {
+                        verify_killed(&mut self.unkillable, *pid, &stat);
+                    }
+                }
+                OrphanState::HasBeenSentSIGKILL(_) => {
+                    debug!("Orphan {} is lingering after SIGKILL", pid)
+                }
+                OrphanState::Errored(errno, _) => {
+                    debug!("Orphan {} could not be signaled: {}", pid, errno)
+                }
+            }
+        }
+
+        for pid in killed {
+            self.orphan_signaled(pid, Signal::SIGKILL);
+        }
+
+        // they are still reaped if they exit after all, as any other child
+        let mut expired = 0;
+        self.orphans.retain(|pid, state| {
+            if !state.is_lingering() || now.duration_since(state.since()) < ORPHAN_EXPIRY {
+                return true;
+            }
+            match state {
+                OrphanState::Errored(errno, _) => warn!(
+                    "Giving up on orphan {}, which could not be signaled for {:?}: {}",
+                    pid, ORPHAN_EXPIRY, errno
+                ),
+                _ => warn!(
+                    "Giving up on orphan {}, which is still lingering {:?} after SIGKILL",
+                    pid, ORPHAN_EXPIRY
+                ),
+            }
+            expired += 1;
+            false
+        });
+        self.stats.orphans_expired += expired;
+    }
+
+    /// Forget about the unkillable processes which are gone after all, and act on the ones
+    /// which are not, as configured. Returns if the system is to be restarted because of them.
+    fn check_unkillable(&mut self) -> bool {
+        let processes = &self.processes;
+        self.unkillable.retain(|pid, start_time| {
+            let alive = processes
+                .stat(*pid)
+                .is_some_and(|stat| stat.start_time == *start_time && stat.state != 'Z');
+            if !alive {
+                info!("Unkillable process {} is gone after all", pid);
+            }
+            alive
+        });
+        self.stats.unkillable_processes = self.unkillable.len();
+        if self.unkillable.is_empty() {
+            self.unkillable_ticks = 0;
+            self.stats.unhealthy = false;
+            return false;
+        }
+
+        self.unkillable_ticks += 1;
+        let mut reboot = false;
+        for action in self.unkillable_actions.clone() {
+            match action {
+                UnkillableAction::RunCommand(action) if self.unkillable_ticks == 1 => {
+                    let hook = command::hook(action);
+                    info!("Processes can't be killed, running {}", hook);
+                    self.spawn_hook(&hook, "unkillable action".to_string());
+                }
+                UnkillableAction::RunCommand(_) => (),
+                UnkillableAction::MarkUnhealthy => self.stats.unhealthy = true,
+                UnkillableAction::Reboot(ticks) if self.unkillable_ticks >= ticks => {
+                    reboot = !self.shutting_down;
+                }
+                UnkillableAction::Reboot(_) => (),
+            }
+        }
+        if reboot {
+            error!(
+                "Processes could not be killed for {} ticks, rebooting",
+                self.unkillable_ticks
+            );
+        }
+        reboot
+    }
+
+    /// Apply the configured priority and memory lock to the reaper. Whatever fails to apply is
+    /// logged, and the reaper carries on without it.
+    fn apply_scheduling(&self) {
+        if let Some(nice) = self.nice {
+            if let Err(e) = sys::set_nice(nice) {
+                warn!(
+                    "Failed to set the nice value of the reaper to {}: {}",
+                    nice, e
+                );
+            }
+        }
+        // children are reset to the normal policy regardless, as a negative nice value is only
+        // reset through the policy
+        let (policy, priority) = match self.realtime_priority {
+            Some(priority) => (libc::SCHED_FIFO, priority),
+            None => (libc::SCHED_OTHER, 0),
+        };
+        if self.nice.is_some() || self.realtime_priority.is_some() {
+            if let Err(e) = sys::set_scheduler(policy, priority, true) {
+                warn!("Failed to set the scheduling policy of the reaper: {}", e);
+            }
+        }
+        if self.lock_memory {
+            if let Err(e) = sys::lock_memory() {
+                warn!("Failed to lock the memory of the reaper: {}", e);
+            }
+        }
+    }
+
+    /// Tell the observers about an event, record it in the event log, if there is one, and
+    /// count it for the metrics.
+    fn record(&mut self, event: ReaperEvent) {
+        for observer in &mut self.observers {
+            observer.on_event(&event);
+        }
+        if let Some(log) = &mut self.event_log {
+            log.record(&event);
+        }
+        *self.event_counts.entry(event.name()).or_default() += 1;
+    }
+
+    /// Record the exit of a process in the status of its service, returning the name of the
+    /// service if there is one.
+    fn service_exited(&mut self, pid: &Pid, event: Event, reason: Option<&str>) -> Option<String> {
+        let (name, status) = self
+            .services
+            .iter_mut()
+            .find(|(_, status)| status.pid == Some(*pid))?;
+        status.exited(event, reason);
+        Some(name.clone())
+    }
+
+    /// Accept the connections on the control socket, and watch them for their requests.
+    fn accept_control_clients(&mut self) {
+        let accepted = match &mut self.control {
+            Some(control) => control.accept(),
+            None => return,
+        };
+        for fd in accepted {
+            if let Err(e) = self.events.watch(fd) {
+                warn!("Failed to watch control connection: {}", e);
+            }
+        }
+    }
+
+    /// Answer the request of a control client, once it fully arrived.
+    fn handle_control_request(&mut self, fd: RawFd) {
+        let (request, client) = match self.control.as_mut().and_then(|c| c.read_request(fd)) {
+            Some(found) => found,
+            None => return,
+        };
+        debug!("Handling control request {:?}", request);
+        let reply = match request {
+            Request::List => {
+                let mut names: Vec<&String> = self.services.keys().collect();
+                names.sort();
+                let services: Vec<Value> = names
+                    .into_iter()
+                    .map(|name| status_json(name, &self.services[name]))
+                    .collect();
+                Ok(Value::object().with("services", services))
+            }
+            Request::Inspect(name) => self
+                .services
+                .get(&name)
+                .map(|status| inspect_json(&name, status.pid))
+                .ok_or_else(|| format!("unknown service {}", name)),
+            Request::Logs(name) => self.logs(&name),
+            Request::Status(target) => match Target::parse(&target) {
+                Target::Service(name) => self
+                    .services
+                    .get(&name)
+                    .map(|status| {
+                        status_json(&name, status)
+                            .with("history", history_json(status))
+                            .with("blocking", self.blocked_by(&name))
+                    })
+                    .ok_or_else(|| format!("unknown service {}", name)),
+                target => self.resolve_target(&target).map(|names| {
+                    let services: Vec<Value> = names
+                        .iter()
+                        .filter_map(|name| {
+                            self.services
+                                .get(name)
+                                .map(|status| status_json(name, status))
+                        })
+                        .collect();
+                    Value::object().with("services", services)
+                }),
+            },
+            Request::Start(target) => {
+                self.apply_to_target(&target, "started", false, Self::start_service)
+            }
+            Request::Stop(target) => {
+                self.apply_to_target(&target, "stopped", true, Self::stop_service)
+            }
+            Request::Restart(target) => {
+                self.apply_to_target(&target, "restarted", true, Self::restart_service)
+            }
+            Request::Reset(target) => {
+                self.apply_to_target(&target, "reset", false, Self::reset_service)
+            }
+            Request::StopAll => {
+                let stopped = self.stop_all();
+                Ok(Value::object().with("stopped", stopped))
+            }
+            Request::Stats => Ok(stats_json(&self.stats())),
+            Request::BootTimes => Ok(self.boot_times.to_json()),
+            Request::Passwords => {
+                let queries: Vec<Value> = self
+                    .passwords
+                    .pending()
+                    .iter()
+                    .map(|query| {
+                        query_json(query).with("blocking", self.blocked_by(&query.service))
+                    })
+                    .collect();
+                Ok(Value::object().with("queries", queries))
+            }
+            Request::Answer(id, password) => self
+                .passwords
+                .answer(id, password.into_bytes())
+                .map(|_| Value::object()),
+            Request::Release(target) => self.release(target.as_deref()),
+            Request::SwitchTarget(target) => self.switch_target(&target),
+            Request::SetEnv(name, env) => self.set_env(&name, &env),
+            // a multiplexed output pipe is closed by the exec, and can't be handed over
+            Request::Reexec if self.log_mux.is_some() => {
+                Err("the output of the services is multiplexed".to_string())
+            }
+            Request::Reexec => {
+                self.reexec_requested = true;
+                Ok(Value::object().with("pid", i32::from(self.pid)))
+            }
+            Request::Protect(pid) => self.protect(pid),
+            Request::Ping => Ok(Value::object()
+                .with("pid", i32::from(self.pid))
+                .with("loop_iterations", self.stats.loop_iterations)),
+        };
+        client.reply(reply);
+    }
+
+    /// Carry out all pending requests of the control handles.
+    fn answer_handle_requests(&mut self) {
+        let requests = match &self.handle_requests {
+            Some(requests) => requests.take(),
+            None => return,
+        };
+        for request in requests {
+            debug!(
+                "Handling {:?} of {} for a control handle",
+                request.op, request.name
+            );
+            let outcome = match request.op {
+                Operation::Start => self.start_service(&request.name),
+                Operation::Stop => self.stop_service(&request.name),
+                Operation::Restart => self.restart_service(&request.name),
+            };
+            request.reply(outcome);
+        }
+    }
+
+    /// Watch the files a command restarts on.
+    fn watch_files(&mut self, cmd: &PersistentCommand) {
+        if cmd.watched_files().is_empty() {
+            return;
+        }
+        if self.watcher.is_none() {
+            let watcher = FileWatcher::new().and_then(|watcher| {
+                self.events.watch(watcher.as_raw_fd())?;
+                Ok(watcher)
+            });
+            match watcher {
+                Ok(watcher) => self.watcher = Some(watcher),
+                Err(e) => {
+                    error!("Failed to set up watching files: {}", e);
+                    return;
+                }
+            }
+        }
+        let watcher = self.watcher.as_mut().unwrap();
+        for path in cmd.watched_files() {
+            if let Err(e) = watcher.watch(path, cmd.service_name()) {
+                warn!("Failed to watch {} for {}: {}", path, cmd.service_name(), e);
+            }
+        }
+    }
+
+    /// Restart, or reload, the running services of which watched files changed.
+    fn handle_file_changes(&mut self, now: Instant) {
+        let due = match &mut self.watcher {
+            Some(watcher) => {
+                watcher.read_events(now);
+                watcher.due(now)
+            }
+            None => return,
+        };
+        for name in due {
+            let pid = match self.service_pid(&name) {
+                Some(pid) => pid,
+                None => {
+                    debug!("Files of {} changed, but it is not running", name);
+                    continue;
+                }
+            };
+            let res = match self.persistent_commands_map[&pid].change_signal() {
+                Some(signal) => {
+                    info!("Files of {} changed, sending {:?}", name, signal);
+                    self.signal_process(pid, signal)
+                        .map_err(|e| format!("failed to signal {}: {}", name, e))
+                }
+                None => {
+                    info!("Files of {} changed, restarting it", name);
+                    self.restart_service(&name)
+                }
+            };
+            if let Err(e) = res {
+                warn!("Failed to act on changed files of {}: {}", name, e);
+            }
+        }
+    }
+
+    /// Apply an operation to the services the target of a request addresses. A single service is
+    /// handled as is, while the services of a group or pattern are handled in dependency order,
+    /// or in reverse if stopping them, and are listed in the reply under key.
+    fn apply_to_target(
+        &mut self,
+        target: &str,
+        key: &str,
+        reverse: bool,
+        op: fn(&mut Self, &str) -> Result<(), String>,
+    ) -> Result<Value, String> {
+        let target = Target::parse(target);
+        if let Target::Service(name) = &target {
+            return op(self, name).map(|_| Value::object());
+        }
+        let mut names = self.resolve_target(&target)?;
+        if reverse {
+            names.reverse();
+        }
+        let mut done = Vec::new();
+        let mut errors = Vec::new();
+        for name in names {
+            match op(self, &name) {
+                Ok(_) => done.push(name),
+                Err(e) => errors.push(e),
+            }
+        }
+        Ok(Value::object().with(key, done).with("errors", errors))
+    }
+
+    /// Find the names of all services a target applies to, in dependency order.
+    fn resolve_target(&self, target: &Target) -> Result<Vec<String>, String> {
+        let matching: Vec<&PersistentCommand> = self
+            .commands()
+            .filter(|cmd| target.matches(cmd.service_name(), cmd.groups()))
+            .collect();
+        if matching.is_empty() {
+            return Err(match target {
+                Target::Group(group) => format!("unknown group {}", group),
+                Target::Pattern(pattern) => format!("no service matches {}", pattern),
+                Target::Service(name) => format!("unknown service {}", name),
+            });
+        }
+        Ok(dependency_order(matching))
+    }
+
+    /// Load the services again: added services are started, removed services are stopped, and
+    /// the definitions of the other services are replaced.
+    fn reload_config(&mut self) {
+        let mut commands = match self.config_loader.as_mut() {
+            Some(loader) => loader(),
+            None => {
+                debug!("Nothing to reload");
+                return;
+            }
+        };
+        command::derive_names(&mut commands);
+        info!("Reloading the configuration, {} services", commands.len());
+        for lint in lint::check(&commands, &[]) {
+            warn!("{}", lint);
+        }
+        self.trap_primary_signals(&commands);
+
+        let names: HashSet<String> = commands
+            .iter()
+            .map(|cmd| cmd.service_name().to_string())
+            .collect();
+        let removed: Vec<String> = dependency_order(
+            self.commands()
+                .filter(|cmd| !names.contains(cmd.service_name()))
+                .collect(),
+        );
+        for name in removed.iter().rev() {
+            info!("{} was removed, stopping it", name);
+            self.forget_service(name);
+        }
+
+        let members = self
+            .target
+            .clone()
+            .map(|target| (target_members(commands.iter(), &target), target));
+        let mut commands: Vec<Option<PersistentCommand<'a>>> =
+            commands.into_iter().map(Some).collect();
+        let order = {
+            let nodes: Vec<(&str, Vec<&str>)> = commands
+                .iter()
+                .flatten()
+                .map(|cmd| {
+                    let deps = cmd.dependencies().map(|(name, _)| name).collect();
+                    (cmd.service_name(), deps)
+                })
+                .collect();
+            let (mut ordered, cyclic) = deps::order(&nodes);
+            ordered.extend(cyclic);
+            ordered
+        };
+        for cmd in order.into_iter().filter_map(|idx| commands[idx].take()) {
+            let name = cmd.service_name().to_string();
+            if let Some(current) = self.command_mut(&name) {
+                debug!("Updating the definition of {}", name);
+                let old = std::mem::replace(current, cmd);
+                current.carry_over(old);
+                continue;
+            }
+            info!("{} was added", name);
+            self.services.insert(name.clone(), ServiceStatus::default());
+            self.watch_files(&cmd);
+            if !cmd.required_mounts().is_empty() {
+                self.track_mounts();
+            }
+            match &members {
+                Some((members, target)) if !members.contains(&name) => {
+                    info!(
+                        "Not starting {}, it is not part of the {} target",
+                        name, target
+                    );
+                    self.inactive_commands.insert(name, cmd);
+                }
+                _ => {
+                    let on_hold = self.hold_all || cmd.is_held();
+                    self.held_commands.push(HeldCommand {
+                        cmd,
+                        on_hold,
+                        waiting_on: None,
+                        waiting_for_mount: None,
+                        waiting_for_path: None,
+                    });
+                }
+            }
+        }
+        self.release_held_commands();
+    }
+
+    /// Stop a service if it is running, and forget about it.
+    fn forget_service(&mut self, name: &str) {
+        if self.service_pid(name).is_some() || self.cancel_delayed(name) {
+            if let Err(e) = self.stop_service(name) {
+                warn!("Failed to stop {}: {}", name, e);
+            }
+        }
+        self.inactive_commands.remove(name);
+        self.held_commands
+            .retain(|held| held.cmd.service_name() != name);
+        self.awaiting_password
+            .retain(|_, cmd| cmd.service_name() != name);
+        self.pre_starting
+            .retain(|_, pre| pre.cmd.service_name() != name);
+        self.health_monitors.remove(name);
+        // a running process is still reaped, it just isn't reported anymore
+        self.services.remove(name);
+    }
+
+    /// Switch to another boot target: the services which are not part of it are stopped, in
+    /// reverse dependency order, and then the services which are, but don't run, are started.
+    fn switch_target(&mut self, target: &str) -> Result<Value, String> {
+        if !self.commands().any(|cmd| cmd.targets().contains(&target)) {
+            return Err(format!("unknown target {}", target));
+        }
+        info!("Switching to the {} target", target);
+        let members = target_members(self.commands(), target);
+        self.target = Some(target.to_string());
+
+        // services which were waiting to start are not started anymore
+        let (held, kept): (Vec<HeldCommand>, Vec<HeldCommand>) = self
+            .held_commands
+            .drain(..)
+            .partition(|held| !members.contains(held.cmd.service_name()));
+        self.held_commands = kept;
+        for held in held {
+            self.inactive_commands
+                .insert(held.cmd.service_name().to_string(), held.cmd);
+        }
+
+        let all = dependency_order(self.commands().collect());
+        let mut stopped = Vec::new();
+        let mut started = Vec::new();
+        let mut errors = Vec::new();
+        for name in all.iter().rev().filter(|name| !members.contains(*name)) {
+            let active = self.service_pid(name).is_some()
+                || self
+                    .delayed_commands
+                    .iter()
+                    .any(|(_, cmd)| cmd.service_name() == name);
+            if active {
+                match self.stop_service(name) {
+                    Ok(_) => stopped.push(name.clone()),
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+        for name in all.iter().filter(|name| members.contains(*name)) {
+            if self.inactive_commands.contains_key(name) {
+                match self.start_service(name) {
+                    Ok(_) => started.push(name.clone()),
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+        Ok(Value::object()
+            .with("target", target)
+            .with("stopped", stopped)
+            .with("started", started)
+            .with("errors", errors))
+    }
+
+    /// Release the services on hold which the target applies to, or all of them. Released
+    /// services are started once their dependencies are met.
+    fn release(&mut self, target: Option<&str>) -> Result<Value, String> {
+        let target = target.map(Target::parse);
+        let names = match &target {
+            Some(Target::Service(name)) => Some(vec![name.clone()]),
+            Some(target) => Some(self.resolve_target(target)?),
+            None => None,
+        };
+        let mut released = Vec::new();
+        for held in self.held_commands.iter_mut().filter(|held| held.on_hold) {
+            let name = held.cmd.service_name();
+            if names
+                .as_ref()
+                .is_none_or(|names| names.iter().any(|n| n == name))
+            {
+                info!("Releasing {}", name);
+                held.on_hold = false;
+                released.push(name.to_string());
+            }
+        }
+        if let Some(Target::Service(name)) = &target {
+            if released.is_empty() {
+                return Err(format!("{} is not on hold", name));
+            }
+        }
+        self.release_held_commands();
+        Ok(Value::object().with("released", released))
+    }
+
+    /// The last output of a service which is kept in memory.
+    fn logs(&self, name: &str) -> Result<Value, String> {
+        let scrollback = self
+            .scrollback
+            .as_ref()
+            .ok_or_else(|| "the output of the services is not kept".to_string())?;
+        if !self.services.contains_key(name) {
+            return Err(format!("unknown service {}", name));
+        }
+        let lines = match scrollback.lock() {
+            Ok(scrollback) => scrollback.lines(name),
+            Err(poisoned) => poisoned.into_inner().lines(name),
+        };
+        Ok(Value::object().with("name", name).with("lines", lines))
+    }
+
+    /// Change the environment of a service. If it has an env file, the file is rewritten and a
+    /// running service is sent its reload signal, so it can pick up the changes without a
+    /// restart. Otherwise the changes apply from the next start on.
+    fn set_env(&mut self, name: &str, env: &[(String, Option<String>)]) -> Result<Value, String> {
+        let pid = self.service_pid(name);
+        let cmd = self
+            .command_mut(name)
+            .ok_or_else(|| format!("unknown service {}", name))?;
+        cmd.update_env(env);
+        let written = cmd
+            .write_env_file()
+            .map_err(|e| format!("failed to write env file of {}: {}", name, e))?;
+        let reloaded = match (pid, cmd.change_signal()) {
+            (Some(pid), Some(signal)) if written => {
+                info!("Environment of {} changed, sending {:?}", name, signal);
+                self.signal_process(pid, signal)
+                    .map_err(|e| format!("failed to signal {}: {}", name, e))?;
+                true
+            }
+            _ => false,
+        };
+        Ok(Value::object().with("reloaded", reloaded))
+    }
+
+    /// Find a command the reaper knows about by the name of its service.
+    fn command_mut(&mut self, name: &str) -> Option<&mut PersistentCommand<'a>> {
+        self.persistent_commands_map
+            .values_mut()
+            .chain(self.delayed_commands.iter_mut().map(|(_, cmd)| cmd))
+            .chain(self.inactive_commands.values_mut())
+            .chain(self.held_commands.iter_mut().map(|held| &mut held.cmd))
+            .chain(self.awaiting_password.values_mut())
+            .chain(self.pre_starting.values_mut().map(|pre| &mut pre.cmd))
+            .chain(self.pid_file_waits.iter_mut().map(|(_, cmd)| cmd))
+            .find(|cmd| cmd.service_name() == name)
+    }
+
+    /// All commands the reaper knows about, whatever their state.
+    fn commands(&self) -> impl Iterator<Item = &PersistentCommand<'a>> {
+        self.persistent_commands_map
+            .values()
+            .chain(self.delayed_commands.iter().map(|(_, cmd)| cmd))
+            .chain(self.inactive_commands.values())
+            .chain(self.held_commands.iter().map(|held| &held.cmd))
+            .chain(self.awaiting_password.values())
+            .chain(self.pre_starting.values().map(|pre| &pre.cmd))
+    }
+
+    /// Spawn the commands of which the password has been given.
+    fn handle_password_answers(&mut self) {
+        for (id, password) in self.passwords.answers() {
+            if let Some(mut cmd) = self.awaiting_password.remove(&id) {
+                info!("Got the password for {}", cmd.service_name());
+                cmd.set_password(password);
+                let cmd_name = format!("{}", cmd);
+                if let Err(e) = self.spawn_persistent_command(cmd, None) {
+                    error!("Failed to spawn persistent command ({}): {}", cmd_name, e);
+                }
+            }
+        }
+    }
+
+    /// Find the pid of the running process of a service.
+    fn service_pid(&self, name: &str) -> Option<Pid> {
+        self.persistent_commands_map
+            .iter()
+            .find(|(_, cmd)| cmd.service_name() == name)
+            .map(|(pid, _)| *pid)
+    }
+
+    /// Spawn the command once it is due, instead of right away.
+    fn delay_spawn(&mut self, at: Instant, pcmd: PersistentCommand<'a>) {
+        if let Some(status) = self.services.get_mut(pcmd.service_name()) {
+            status.respawn_at = Some(at);
+        }
+        self.delayed_commands.push((at, pcmd));
+    }
+
+    /// Move a command which is waiting to be respawned, or for its pre-start hook, to the
+    /// inactive commands. The hook is stopped.
+    fn cancel_delayed(&mut self, name: &str) -> bool {
+        let hook = self
+            .pre_starting
+            .iter()
+            .find(|(_, pre)| pre.cmd.service_name() == name)
+            .map(|(pid, _)| *pid);
+        if let Some(pid) = hook {
+            if let Err(e) = self.processes.kill(pid, Some(Signal::SIGTERM)) {
+                warn!("Failed to stop pre-start hook of {}: {}", name, e);
+            }
+            // reaped like any other hook from now on
+            self.hooks
+                .insert(pid, format!("pre-start hook of {}", name));
+            let mut cmd = self.pre_starting.remove(&pid).unwrap().cmd;
+            cmd.cancel_pre_start();
+            self.inactive_commands.insert(name.to_string(), cmd);
+            return true;
+        }
+        match self
+            .delayed_commands
+            .iter()
+            .position(|(_, cmd)| cmd.service_name() == name)
+        {
+            Some(idx) => {
+                let (_, cmd) = self.delayed_commands.remove(idx);
+                self.inactive_commands.insert(name.to_string(), cmd);
+                if let Some(status) = self.services.get_mut(name) {
+                    status.waiting_for_executable = None;
+                    status.respawn_at = None;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start an inactive service, regardless of its spawn limits.
+    fn start_service(&mut self, name: &str) -> Result<(), String> {
+        if self.service_pid(name).is_some() {
+            return Err(format!("{} is already running", name));
+        }
+        let seq = self.journal_begin(Intent::Start, name, None);
+        let res = self.spawn_service(name);
+        self.journal_complete(seq);
+        res
+    }
+
+    fn spawn_service(&mut self, name: &str) -> Result<(), String> {
+        self.cancel_delayed(name);
+        let mut cmd = self
+            .inactive_commands
+            .remove(name)
+            .ok_or_else(|| format!("unknown service {}", name))?;
+        info!("Starting {} on request", name);
+        cmd.reset_spawns();
+        self.spawn_persistent_command(cmd, None)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Clear the failure of a service, along with its spawn history, so it is started again like
+    /// any other stopped service, e.g. when its boot target is switched to.
+    fn reset_service(&mut self, name: &str) -> Result<(), String> {
+        let status = self
+            .services
+            .get_mut(name)
+            .ok_or_else(|| format!("unknown service {}", name))?;
+        if status.failure.take().is_none() {
+            return Err(format!("{} did not fail", name));
+        }
+        if let Some(cmd) = self.inactive_commands.get_mut(name) {
+            cmd.reset_spawns();
+        }
+        info!("Reset the failure of {}", name);
+        Ok(())
+    }
+
+    /// Stop a service by sending SIGTERM to its process. It is not respawned when it exits.
+    fn stop_service(&mut self, name: &str) -> Result<(), String> {
+        let pid = match self.service_pid(name) {
+            Some(pid) => pid,
+            None if self.cancel_delayed(name) => {
+                let seq = self.journal_begin(Intent::Stop, name, None);
+                self.journal_complete(seq);
+                return Ok(());
+            }
+            None if self.inactive_commands.contains_key(name) => {
+                return Err(format!("{} is not running", name))
+            }
+            None => return Err(format!("unknown service {}", name)),
+        };
+        info!("Stopping {} on request", name);
+        if let Some(seq) = self.journal_begin(Intent::Stop, name, Some(pid)) {
+            self.awaiting_exit
+                .entry(name.to_string())
+                .or_default()
+                .push(seq);
+        }
+        if let Some(cmd) = self.persistent_commands_map.remove(&pid) {
+            self.inactive_commands.insert(name.to_string(), cmd);
+        }
+        // the status is updated once the process is reaped
+        self.signal_service(pid, Signal::SIGTERM)
+            .map_err(|e| format!("failed to signal {}: {}", name, e))
+    }
+
+    /// Stop all services which are running or waiting to be respawned, returning their names.
+    fn stop_all(&mut self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .persistent_commands_map
+            .values()
+            .chain(self.delayed_commands.iter().map(|(_, cmd)| cmd))
+            .map(|cmd| cmd.service_name().to_string())
+            .collect();
+        names.sort();
+        names.dedup();
+        names.retain(|name| match self.stop_service(name) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Failed to stop {}: {}", name, e);
+                false
+            }
+        });
+        names
+    }
+
+    /// Terminate the later stages of the pipeline of a process which exited. They are still
+    /// reaped as stages, but no longer belong to a running service.
+    fn stop_pipeline_stages(&mut self, main: Pid) {
+        for (stage, _) in self.pipeline_stages.iter().filter(|(_, of)| **of == main) {
+            if let Err(e) = self.processes.kill(*stage, Some(Signal::SIGTERM)) {
+                warn!("Failed to signal pipeline stage {}: {}", stage, e);
+            }
+        }
+    }
+
+    /// Stop a service and start it again once it exited, or start it if it is not running.
+    fn restart_service(&mut self, name: &str) -> Result<(), String> {
+        if self.service_pid(name).is_none() {
+            return self.start_service(name);
+        }
+        let pid = self.service_pid(name);
+        self.stop_service(name)?;
+        // journaled after the stop, so a restart that got interrupted still starts the service
+        if let Some(seq) = self.journal_begin(Intent::Restart, name, pid) {
+            self.awaiting_exit
+                .entry(name.to_string())
+                .or_default()
+                .push(seq);
+        }
+        self.pending_starts.push(name.to_string());
+        Ok(())
+    }
+
+    /// Keep a service which was stopped by a previous reaper stopped. If the stop did not
+    /// complete, its process is stopped if it is still around.
+    fn resume_stop(&mut self, cmd: PersistentCommand<'a>, recovered: Recovered) {
+        let name = cmd.service_name().to_string();
+        info!("Keeping {} stopped, as journaled", name);
+        if let (false, Some(pid)) = (recovered.done, recovered.pid) {
+            if self.children.contains(&pid) {
+                info!("Finishing interrupted stop of {} ({})", name, pid);
+                if let Err(e) = self.processes.kill(pid, Some(Signal::SIGTERM)) {
+                    warn!("Failed to signal {}: {}", pid, e);
+                }
+            }
+        }
+        self.inactive_commands.insert(name, cmd);
+    }
+
+    /// Supervise the process the previous reaper handed over for a service, if it is still
+    /// running, or keep the service stopped if it was stopped. The command is given back if it
+    /// must be started as usual instead.
+    fn adopt(
+        &mut self,
+        mut cmd: PersistentCommand<'a>,
+        handed: Handed,
+    ) -> Option<PersistentCommand<'a>> {
+        let name = cmd.service_name().to_string();
+        let pid = match handed.pid {
+            Some(pid) => pid,
+            None => {
+                info!("Keeping {} stopped, as handed over", name);
+                self.inactive_commands.insert(name, cmd);
+                return None;
+            }
+        };
+        let stat = match self.processes.stat(pid) {
+            Some(stat) => stat,
+            None => {
+                warn!("Process {} of {} is gone, starting it again", pid, name);
+                return Some(cmd);
+            }
+        };
+        if handed
+            .start_time
+            .is_some_and(|start| start != stat.start_time)
+        {
+            warn!(
+                "Process {} of {} was replaced, starting it again",
+                pid, name
+            );
+            return Some(cmd);
+        }
+        // a process which exited in the meantime is still a zombie child, and is reaped as usual
+        if stat.ppid != self.pid {
+            if stat.state == 'Z' {
+                warn!("Process {} of {} exited, starting it again", pid, name);
+                return Some(cmd);
+            }
+            warn!(
+                "Process {} of {} is not a child of the reaper, polling it for its exit",
+                pid, name
+            );
+            self.unparented.insert(pid, stat.start_time);
+        }
+        info!("Adopting {} ({})", name, pid);
+        // the service still sends to the same path
+        if cmd.needs_notify_socket() {
+            self.set_up_notify_socket(&mut cmd);
+        }
+        if let Some(root) = &self.cgroups {
+            match root.service(&name) {
+                Ok(cgroup) => {
+                    self.service_cgroups.insert(name.clone(), cgroup);
+                }
+                Err(e) => warn!("Failed to find the cgroup of {}: {}", name, e),
+            }
+        }
+        self.services.entry(name.clone()).or_default().adopted(
+            pid,
+            handed.restarts,
+            handed.uptime,
+            handed.ready,
+        );
+        self.console.spawned(&name, handed.ready, Instant::now());
+        for stage in handed.stages {
+            if self.children.contains(&stage) {
+                self.pipeline_stages.insert(stage, pid);
+            }
+        }
+        self.monitor_health(&cmd);
+        self.persistent_commands_map.insert(pid, cmd);
+        // a process which was not spawned by a reaper might share its process group
+        if stat.pgrp == pid {
+            self.process_groups.insert(pid, pid);
+        }
+        None
+    }
+
+    /// Supervise a child which was running the command before the reaper started, instead of
+    /// starting another one. The command is given back if there is no such child.
+    fn adopt_existing(&mut self, cmd: PersistentCommand<'a>) -> Option<PersistentCommand<'a>> {
+        let pid = self.children.iter().copied().find(|pid| {
+            !self.persistent_commands_map.contains_key(pid)
+                && !self.orphans.contains_key(pid)
+                && process_cmdline(*pid).is_some_and(|cmdline| cmd.runs_in(&cmdline))
+        });
+        let pid = match pid {
+            Some(pid) => pid,
+            None => return Some(cmd),
+        };
+        info!(
+            "{} is already running as {}, supervising it",
+            cmd.service_name(),
+            pid
+        );
+        if let Some(root) = &self.cgroups {
+            let moved = root
+                .service(cmd.service_name())
+                .and_then(|cgroup| cgroup.add(pid));
+            if let Err(e) = moved {
+                warn!(
+                    "Failed to move {} into the cgroup of {}: {}",
+                    pid,
+                    cmd.service_name(),
+                    e
+                );
+            }
+        }
+        // its uptime is counted from now, and it is taken to be ready, as it has been running
+        let handed = Handed {
+            pid: Some(pid),
+            start_time: None,
+            stages: Vec::new(),
+            restarts: 0,
+            uptime: Duration::default(),
+            ready: true,
+        };
+        self.adopt(cmd, handed)
+    }
+
+    /// The state to hand over to a later reaper: the running services and the ones which were
+    /// stopped, and the orphans being terminated.
+    fn handover_state(&self) -> Handover {
+        let services = self
+            .services
+            .iter()
+            .filter(|(name, status)| {
+                status.is_running() || self.inactive_commands.contains_key(*name)
+            })
+            .map(|(name, status)| {
+                let stages = self
+                    .pipeline_stages
+                    .iter()
+                    .filter(|(_, main)| Some(**main) == status.pid)
+                    .map(|(stage, _)| *stage)
+                    .collect();
+                let handed = Handed {
+                    pid: status.pid,
+                    start_time: status
+                        .pid
+                        .and_then(|pid| self.processes.stat(pid))
+                        .map(|stat| stat.start_time),
+                    stages,
+                    restarts: status.restarts,
+                    uptime: status.uptime().unwrap_or_default(),
+                    ready: status.ready,
+                };
+                (name.clone(), handed)
+            })
+            .collect();
+        Handover {
+            target: self.target.clone(),
+            services,
+            orphans: self.orphans.keys().copied().collect(),
+        }
+    }
+
+    /// Write a snapshot of the state, if one is due.
+    fn snapshot_if_due(&mut self, now: Instant) {
+        let (path, interval) = match &self.state_snapshots {
+            Some(snapshots) => snapshots,
+            None => return,
+        };
+        if self.next_snapshot.is_some_and(|next| next > now) {
+            return;
+        }
+        if let Err(e) = self.handover_state().write(path) {
+            warn!("Failed to snapshot the state to {}: {}", path, e);
+        }
+        self.next_snapshot = Some(now + *interval);
+    }
+
+    /// Handle the exit of the recovered processes which are not children of the reaper, which
+    /// is only noticed once they are gone.
+    fn check_unparented(&mut self) {
+        let exited: Vec<Pid> = self
+            .unparented
+            .iter()
+            .filter(|(pid, start)| {
+                !self
+                    .processes
+                    .stat(**pid)
+                    .is_some_and(|stat| stat.start_time == **start && stat.state != 'Z')
+            })
+            .map(|(pid, _)| *pid)
+            .collect();
+        for pid in exited {
+            self.unparented.remove(&pid);
+            let event = Event::ExitSuccess;
+            match self.persistent_commands_map.get(&pid) {
+                Some(cmd) => info!("{} ({}) exited", cmd.service_name(), pid),
+                None => debug!("Recovered process {} exited", pid),
+            }
+            self.process_groups.remove(&pid);
+            self.stop_pipeline_stages(pid);
+            self.run_exit_hook(pid, event);
+            let service = self.service_exited(&pid, event, None);
+            if let Err(e) = self.ensure_process(&pid, Some(event)) {
+                warn!("{}", e);
+            }
+            if let Some(name) = service {
+                self.finish_requests(&name);
+            }
+        }
+    }
+
+    /// Record the resources the running processes of the services used so far.
+    fn sample_usage(&mut self) {
+        for (pid, cmd) in &self.persistent_commands_map {
+            let usage = match self.processes.usage(*pid) {
+                Some(usage) => usage,
+                None => continue,
+            };
+            if let Some(status) = self.services.get_mut(cmd.service_name()) {
+                status.usage.sampled(&usage);
+            }
+        }
+    }
+
+    /// Remember the descendants of the running services which supervise their own processes,
+    /// so they are still known as theirs once they were reparented, and forget the ones which
+    /// are gone.
+    fn track_delegated(&mut self) {
+        let processes = &self.processes;
+        self.delegated
+            .retain(|pid, _| processes.kill(*pid, None).is_ok());
+        let supervisors: Vec<(Pid, String)> = self
+            .persistent_commands_map
+            .iter()
+            .filter(|(_, cmd)| cmd.is_supervisor())
+            .map(|(pid, cmd)| (*pid, cmd.service_name().to_string()))
+            .collect();
+        if supervisors.is_empty() {
+            return;
+        }
+        let scanned;
+        let tree = if self.follows_lineage() {
+            &self.process_tree
+        } else {
+            scanned = scan_lineage(&*self.processes);
+            &scanned
+        };
+        for (pid, name) in supervisors {
+            for descendant in tree.descendants(pid) {
+                self.delegated.insert(descendant, name.clone());
+            }
+        }
+    }
+
+    /// Hand the running services over to a fresh instance of the binary of the reaper, and
+    /// execute it in place. The services keep running, and are adopted by the new instance.
+    /// Supervision just goes on if the binary can't be executed.
+    fn reexec(&mut self) {
+        let handover = self.handover_state();
+        let path = self.handover_path();
+        if let Err(e) = handover.write(&path) {
+            error!("Failed to write the handover to {}: {}", path, e);
+            return;
+        }
+        info!(
+            "Re-executing, handing over {} services",
+            handover.services.len()
+        );
+        log::logger().flush();
+        let e = handover::exec_self();
+        error!("Failed to re-execute: {}", e);
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Failed to remove the handover {}: {}", path, e);
+        }
+    }
+
+    fn journal_begin(&mut self, intent: Intent, name: &str, pid: Option<Pid>) -> Option<u64> {
+        self.journal
+            .as_mut()
+            .map(|journal| journal.begin(intent, name, pid))
+    }
+
+    fn journal_complete(&mut self, seq: Option<u64>) {
+        if let (Some(journal), Some(seq)) = (&mut self.journal, seq) {
+            journal.complete(seq);
+        }
+    }
+
+    /// Start tracking the forking daemons which wrote their pid file, and give up on the ones
+    /// which did not do so in time, treating them as exited.
+    fn read_pid_files(&mut self, now: Instant) {
+        for (since, cmd) in std::mem::take(&mut self.pid_file_waits) {
+            let path = cmd.pid_file_path().unwrap_or_default();
+            let pid = std::fs::read_to_string(path)
+                .ok()
+                .and_then(|content| content.trim().parse().ok())
+                .map(Pid::from_raw)
+                // a stale pid file of a previous run could name a process which is long gone
+                .filter(|pid| self.processes.kill(*pid, None).is_ok());
+            match pid {
+                Some(pid) => {
+                    info!(
+                        "{} forked, tracking {} from {}",
+                        cmd.service_name(),
+                        pid,
+                        path
+                    );
+                    if let Some(status) = self.services.get_mut(cmd.service_name()) {
+                        status.pid = Some(pid);
+                    }
+                    self.persistent_commands_map.insert(pid, cmd);
+                }
+                None if now.duration_since(since) < PID_FILE_TIMEOUT => {
+                    self.pid_file_waits.push((since, cmd));
+                }
+                None => {
+                    warn!(
+                        "{} did not write a valid pid file to {} in time, considering it exited",
+                        cmd.service_name(),
+                        path
+                    );
+                    if let Err(e) = self.spawn_persistent_command(cmd, Some(Event::ExitSuccess)) {
+                        info!("{}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn update_ensured_process_pid(&mut self, pid: &Pid, new_pid: &Pid) {
+        if let Some(cmd) = self.persistent_commands_map.remove(pid) {
+            if let Some(status) = self.services.get_mut(cmd.service_name()) {
+                status.pid = Some(*new_pid);
+            }
+            let _ = self.persistent_commands_map.insert(*new_pid, cmd);
+            if let Some(pgid) = self.process_groups.remove(pid) {
+                self.process_groups.insert(*new_pid, pgid);
+            }
+            if let Some(mux) = &self.log_mux {
+                mux.rekey(*pid, *new_pid);
+            }
+        }
+    }
+}
+
+/// Order services so every service comes after the services it depends on. Services which
+/// don't depend on each other are ordered by name, and services in a dependency cycle come
+/// last.
+fn dependency_order(mut commands: Vec<&PersistentCommand>) -> Vec<String> {
+    commands.sort_by_key(|cmd| cmd.service_name());
+    let nodes: Vec<(&str, Vec<&str>)> = commands
+        .iter()
+        .map(|cmd| {
+            let deps = cmd.dependencies().map(|(name, _)| name).collect();
+            (cmd.service_name(), deps)
+        })
+        .collect();
+    let (mut ordered, cyclic) = deps::order(&nodes);
+    ordered.extend(cyclic);
+    let mut names: Vec<String> = ordered
+        .into_iter()
+        .map(|idx| commands[idx].service_name().to_string())
+        .collect();
+    // a command can be known twice for a moment, e.g. while it is being respawned
+    let mut seen = HashSet::new();
+    names.retain(|name| seen.insert(name.clone()));
+    names
+}
+
+/// The names of the services which are part of a boot target, along with the services they
+/// depend on.
+fn target_members<'c, 'a: 'c>(
+    commands: impl Iterator<Item = &'c PersistentCommand<'a>>,
+    target: &str,
+) -> HashSet<String> {
+    let commands: Vec<&PersistentCommand> = commands.collect();
+    let mut members: HashSet<String> = commands
+        .iter()
+        .filter(|cmd| cmd.in_target(target))
+        .map(|cmd| cmd.service_name().to_string())
+        .collect();
+    let mut pending: Vec<String> = members.iter().cloned().collect();
+    while let Some(name) = pending.pop() {
+        for cmd in commands.iter().filter(|cmd| cmd.service_name() == name) {
+            for (dep, _) in cmd.dependencies() {
+                if members.insert(dep.to_string()) {
+                    pending.push(dep.to_string());
+                }
+            }
         }
     }
+    members
 }
@@ -0,0 +1,95 @@
+//! The lineage of processes, followed through a source of process lifecycle events, so the
+//! descendants of a process are known without scanning /proc, even once they were reparented.
+//! Without such a source, the lineage of the processes of the services is recorded while their
+//! parents are still around instead, which misses the processes that fork and exit in between.
+
+use std::collections::HashMap;
+
+use nix::unistd::Pid;
+
+/// A lifecycle event of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProcessEvent {
+    Fork { parent: Pid, child: Pid },
+    Exit(Pid),
+}
+
+/// The lineage of processes: the process which forked every process, as far as it is known.
+/// Unlike the parent of a process, this does not change when the parent exits.
+#[derive(Debug, Default)]
+pub(crate) struct ProcessTree {
+    parents: HashMap<Pid, Pid>,
+}
+
+impl ProcessTree {
+    pub(crate) fn forked(&mut self, parent: Pid, child: Pid) {
+        self.parents.insert(child, parent);
+    }
+
+    /// Record the parent of a process, unless its parent is known already, as a process which
+    /// is seen again after it was reparented keeps the parent it was seen with first.
+    pub(crate) fn record(&mut self, parent: Pid, child: Pid) {
+        self.parents.entry(child).or_insert(parent);
+    }
+
+    /// The processes which are known, as the child of another process.
+    pub(crate) fn known(&self) -> Vec<Pid> {
+        self.parents.keys().copied().collect()
+    }
+
+    /// The parent of the process, its parent, and so on, as far as they are known.
+    pub(crate) fn ancestors(&self, pid: Pid) -> Vec<Pid> {
+        let mut ancestors = Vec::new();
+        let mut current = pid;
+        while let Some(parent) = self.parents.get(&current) {
+            // a pid which was reused could make for a loop
+            if ancestors.contains(parent) {
+                break;
+            }
+            ancestors.push(*parent);
+            current = *parent;
+        }
+        ancestors
+    }
+
+    /// Forget a process which exited. Its children are attributed to the process which forked
+    /// it, so they stay descendants of whatever it descended from.
+    pub(crate) fn forget(&mut self, pid: Pid) {
+        let parent = self.parents.remove(&pid);
+        for p in self.parents.values_mut().filter(|p| **p == pid) {
+            match parent {
+                Some(parent) => *p = parent,
+                None => *p = Pid::from_raw(0),
+            }
+        }
+        self.parents.retain(|_, p| i32::from(*p) != 0);
+    }
+
+    /// The processes the process forked which are still around, along with those which were
+    /// attributed to it as their parent exited.
+    pub(crate) fn children(&self, pid: Pid) -> Vec<Pid> {
+        self.parents
+            .iter()
+            .filter(|(_, p)| **p == pid)
+            .map(|(child, _)| *child)
+            .collect()
+    }
+
+    /// All known descendants of the process.
+    pub(crate) fn descendants(&self, pid: Pid) -> Vec<Pid> {
+        let mut found = vec![pid];
+        let mut idx = 0;
+        while idx < found.len() {
+            let parent = found[idx];
+            found.extend(
+                self.parents
+                    .iter()
+                    .filter(|(_, p)| **p == parent)
+                    .map(|(child, _)| *child),
+            );
+            idx += 1;
+        }
+        found.remove(0);
+        found
+    }
+}
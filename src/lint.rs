@@ -0,0 +1,164 @@
+//! Checks for service definitions which are valid, but most likely not what was meant. The
+//! findings are logged when the reaper starts, and can be listed up front with
+//! `rsinit --check-config`.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::command::{OneShotCommand, PersistentCommand};
+use crate::deps;
+use crate::namespaces::Namespace;
+use crate::readiness;
+
+/// Programs which usually do their work and exit, so restarting them once they succeed likely
+/// makes no sense. This is a heuristic on the file name of the executable alone, so a program
+/// by one of these names which keeps running is flagged too, while a wrapper script around one
+/// of them is not. It only ever produces a lint, and never changes how a service is run.
+const ONE_SHOT_PROGRAMS: [&str; 16] = [
+    "true", "echo", "mkdir", "mount", "umount", "modprobe", "sysctl", "hostname", "ip", "chmod",
+    "chown", "ln", "touch", "hwclock", "swapon", "loadkeys",
+];
+
+/// A suspicious part of the definition of a service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    /// The name of the service the lint applies to.
+    pub service: String,
+    /// What is suspicious about it, and what to do about it.
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.service, self.message)
+    }
+}
+
+/// Check the persistent and one-shot commands, which together make up the services.
+pub fn check(persistent: &[PersistentCommand], one_shots: &[OneShotCommand]) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let mut lint = |service: &str, message: String| {
+        lints.push(Lint {
+            service: service.to_string(),
+            message,
+        })
+    };
+
+    for cmd in one_shots {
+        if let Some(message) = check_program(cmd.program(), None) {
+            lint(cmd.service_name(), message);
+        }
+    }
+
+    let names: HashSet<&str> = persistent
+        .iter()
+        .map(PersistentCommand::service_name)
+        .chain(one_shots.iter().map(OneShotCommand::service_name))
+        .chain(readiness::BUILTIN.iter().copied())
+        .collect();
+    for cmd in persistent {
+        let name = cmd.service_name();
+        if let Some(message) = check_program(cmd.program(), cmd.root()) {
+            lint(name, message);
+        }
+        let program = Path::new(cmd.program())
+            .file_name()
+            .and_then(|p| p.to_str());
+        if cmd.restarts_on_success() && program.is_some_and(|p| ONE_SHOT_PROGRAMS.contains(&p)) {
+            lint(
+                name,
+                format!(
+                    "restarts on success, but {} usually exits once it is done, so it would run \
+                     over and over; use a one-shot command instead",
+                    cmd.program()
+                ),
+            );
+        }
+        if cmd.has_spawn_limit() && !cmd.is_paced() {
+            lint(
+                name,
+                "has a spawn limit, but restarts immediately, so the limit can be used up in \
+                 a fraction of a second; add a spawn limit window to slow restarts down"
+                    .to_string(),
+            );
+        }
+        if cmd.pid_file_path().is_some() && cmd.daemonizing() == Some(false) {
+            lint(
+                name,
+                "has a pid file, but does not daemonize, so what it leaves behind is terminated \
+                 instead of tracked; drop the pid file or let it daemonize"
+                    .to_string(),
+            );
+        }
+        if let Some(netns) = cmd.joined_netns().filter(|_| cmd.unshares(Namespace::Net)) {
+            lint(
+                name,
+                format!(
+                    "joins network namespace {}, but gets a new network namespace as well, \
+                     which it ends up in instead",
+                    netns
+                ),
+            );
+        }
+        for (dep, _) in cmd.dependencies() {
+            if dep == name {
+                lint(name, "depends on itself".to_string());
+            } else if !names.contains(dep) {
+                lint(name, format!("depends on unknown service {}", dep));
+            }
+        }
+    }
+
+    let primaries: Vec<&str> = persistent
+        .iter()
+        .filter(|cmd| cmd.is_primary())
+        .map(PersistentCommand::service_name)
+        .collect();
+    for name in primaries.iter().skip(1) {
+        lint(
+            name,
+            format!(
+                "is a primary service, as is {}, but signals are only forwarded to one of them",
+                primaries[0]
+            ),
+        );
+    }
+
+    let nodes: Vec<(&str, Vec<&str>)> = persistent
+        .iter()
+        .map(|cmd| {
+            let deps = cmd.dependencies().map(|(name, _)| name).collect();
+            (cmd.service_name(), deps)
+        })
+        .collect();
+    for idx in deps::order(&nodes).1 {
+        lint(
+            nodes[idx].0,
+            "is part of, or depends on, a dependency cycle, so it is never started".to_string(),
+        );
+    }
+
+    lints
+}
+
+/// Check that the program of a command can be executed, within root if it is chrooted.
+fn check_program(program: &str, root: Option<&str>) -> Option<String> {
+    if !program.starts_with('/') {
+        return Some(format!(
+            "{} is not an absolute path, so it depends on the PATH of init",
+            program
+        ));
+    }
+    let path = match root {
+        Some(root) => Path::new(root).join(&program[1..]),
+        None => Path::new(program).to_path_buf(),
+    };
+    match fs::metadata(&path) {
+        Ok(meta) if meta.is_file() && meta.permissions().mode() & 0o111 != 0 => None,
+        Ok(_) => Some(format!("{} is not an executable file", path.display())),
+        Err(e) => Some(format!("{} can't be executed: {}", path.display(), e)),
+    }
+}
@@ -0,0 +1,169 @@
+//! The log file of init. It typically lives on a filesystem which is not mounted, or still
+//! read-only, when init starts, so messages are kept in memory until the early mounts are done
+//! and the file can be written.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Default path of the log file.
+pub const LOG_PATH: &str = "/log";
+
+/// How many bytes of messages are kept in memory at most, until the log file is opened. Older
+/// messages are dropped first.
+const BUFFER_LIMIT: usize = 256 * 1024;
+
+/// How often opening the log file is tried again, while it can't be written.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+static RELEASED: AtomicBool = AtomicBool::new(false);
+
+/// Bumped whenever the log files are to be opened again.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Allow log files to be opened, once the filesystems they are on are mounted. The
+/// [`Reaper`] does so after the one-shot commands ran.
+///
+/// [`Reaper`]: ../struct.Reaper.html
+pub fn release() {
+    RELEASED.store(true, Ordering::SeqCst);
+    log::logger().flush();
+}
+
+/// Have the log files opened again before their next message, e.g. once they were rotated.
+/// The [`Reaper`] does so on SIGHUP.
+///
+/// [`Reaper`]: ../struct.Reaper.html
+pub fn reopen() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// A log file which is only opened once logs are [released], buffering the messages written
+/// before then in memory.
+///
+/// [released]: fn.release.html
+pub struct DeferredLog {
+    path: PathBuf,
+    file: Option<File>,
+    /// The generation the file was opened in.
+    generation: usize,
+    buffer: Vec<u8>,
+    dropped: usize,
+    last_attempt: Option<Instant>,
+    /// Whether failing to open the file was reported since it was last opened.
+    reported: bool,
+}
+
+impl DeferredLog {
+    /// Log to the file at path, which is created if it doesn't exist.
+    pub fn new(path: &str) -> Self {
+        DeferredLog {
+            path: PathBuf::from(path),
+            file: None,
+            generation: 0,
+            buffer: Vec::new(),
+            dropped: 0,
+            last_attempt: None,
+            reported: false,
+        }
+    }
+
+    /// Get the log file, opening it if it is time to try so.
+    fn file(&mut self) -> Option<&mut File> {
+        let generation = GENERATION.load(Ordering::SeqCst);
+        if self.file.is_some() && self.generation != generation {
+            self.file = None;
+            self.last_attempt = None;
+        }
+        if self.file.is_none() && RELEASED.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            if self
+                .last_attempt
+                .is_none_or(|last| now.duration_since(last) >= RETRY_INTERVAL)
+            {
+                self.last_attempt = Some(now);
+                self.open();
+            }
+        }
+        self.file.as_mut()
+    }
+
+    /// Open the log file and write out the buffered messages. If that fails, the messages are
+    /// kept for the next attempt.
+    fn open(&mut self) {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path);
+        let mut file = match file {
+            Ok(file) => file,
+            Err(e) => return self.report(e),
+        };
+        if self.dropped > 0 {
+            let _ = writeln!(
+                file,
+                "[{} bytes of early log messages were dropped]",
+                self.dropped
+            );
+        }
+        if let Err(e) = file.write_all(&self.buffer) {
+            return self.report(e);
+        }
+        self.buffer = Vec::new();
+        self.dropped = 0;
+        self.file = Some(file);
+        self.generation = GENERATION.load(Ordering::SeqCst);
+        self.reported = false;
+    }
+
+    /// Report that the log file can't be written on stderr, which is the console for init, as
+    /// the logger can't report it. This is only done once until the file could be opened, so a
+    /// filesystem which stays read-only doesn't flood the console.
+    fn report(&mut self, e: io::Error) {
+        if !std::mem::replace(&mut self.reported, true) {
+            // unlike eprintln!, this doesn't panic if there is no stderr
+            let _ = writeln!(
+                io::stderr(),
+                "Failed to write the log file {}, keeping messages in memory: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    fn keep(&mut self, buf: &[u8]) {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() <= BUFFER_LIMIT {
+            return;
+        }
+        // drop whole lines, so the rest stays readable
+        let excess = self.buffer.len() - BUFFER_LIMIT;
+        let cut = match self.buffer[excess..].iter().position(|b| *b == b'\n') {
+            Some(idx) => excess + idx + 1,
+            None => excess,
+        };
+        self.buffer.drain(..cut);
+        self.dropped += cut;
+    }
+}
+
+impl Write for DeferredLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.file() {
+            Some(file) => file.write(buf),
+            None => {
+                self.keep(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Multiplexing of the output of supervised commands into a single stream, where every line is
+//! prefixed with a timestamp and the name of the command which produced it, or into syslog,
+//! where every line is a message tagged with the name of the command, or into several of these
+//! at once.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::poll::{poll, EventFlags, PollFd};
+use nix::unistd::{pipe2, Pid};
+
+use crate::logsink::{Syslog, SEVERITY_INFO};
+
+/// Lines longer than this are split, so a process which never writes a newline can't make us
+/// buffer without bounds.
+const MAX_LINE: usize = 4096;
+
+enum Request {
+    Attach {
+        pid: Pid,
+        name: String,
+        pipes: Vec<File>,
+    },
+    Detach(Pid),
+    Rekey(Pid, Pid),
+    AddSink(Box<dyn LineSink>),
+}
+
+/// Where the multiplexed lines go.
+pub(crate) trait LineSink: Send {
+    /// Write out a line of the process with the given pid of the named command. Names are
+    /// padded to width, if the sink aligns them.
+    fn write_line(&mut self, name: &str, pid: Pid, width: usize, line: &[u8]);
+}
+
+/// A stream with every line prefixed by a timestamp and the name of its command.
+pub(crate) struct Prefixed(pub(crate) Box<dyn Write + Send>);
+
+impl LineSink for Prefixed {
+    fn write_line(&mut self, name: &str, _: Pid, width: usize, line: &[u8]) {
+        // there is nothing sensible to do if this fails, logging it is pointless as the log
+        // output could well be the very same stream
+        let _ = writeln!(
+            self.0,
+            "{} {:width$} | {}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            name,
+            String::from_utf8_lossy(line),
+            width = width
+        )
+        .and_then(|_| self.0.flush());
+    }
+}
+
+/// Several sinks, which all get every line.
+struct Sinks(Vec<Box<dyn LineSink>>);
+
+impl LineSink for Sinks {
+    fn write_line(&mut self, name: &str, pid: Pid, width: usize, line: &[u8]) {
+        for sink in &mut self.0 {
+            sink.write_line(name, pid, width, line);
+        }
+    }
+}
+
+impl LineSink for Syslog {
+    fn write_line(&mut self, name: &str, pid: Pid, _: usize, line: &[u8]) {
+        self.send(
+            SEVERITY_INFO,
+            name,
+            i32::from(pid) as u32,
+            &String::from_utf8_lossy(line),
+        );
+    }
+}
+
+/// Handle to the multiplexer thread. The thread polls the output pipes of all attached
+/// processes, and exits once this handle is dropped and all pipes are closed.
+pub(crate) struct LogMultiplexer {
+    requests: Sender<Request>,
+    wake: File,
+}
+
+impl LogMultiplexer {
+    /// Start the multiplexer thread, writing all output to the given sink.
+    pub(crate) fn start(out: Box<dyn LineSink>) -> io::Result<Self> {
+        let (wake_rx, wake_tx) =
+            pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK).map_err(io::Error::other)?;
+        let (wake_rx, wake_tx) =
+            unsafe { (File::from_raw_fd(wake_rx), File::from_raw_fd(wake_tx)) };
+        let (tx, rx) = channel();
+
+        thread::Builder::new()
+            .name("logmux".to_string())
+            .spawn(move || multiplex(out, rx, wake_rx))?;
+
+        Ok(LogMultiplexer {
+            requests: tx,
+            wake: wake_tx,
+        })
+    }
+
+    /// Start multiplexing the given output pipes of a process.
+    pub(crate) fn attach<I, P>(&self, pid: Pid, name: &str, pipes: I)
+    where
+        I: IntoIterator<Item = Option<P>>,
+        P: IntoRawFd,
+    {
+        let pipes: Vec<File> = pipes
+            .into_iter()
+            .flatten()
+            .map(|p| unsafe { File::from_raw_fd(p.into_raw_fd()) })
+            .collect();
+        if pipes.is_empty() {
+            return;
+        }
+        self.send(Request::Attach {
+            pid,
+            name: name.to_string(),
+            pipes,
+        });
+    }
+
+    /// Stop multiplexing the output of a process which died. Output still in the pipes is
+    /// written out before they are closed.
+    pub(crate) fn detach(&self, pid: Pid) {
+        self.send(Request::Detach(pid));
+    }
+
+    /// Keep multiplexing the pipes of a process which forked, under the pid of the fork.
+    pub(crate) fn rekey(&self, pid: Pid, new_pid: Pid) {
+        self.send(Request::Rekey(pid, new_pid));
+    }
+
+    /// Write all output to another sink as well, from now on.
+    pub(crate) fn add_sink(&self, sink: Box<dyn LineSink>) {
+        self.send(Request::AddSink(sink));
+    }
+
+    fn send(&self, req: Request) {
+        if self.requests.send(req).is_ok() {
+            // the pipe is only used to wake up the thread, if it's full a wake up is pending
+            // anyway
+            let _ = (&self.wake).write(&[0]);
+        }
+    }
+}
+
+/// An output pipe of a process, or the master of its pseudo-terminal.
+struct Source {
+    pid: Pid,
+    name: String,
+    pipe: File,
+    partial: Vec<u8>,
+}
+
+impl Source {
+    /// Read what is available from the pipe and write out all complete lines. Returns false if
+    /// the pipe is closed.
+    fn forward(&mut self, out: &mut dyn LineSink, width: usize) -> bool {
+        let mut buf = [0; MAX_LINE];
+        loop {
+            match self.pipe.read(&mut buf) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.partial.extend_from_slice(&buf[..n]);
+                    while let Some(idx) = self.partial.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = self.partial.drain(..=idx).collect();
+                        self.write_line(out, width, &line[..idx]);
+                    }
+                    if self.partial.len() >= MAX_LINE {
+                        let line: Vec<u8> = self.partial.drain(..).collect();
+                        self.write_line(out, width, &line);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                // the master of a pseudo-terminal reports its slave being closed this way,
+                // rather than as the end of the output
+                Err(ref e) if e.raw_os_error() == Some(libc::EIO) => return false,
+                Err(e) => {
+                    warn!("Failed to read output of {}: {}", self.name, e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Write out any unterminated line which is left.
+    fn flush(&mut self, out: &mut dyn LineSink, width: usize) {
+        if !self.partial.is_empty() {
+            let line: Vec<u8> = self.partial.drain(..).collect();
+            self.write_line(out, width, &line);
+        }
+    }
+
+    fn write_line(&self, out: &mut dyn LineSink, width: usize, line: &[u8]) {
+        out.write_line(&self.name, self.pid, width, line);
+    }
+}
+
+fn multiplex(out: Box<dyn LineSink>, requests: Receiver<Request>, mut wake: File) {
+    let mut out = Sinks(vec![out]);
+    let mut sources: Vec<Source> = Vec::new();
+    let mut width = 0;
+    let mut handle_closed = false;
+
+    loop {
+        if handle_closed && sources.is_empty() {
+            debug!("Log multiplexer stopped");
+            return;
+        }
+
+        let mut fds: Vec<PollFd> = Some(&wake)
+            .filter(|_| !handle_closed)
+            .into_iter()
+            .chain(sources.iter().map(|s| &s.pipe))
+            .map(|f| PollFd::new(f.as_raw_fd(), EventFlags::POLLIN))
+            .collect();
+
+        match poll(&mut fds, -1) {
+            Ok(_) => (),
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(e) => {
+                error!("Log multiplexer failed to poll: {}", e);
+                return;
+            }
+        }
+
+        let offset = if handle_closed { 0 } else { 1 };
+        let ready: Vec<bool> = fds
+            .iter()
+            .map(|fd| fd.revents().map(|ev| !ev.is_empty()).unwrap_or(false))
+            .collect();
+
+        // walk backwards so removing a source doesn't shift the ones still to be handled
+        for i in (0..sources.len()).rev() {
+            if ready[i + offset] && !sources[i].forward(&mut out, width) {
+                let mut source = sources.remove(i);
+                source.flush(&mut out, width);
+            }
+        }
+
+        if offset == 1 && ready[0] {
+            let mut buf = [0; 64];
+            if let Ok(0) = wake.read(&mut buf) {
+                // all handles are gone
+                handle_closed = true;
+            }
+
+            while let Ok(req) = requests.try_recv() {
+                match req {
+                    Request::Attach { pid, name, pipes } => {
+                        width = width.max(name.len());
+                        for pipe in pipes {
+                            if let Err(e) =
+                                fcntl(pipe.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+                            {
+                                warn!("Failed to make output pipe of {} non blocking: {}", name, e);
+                                continue;
+                            }
+                            sources.push(Source {
+                                pid,
+                                name: name.clone(),
+                                pipe,
+                                partial: Vec::new(),
+                            });
+                        }
+                    }
+                    Request::Detach(pid) => {
+                        for i in (0..sources.len()).rev() {
+                            if sources[i].pid == pid {
+                                let mut source = sources.remove(i);
+                                source.forward(&mut out, width);
+                                source.flush(&mut out, width);
+                            }
+                        }
+                    }
+                    Request::Rekey(pid, new_pid) => {
+                        for source in sources.iter_mut().filter(|s| s.pid == pid) {
+                            source.pid = new_pid;
+                        }
+                    }
+                    Request::AddSink(sink) => out.0.push(sink),
+                }
+            }
+        }
+    }
+}
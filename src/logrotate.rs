@@ -0,0 +1,145 @@
+//! Rotation of the log files which would grow without bounds otherwise: the log files of init
+//! itself, and the files the output of services is redirected to. A file which grew too large
+//! or too old is moved to `PATH.1`, after the older ones were moved to `PATH.2` and so on, and
+//! the oldest one is removed. Rotated files can be compressed with `gzip`, to `PATH.1.gz`.
+//!
+//! A log file of init is renamed and opened again. The file of a service is held open by its
+//! process, so it is copied and truncated instead, which can lose what is written in between.
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// When and how log files are rotated, see [`rotate_logs`].
+///
+/// [`rotate_logs`]: ../struct.Reaper.html#method.rotate_logs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRotation {
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    keep: u32,
+    compress: bool,
+    own_logs: Vec<String>,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogRotation {
+    /// Keep 5 rotated files, without rotating anything until a size or age is set.
+    pub fn new() -> Self {
+        LogRotation {
+            max_size: None,
+            max_age: None,
+            keep: 5,
+            compress: false,
+            own_logs: Vec::new(),
+        }
+    }
+
+    /// Rotate a file once it is larger than the given amount of bytes.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotate a file which is not empty once it was last rotated, or created, longer than the
+    /// given age ago.
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Keep the given amount of rotated files. With none, a file is just emptied.
+    pub fn keep(mut self, files: u32) -> Self {
+        self.keep = files;
+        self
+    }
+
+    /// Compress the rotated files with `gzip`.
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Rotate a log file of init itself as well, which is written through a [`DeferredLog`].
+    ///
+    /// [`DeferredLog`]: ../logfile/struct.DeferredLog.html
+    pub fn own_log(mut self, path: &str) -> Self {
+        self.own_logs.push(path.to_string());
+        self
+    }
+
+    pub(crate) fn own_logs(&self) -> &[String] {
+        &self.own_logs
+    }
+
+    pub(crate) fn compresses(&self) -> bool {
+        self.compress && self.keep > 0
+    }
+
+    /// Check whether the file at path is due to be rotated.
+    pub(crate) fn is_due(&self, path: &str) -> bool {
+        let meta = match fs::metadata(path) {
+            Ok(meta) if meta.is_file() && meta.len() > 0 => meta,
+            _ => return false,
+        };
+        if self.max_size.is_some_and(|max| meta.len() > max) {
+            return true;
+        }
+        let max_age = match self.max_age {
+            Some(max_age) => max_age,
+            None => return false,
+        };
+        // a file which is copied and truncated keeps its creation time
+        let since = [format!("{}.1", path), format!("{}.1.gz", path)]
+            .iter()
+            .find_map(|rotated| fs::metadata(rotated).and_then(|meta| meta.modified()).ok())
+            .or_else(|| meta.created().ok());
+        since.is_some_and(|since| {
+            SystemTime::now()
+                .duration_since(since)
+                .is_ok_and(|age| age >= max_age)
+        })
+    }
+
+    /// Rotate the file at path, by renaming it, or by copying and truncating it if it is held
+    /// open. Returns the path of the file it was rotated to, if it is kept.
+    pub(crate) fn rotate(&self, path: &str, truncate: bool) -> io::Result<Option<String>> {
+        let rotated = |idx: u32, suffix: &str| format!("{}.{}{}", path, idx, suffix);
+        if self.keep == 0 {
+            OpenOptions::new().write(true).open(path)?.set_len(0)?;
+            return Ok(None);
+        }
+        for suffix in &["", ".gz"] {
+            remove_if_exists(&rotated(self.keep, suffix))?;
+        }
+        for idx in (1..self.keep).rev() {
+            for suffix in &["", ".gz"] {
+                let from = rotated(idx, suffix);
+                if Path::new(&from).exists() {
+                    fs::rename(&from, rotated(idx + 1, suffix))?;
+                }
+            }
+        }
+        let to = rotated(1, "");
+        if truncate {
+            fs::copy(path, &to)?;
+            OpenOptions::new().write(true).open(path)?.set_len(0)?;
+        } else {
+            fs::rename(path, &to)?;
+        }
+        Ok(Some(to))
+    }
+}
+
+fn remove_if_exists(path: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
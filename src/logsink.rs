@@ -0,0 +1,241 @@
+//! Log backends for when init has neither a terminal nor a writable filesystem to log to, which
+//! is the case early in boot: the kernel log buffer, read with `dmesg`, and syslog, either a
+//! local daemon once it runs, or a remote collector over UDP.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use simplelog::{Config, SharedLogger};
+
+/// The kernel log buffer.
+pub const KMSG_PATH: &str = "/dev/kmsg";
+
+/// The socket syslog daemons listen on.
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// The kernel drops the rest of longer messages.
+const KMSG_LINE_LIMIT: usize = 976;
+
+/// The syslog facility of system daemons.
+const FACILITY_DAEMON: u8 = 3;
+
+/// The syslog severity of informational messages.
+pub(crate) const SEVERITY_INFO: u8 = 6;
+
+/// The syslog severity of a message of the given level.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => SEVERITY_INFO,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// The syslog priority of a message of the given severity, which the kernel log uses as well.
+fn priority(severity: u8) -> u8 {
+    FACILITY_DAEMON * 8 + severity
+}
+
+/// Cut a line to at most limit bytes, without splitting a character.
+fn truncate(line: &str, limit: usize) -> &str {
+    if line.len() <= limit {
+        return line;
+    }
+    let mut end = limit;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+/// Logs to the kernel log buffer, every line of a message as a record of its own with the
+/// priority of the message, so `dmesg --level` can filter them.
+pub struct KmsgLogger {
+    level: LevelFilter,
+    kmsg: Mutex<File>,
+}
+
+impl KmsgLogger {
+    /// Log the messages of at least the given level to [`KMSG_PATH`].
+    ///
+    /// [`KMSG_PATH`]: constant.KMSG_PATH.html
+    pub fn new(level: LevelFilter) -> io::Result<Box<Self>> {
+        let kmsg = OpenOptions::new().write(true).open(KMSG_PATH)?;
+        Ok(Box::new(KmsgLogger {
+            level,
+            kmsg: Mutex::new(kmsg),
+        }))
+    }
+}
+
+impl Log for KmsgLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        let mut kmsg = match self.kmsg.lock() {
+            Ok(kmsg) => kmsg,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for line in message.lines() {
+            // every write is a record, which is dropped when the kernel limits the rate
+            let _ = kmsg.write_all(
+                format!(
+                    "<{}>rsinit: {}\n",
+                    priority(severity(record.level())),
+                    truncate(line, KMSG_LINE_LIMIT)
+                )
+                .as_bytes(),
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for KmsgLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+enum Transport {
+    Local(UnixDatagram),
+    Remote(UdpSocket),
+}
+
+/// A destination for syslog messages: the local syslog daemon listening on [`SYSLOG_SOCKET`],
+/// or a remote collector, in the format of RFC 3164. Messages are sent as the daemon facility,
+/// and are dropped if they can't be delivered, e.g. while no daemon is listening yet.
+///
+/// [`SYSLOG_SOCKET`]: constant.SYSLOG_SOCKET.html
+pub struct Syslog {
+    transport: Transport,
+    // remote collectors need to be told where a message comes from
+    hostname: Option<String>,
+}
+
+impl Syslog {
+    /// Send messages to the local syslog daemon.
+    pub fn local() -> io::Result<Self> {
+        Ok(Syslog {
+            transport: Transport::Local(UnixDatagram::unbound()?),
+            hostname: None,
+        })
+    }
+
+    /// Send messages over UDP to the collector at the given address, like `logs.lan:514`.
+    pub fn remote(addr: &str) -> io::Result<Self> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", addr))
+        })?;
+        let bind = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(addr)?;
+        let hostname = fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|name| name.trim().to_string())
+            .unwrap_or_else(|_| "-".to_string());
+        Ok(Syslog {
+            transport: Transport::Remote(socket),
+            hostname: Some(hostname),
+        })
+    }
+
+    /// Send a message of the given severity, tagged with the name and pid of the program it
+    /// comes from.
+    pub(crate) fn send(&self, severity: u8, tag: &str, pid: u32, message: &str) {
+        let timestamp = chrono::Local::now().format("%b %e %H:%M:%S");
+        let message = match &self.hostname {
+            Some(hostname) => format!(
+                "<{}>{} {} {}[{}]: {}",
+                priority(severity),
+                timestamp,
+                hostname,
+                tag,
+                pid,
+                message
+            ),
+            None => format!(
+                "<{}>{} {}[{}]: {}",
+                priority(severity),
+                timestamp,
+                tag,
+                pid,
+                message
+            ),
+        };
+        let _ = match &self.transport {
+            Transport::Local(socket) => socket.send_to(message.as_bytes(), SYSLOG_SOCKET),
+            Transport::Remote(socket) => socket.send(message.as_bytes()),
+        };
+    }
+}
+
+/// Logs to syslog.
+pub struct SyslogLogger {
+    level: LevelFilter,
+    syslog: Syslog,
+}
+
+impl SyslogLogger {
+    /// Log the messages of at least the given level to the given syslog.
+    pub fn new(level: LevelFilter, syslog: Syslog) -> Box<Self> {
+        Box::new(SyslogLogger { level, syslog })
+    }
+}
+
+impl Log for SyslogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.syslog.send(
+            severity(record.level()),
+            "rsinit",
+            std::process::id(),
+            &record.args().to_string(),
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for SyslogLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
@@ -0,0 +1,55 @@
+use std::fs::read_to_string;
+use std::io;
+use std::path::Path;
+
+use crate::sys::PreparedWrite;
+
+/// A security label which is applied to a process right before it execs, so the new program
+/// runs confined by the Linux Security Module which is active on the system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityLabel<'a> {
+    /// An SELinux security context, e.g. `system_u:system_r:sshd_t:s0`.
+    SELinux(&'a str),
+    /// An AppArmor profile name, equivalent to calling `aa_change_onexec`.
+    AppArmor(&'a str),
+}
+
+impl<'a> SecurityLabel<'a> {
+    /// Check if the LSM this label targets is active on the running kernel.
+    pub fn lsm_active(&self) -> bool {
+        match self {
+            SecurityLabel::SELinux(_) => Path::new("/sys/fs/selinux/enforce").exists(),
+            SecurityLabel::AppArmor(_) => read_to_string("/sys/module/apparmor/parameters/enabled")
+                .map(|s| s.trim() == "Y")
+                .unwrap_or(false),
+        }
+    }
+
+    /// Prepare the attribute write for this label. Everything which needs to allocate is done
+    /// here, so the returned write can be applied after fork.
+    pub(crate) fn prepare(&self) -> io::Result<PreparedWrite> {
+        let (path, payload) = match self {
+            SecurityLabel::SELinux(ctx) => ("/proc/self/attr/exec", ctx.to_string()),
+            SecurityLabel::AppArmor(profile) => {
+                // newer kernels expose a per LSM attribute directory, prefer that one
+                let path = if Path::new("/proc/self/attr/apparmor/exec").exists() {
+                    "/proc/self/attr/apparmor/exec"
+                } else {
+                    "/proc/self/attr/exec"
+                };
+                (path, format!("exec {}", profile))
+            }
+        };
+
+        PreparedWrite::new(path, payload)
+    }
+}
+
+impl<'a> std::fmt::Display for SecurityLabel<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SecurityLabel::SELinux(ctx) => write!(f, "selinux:{}", ctx),
+            SecurityLabel::AppArmor(profile) => write!(f, "apparmor:{}", profile),
+        }
+    }
+}
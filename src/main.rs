@@ -1,39 +1,857 @@
-use librsinit::PersistentCommand;
+#[macro_use]
+extern crate log;
+
+use librsinit::{
+    boot, boottimes, cmdline, emergency, emergency::EmergencyMode, inittab, inittab::Inittab, lint,
+    logfile, logfile::DeferredLog, logrotate::LogRotation, logsink::KmsgLogger, logsink::Syslog,
+    logsink::SyslogLogger, logsink::KMSG_PATH, power, power::PowerAction, profile,
+    session::UserSession, signalmap, tmpfiles, ConsoleMode, NoticeFile, PersistentCommand, Reaper,
+    ReaperError, SetupError, SignalAction, CONTROL_SOCKET, EVENT_LOG_PATH, HANDOVER_PATH,
+    HEARTBEAT_PATH, JOURNAL_PATH, METRICS_PATH, SHUTDOWN_NOTICE_PATH,
+};
 use simplelog::*;
+use std::fmt;
 use std::fs::OpenOptions;
+use std::os::unix::fs::OpenOptionsExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::Duration;
 
-const PROCESSES: [(&'static str, &'static str); 2] =
-    [("/usr/sbin/sshd", ""), ("/usr/sbin/haveged", "")];
+const PROCESSES: [(&str, &str); 2] = [("/usr/sbin/sshd", ""), ("/usr/sbin/haveged", "")];
 
-fn main() {
-    CombinedLogger::init(vec![
-        TermLogger::new(log::LevelFilter::Debug, Config::default()).unwrap(),
-        WriteLogger::new(
-            log::LevelFilter::Trace,
-            Config::default(),
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .append(true)
-                .open("/log")
-                .expect("Failed to open log file"),
-        ),
-    ])
-    .expect("Failed to set up logger");
+const USAGE: &str = "usage: rsinit [boot] [--user] [--config PATH]
+       rsinit validate [--user] [--config PATH]
+       rsinit single -- CMD [ARGS...]
+
+commands:
+    boot                boot the system, or supervise the services of a user
+                        session with --user; the default
+    validate            check the services and their dependencies without
+                        starting anything, exiting with 1 if anything is wrong
+    single              run a single command, like the init of a container
+
+options:
+    --user              supervise the services of the user session, from
+                        ~/.config/rsinit, instead of those of the system
+    --config PATH       read the services from the inittab at PATH, instead of
+                        from /etc/inittab or ~/.config/rsinit/inittab
+
+As PID 1, arguments which are not understood are ignored, as the kernel passes on
+the words of its command line it does not know, like `single`.";
+
+/// What rsinit is asked to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Subcommand {
+    Boot,
+    Validate,
+    /// Run the command with its arguments.
+    Single(Vec<String>),
+}
+
+/// The command line of rsinit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cli {
+    command: Subcommand,
+    user: bool,
+    /// The inittab to read, instead of the default one.
+    config: Option<String>,
+}
+
+impl Cli {
+    /// Parse the arguments, without the name of the binary. As PID 1, arguments which are not
+    /// understood come from the kernel command line, and are ignored.
+    fn parse(args: &[String], pid1: bool) -> Result<Self, String> {
+        let (options, single) = match args.iter().position(|arg| arg == "--") {
+            Some(idx) => (&args[..idx], Some(args[idx + 1..].to_vec())),
+            None => (args, None),
+        };
+        let mut options = options.iter().map(String::as_str).peekable();
+        let mut command = match (options.peek().copied(), &single) {
+            (Some("boot"), _) => Some(Subcommand::Boot),
+            (Some("validate"), _) => Some(Subcommand::Validate),
+            (Some("single"), Some(_)) => Some(Subcommand::Single(Vec::new())),
+            (Some("single"), None) if !pid1 => {
+                return Err("single needs a command after --".to_string())
+            }
+            _ => None,
+        };
+        if command.is_some() {
+            options.next();
+        }
+        let mut cli = Cli {
+            command: Subcommand::Boot,
+            user: false,
+            config: None,
+        };
+        while let Some(option) = options.next() {
+            match option {
+                "--user" => cli.user = true,
+                "--config" => match options.next() {
+                    Some(path) => cli.config = Some(path.to_string()),
+                    None => return Err("--config needs a path".to_string()),
+                },
+                // before there were subcommands
+                "--check-config" if command.is_none() => command = Some(Subcommand::Validate),
+                "-h" | "--help" if !pid1 => return Err(String::new()),
+                _ if pid1 => (),
+                other => return Err(format!("unknown argument {}", other)),
+            }
+        }
+        cli.command = match (command, single) {
+            (Some(Subcommand::Validate), Some(_)) | (Some(Subcommand::Boot), Some(_)) => {
+                return Err("-- only goes with single".to_string())
+            }
+            (Some(command), None) => command,
+            // `rsinit -- CMD` runs a single command as well
+            (Some(Subcommand::Single(_)), Some(cmd)) | (None, Some(cmd)) => {
+                if cmd.is_empty() {
+                    return Err("no command given after --".to_string());
+                }
+                Subcommand::Single(cmd)
+            }
+            (None, None) => Subcommand::Boot,
+        };
+        Ok(cli)
+    }
+}
 
-    let mut persistent_commands = Vec::with_capacity(PROCESSES.len());
-    for (cmd, args) in &PROCESSES {
+/// The services rsinit supervises: the given processes, a getty on each of the given terminals,
+/// and the services of the inittab.
+fn services<'a>(
+    processes: &[(&'a str, &'a str)],
+    gettys: &'a [String],
+    inittab: &'a Inittab,
+) -> Vec<PersistentCommand<'a>> {
+    let mut persistent_commands = Vec::with_capacity(processes.len() + gettys.len());
+    for (cmd, args) in processes {
         persistent_commands.push(
             PersistentCommand::new(cmd, args)
+                .target("default")
                 .spawn_limit(10)
                 .restart_on_error(true)
                 .restart_on_signal(true)
                 .restart_on_success(true),
         );
     }
-    // Start reaper
-    let reaper = librsinit::Reaper::new();
+    for tty in gettys {
+        persistent_commands.push(PersistentCommand::getty(tty));
+    }
+    persistent_commands.extend(inittab.services());
+    persistent_commands
+}
+
+/// The boot target, chosen with e.g. `rsinit.target=network-online` on the kernel command
+/// line, or `single` like with sysvinit. The gettys are part of every target, the other
+/// services only of `default`.
+fn target() -> String {
+    match cmdline::value("rsinit.target") {
+        Some(target) => target,
+        None if cmdline::has_flag("single") || cmdline::has_flag("S") => "single".to_string(),
+        None => "default".to_string(),
+    }
+}
+
+/// The entries of the inittab at path, usually `/etc/inittab`, e.g. of a busybox based image,
+/// unless it is ignored with `rsinit.noinittab`.
+fn read_inittab(path: &str) -> Inittab {
+    if cmdline::has_flag("rsinit.noinittab") {
+        return Inittab::default();
+    }
+    match Inittab::read(path) {
+        Ok(inittab) => inittab,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Inittab::default(),
+        Err(e) => {
+            error!("Failed to read {}: {}", path, e);
+            Inittab::default()
+        }
+    }
+}
+
+/// The terminals to run a getty on, from `rsinit.getty=tty1,ttyS0` on the kernel command line.
+fn gettys() -> Vec<String> {
+    cmdline::value("rsinit.getty")
+        .map(|ttys| {
+            ttys.split(',')
+                .filter(|tty| !tty.is_empty())
+                .map(|tty| format!("/dev/{}", tty))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Keep init responsive when the services saturate the system, if asked for on the kernel
+/// command line: `rsinit.mlock` runs it at nice -10 with its memory locked, the nice value can
+/// be set with e.g. `rsinit.nice=-5`, and a realtime priority with e.g. `rsinit.realtime=10`.
+/// Nothing changes by default.
+fn prioritized(reaper: Reaper) -> Reaper {
+    let lock = cmdline::has_flag("rsinit.mlock");
+    let mut reaper = reaper.lock_memory(lock);
+    if let Some(nice) = parsed_option("rsinit.nice").or(Some(-10).filter(|_| lock)) {
+        reaper = reaper.nice(nice);
+    }
+    if let Some(priority) = parsed_option("rsinit.realtime") {
+        reaper = reaper.realtime_priority(priority);
+    }
+    reaper
+}
+
+/// The loggers for the console. As the first process, every console on the kernel command line
+/// gets the log, e.g. both `console=ttyS0,115200 console=tty1`, at its own level like
+/// `rsinit.loglevel.ttyS0=info`. Otherwise the log goes to the terminal, if there is one.
+fn console_loggers(
+    pid1: bool,
+    level: log::LevelFilter,
+    level_of: &dyn Fn(&str, log::LevelFilter) -> log::LevelFilter,
+) -> Vec<Box<dyn SharedLogger>> {
+    let consoles = if pid1 {
+        cmdline::consoles()
+    } else {
+        Vec::new()
+    };
+    if consoles.is_empty() {
+        return TermLogger::new(level, Config::default())
+            .map(|logger| logger as Box<dyn SharedLogger>)
+            .into_iter()
+            .collect();
+    }
+    let mut loggers: Vec<Box<dyn SharedLogger>> = Vec::new();
+    for name in &consoles {
+        // a console which can't keep up loses messages rather than holding up init
+        let tty = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(format!("/dev/{}", name));
+        match tty {
+            Ok(tty) => loggers.push(WriteLogger::new(
+                level_of(name, level),
+                Config::default(),
+                tty,
+            )),
+            Err(e) => eprintln!("Failed to log to console {}: {}", name, e),
+        }
+    }
+    loggers
+}
+
+/// Where syslog messages go: to the collector at e.g. `rsinit.syslog=10.0.0.1:514` over UDP,
+/// or else to the local syslog daemon. The network is usually not up yet when the collector is
+/// looked up, so it is best given by address.
+fn syslog() -> std::io::Result<Syslog> {
+    match cmdline::value("rsinit.syslog") {
+        Some(addr) => Syslog::remote(&addr),
+        None => Syslog::local(),
+    }
+}
+
+/// How the log files are rotated, once they grow larger than e.g. `rsinit.logrotate.size=1024`
+/// KiB, or older than e.g. `rsinit.logrotate.age=86400` seconds. `rsinit.logrotate.keep=3` keeps
+/// 3 rotated files instead of 5, and `rsinit.logrotate.compress` compresses them.
+fn log_rotation() -> Option<LogRotation> {
+    let size = parsed_option::<u64>("rsinit.logrotate.size");
+    let age = parsed_option("rsinit.logrotate.age").map(Duration::from_secs);
+    if size.is_none() && age.is_none() {
+        return None;
+    }
+    let mut rotation = LogRotation::new().compress(cmdline::has_flag("rsinit.logrotate.compress"));
+    if let Some(kib) = size {
+        rotation = rotation.max_size(kib * 1024);
+    }
+    if let Some(age) = age {
+        rotation = rotation.max_age(age);
+    }
+    if let Some(keep) = parsed_option("rsinit.logrotate.keep") {
+        rotation = rotation.keep(keep);
+    }
+    Some(rotation)
+}
+
+/// Start a shell on the console and wait for it to exit, as mode says.
+fn emergency_shell(reason: &str, mode: EmergencyMode) {
+    match emergency::run_shell(reason, mode) {
+        Ok(true) => info!("The emergency shell exited"),
+        Ok(false) => (),
+        Err(e) => error!("Failed to start an emergency shell: {}", e),
+    }
+}
+
+/// Take the reaper with an optional part of it set up, or without it if that failed.
+fn optional<'a>(what: &str, setup: Result<Reaper<'a>, SetupError<'a>>) -> Reaper<'a> {
+    setup.unwrap_or_else(|e| {
+        warn!(
+            "Supervising without the {}, which failed to be set up: {}",
+            what, e
+        );
+        *e.reaper
+    })
+}
+
+/// Create the reaper. Nothing can be supervised without it, so booting fails if it can't be
+/// created.
+fn new_reaper<'a>(pid1: bool, emergency_mode: EmergencyMode) -> Reaper<'a> {
+    match Reaper::new() {
+        Ok(reaper) => reaper,
+        Err(e) => {
+            error!("{}", e);
+            emergency_shell(&format!("Booting failed: {}", e), emergency_mode);
+            if pid1 {
+                power::shut_down_as_init(PowerAction::Halt);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parse the value of an option on the kernel command line, ignoring it if it is invalid.
+fn parsed_option<T: FromStr>(name: &str) -> Option<T>
+where
+    T::Err: fmt::Display,
+{
+    let value = cmdline::value(name)?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!("Ignoring {}={}: {}", name, value, e);
+            None
+        }
+    }
+}
+
+/// The kernel settings for when things go wrong, from options like `rsinit.panic=10`,
+/// `rsinit.panic_on_oops=1`, `rsinit.sysrq=176` and `rsinit.core_pattern=/var/crash/core.%p`
+/// on the kernel command line.
+fn kernel_settings() -> boot::KernelSettings {
+    let mut settings = boot::KernelSettings::new();
+    if let Some(secs) = parsed_option("rsinit.panic") {
+        settings = settings.panic_timeout(secs);
+    }
+    if let Some(panic) = parsed_option::<u8>("rsinit.panic_on_oops") {
+        settings = settings.panic_on_oops(panic != 0);
+    }
+    if let Some(mask) = parsed_option("rsinit.sysrq") {
+        settings = settings.sysrq(mask);
+    }
+    if let Some(pattern) = cmdline::value("rsinit.core_pattern") {
+        settings = settings.core_pattern(&pattern);
+    }
+    settings
+}
+
+/// Whether the real time clock keeps UTC, from `rsinit.hwclock=utc` or `rsinit.hwclock=localtime`
+/// on the kernel command line, or `None` if the system clock is not to be synced with it.
+fn hwclock_utc() -> Option<bool> {
+    match cmdline::value("rsinit.hwclock")?.as_str() {
+        "utc" => Some(true),
+        "localtime" => Some(false),
+        value => {
+            warn!(
+                "Ignoring rsinit.hwclock={}: expected utc or localtime",
+                value
+            );
+            None
+        }
+    }
+}
+
+fn main() {
+    // as the first process nothing is mounted yet, not even /proc which the kernel command line
+    // is read from. This is reported once there is a logger.
+    boottimes::phase("init started");
+    let pid1 = nix::unistd::getpid() == nix::unistd::Pid::from_raw(1);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cli = match Cli::parse(&args, pid1) {
+        Ok(cli) => cli,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{}\n", e);
+            }
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // `rsinit --user` supervises the services of a user session instead of booting the system
+    let session = if cli.user {
+        if pid1 {
+            eprintln!("rsinit --user can't run as PID 1");
+            std::process::exit(2);
+        }
+        match UserSession::detect() {
+            Ok(session) => {
+                cmdline::read_from(&session.config_path("options"));
+                Some(session)
+            }
+            Err(e) => {
+                eprintln!("Failed to find the directories of the user session: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    // the files which live in /run for the system live in the runtime directory of a session
+    let runtime_path = |name: &str, system: &str| match &session {
+        Some(session) => session.runtime_path(name),
+        None => system.to_string(),
+    };
+    let inittab_path = match (&cli.config, &session) {
+        (Some(path), _) => path.clone(),
+        (None, Some(session)) => session.config_path("inittab"),
+        (None, None) => inittab::INITTAB_PATH.to_string(),
+    };
+    let processes: &[(&str, &str)] = if session.is_some() { &[] } else { &PROCESSES };
+
+    // after `rsinitctl reexec` the system is up already, and the services are adopted
+    let handover_path = runtime_path("handover", HANDOVER_PATH);
+    let booting = !Path::new(&handover_path).exists();
+    let mounted = if pid1 && booting {
+        boot::mount_pseudo_filesystems()
+    } else {
+        Ok(())
+    };
+
+    // `rsinit validate` lists the lines of the inittab which can't be used and suspicious
+    // service definitions, like unknown or cyclic dependencies, without starting anything
+    if cli.command == Subcommand::Validate {
+        let (inittab, problems) = match Inittab::validate(&inittab_path) {
+            Ok(validated) => validated,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => (Inittab::default(), vec![]),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", inittab_path, e);
+                std::process::exit(1);
+            }
+        };
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        let lints = lint::check(
+            &services(processes, &gettys(), &inittab),
+            &inittab.one_shots(),
+        );
+        for lint in &lints {
+            println!("{}", lint);
+        }
+        let valid = problems.is_empty() && lints.is_empty();
+        std::process::exit(if valid { 0 } else { 1 });
+    }
+
+    if std::env::var_os("RSINIT_PROFILE").is_some() || cmdline::has_flag("rsinit.profile") {
+        profile::enable();
+    }
 
-    reaper.spawn(persistent_commands);
+    // the status of the services is shown on the console while booting, unless booting quietly.
+    // `rsinit.console=plain` suits a serial console.
+    let quiet = cmdline::has_flag("quiet");
+    let console = match cmdline::value("rsinit.console") {
+        _ if quiet => ConsoleMode::Off,
+        // a single command is not a service
+        _ if matches!(cli.command, Subcommand::Single(_)) => ConsoleMode::Off,
+        Some(mode) => mode.parse().unwrap_or_else(|e| {
+            eprintln!("Ignoring rsinit.console: {}", e);
+            ConsoleMode::detect()
+        }),
+        None => ConsoleMode::detect(),
+    };
+
+    let logger_span = profile::span("logger setup");
+    let mut loggers: Vec<Box<dyn SharedLogger>> = Vec::new();
+    // the console status would drown in the log otherwise
+    let console_level = if quiet || console != ConsoleMode::Off {
+        log::LevelFilter::Warn
+    } else {
+        log::LevelFilter::Debug
+    };
+    // where the log goes, e.g. `rsinit.logto=kmsg,file`. As the first process there might be
+    // no sane terminal, so the kernel log gets it as well by default.
+    let sinks: Vec<String> = match cmdline::value("rsinit.logto") {
+        Some(sinks) => sinks.split(',').map(str::to_string).collect(),
+        None if pid1 => vec!["kmsg".into(), "console".into(), "file".into()],
+        None => vec!["console".into(), "file".into()],
+    };
+    // every sink logs at its own level, like `rsinit.loglevel.kmsg=debug`, or else at the one
+    // of all sinks, like `rsinit.loglevel=info`
+    let level_of = |name: &str, default| match cmdline::value(&format!("rsinit.loglevel.{}", name))
+        .or_else(|| cmdline::value("rsinit.loglevel"))
+    {
+        Some(level) => level.parse().unwrap_or_else(|e| {
+            eprintln!("Ignoring the log level of {}: {}", name, e);
+            default
+        }),
+        None => default,
+    };
+    // only opened once the early mounts are done, e.g. `rsinit.log=/var/log/init`, or
+    // `rsinit.log=/var/log/init,/run/init.log` for several files. They are opened again on
+    // SIGHUP, once they were rotated.
+    let log_paths: Vec<String> = std::env::var("RSINIT_LOG")
+        .ok()
+        .or_else(|| cmdline::value("rsinit.log"))
+        .unwrap_or_else(|| runtime_path("log", logfile::LOG_PATH))
+        .split(',')
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect();
+    for sink in &sinks {
+        match sink.as_str() {
+            "console" => loggers.extend(console_loggers(pid1, console_level, &level_of)),
+            "kmsg" => match KmsgLogger::new(level_of("kmsg", log::LevelFilter::Info)) {
+                Ok(logger) => loggers.push(logger),
+                Err(e) => eprintln!("Failed to log to {}: {}", KMSG_PATH, e),
+            },
+            "syslog" => match syslog() {
+                Ok(syslog) => loggers.push(SyslogLogger::new(
+                    level_of("syslog", log::LevelFilter::Info),
+                    syslog,
+                )),
+                Err(e) => eprintln!("Failed to log to syslog: {}", e),
+            },
+            "file" => {
+                for log_path in &log_paths {
+                    loggers.push(WriteLogger::new(
+                        level_of("file", log::LevelFilter::Trace),
+                        Config::default(),
+                        DeferredLog::new(log_path),
+                    ));
+                }
+            }
+            other => eprintln!("Ignoring unknown log sink {}", other),
+        }
+    }
+    if let Err(e) = CombinedLogger::init(loggers) {
+        eprintln!("Failed to set up logger: {}", e);
+    }
+    drop(logger_span);
+    if let Err(e) = mounted {
+        error!("Failed to mount the pseudo filesystems: {}", e);
+    }
+    // before anything else can go wrong
+    if pid1 && booting {
+        kernel_settings().apply();
+    }
+    // so what is logged from here on has the right time, on boards which have a real time clock
+    // the kernel doesn't read itself
+    if pid1 && booting {
+        if let Some(utc) = hwclock_utc() {
+            match boot::hwclock_to_system(boot::RTC_PATH, utc) {
+                Ok(time) => info!(
+                    "Set the system clock from {} to {}",
+                    boot::RTC_PATH,
+                    chrono::DateTime::<chrono::Utc>::from(time).to_rfc3339()
+                ),
+                Err(e) => warn!("Failed to read the time from {}: {}", boot::RTC_PATH, e),
+            }
+        }
+    }
+
+    // e.g. /var, unless mounting is left to the services with `rsinit.nofstab`
+    if pid1 && booting && !cmdline::has_flag("rsinit.nofstab") {
+        let fstab_span = profile::span("fstab");
+        match boot::remount_root_rw(boot::FSTAB_PATH) {
+            Ok(true) => info!("Remounted / read-write"),
+            Ok(false) => (),
+            Err(e) => error!("{}", e),
+        }
+        match boot::mount_fstab(boot::FSTAB_PATH) {
+            Ok(0) => (),
+            Ok(failed) => error!(
+                "{} filesystems in {} failed to mount",
+                failed,
+                boot::FSTAB_PATH
+            ),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => error!("Failed to read {}: {}", boot::FSTAB_PATH, e),
+        }
+        match boot::swapon_all(boot::FSTAB_PATH) {
+            Ok(0) => (),
+            Ok(failed) => error!("{} swap areas failed to activate", failed),
+            Err(e) => error!("Failed to activate swap: {}", e),
+        }
+        drop(fstab_span);
+        boottimes::phase("filesystems mounted");
+    }
+
+    if pid1 && booting {
+        let setup_span = profile::span("system setup");
+        match boot::set_hostname(boot::HOSTNAME_PATH) {
+            Ok(Some(name)) => info!("Set hostname to {}", name),
+            Ok(None) => (),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => error!(
+                "Failed to set the hostname from {}: {}",
+                boot::HOSTNAME_PATH,
+                e
+            ),
+        }
+        if let Err(e) = boot::bring_up_loopback() {
+            error!("Failed to bring up the loopback interface: {}", e);
+        }
+        match boot::apply_sysctl(boot::SYSCTL_CONF, boot::SYSCTL_DIR) {
+            0 => (),
+            failed => error!("{} kernel parameters failed to apply", failed),
+        }
+        match boot::init_machine_id(boot::MACHINE_ID_PATH) {
+            Ok(Some(id)) => info!("Generated machine id {}", id),
+            Ok(None) => (),
+            Err(e) => error!(
+                "Failed to set up the machine id in {}: {}",
+                boot::MACHINE_ID_PATH,
+                e
+            ),
+        }
+        match boot::load_random_seed(boot::RANDOM_SEED_PATH) {
+            Ok(true) => debug!("Seeded the random number generator"),
+            Ok(false) => (),
+            Err(e) => warn!("Failed to load the random seed: {}", e),
+        }
+        // without a real time clock the clock starts at 1970, which is at least moved past the
+        // last shutdown
+        match boot::advance_clock(boot::CLOCK_STAMP_PATH) {
+            Ok(Some(time)) => info!(
+                "Advanced the system clock to {}",
+                chrono::DateTime::<chrono::Utc>::from(time).to_rfc3339()
+            ),
+            Ok(None) => (),
+            Err(e) => warn!("Failed to advance the system clock: {}", e),
+        }
+        drop(setup_span);
+        boottimes::phase("system set up");
+    }
+
+    // like the rest of the system setup, only for the system or a session, not e.g. a test run
+    if booting && (pid1 || session.is_some()) {
+        let tmpfiles_span = profile::span("tmpfiles");
+        let tmpfiles_dir = match &session {
+            Some(session) => session.config_path("tmpfiles.d"),
+            None => tmpfiles::TMPFILES_DIR.to_string(),
+        };
+        match tmpfiles::run(tmpfiles_dir, true) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => warn!("Failed to apply tmpfiles configuration: {}", e),
+        }
+        drop(tmpfiles_span);
+        boottimes::phase("tmpfiles applied");
+    }
+
+    // `rsinit single -- CMD [ARGS...]` runs a single command, like the init of a container
+    if let Subcommand::Single(single) = &cli.command {
+        let mut cmd = Command::new(&single[0]);
+        cmd.args(&single[1..]);
+        let code = match Reaper::new().and_then(|reaper| reaper.supervise_single(cmd)) {
+            Ok(code) => code,
+            Err(e) => {
+                error!("{}", e);
+                1
+            }
+        };
+        std::process::exit(code);
+    }
+
+    // a shell on the console when booting fails, once Enter is pressed, or right away with
+    // `rsinit.emergency=shell`
+    let emergency_mode = if pid1 {
+        parsed_option("rsinit.emergency").unwrap_or_default()
+    } else {
+        EmergencyMode::Off
+    };
+    // `rescue` on the kernel command line asks for a shell before anything is started
+    if pid1 && booting && cmdline::has_flag("rescue") {
+        emergency_shell("Rescue mode requested", EmergencyMode::Shell);
+    }
+
+    let inittab = if pid1 || session.is_some() {
+        read_inittab(&inittab_path)
+    } else {
+        Inittab::default()
+    };
+    let gettys = if session.is_some() {
+        Vec::new()
+    } else {
+        gettys()
+    };
+
+    // `rsinit.syslog_services` sends the output of the services to syslog
+    let service_syslog = if cmdline::has_flag("rsinit.syslog_services") {
+        match syslog() {
+            Ok(syslog) => Some(syslog),
+            Err(e) => {
+                error!("Failed to send the output of the services to syslog: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // e.g. `rsinit.signal.SIGPWR=poweroff` powers off when a UPS daemon reports a power failure,
+    // and `rsinit.signal.SIGUSR1=restart:sshd` restarts sshd instead of halting
+    let signal_map = cmdline::with_prefix("rsinit.signal.");
+
+    if let Some(Err(e)) = session.as_ref().map(UserSession::create_runtime_dir) {
+        error!(
+            "Failed to create the runtime directory of the session: {}",
+            e
+        );
+    }
+    let control_socket = match &session {
+        Some(session) => session.control_socket(),
+        None => CONTROL_SOCKET.to_string(),
+    };
+
+    // Start reaper. Each of these is optional, so one which fails to be set up, e.g. on a
+    // read-only /run, leaves the others in place.
+    let reaper = new_reaper(pid1, emergency_mode);
+    let reaper = optional(
+        "journal",
+        reaper.journal(&runtime_path("journal", JOURNAL_PATH)),
+    );
+    let reaper = optional("control socket", reaper.control_socket(&control_socket));
+    let reaper = optional(
+        "heartbeat",
+        reaper.heartbeat(&runtime_path("alive", HEARTBEAT_PATH)),
+    );
+    let reaper = optional(
+        "metrics",
+        reaper.metrics_file(&runtime_path("metrics.prom", METRICS_PATH)),
+    );
+    let reaper = match service_syslog {
+        Some(syslog) => optional("syslog output", reaper.syslog_output(syslog)),
+        None => reaper,
+    };
+    // e.g. `rsinit.keep_output=64` keeps the last 64 KiB of output of every service, for
+    // `rsinitctl logs`
+    let reaper = match parsed_option("rsinit.keep_output") {
+        Some(kib) => optional("kept output", reaper.keep_output(kib)),
+        None => reaper,
+    };
+    // e.g. `RSINIT_EVENT_FD=3` writes the events to a pipe of whatever started rsinit
+    let reaper = optional(
+        "event log",
+        match std::env::var("RSINIT_EVENT_FD").map(|fd| fd.parse()) {
+            Ok(Ok(fd)) => reaper.event_log_fd(fd),
+            _ => reaper.event_log(&runtime_path("events", EVENT_LOG_PATH)),
+        },
+    );
+    let reaper = reaper
+        .hold_all(cmdline::has_flag("rsinit.hold"))
+        .target(&target())
+        // services can finish their work when shutting down, e.g. with `rsinit.drain=10`
+        .shutdown_notice(NoticeFile::new(&runtime_path(
+            "stopping",
+            SHUTDOWN_NOTICE_PATH,
+        )))
+        .shutdown_drain(Duration::from_secs(
+            parsed_option("rsinit.drain").unwrap_or(0),
+        ))
+        .console_status(console);
+    let reaper = if pid1 { prioritized(reaper) } else { reaper };
+    // as init, Ctrl-Alt-Del reboots once the services are stopped, like other inits do
+    let reaper = if pid1 {
+        reaper.ctrl_alt_del(Some(PowerAction::Reboot))
+    } else {
+        reaper
+    };
+    let reaper = match log_rotation() {
+        Some(rotation) if sinks.iter().any(|sink| sink == "file") => reaper.rotate_logs(
+            log_paths
+                .iter()
+                .fold(rotation, |rotation, path| rotation.own_log(path)),
+        ),
+        Some(rotation) => reaper.rotate_logs(rotation),
+        None => reaper,
+    };
+    // a session adopts what its services leave behind, and keeps its files to itself
+    let reaper = match &session {
+        Some(session) => reaper
+            .runtime_dir(session.runtime_dir())
+            .child_subreaper()
+            .unwrap_or_else(|e| {
+                error!("Failed to become a child subreaper: {}", e);
+                std::process::exit(1);
+            }),
+        None => reaper,
+    };
+    let reaper = signal_map.iter().fold(reaper, |reaper, (name, action)| {
+        match (signalmap::parse_signal(name), SignalAction::parse(action)) {
+            (Ok(signal), Ok(action)) => reaper.on_signal(signal, action),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Ignoring rsinit.signal.{}: {}", name, e);
+                reaper
+            }
+        }
+    });
+    // outside of PID 1, e.g. `RSINIT_STATE=/run/rsinit.state` snapshots the state, so the
+    // services are adopted again if rsinit is restarted after it crashed
+    let reaper = match std::env::var("RSINIT_STATE") {
+        Ok(path) if !pid1 => reaper
+            .snapshot_state(&path, Duration::from_secs(10))
+            .resume(&path),
+        _ => reaper,
+    };
+    let reaper = if booting {
+        inittab
+            .one_shots()
+            .into_iter()
+            .fold(reaper, Reaper::one_shot)
+    } else {
+        reaper.resume(&handover_path)
+    };
+    let reaper = inittab
+        .shutdown_commands()
+        .into_iter()
+        .fold(reaper, Reaper::shutdown_command);
+    // SIGHUP reads the inittab again
+    let reaper = if pid1 || session.is_some() {
+        reaper.reload_with(|| {
+            // the services borrow from the inittab for as long as they are supervised, so every
+            // inittab which is read is kept around. Reloads are rare, and inittabs are small.
+            let inittab: &Inittab = Box::leak(Box::new(read_inittab(&inittab_path)));
+            services(processes, &gettys, inittab)
+        })
+    } else {
+        reaper
+    };
+
+    // a panic must not leave a dead machine either
+    let action = match panic::catch_unwind(AssertUnwindSafe(|| {
+        reaper.spawn(services(processes, &gettys, &inittab))
+    })) {
+        Ok(Ok(action)) => action,
+        Ok(Err(e)) => {
+            error!("Reaper failed: {}", e);
+            match e {
+                ReaperError::PermanentFailure(_, action) => action,
+                ReaperError::Unkillable(_) => PowerAction::Reboot,
+                e => {
+                    emergency_shell(&format!("Booting failed: {}", e), emergency_mode);
+                    PowerAction::Halt
+                }
+            }
+        }
+        Err(_) => {
+            error!("Reaper panicked");
+            emergency_shell("Booting failed: the reaper panicked", emergency_mode);
+            PowerAction::Halt
+        }
+    };
+
+    // all services are stopped
+    if pid1 {
+        // the seed is saved while the filesystem it is on is still writable
+        if let Err(e) = boot::save_random_seed(boot::RANDOM_SEED_PATH) {
+            warn!("Failed to save the random seed: {}", e);
+        }
+        if let Err(e) = boot::save_clock_stamp(boot::CLOCK_STAMP_PATH) {
+            warn!("Failed to save the time: {}", e);
+        }
+        if let Some(utc) = hwclock_utc() {
+            if let Err(e) = boot::system_to_hwclock(boot::RTC_PATH, utc) {
+                warn!("Failed to write the time to {}: {}", boot::RTC_PATH, e);
+            }
+        }
+        // init must never exit, as that makes the kernel panic
+        power::shut_down_as_init(action);
+    } else {
+        unsafe { libc::sync() };
+    }
 }
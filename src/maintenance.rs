@@ -0,0 +1,291 @@
+//! Periodic housekeeping jobs which are run by init itself, so small systems don't need a cron
+//! daemon just to keep their filesystems tidy.
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::sys::Dir;
+use crate::tmpfiles;
+
+/// `FITRIM` ioctl, `_IOWR('X', 121, struct fstrim_range)`.
+const FITRIM: libc::c_ulong = 0xC018_5879;
+
+#[repr(C)]
+struct FstrimRange {
+    start: u64,
+    len: u64,
+    minlen: u64,
+}
+
+/// A housekeeping task.
+#[derive(Debug, Clone)]
+pub enum MaintenanceTask {
+    /// Discard unused blocks on the filesystems mounted at the given paths, like `fstrim`.
+    Fstrim(Vec<PathBuf>),
+    /// Remove files and empty directories below a directory which have not been accessed or
+    /// modified for the given age. The cleanup does not cross filesystem boundaries.
+    CleanDirectory { path: PathBuf, max_age: Duration },
+    /// Remove the oldest files in a log directory until the total size of the files in it is
+    /// at most the given amount of bytes.
+    VacuumLogs { path: PathBuf, max_size: u64 },
+    /// Apply the tmpfiles configuration in the given directory, which creates missing paths and
+    /// cleans up directories by age.
+    Tmpfiles(PathBuf),
+}
+
+impl MaintenanceTask {
+    fn run(&self) -> io::Result<()> {
+        match self {
+            MaintenanceTask::Fstrim(mountpoints) => {
+                for mp in mountpoints {
+                    match fstrim(mp) {
+                        Ok(trimmed) => info!("Trimmed {} bytes on {:?}", trimmed, mp),
+                        Err(e) => warn!("Failed to trim {:?}: {}", mp, e),
+                    }
+                }
+                Ok(())
+            }
+            MaintenanceTask::CleanDirectory { path, max_age } => {
+                // an age from before the epoch leaves everything in place
+                let cutoff = SystemTime::now()
+                    .checked_sub(*max_age)
+                    .unwrap_or(UNIX_EPOCH);
+                let removed = clean_directory(path, cutoff)?;
+                info!("Removed {} stale entries from {:?}", removed, path);
+                Ok(())
+            }
+            MaintenanceTask::VacuumLogs { path, max_size } => {
+                let freed = vacuum(path, *max_size)?;
+                info!("Vacuumed {} bytes of logs in {:?}", freed, path);
+                Ok(())
+            }
+            MaintenanceTask::Tmpfiles(dir) => tmpfiles::run(dir, false),
+        }
+    }
+}
+
+impl std::fmt::Display for MaintenanceTask {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MaintenanceTask::Fstrim(_) => write!(f, "fstrim"),
+            MaintenanceTask::CleanDirectory { path, .. } => write!(f, "clean {:?}", path),
+            MaintenanceTask::VacuumLogs { path, .. } => write!(f, "vacuum {:?}", path),
+            MaintenanceTask::Tmpfiles(dir) => write!(f, "tmpfiles {:?}", dir),
+        }
+    }
+}
+
+/// A [`MaintenanceTask`] which is run periodically by the [`Reaper`]. Every run happens on a
+/// separate thread, so a slow job does not hold up reaping. A job is never run concurrently
+/// with itself.
+///
+/// [`MaintenanceTask`]: enum.MaintenanceTask.html
+/// [`Reaper`]: ../struct.Reaper.html
+#[derive(Debug)]
+pub struct MaintenanceJob {
+    task: Arc<MaintenanceTask>,
+    interval: Duration,
+    on_startup: bool,
+
+    next_run: Option<Instant>,
+    running: Arc<AtomicBool>,
+}
+
+impl MaintenanceJob {
+    /// Run the task every interval, e.g. weekly for fstrim. An interval too long to be
+    /// scheduled runs the task only on startup, if at all.
+    pub fn new(task: MaintenanceTask, interval: Duration) -> Self {
+        MaintenanceJob {
+            task: Arc::new(task),
+            interval,
+            on_startup: false,
+
+            next_run: None,
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Also run the task as soon as the reaper starts, instead of only after the first interval.
+    pub fn on_startup(mut self, on_startup: bool) -> Self {
+        self.on_startup = on_startup;
+        self
+    }
+
+    /// Schedule the first run of the job.
+    pub(crate) fn schedule(&mut self, now: Instant) {
+        self.next_run = if self.on_startup {
+            Some(now)
+        } else {
+            now.checked_add(self.interval)
+        };
+    }
+
+    /// When the job is due to run next, once it is scheduled.
+    pub(crate) fn next_run(&self) -> Option<Instant> {
+        self.next_run
+    }
+
+    /// Start the job if it is due.
+    pub(crate) fn run_if_due(&mut self, now: Instant) {
+        match self.next_run {
+            Some(next) if next <= now => (),
+            _ => return,
+        }
+        self.next_run = now.checked_add(self.interval);
+
+        if self.running.swap(true, Ordering::SeqCst) {
+            warn!("Maintenance job {} is still running, skipping", self.task);
+            return;
+        }
+
+        let task = self.task.clone();
+        let running = self.running.clone();
+        let spawned = thread::Builder::new()
+            .name("maintenance".to_string())
+            .spawn(move || {
+                debug!("Running maintenance job {}", task);
+                if let Err(e) = task.run() {
+                    warn!("Maintenance job {} failed: {}", task, e);
+                }
+                running.store(false, Ordering::SeqCst);
+            });
+        if let Err(e) = spawned {
+            error!("Failed to start maintenance job {}: {}", self.task, e);
+            self.running.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Trim the filesystem mounted at path, returning the amount of bytes trimmed.
+fn fstrim(path: &Path) -> io::Result<u64> {
+    let dir = File::open(path)?;
+    let mut range = FstrimRange {
+        start: 0,
+        len: u64::MAX,
+        minlen: 0,
+    };
+    if unsafe { libc::ioctl(dir.as_raw_fd(), FITRIM as _, &mut range) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(range.len)
+}
+
+/// Recursively remove entries below the directory at path which were last used before the
+/// cutoff, without crossing filesystem boundaries. The directory is walked through file
+/// descriptors, so a subdirectory which is swapped for a symlink is never followed. Entries which
+/// fail to be examined or removed are skipped. Returns the amount of entries removed.
+pub(crate) fn clean_directory(path: &Path, cutoff: SystemTime) -> io::Result<usize> {
+    let dir = Dir::open(path)?;
+    let dev = dir.as_file().metadata()?.dev();
+    Ok(clean_dir(&dir, path, dev, cutoff))
+}
+
+fn clean_dir(dir: &Dir, path: &Path, dev: u64, cutoff: SystemTime) -> usize {
+    let entries = match dir.entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Failed to read {:?}: {}", path, e);
+            return 0;
+        }
+    };
+    let mut removed = 0;
+    for name in entries {
+        let path = path.join(OsStr::from_bytes(name.to_bytes()));
+        let stat = match dir.stat(&name) {
+            Ok(stat) => stat,
+            // raced with something else removing it
+            Err(_) => continue,
+        };
+        if stat.dev != dev {
+            continue;
+        }
+
+        let last_used = stat.accessed.max(stat.modified);
+        if stat.is_dir {
+            match dir.open_dir(&name) {
+                Ok(sub) => removed += clean_dir(&sub, &path, dev, cutoff),
+                Err(e) => {
+                    warn!("Failed to open {:?}: {}", path, e);
+                    continue;
+                }
+            }
+            if last_used < cutoff && dir.remove(&name, true).is_ok() {
+                removed += 1;
+            }
+        } else if last_used < cutoff {
+            match dir.remove(&name, false) {
+                Ok(_) => removed += 1,
+                Err(e) => debug!("Failed to remove {:?}: {}", path, e),
+            }
+        }
+    }
+    removed
+}
+
+/// Remove the oldest regular files in dir until their total size is at most max_size. Returns
+/// the amount of bytes freed.
+fn vacuum(dir: &Path, max_size: u64) -> io::Result<u64> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_file() {
+            files.push((meta.modified()?, meta.len(), entry.path()));
+        }
+    }
+    files.sort();
+
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    let mut freed = 0;
+    for (_, size, path) in files {
+        if total <= max_size {
+            break;
+        }
+        fs::remove_file(&path)?;
+        total -= size;
+        freed += size;
+    }
+    Ok(freed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn clean_directory_does_not_follow_symlinks() {
+        let base = std::env::temp_dir().join(format!("rsinit-clean-{}", std::process::id()));
+        let tree = base.join("tree");
+        let outside = base.join("outside");
+        fs::create_dir_all(tree.join("sub")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(tree.join("sub/file"), "").unwrap();
+        fs::write(outside.join("keep"), "").unwrap();
+        symlink(&outside, tree.join("link")).unwrap();
+
+        // everything counts as stale
+        let cutoff = SystemTime::now() + Duration::from_secs(3600);
+        let removed = clean_directory(&tree, cutoff).unwrap();
+        assert_eq!(removed, 3);
+        assert_eq!(fs::read_dir(&tree).unwrap().count(), 0);
+        assert!(outside.join("keep").exists());
+
+        // the directory itself is never followed either
+        symlink(&outside, base.join("tree-link")).unwrap();
+        let e = clean_directory(&base.join("tree-link"), cutoff).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENOTDIR));
+        assert!(outside.join("keep").exists());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
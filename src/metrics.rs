@@ -0,0 +1,162 @@
+//! The state of the services as metrics in the text format of Prometheus, written to a file on
+//! every pass of the main loop, e.g. for the textfile collector of the node exporter. Operators
+//! can alert on services which keep crashing without talking to the reaper.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::command::Event;
+use crate::stats::ReaperStats;
+use crate::status::ServiceStatus;
+
+/// Default location of the metrics file.
+pub const METRICS_PATH: &str = "/run/rsinit/metrics.prom";
+
+pub(crate) struct MetricsFile {
+    path: PathBuf,
+    tmp: PathBuf,
+}
+
+impl MetricsFile {
+    pub(crate) fn create(path: &str) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(MetricsFile {
+            tmp: path.with_extension("tmp"),
+            path,
+        })
+    }
+
+    /// Write the metrics of the given services and of the reaper itself, with how often each
+    /// event happened, to the file.
+    pub(crate) fn write(
+        &self,
+        services: &HashMap<String, ServiceStatus>,
+        stats: &ReaperStats,
+        events: &BTreeMap<&'static str, u64>,
+    ) {
+        let res = fs::write(&self.tmp, render(services, stats, events))
+            .and_then(|_| fs::rename(&self.tmp, &self.path));
+        if let Err(e) = res {
+            warn!("Failed to write metrics to {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Render the metrics, with the services in order of their name.
+fn render(
+    services: &HashMap<String, ServiceStatus>,
+    stats: &ReaperStats,
+    events: &BTreeMap<&'static str, u64>,
+) -> String {
+    let mut names: Vec<&String> = services.keys().collect();
+    names.sort();
+    let mut out = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, value: &dyn Fn(&ServiceStatus) -> f64| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for service in &names {
+            let _ = writeln!(
+                out,
+                "{}{{service=\"{}\"}} {}",
+                name,
+                escape(service),
+                value(&services[*service])
+            );
+        }
+    };
+    family(
+        "rsinit_service_running",
+        "gauge",
+        "Whether the service has a running process.",
+        &|status| status.is_running() as u8 as f64,
+    );
+    family(
+        "rsinit_service_restarts_total",
+        "counter",
+        "How often the service was started again.",
+        &|status| status.restarts as f64,
+    );
+    family(
+        "rsinit_service_last_exit_code",
+        "gauge",
+        "The exit code of the last process of the service, or 128 plus the signal which killed it.",
+        &|status| match status.last_exit {
+            Some(Event::ExitSuccess) | None => 0.0,
+            Some(Event::ExitCode(code))
+            | Some(Event::StartFailed {
+                code: Some(code), ..
+            }) => code as f64,
+            Some(Event::ExitSignal(signal))
+            | Some(Event::StartFailed {
+                signal: Some(signal),
+                ..
+            }) => (128 + signal as i32) as f64,
+            Some(Event::StartFailed { .. }) => 0.0,
+        },
+    );
+    family(
+        "rsinit_service_cpu_seconds_total",
+        "counter",
+        "The user and system CPU time the processes of the service used, as last sampled.",
+        &|status| status.usage.cpu_time.as_secs_f64(),
+    );
+    family(
+        "rsinit_service_max_rss_bytes",
+        "gauge",
+        "The highest resident memory of a process of the service, as last sampled.",
+        &|status| status.usage.max_rss_bytes as f64,
+    );
+    family(
+        "rsinit_service_uptime_seconds",
+        "gauge",
+        "How long the running process of the service has been running.",
+        &|status| status.uptime().unwrap_or_default().as_secs_f64(),
+    );
+
+    for (name, help, value) in &[
+        (
+            "rsinit_zombies_reaped_total",
+            "The amount of zombie processes which were reaped.",
+            stats.zombies_reaped,
+        ),
+        (
+            "rsinit_orphans_terminated_total",
+            "The amount of orphans which were terminated because their parent failed.",
+            stats.orphans_terminated,
+        ),
+        (
+            "rsinit_reaps_deferred_total",
+            "The amount of times reaping was cut short and carried on in the next pass.",
+            stats.reaps_deferred,
+        ),
+    ] {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    // labeled by the stable names of the events
+    let _ = writeln!(
+        out,
+        "# HELP rsinit_events_total How often each event of the reaper happened."
+    );
+    let _ = writeln!(out, "# TYPE rsinit_events_total counter");
+    for (event, count) in events {
+        let _ = writeln!(out, "rsinit_events_total{{event=\"{}\"}} {}", event, count);
+    }
+    out
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
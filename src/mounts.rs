@@ -0,0 +1,234 @@
+//! Tracking which filesystems are mounted, so services can wait for the storage they need.
+//!
+//! The kernel flags `/proc/self/mountinfo` with an exceptional condition whenever a filesystem
+//! is mounted or unmounted, so the file descriptor is watched by the event loop and the table
+//! is only read again after a change.
+//!
+//! Filesystems which are rarely used can be declared as [`Automount`]s, which are only mounted
+//! once a service which requires them is started, to keep them out of the way of booting.
+//!
+//! [`Automount`]: struct.Automount.html
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use nix::mount::{mount, umount, MsFlags};
+
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+pub(crate) struct MountTable {
+    file: File,
+    mount_points: HashSet<String>,
+}
+
+impl MountTable {
+    pub(crate) fn open() -> io::Result<Self> {
+        let mut table = MountTable {
+            file: File::open(MOUNTINFO_PATH)?,
+            mount_points: HashSet::new(),
+        };
+        table.refresh()?;
+        Ok(table)
+    }
+
+    /// Read the mount points again, after the mount table changed.
+    pub(crate) fn refresh(&mut self) -> io::Result<()> {
+        let mut content = String::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_string(&mut content)?;
+        self.mount_points = parse_mount_points(&content).collect();
+        Ok(())
+    }
+
+    /// Check if a filesystem is mounted at path.
+    pub(crate) fn is_mounted(&self, path: &str) -> bool {
+        self.mount_points.contains(normalize(path))
+    }
+}
+
+impl AsRawFd for MountTable {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// A filesystem which is mounted once a service which [requires it] is started, and, optionally,
+/// unmounted again once none of those services has been running for a while.
+///
+/// [requires it]: struct.PersistentCommand.html#method.requires_mount
+#[derive(Debug)]
+pub struct Automount {
+    source: String,
+    target: String,
+    fstype: String,
+    options: Option<String>,
+    flags: MsFlags,
+    unmount_after: Option<Duration>,
+
+    mounted: bool,
+    failed: bool,
+    idle_since: Option<Instant>,
+}
+
+impl Automount {
+    /// Mount the filesystem of the given type from source, e.g. a device, at target.
+    pub fn new(source: &str, target: &str, fstype: &str) -> Self {
+        Automount {
+            source: source.to_string(),
+            target: normalize(target).to_string(),
+            fstype: fstype.to_string(),
+            options: None,
+            flags: MsFlags::empty(),
+            unmount_after: None,
+
+            mounted: false,
+            failed: false,
+            idle_since: None,
+        }
+    }
+
+    /// Pass filesystem specific options, as given to mount(8) with `-o`, e.g. `compress=zstd`.
+    pub fn options(mut self, options: &str) -> Self {
+        self.options = Some(options.to_string());
+        self
+    }
+
+    /// Mount the filesystem read-only.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.flags.set(MsFlags::MS_RDONLY, read_only);
+        self
+    }
+
+    /// Unmount the filesystem once no service which requires it has been running for the given
+    /// time. By default, the filesystem stays mounted once it is.
+    pub fn unmount_after(mut self, idle: Duration) -> Self {
+        self.unmount_after = Some(idle);
+        self
+    }
+
+    /// Check if this mounts the filesystem at path.
+    pub(crate) fn mounts_at(&self, path: &str) -> bool {
+        self.target == normalize(path)
+    }
+
+    /// Mount the filesystem, unless mounting it failed before.
+    pub(crate) fn mount(&mut self) {
+        if self.mounted || self.failed {
+            return;
+        }
+        info!("Mounting {} at {}", self.source, self.target);
+        let res = mount(
+            Some(self.source.as_str()),
+            self.target.as_str(),
+            Some(self.fstype.as_str()),
+            self.flags,
+            self.options.as_deref(),
+        );
+        match res {
+            Ok(_) => {
+                self.mounted = true;
+                self.idle_since = None;
+            }
+            Err(e) => {
+                error!("Failed to mount {} at {}: {}", self.source, self.target, e);
+                self.failed = true;
+            }
+        }
+    }
+
+    /// Unmount the filesystem if it has not been in use for long enough.
+    pub(crate) fn unmount_if_idle(&mut self, in_use: bool, now: Instant) {
+        if !self.mounted || in_use {
+            self.idle_since = None;
+            return;
+        }
+        let idle_since = *self.idle_since.get_or_insert(now);
+        match self.unmount_after {
+            Some(idle) if now.duration_since(idle_since) >= idle => (),
+            _ => return,
+        }
+        debug!("{} is idle, unmounting it", self.target);
+        if !self.unmount() {
+            // something else is using it, try again later
+            self.idle_since = Some(now);
+        }
+    }
+
+    /// When the filesystem is due to be unmounted, if it stays idle.
+    pub(crate) fn next_unmount(&self) -> Option<Instant> {
+        match (self.mounted, self.idle_since, self.unmount_after) {
+            (true, Some(since), Some(idle)) => Some(since + idle),
+            _ => None,
+        }
+    }
+
+    /// Unmount the filesystem if it was mounted, returning whether it is unmounted.
+    pub(crate) fn unmount(&mut self) -> bool {
+        if !self.mounted {
+            return true;
+        }
+        info!("Unmounting {}", self.target);
+        match umount(self.target.as_str()) {
+            Ok(_) => {
+                self.mounted = false;
+                self.idle_since = None;
+                true
+            }
+            Err(e) => {
+                warn!("Failed to unmount {}: {}", self.target, e);
+                false
+            }
+        }
+    }
+}
+
+/// The mount points of all filesystems, in the order they were mounted.
+pub(crate) fn mount_points() -> io::Result<Vec<String>> {
+    let content = fs::read_to_string(MOUNTINFO_PATH)?;
+    Ok(parse_mount_points(&content).collect())
+}
+
+fn parse_mount_points(mountinfo: &str) -> impl Iterator<Item = String> + '_ {
+    // every line is id parent major:minor root mount-point options...
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(unescape)
+}
+
+/// Strip trailing slashes from a path, so paths can be compared.
+pub(crate) fn normalize(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        path => path,
+    }
+}
+
+/// Undo the octal escapes the kernel uses for spaces, tabs, newlines and backslashes in paths.
+/// fstab uses the same escapes.
+pub(crate) fn unescape(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let escaped = bytes.get(idx + 1..idx + 4).and_then(|digits| {
+            std::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 8).ok())
+        });
+        match escaped {
+            Some(byte) if bytes[idx] == b'\\' => {
+                out.push(byte);
+                idx += 4;
+            }
+            _ => {
+                out.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
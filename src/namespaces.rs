@@ -0,0 +1,213 @@
+//! Linux namespaces, so a service can be isolated from the rest of the system without a
+//! container runtime.
+//!
+//! Most namespaces are entered with a plain unshare(2) before exec. A new PID namespace only
+//! applies to the children of the process which unshares it, so for one the process forks: the
+//! child becomes PID 1 of the namespace and goes on to exec the service, with a private /proc
+//! which shows the processes of the namespace. The parent stays behind as the process the reaper
+//! supervises, forwarding signals to the service and exiting like it does. Note that a process
+//! which is PID 1 of its namespace only receives the signals it installed a handler for, other
+//! than SIGKILL, like init.
+
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// Where `ip netns add` creates its named network namespaces.
+const NETNS_DIR: &str = "/var/run/netns";
+
+/// A namespace a service can get a new instance of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    /// Mounts, which don't propagate back to the rest of the system.
+    Mount,
+    /// Process ids, with the service as PID 1. Implies a mount namespace, to mount a private
+    /// /proc in.
+    Pid,
+    /// System V IPC objects and POSIX message queues.
+    Ipc,
+    /// The hostname and domain name.
+    Uts,
+    /// Network interfaces, routes and sockets. The loopback interface is brought up, all other
+    /// interfaces are left to the service.
+    Net,
+}
+
+impl Namespace {
+    fn flag(self) -> libc::c_int {
+        match self {
+            Namespace::Mount => libc::CLONE_NEWNS,
+            Namespace::Pid => libc::CLONE_NEWPID | libc::CLONE_NEWNS,
+            Namespace::Ipc => libc::CLONE_NEWIPC,
+            Namespace::Uts => libc::CLONE_NEWUTS,
+            Namespace::Net => libc::CLONE_NEWNET,
+        }
+    }
+}
+
+/// New namespaces for a service, prepared up front so they can be entered after fork.
+#[derive(Debug)]
+pub(crate) struct Namespaces {
+    flags: libc::c_int,
+    /// Where the private /proc of a PID namespace is mounted.
+    proc_dir: Option<CString>,
+    /// The reaper, if the process which stays behind for a PID namespace dies with it.
+    parent: Option<libc::pid_t>,
+}
+
+impl Namespaces {
+    /// Prepare the given namespaces, for a service with its root at root. With a parent, the
+    /// process which stays behind for a PID namespace is killed once that parent dies.
+    pub(crate) fn prepare(
+        namespaces: &[Namespace],
+        root: Option<&str>,
+        parent: Option<libc::pid_t>,
+    ) -> io::Result<Self> {
+        let flags = namespaces.iter().fold(0, |flags, ns| flags | ns.flag());
+        let proc_dir = if flags & libc::CLONE_NEWPID != 0 {
+            let dir = Path::new(root.unwrap_or("/")).join("proc");
+            Some(CString::new(dir.to_string_lossy().as_bytes())?)
+        } else {
+            None
+        };
+        Ok(Namespaces {
+            flags,
+            proc_dir,
+            parent,
+        })
+    }
+
+    /// Move the calling process into the new namespaces, forking for a PID namespace. Only raw
+    /// syscalls are used, so this can be done in between fork and exec.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        unsafe {
+            check(libc::unshare(self.flags))?;
+            if self.flags & libc::CLONE_NEWNS != 0 {
+                // keep the mounts of the service from propagating back to init
+                check(libc::mount(
+                    std::ptr::null(),
+                    b"/\0".as_ptr() as *const libc::c_char,
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_SLAVE,
+                    std::ptr::null(),
+                ))?;
+            }
+            if self.flags & libc::CLONE_NEWNET != 0 {
+                crate::sys::interface_up(CStr::from_bytes_with_nul_unchecked(b"lo\0"))?;
+            }
+            if let Some(proc_dir) = &self.proc_dir {
+                self.fork_into_pid_namespace()?;
+                check(libc::mount(
+                    b"proc\0".as_ptr() as *const libc::c_char,
+                    proc_dir.as_ptr(),
+                    b"proc\0".as_ptr() as *const libc::c_char,
+                    libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                    std::ptr::null(),
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fork, returning in the child, which is PID 1 of the new PID namespace. The parent never
+    /// returns: it forwards all signals to the child until the child exits, and then exits the
+    /// same way.
+    unsafe fn fork_into_pid_namespace(&self) -> io::Result<()> {
+        if let Some(parent) = self.parent {
+            crate::sys::signal_on_parent_death(libc::SIGKILL, parent)?;
+        }
+        // block all signals before forking, so none goes unnoticed in between
+        let mut all: libc::sigset_t = std::mem::zeroed();
+        let mut original: libc::sigset_t = std::mem::zeroed();
+        libc::sigfillset(&mut all);
+        check(libc::sigprocmask(libc::SIG_SETMASK, &all, &mut original))?;
+        let child = libc::fork();
+        if child < 0 {
+            let e = io::Error::last_os_error();
+            libc::sigprocmask(libc::SIG_SETMASK, &original, std::ptr::null_mut());
+            return Err(e);
+        }
+        if child == 0 {
+            check(libc::sigprocmask(
+                libc::SIG_SETMASK,
+                &original,
+                std::ptr::null_mut(),
+            ))?;
+            return Ok(());
+        }
+
+        // the parent waits for the exec, which this process never does, until every copy of
+        // the pipe it is told about a failed exec through is closed. The output of the service
+        // is not held open either.
+        crate::sys::close_all_fds();
+        loop {
+            let mut info: libc::siginfo_t = std::mem::zeroed();
+            let signal = libc::sigwaitinfo(&all, &mut info);
+            if signal < 0 {
+                continue;
+            }
+            if signal != libc::SIGCHLD {
+                libc::kill(child, signal);
+                continue;
+            }
+            let mut status = 0;
+            if libc::waitpid(child, &mut status, libc::WNOHANG) != child {
+                continue;
+            }
+            if libc::WIFSIGNALED(status) {
+                // die of the same signal, so the reaper sees what the service died of
+                let signal = libc::WTERMSIG(status);
+                let mut set: libc::sigset_t = std::mem::zeroed();
+                libc::sigemptyset(&mut set);
+                libc::sigaddset(&mut set, signal);
+                libc::signal(signal, libc::SIG_DFL);
+                libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+                libc::raise(signal);
+                libc::_exit(128 + signal);
+            }
+            libc::_exit(libc::WEXITSTATUS(status));
+        }
+    }
+}
+
+/// An existing network namespace for a service to join, opened up front so it can be entered
+/// after fork.
+#[derive(Debug)]
+pub(crate) struct NetnsJoin {
+    file: File,
+}
+
+impl NetnsJoin {
+    /// Open the network namespace with the given name, as created by `ip netns add`, or at the
+    /// given path if it contains a slash, e.g. a namespace which was bind mounted elsewhere.
+    pub(crate) fn open(netns: &str) -> io::Result<Self> {
+        let path = if netns.contains('/') {
+            Path::new(netns).to_path_buf()
+        } else {
+            Path::new(NETNS_DIR).join(netns)
+        };
+        let file = File::open(&path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("failed to open network namespace {}: {}", path.display(), e),
+            )
+        })?;
+        Ok(NetnsJoin { file })
+    }
+
+    /// Move the calling process into the network namespace. This can be done in between fork
+    /// and exec.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        check(unsafe { libc::setns(self.file.as_raw_fd(), libc::CLONE_NEWNET) })
+    }
+}
+
+fn check(res: libc::c_int) -> io::Result<()> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
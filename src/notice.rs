@@ -0,0 +1,77 @@
+//! Advance warning for services that the system is shutting down, so stateful daemons can finish
+//! the work in flight before they are sent SIGTERM, see [`Reaper::shutdown_drain`].
+//!
+//! Services can be sent a signal of their choice, see [`PersistentCommand::shutdown_notice`].
+//! Beyond that, a [`ShutdownNotice`] tells every running service, e.g. through a [`NoticeFile`].
+//!
+//! [`Reaper::shutdown_drain`]: ../struct.Reaper.html#method.shutdown_drain
+//! [`PersistentCommand::shutdown_notice`]: ../struct.PersistentCommand.html#method.shutdown_notice
+//! [`ShutdownNotice`]: trait.ShutdownNotice.html
+//! [`NoticeFile`]: struct.NoticeFile.html
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use nix::unistd::Pid;
+
+/// Default location of the notice file.
+pub const SHUTDOWN_NOTICE_PATH: &str = "/run/rsinit/stopping";
+
+/// A way to tell the running services that they are about to be stopped.
+pub trait ShutdownNotice {
+    /// Tell the service with the given name, of which the process has the given pid, that it
+    /// is sent SIGTERM once the drain period is over.
+    fn notify(&mut self, service: &str, pid: Pid, drain: Duration) -> io::Result<()>;
+}
+
+/// Creates a file once the system is shutting down, which services can watch for. It holds
+/// `STOPPING=1` and, as `DRAIN_USEC`, how long the services have left, in the format of the
+/// messages services send to their notification socket.
+#[derive(Debug, Clone)]
+pub struct NoticeFile {
+    path: PathBuf,
+    written: bool,
+}
+
+impl NoticeFile {
+    /// Create the notice at the given path, usually [`SHUTDOWN_NOTICE_PATH`], once shutting
+    /// down. A file left behind from before is removed right away.
+    ///
+    /// [`SHUTDOWN_NOTICE_PATH`]: constant.SHUTDOWN_NOTICE_PATH.html
+    pub fn new(path: &str) -> Self {
+        let path = PathBuf::from(path);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                warn!("Failed to remove stale shutdown notice {:?}: {}", path, e)
+            }
+            _ => (),
+        }
+        NoticeFile {
+            path,
+            written: false,
+        }
+    }
+}
+
+impl ShutdownNotice for NoticeFile {
+    fn notify(&mut self, _: &str, _: Pid, drain: Duration) -> io::Result<()> {
+        // all services share the file
+        if self.written {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // created at once, so it is never read half written
+        let tmp = self.path.with_extension("tmp");
+        fs::write(
+            &tmp,
+            format!("STOPPING=1\nDRAIN_USEC={}\n", drain.as_micros()),
+        )?;
+        fs::rename(&tmp, &self.path)?;
+        self.written = true;
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+//! Readiness notification, compatible with `sd_notify(3)`. Services which opt in are passed the
+//! path of a datagram socket of their own in `NOTIFY_SOCKET`, to which they send newline
+//! separated `KEY=VALUE` assignments, e.g. `READY=1` once they are done starting up. The socket
+//! passes the credentials of the sender along with every message, so messages from processes
+//! which are not part of the service can be dropped.
+
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+use nix::unistd::Pid;
+
+/// Default directory in which the notification sockets of services are created.
+pub const NOTIFY_DIR: &str = "/run/rsinit/notify";
+
+/// A message sent by a service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Notification {
+    /// The service finished starting up.
+    Ready,
+    /// A human readable description of what the service is doing.
+    Status(String),
+    /// The service is still alive, for services which are watched.
+    Watchdog,
+}
+
+/// The notification socket of a single service.
+pub(crate) struct NotifySocket {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl NotifySocket {
+    /// Create the socket for the named service in dir.
+    pub(crate) fn bind(dir: &str, service: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = Path::new(dir).join(service.replace('/', "_"));
+        // left behind by a previous run
+        let _ = fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path)?;
+        socket.set_nonblocking(true)?;
+        let on: libc::c_int = 1;
+        if unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PASSCRED,
+                &on as *const _ as *const libc::c_void,
+                mem::size_of_val(&on) as libc::socklen_t,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        // the service might not run as root, anyone else who sends to the socket is told apart
+        // by the credentials passed along
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o666))?;
+        Ok(NotifySocket { socket, path })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Receive all pending notifications, along with the pid of the process which sent them.
+    /// Messages of which the sender is not known are dropped.
+    pub(crate) fn notifications(&self) -> Vec<(Pid, Notification)> {
+        let mut notifications = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let (len, sender) = match self.recv(&mut buf) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Failed to receive from {}: {}", self.path.display(), e);
+                    break;
+                }
+            };
+            let sender = match sender {
+                Some(sender) => sender,
+                None => {
+                    warn!(
+                        "Dropping notification without credentials on {}",
+                        self.path.display()
+                    );
+                    continue;
+                }
+            };
+            let message = String::from_utf8_lossy(&buf[..len]);
+            notifications.extend(message.lines().filter_map(parse).map(|n| (sender, n)));
+        }
+        notifications
+    }
+
+    /// Receive a single message, with the pid of its sender from the credentials passed along.
+    fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, Option<Pid>)> {
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // u64 keeps the buffer aligned for the cmsghdr
+        let mut control =
+            [0u64; (mem::size_of::<libc::cmsghdr>() + mem::size_of::<libc::ucred>()) / 8 + 2];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+        let len = unsafe { libc::recvmsg(self.socket.as_raw_fd(), &mut msg, 0) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut sender = None;
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET
+                    && (*cmsg).cmsg_type == libc::SCM_CREDENTIALS
+                {
+                    let cred = (libc::CMSG_DATA(cmsg) as *const libc::ucred).read_unaligned();
+                    sender = Some(Pid::from_raw(cred.pid));
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        Ok((len as usize, sender))
+    }
+}
+
+impl AsRawFd for NotifySocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl Drop for NotifySocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Parse a single assignment. Assignments which are not understood are ignored, as sd_notify
+/// allows for many more than are supported here.
+fn parse(line: &str) -> Option<Notification> {
+    let (key, value) = line.split_at(line.find('=')?);
+    match (key, &value[1..]) {
+        ("READY", "1") => Some(Notification::Ready),
+        ("STATUS", status) => Some(Notification::Status(status.to_string())),
+        ("WATCHDOG", "1") => Some(Notification::Watchdog),
+        _ => {
+            trace!("Ignoring notification {}", line);
+            None
+        }
+    }
+}
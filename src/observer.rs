@@ -0,0 +1,91 @@
+//! Callbacks for what the reaper does, for programs which embed it and need to follow its
+//! services without reading its log, see [`Reaper::observer`].
+//!
+//! [`Reaper::observer`]: ../struct.Reaper.html#method.observer
+
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+
+use crate::events::ReaperEvent;
+use crate::Carcass;
+
+/// Is told about the lifecycle of the services and the processes of the reaper. All methods do
+/// nothing by default, so an observer only implements the ones it is interested in.
+///
+/// The methods are called from the main loop of the reaper, which is held up until they
+/// return, so they must not block.
+pub trait ReaperObserver {
+    /// Every event, of which the most common are also passed to the methods below. By default
+    /// this calls the method for the event, if there is one, so an observer which implements
+    /// this and wants those calls as well calls [`dispatch`] itself.
+    ///
+    /// [`dispatch`]: fn.dispatch.html
+    fn on_event(&mut self, event: &ReaperEvent) {
+        dispatch(self, event);
+    }
+
+    /// A process was spawned for the service with the given name.
+    fn on_spawn(&mut self, _service: &str, _pid: Pid) {}
+
+    /// A child of the reaper was reaped, whether it belonged to a service or not.
+    fn on_reap(&mut self, _carcass: &Carcass) {}
+
+    /// The service with the given name was spawned again after its previous process exited, for
+    /// the given amount of times in total. This follows the call to [`on_spawn`].
+    ///
+    /// [`on_spawn`]: trait.ReaperObserver.html#method.on_spawn
+    fn on_restart(&mut self, _service: &str, _restarts: usize) {}
+
+    /// An orphan was sent the given signal, because the process it was left behind by failed.
+    /// A negative pid is a process group.
+    fn on_orphan_signaled(&mut self, _pid: Pid, _signal: Signal) {}
+
+    /// An orphan which was being terminated, because the process it was left behind by failed,
+    /// exited.
+    fn on_orphan_killed(&mut self, _pid: Pid) {}
+
+    /// The service with the given name is no longer spawned, as it was spawned too often.
+    fn on_spawn_limit(&mut self, _service: &str) {}
+
+    /// A child of the reaper was stopped by the given signal. Only told if the reaper follows
+    /// [stopped processes].
+    ///
+    /// [stopped processes]: ../struct.Reaper.html#method.stopped_processes
+    fn on_stop(&mut self, _pid: Pid, _signal: Signal) {}
+
+    /// A child of the reaper which was stopped was continued. Only told if the reaper follows
+    /// [stopped processes].
+    ///
+    /// [stopped processes]: ../struct.Reaper.html#method.stopped_processes
+    fn on_continue(&mut self, _pid: Pid) {}
+
+    /// The services are being stopped, to shut down.
+    fn on_shutdown(&mut self) {}
+}
+
+/// Call the method of the observer for the event, if there is one.
+pub fn dispatch<O: ReaperObserver + ?Sized>(observer: &mut O, event: &ReaperEvent) {
+    match event {
+        ReaperEvent::Spawned { service, pid } => observer.on_spawn(service, *pid),
+        ReaperEvent::Reaped(carcass) => observer.on_reap(carcass),
+        ReaperEvent::Restarted {
+            service, restarts, ..
+        } => observer.on_restart(service, *restarts),
+        ReaperEvent::OrphanSignaled { pid, signal } => observer.on_orphan_signaled(*pid, *signal),
+        ReaperEvent::OrphanKilled(carcass) => observer.on_orphan_killed(carcass.pid),
+        ReaperEvent::SpawnLimit(service) => observer.on_spawn_limit(service),
+        ReaperEvent::Stopped { pid, signal, .. } => observer.on_stop(*pid, *signal),
+        ReaperEvent::Continued { pid, .. } => observer.on_continue(*pid),
+        ReaperEvent::ShutdownStarted => observer.on_shutdown(),
+        _ => (),
+    }
+}
+
+/// Passes what the reaper does on as events.
+pub(crate) struct EventForwarder<F: FnMut(ReaperEvent)>(pub(crate) F);
+
+impl<F: FnMut(ReaperEvent)> ReaperObserver for EventForwarder<F> {
+    fn on_event(&mut self, event: &ReaperEvent) {
+        (self.0)(event.clone());
+    }
+}
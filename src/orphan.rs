@@ -0,0 +1,146 @@
+//! Orphans are the children a failed process leaves behind. They are terminated, first politely
+//! with SIGTERM and, if they linger, with SIGKILL, unless they are exempted.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use nix::errno::Errno;
+use nix::unistd::Pid;
+
+/// What to do about processes which are still around once they were given the
+/// [`term_to_kill_timeout`] to exit after SIGKILL as well, e.g. because they are stuck in
+/// uninterruptible sleep on a hung filesystem or device. Several actions can be combined.
+///
+/// [`term_to_kill_timeout`]: struct.ReaperBuilder.html#method.term_to_kill_timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnkillableAction<'a> {
+    /// Run a command, with its arguments separated by whitespace, once processes became
+    /// unkillable, e.g. to send an alert.
+    RunCommand(&'a str),
+    /// Report the system as unhealthy in the stats for as long as unkillable processes are
+    /// around.
+    MarkUnhealthy,
+    /// Stop all services and restart the system once there have been unkillable processes for
+    /// the given amount of ticks in a row, which only makes sense when running as PID 1.
+    Reboot(u32),
+}
+
+/// How far an orphan is in being terminated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OrphanState {
+    /// SIGTERM was sent at the given moment.
+    HasBeenSentSIGTERM(Instant),
+    /// SIGKILL was sent at the given moment, the orphan should be gone soon.
+    HasBeenSentSIGKILL(Instant),
+    /// Signaling the orphan failed at the given moment.
+    Errored(Errno, Instant),
+}
+
+impl OrphanState {
+    pub(crate) fn errored(e: nix::Error, at: Instant) -> OrphanState {
+        match e {
+            nix::Error::Sys(errno) => OrphanState::Errored(errno, at),
+            _ => OrphanState::Errored(Errno::UnknownErrno, at),
+        }
+    }
+
+    /// When the orphan entered this state.
+    pub(crate) fn since(&self) -> Instant {
+        match self {
+            OrphanState::HasBeenSentSIGTERM(at)
+            | OrphanState::HasBeenSentSIGKILL(at)
+            | OrphanState::Errored(_, at) => *at,
+        }
+    }
+
+    /// Check if nothing is left to do for the orphan but to wait until it exits, or to give up
+    /// on it.
+    pub(crate) fn is_lingering(&self) -> bool {
+        !matches!(self, OrphanState::HasBeenSentSIGTERM(_))
+    }
+}
+
+/// What happens to the children a process of a service leaves behind when it exits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OrphanPolicy {
+    /// Terminate the children, whether the process failed or not.
+    KillAlways,
+    /// Terminate the children if the process failed. If it exited successfully, e.g. a daemon
+    /// which forked, the first child becomes the process of the service. This is the default.
+    #[default]
+    KillOnFailure,
+    /// Leave the children running, e.g. the workers of a forking daemon which are picked up
+    /// again by its restarted process. They are still reaped once they exit.
+    Adopt,
+}
+
+/// Which processes of a service are signaled when it is stopped, and terminated once its
+/// process exited and its [`OrphanPolicy`] says to kill its children.
+///
+/// [`OrphanPolicy`]: enum.OrphanPolicy.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillMode {
+    /// Only the process of the service, e.g. a daemon which stops its own workers. Whatever
+    /// it leaves behind keeps running.
+    Process,
+    /// The process group the process of the service leads, which leaves out the processes
+    /// that started a session of their own.
+    Group,
+    /// The process group and every descendant of the process of the service, found through
+    /// its cgroup if it has one, or through the lineage of processes otherwise.
+    Subtree,
+}
+
+/// Processes which are never terminated as orphans, e.g. a dhcp client started by a network
+/// script which exits with an error afterwards. They are not signaled by sweeps of the process
+/// group or cgroup of a failed service either; a group with an exempted process in it is left
+/// alone, and exempted processes are moved out of a cgroup before it is killed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrphanExemption {
+    /// Match the command name in `/proc/<pid>/comm`, which the kernel truncates to 15 bytes.
+    Comm(String),
+    /// Match the path of the executable.
+    Exe(PathBuf),
+    /// Match processes in the given cgroup, or any cgroup below it.
+    Cgroup(String),
+    /// Match a single process, e.g. a debug shell started out of band, until it is reaped.
+    Pid(Pid),
+    /// Match the process of the named service and the processes it forked, as far as their
+    /// lineage is followed, e.g. a watchdog daemon which must never go down along with another
+    /// service.
+    Service(String),
+}
+
+impl OrphanExemption {
+    /// Check if the process matches the exemption. Which processes belong to a service is up to
+    /// the reaper, so a service never matches here.
+    pub(crate) fn matches(&self, pid: Pid) -> bool {
+        match self {
+            OrphanExemption::Pid(exempted) => *exempted == pid,
+            OrphanExemption::Service(_) => false,
+            OrphanExemption::Comm(comm) => fs::read_to_string(format!("/proc/{}/comm", pid))
+                .map(|c| c.trim_end_matches('\n') == comm)
+                .unwrap_or(false),
+            OrphanExemption::Exe(exe) => fs::read_link(format!("/proc/{}/exe", pid))
+                .map(|e| e == *exe)
+                .unwrap_or(false),
+            OrphanExemption::Cgroup(cgroup) => {
+                let cgroup = cgroup.trim_end_matches('/');
+                fs::read_to_string(format!("/proc/{}/cgroup", pid))
+                    .map(|content| {
+                        // every line is hierarchy-id:controllers:path
+                        content
+                            .lines()
+                            .filter_map(|line| line.splitn(3, ':').nth(2))
+                            .any(|path| {
+                                path == cgroup
+                                    || (path.starts_with(cgroup)
+                                        && path[cgroup.len()..].starts_with('/'))
+                            })
+                    })
+                    .unwrap_or(false)
+            }
+        }
+    }
+}
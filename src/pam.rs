@@ -0,0 +1,262 @@
+//! Minimal bindings to open and close a PAM session around a spawned command, so login like
+//! services get the limits, environment and session bookkeeping configured by the PAM stack.
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::{c_char, c_int, c_void};
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_BUF_ERR: c_int = 5;
+const PAM_CONV_ERR: c_int = 19;
+const PAM_ESTABLISH_CRED: c_int = 0x2;
+const PAM_DELETE_CRED: c_int = 0x4;
+const PAM_TTY: c_int = 3;
+const PAM_ERROR_MSG: c_int = 3;
+const PAM_TEXT_INFO: c_int = 4;
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+#[repr(C)]
+struct PamConv {
+    conv: extern "C" fn(c_int, *mut *const PamMessage, *mut *mut PamResponse, *mut c_void) -> c_int,
+    appdata_ptr: *mut c_void,
+}
+
+#[link(name = "pam")]
+extern "C" {
+    fn pam_start(
+        service_name: *const c_char,
+        user: *const c_char,
+        pam_conversation: *const PamConv,
+        pamh: *mut *mut c_void,
+    ) -> c_int;
+    fn pam_end(pamh: *mut c_void, pam_status: c_int) -> c_int;
+    fn pam_set_item(pamh: *mut c_void, item_type: c_int, item: *const c_void) -> c_int;
+    fn pam_setcred(pamh: *mut c_void, flags: c_int) -> c_int;
+    fn pam_open_session(pamh: *mut c_void, flags: c_int) -> c_int;
+    fn pam_close_session(pamh: *mut c_void, flags: c_int) -> c_int;
+    fn pam_getenvlist(pamh: *mut c_void) -> *mut *mut c_char;
+    fn pam_strerror(pamh: *mut c_void, errnum: c_int) -> *const c_char;
+}
+
+/// Conversation function for a non interactive session. Informational messages are logged,
+/// any prompt fails the conversation since there is nobody to answer it.
+extern "C" fn conversation(
+    num_msg: c_int,
+    msg: *mut *const PamMessage,
+    resp: *mut *mut PamResponse,
+    _appdata: *mut c_void,
+) -> c_int {
+    if num_msg <= 0 {
+        return PAM_CONV_ERR;
+    }
+
+    for i in 0..num_msg as isize {
+        let m = unsafe { &**msg.offset(i) };
+        let text = unsafe { CStr::from_ptr(m.msg) }.to_string_lossy();
+        match m.msg_style {
+            PAM_ERROR_MSG => warn!("PAM: {}", text),
+            PAM_TEXT_INFO => info!("PAM: {}", text),
+            _ => {
+                warn!("PAM requested input ({}), which can't be provided", text);
+                return PAM_CONV_ERR;
+            }
+        }
+    }
+
+    // PAM frees the responses, so they must be allocated with the C allocator
+    let responses = unsafe { libc::calloc(num_msg as usize, std::mem::size_of::<PamResponse>()) };
+    if responses.is_null() {
+        return PAM_BUF_ERR;
+    }
+    unsafe { *resp = responses as *mut PamResponse };
+
+    PAM_SUCCESS
+}
+
+/// An open PAM session. The session is closed and its credentials deleted when this is dropped.
+pub(crate) struct PamSession {
+    handle: *mut c_void,
+    limits: Vec<(c_int, libc::rlimit)>,
+}
+
+impl std::fmt::Debug for PamSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PamSession")
+            .field("handle", &self.handle)
+            .finish()
+    }
+}
+
+impl PamSession {
+    /// Start a PAM transaction for the given service and user, establish credentials and open
+    /// a session.
+    ///
+    /// Modules like `pam_limits` change the resource limits of the calling process, which is
+    /// init. The limits of init are saved here, and must be restored with
+    /// [`restore_limits`] once the session process has been spawned.
+    ///
+    /// [`restore_limits`]: struct.PamSession.html#method.restore_limits
+    pub(crate) fn open(service: &str, user: &str, tty: Option<&str>) -> io::Result<PamSession> {
+        let c_service = CString::new(service)?;
+        let c_user = CString::new(user)?;
+        let conv = PamConv {
+            conv: conversation,
+            appdata_ptr: std::ptr::null_mut(),
+        };
+
+        let mut handle = std::ptr::null_mut();
+        let res = unsafe { pam_start(c_service.as_ptr(), c_user.as_ptr(), &conv, &mut handle) };
+        if res != PAM_SUCCESS {
+            return Err(io::Error::other(format!(
+                "pam_start for service {} failed ({})",
+                service, res
+            )));
+        }
+
+        let limits = save_limits();
+
+        let res = match tty {
+            Some(tty) => {
+                let c_tty = CString::new(tty)?;
+                unsafe { pam_set_item(handle, PAM_TTY, c_tty.as_ptr() as *const c_void) }
+            }
+            None => PAM_SUCCESS,
+        };
+        let res = if res == PAM_SUCCESS {
+            unsafe { pam_setcred(handle, PAM_ESTABLISH_CRED) }
+        } else {
+            res
+        };
+        if res != PAM_SUCCESS {
+            let e = pam_error(handle, res);
+            unsafe { pam_end(handle, res) };
+            return Err(e);
+        }
+
+        let res = unsafe { pam_open_session(handle, 0) };
+        if res != PAM_SUCCESS {
+            let e = pam_error(handle, res);
+            unsafe {
+                pam_setcred(handle, PAM_DELETE_CRED);
+                pam_end(handle, res);
+            }
+            return Err(e);
+        }
+
+        Ok(PamSession { handle, limits })
+    }
+
+    /// The environment set up by the PAM modules of this session.
+    pub(crate) fn env(&self) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+
+        let list = unsafe { pam_getenvlist(self.handle) };
+        if list.is_null() {
+            return env;
+        }
+
+        let mut i = 0;
+        loop {
+            let entry = unsafe { *list.offset(i) };
+            if entry.is_null() {
+                break;
+            }
+            let var = unsafe { CStr::from_ptr(entry) }
+                .to_string_lossy()
+                .into_owned();
+            if let Some(idx) = var.find('=') {
+                env.push((var[..idx].to_string(), var[idx + 1..].to_string()));
+            }
+            unsafe { libc::free(entry as *mut c_void) };
+            i += 1;
+        }
+        unsafe { libc::free(list as *mut c_void) };
+
+        env
+    }
+
+    /// Restore the resource limits init had before the session was opened.
+    pub(crate) fn restore_limits(&self) {
+        for (resource, limit) in &self.limits {
+            if unsafe { libc::setrlimit(*resource as _, limit) } < 0 {
+                warn!(
+                    "Failed to restore resource limit {} after opening PAM session: {}",
+                    resource,
+                    io::Error::last_os_error()
+                );
+            }
+        }
+    }
+}
+
+impl Drop for PamSession {
+    fn drop(&mut self) {
+        debug!("Closing PAM session");
+        unsafe {
+            let res = pam_close_session(self.handle, 0);
+            if res != PAM_SUCCESS {
+                warn!(
+                    "Failed to close PAM session: {}",
+                    pam_error(self.handle, res)
+                );
+            }
+            pam_setcred(self.handle, PAM_DELETE_CRED);
+            pam_end(self.handle, res);
+        }
+    }
+}
+
+/// Convert a PAM error code in an io error.
+fn pam_error(handle: *mut c_void, res: c_int) -> io::Error {
+    let msg = unsafe { CStr::from_ptr(pam_strerror(handle, res)) };
+    io::Error::other(format!("PAM: {}", msg.to_string_lossy()))
+}
+
+/// The resource limits a PAM module can change.
+const RESOURCES: [c_int; 16] = [
+    libc::RLIMIT_CPU as c_int,
+    libc::RLIMIT_FSIZE as c_int,
+    libc::RLIMIT_DATA as c_int,
+    libc::RLIMIT_STACK as c_int,
+    libc::RLIMIT_CORE as c_int,
+    libc::RLIMIT_RSS as c_int,
+    libc::RLIMIT_NPROC as c_int,
+    libc::RLIMIT_NOFILE as c_int,
+    libc::RLIMIT_MEMLOCK as c_int,
+    libc::RLIMIT_AS as c_int,
+    libc::RLIMIT_LOCKS as c_int,
+    libc::RLIMIT_SIGPENDING as c_int,
+    libc::RLIMIT_MSGQUEUE as c_int,
+    libc::RLIMIT_NICE as c_int,
+    libc::RLIMIT_RTPRIO as c_int,
+    libc::RLIMIT_RTTIME as c_int,
+];
+
+/// Get all resource limits of the current process.
+fn save_limits() -> Vec<(c_int, libc::rlimit)> {
+    RESOURCES
+        .iter()
+        .copied()
+        .filter_map(|resource| {
+            let mut limit = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            if unsafe { libc::getrlimit(resource as _, &mut limit) } < 0 {
+                return None;
+            }
+            Some((resource, limit))
+        })
+        .collect()
+}
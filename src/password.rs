@@ -0,0 +1,170 @@
+//! Passwords requested by services before they start, e.g. the passphrase of an encrypted disk.
+//! Queries are answered either on a console, or through the control socket.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use nix::sys::signal::{kill, Signal};
+use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg};
+use nix::unistd::getpid;
+
+/// A pending request for a password.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Query {
+    pub(crate) id: u64,
+    pub(crate) service: String,
+    pub(crate) prompt: String,
+}
+
+type Pending = Arc<Mutex<BTreeMap<u64, Query>>>;
+
+/// Keeps track of unanswered password queries and collects the answers.
+pub(crate) struct PasswordAgent {
+    next_id: u64,
+    pending: Pending,
+    answers_tx: Sender<(u64, Vec<u8>)>,
+    answers: Receiver<(u64, Vec<u8>)>,
+    console: Option<Sender<Query>>,
+}
+
+impl PasswordAgent {
+    pub(crate) fn new() -> Self {
+        let (answers_tx, answers) = channel();
+        PasswordAgent {
+            next_id: 1,
+            pending: Arc::new(Mutex::new(BTreeMap::new())),
+            answers_tx,
+            answers,
+            console: None,
+        }
+    }
+
+    /// Also prompt for passwords on the console at path. Queries are prompted one at a time, in
+    /// the order they were made.
+    pub(crate) fn prompt_on(&mut self, path: &str) -> io::Result<()> {
+        let console = OpenOptions::new().read(true).write(true).open(path)?;
+        let (tx, rx) = channel();
+        let pending = self.pending.clone();
+        let answers = self.answers_tx.clone();
+        thread::Builder::new()
+            .name("password".to_string())
+            .spawn(move || prompt(console, rx, pending, answers))?;
+        self.console = Some(tx);
+        Ok(())
+    }
+
+    /// Ask for a password on behalf of a service. Returns the id of the query.
+    pub(crate) fn ask(&mut self, service: &str, prompt: &str) -> u64 {
+        let query = Query {
+            id: self.next_id,
+            service: service.to_string(),
+            prompt: prompt.to_string(),
+        };
+        self.next_id += 1;
+        info!(
+            "Service {} is waiting for a password (query {})",
+            query.service, query.id
+        );
+        self.pending.lock().unwrap().insert(query.id, query.clone());
+        if let Some(console) = &self.console {
+            let _ = console.send(query.clone());
+        }
+        query.id
+    }
+
+    /// All queries which are not answered yet.
+    pub(crate) fn pending(&self) -> Vec<Query> {
+        self.pending.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Answer a pending query.
+    pub(crate) fn answer(&self, id: u64, password: Vec<u8>) -> Result<(), String> {
+        answer(&self.pending, &self.answers_tx, id, password)
+    }
+
+    /// Collect all answers which came in since the last call.
+    pub(crate) fn answers(&self) -> Vec<(u64, Vec<u8>)> {
+        self.answers.try_iter().collect()
+    }
+}
+
+fn answer(
+    pending: &Pending,
+    answers: &Sender<(u64, Vec<u8>)>,
+    id: u64,
+    password: Vec<u8>,
+) -> Result<(), String> {
+    if pending.lock().unwrap().remove(&id).is_none() {
+        return Err(format!("no pending password query {}", id));
+    }
+    answers
+        .send((id, password))
+        .map_err(|_| "the reaper is gone".to_string())
+}
+
+/// Prompt for every query on the console, and pass on the answers.
+fn prompt(
+    mut console: File,
+    queries: Receiver<Query>,
+    pending: Pending,
+    answers: Sender<(u64, Vec<u8>)>,
+) {
+    for query in queries {
+        if !pending.lock().unwrap().contains_key(&query.id) {
+            // answered some other way in the meantime
+            continue;
+        }
+        let password = match read_password(&mut console, &query) {
+            Ok(password) => password,
+            Err(e) => {
+                warn!("Failed to prompt for password of {}: {}", query.service, e);
+                continue;
+            }
+        };
+        match answer(&pending, &answers, query.id, password) {
+            // wake up the reaper, which waits for signals
+            Ok(_) => {
+                let _ = kill(getpid(), Signal::SIGIO);
+            }
+            Err(_) => {
+                let _ = writeln!(console, "Password for {} was already given", query.service);
+            }
+        }
+    }
+}
+
+/// Prompt on the console and read a line without echoing it.
+fn read_password(console: &mut File, query: &Query) -> io::Result<Vec<u8>> {
+    write!(console, "\n({}) {}: ", query.service, query.prompt)?;
+
+    let fd = console.as_raw_fd();
+    let original = tcgetattr(fd).ok();
+    if let Some(original) = &original {
+        let mut silent = original.clone();
+        silent.local_flags.remove(LocalFlags::ECHO);
+        silent.local_flags.insert(LocalFlags::ECHONL);
+        let _ = tcsetattr(fd, SetArg::TCSAFLUSH, &silent);
+    }
+
+    let mut password = Vec::new();
+    let mut byte = [0];
+    let res = loop {
+        match console.read(&mut byte) {
+            Ok(0) => break Ok(()),
+            Ok(_) if byte[0] == b'\n' => break Ok(()),
+            Ok(_) => password.push(byte[0]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e),
+        }
+    };
+
+    if let Some(original) = &original {
+        let _ = tcsetattr(fd, SetArg::TCSAFLUSH, original);
+    }
+    res.map(|_| password)
+}
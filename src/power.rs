@@ -0,0 +1,176 @@
+//! Halting, powering off and restarting the system once init is done, which is the last thing
+//! init does: if it exits instead, the kernel panics.
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::mount::{mount, umount, MsFlags};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+
+use crate::mounts;
+use crate::process;
+use crate::sys::{self, RebootCommand};
+
+/// How long the processes which are left when shutting down get to exit after SIGTERM.
+const TERM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the processes which are left to die after SIGKILL. Processes which are
+/// stuck in the kernel, e.g. on a hung NFS mount, never do.
+const KILL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long init waits before it tries to perform an action which failed again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What to do with the system once all services are stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum PowerAction {
+    /// Stop the system, but leave it powered on.
+    Halt,
+    /// Stop the system and turn it off.
+    PowerOff,
+    /// Restart the system.
+    Reboot,
+}
+
+/// Perform the action. Filesystems are not synced, so this should be done first. This only
+/// returns if the action failed.
+pub fn perform(action: PowerAction) -> io::Error {
+    sys::reboot(match action {
+        PowerAction::Halt => RebootCommand::Halt,
+        PowerAction::PowerOff => RebootCommand::PowerOff,
+        PowerAction::Reboot => RebootCommand::Restart,
+    })
+}
+
+/// Bring the system down once all services are stopped: terminate the processes which are
+/// left, sync, stop swapping, unmount the filesystems in reverse order and perform the action.
+/// Filesystems which are still in use, such as the root filesystem, are remounted read-only
+/// instead, so they are clean when the system goes down. This only returns if the action failed.
+pub fn shut_down(action: PowerAction) -> io::Error {
+    kill_all();
+    unsafe { libc::sync() };
+    swap_off_all();
+    match mounts::mount_points() {
+        // the last one mounted first, as it might be mounted on top of another
+        Ok(points) => {
+            for point in points.iter().rev() {
+                match umount(point.as_str()) {
+                    Ok(_) => debug!("Unmounted {}", point),
+                    Err(e) => {
+                        let remounted = mount(
+                            None::<&str>,
+                            point.as_str(),
+                            None::<&str>,
+                            MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+                            None::<&str>,
+                        );
+                        match remounted {
+                            Ok(_) => debug!("Remounted {} read-only: {}", point, e),
+                            // the root filesystem must never be left writable
+                            Err(e) if point == "/" => warn!("Failed to remount / read-only: {}", e),
+                            Err(e) => debug!("Leaving {} mounted: {}", point, e),
+                        }
+                    }
+                }
+            }
+        }
+        Err(e) => warn!("Failed to list the mounted filesystems: {}", e),
+    }
+    unsafe { libc::sync() };
+    perform(action)
+}
+
+/// Bring the system down like [`shut_down`], as init, which must never exit. If the action
+/// fails, e.g. without CAP_SYS_BOOT, it is tried again every so often, forever.
+///
+/// [`shut_down`]: fn.shut_down.html
+pub fn shut_down_as_init(action: PowerAction) -> ! {
+    let e = shut_down(action);
+    error!(
+        "Failed to perform {:?}: {}, trying again every {} seconds",
+        action,
+        e,
+        RETRY_INTERVAL.as_secs()
+    );
+    loop {
+        thread::sleep(RETRY_INTERVAL);
+        unsafe { libc::sync() };
+        let e = perform(action);
+        debug!("Failed to perform {:?}: {}", action, e);
+    }
+}
+
+/// Terminate all processes which are left, except init itself and kernel threads, which can't
+/// be signaled: with SIGTERM first, and SIGKILL for the ones which don't exit in time. They are
+/// reaped as they exit, as init inherits every orphan.
+fn kill_all() {
+    for &(signal, timeout) in &[
+        (Signal::SIGTERM, TERM_TIMEOUT),
+        (Signal::SIGKILL, KILL_TIMEOUT),
+    ] {
+        if !processes_left() {
+            return;
+        }
+        info!("Sending {:?} to all remaining processes", signal);
+        let _ = kill(Pid::from_raw(-1), signal);
+        // stopped processes only handle SIGTERM once they are continued
+        let _ = kill(Pid::from_raw(-1), Signal::SIGCONT);
+        let deadline = Instant::now() + timeout;
+        while processes_left() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+    if processes_left() {
+        warn!("Some processes survived SIGKILL");
+    }
+}
+
+/// Reap the processes which exited, and check if any others are left. If that can't be told,
+/// they are assumed to be.
+fn processes_left() -> bool {
+    while let Ok(status) = waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+        if status == WaitStatus::StillAlive {
+            break;
+        }
+    }
+    process::user_processes_left().unwrap_or_else(|e| {
+        debug!("Failed to list the processes which are left: {}", e);
+        true
+    })
+}
+
+/// Stop swapping to all swap areas, so none are in use when their filesystems are unmounted.
+fn swap_off_all() {
+    let swaps = match fs::read_to_string("/proc/swaps") {
+        Ok(swaps) => swaps,
+        Err(e) => {
+            warn!("Failed to list the swap areas: {}", e);
+            return;
+        }
+    };
+    // the first line holds the names of the columns
+    for path in swaps
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+    {
+        let path = mounts::unescape(path);
+        let res = CString::new(path.as_str())
+            .map_err(io::Error::from)
+            .and_then(|path| sys::swap_off(&path));
+        match res {
+            Ok(_) => debug!("Stopped swapping to {}", path),
+            Err(e) => warn!("Failed to stop swapping to {}: {}", path, e),
+        }
+    }
+}
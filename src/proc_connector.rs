@@ -0,0 +1,231 @@
+//! A source of process lifecycle events through the proc connector of netlink, which the kernel
+//! multicasts every fork and exit in the system to. Unlike the BPF programs, this works on any
+//! kernel built with `CONFIG_PROC_EVENTS`, but it requires CAP_NET_ADMIN and reports the pids of
+//! the initial PID namespace.
+//!
+//! Events are lost if the socket buffer overflows, e.g. under a fork storm, after which the
+//! lineage has to be read from /proc again.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use nix::unistd::Pid;
+
+use crate::lineage::ProcessEvent;
+
+const NETLINK_CONNECTOR: libc::c_int = 11;
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+
+const PROC_EVENT_FORK: u32 = 0x0000_0001;
+const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+
+/// The size of `struct nlmsghdr`.
+const NLMSG_HDRLEN: usize = 16;
+/// The offset of the data of a `struct cn_msg`, which follows the netlink header.
+const CN_MSG_DATA: usize = NLMSG_HDRLEN + 20;
+/// The offset of the event data in a `struct proc_event`, after the kind, cpu and timestamp.
+const EVENT_DATA: usize = CN_MSG_DATA + 16;
+
+#[repr(C)]
+struct SockaddrNl {
+    family: libc::sa_family_t,
+    pad: libc::c_ushort,
+    pid: u32,
+    groups: u32,
+}
+
+/// The message which subscribes to the events: a netlink header, the header of a connector
+/// message and the operation.
+#[repr(C)]
+struct ListenMessage {
+    len: u32,
+    kind: u16,
+    flags: u16,
+    seq: u32,
+    pid: u32,
+    idx: u32,
+    val: u32,
+    cn_seq: u32,
+    ack: u32,
+    data_len: u16,
+    cn_flags: u16,
+    op: u32,
+}
+
+/// The stream of fork and exit events of processes. Its file descriptor becomes readable when
+/// events are pending.
+pub(crate) struct ProcConnector {
+    socket: RawFd,
+    /// Set when events were dropped by the kernel since this was last checked.
+    lost: bool,
+}
+
+impl ProcConnector {
+    /// Subscribe to the events of the proc connector.
+    pub(crate) fn open() -> io::Result<Self> {
+        let socket = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                NETLINK_CONNECTOR,
+            )
+        };
+        if socket < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let connector = ProcConnector {
+            socket,
+            lost: false,
+        };
+
+        let addr = SockaddrNl {
+            family: libc::AF_NETLINK as libc::sa_family_t,
+            pad: 0,
+            pid: 0,
+            groups: CN_IDX_PROC,
+        };
+        let res = unsafe {
+            libc::bind(
+                socket,
+                &addr as *const SockaddrNl as *const libc::sockaddr,
+                std::mem::size_of::<SockaddrNl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let msg = ListenMessage {
+            len: std::mem::size_of::<ListenMessage>() as u32,
+            kind: libc::NLMSG_DONE as u16,
+            flags: 0,
+            seq: 0,
+            pid: 0,
+            idx: CN_IDX_PROC,
+            val: CN_VAL_PROC,
+            cn_seq: 0,
+            ack: 0,
+            data_len: std::mem::size_of::<u32>() as u16,
+            cn_flags: 0,
+            op: PROC_CN_MCAST_LISTEN,
+        };
+        let res = unsafe {
+            libc::send(
+                socket,
+                &msg as *const ListenMessage as *const libc::c_void,
+                std::mem::size_of::<ListenMessage>(),
+                0,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(connector)
+    }
+
+    /// Take all pending events of processes, oldest first. Events of threads are left out.
+    pub(crate) fn read(&mut self) -> Vec<ProcessEvent> {
+        let mut events = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let mut addr: SockaddrNl = unsafe { std::mem::zeroed() };
+            let mut addr_len = std::mem::size_of::<SockaddrNl>() as libc::socklen_t;
+            let res = unsafe {
+                libc::recvfrom(
+                    self.socket,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                    &mut addr as *mut SockaddrNl as *mut libc::sockaddr,
+                    &mut addr_len,
+                )
+            };
+            if res < 0 {
+                let e = io::Error::last_os_error();
+                match e.raw_os_error() {
+                    Some(libc::ENOBUFS) => {
+                        self.lost = true;
+                        continue;
+                    }
+                    Some(libc::EINTR) => continue,
+                    Some(libc::EAGAIN) => (),
+                    _ => warn!("Failed to read process events: {}", e),
+                }
+                return events;
+            }
+            // only the kernel is trusted to tell about processes
+            if addr.pid != 0 {
+                continue;
+            }
+            parse_messages(&buf[..res as usize], &mut events);
+        }
+    }
+
+    /// Check if events were lost since this was last checked.
+    pub(crate) fn take_lost(&mut self) -> bool {
+        std::mem::replace(&mut self.lost, false)
+    }
+}
+
+/// Parse the netlink messages in a datagram, adding the events in them.
+fn parse_messages(mut data: &[u8], events: &mut Vec<ProcessEvent>) {
+    while data.len() >= NLMSG_HDRLEN {
+        let len = read_u32(data, 0) as usize;
+        if len < NLMSG_HDRLEN || len > data.len() {
+            return;
+        }
+        let msg = &data[..len];
+        if msg.len() >= EVENT_DATA
+            && read_u32(msg, NLMSG_HDRLEN) == CN_IDX_PROC
+            && read_u32(msg, NLMSG_HDRLEN + 4) == CN_VAL_PROC
+        {
+            if let Some(event) = parse_event(msg) {
+                events.push(event);
+            }
+        }
+        // messages are aligned to 4 bytes
+        data = &data[((len + 3) & !3).min(data.len())..];
+    }
+}
+
+fn parse_event(msg: &[u8]) -> Option<ProcessEvent> {
+    let pid = |idx: usize| {
+        let offset = EVENT_DATA + 4 * idx;
+        (msg.len() >= offset + 4).then(|| Pid::from_raw(read_u32(msg, offset) as i32))
+    };
+    match read_u32(msg, CN_MSG_DATA) {
+        PROC_EVENT_FORK => {
+            let (parent_tgid, child_pid, child_tgid) = (pid(1)?, pid(2)?, pid(3)?);
+            // a new thread is not a new process
+            (child_pid == child_tgid).then_some(ProcessEvent::Fork {
+                parent: parent_tgid,
+                child: child_tgid,
+            })
+        }
+        PROC_EVENT_EXIT => {
+            let (pid, tgid) = (pid(0)?, pid(1)?);
+            (pid == tgid).then_some(ProcessEvent::Exit(tgid))
+        }
+        _ => None,
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_ne_bytes(bytes)
+}
+
+impl AsRawFd for ProcConnector {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket
+    }
+}
+
+impl Drop for ProcConnector {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.socket) };
+    }
+}
@@ -0,0 +1,578 @@
+//! The operations the reaper performs on processes: waiting for its children, signaling and
+//! listing processes. By default these are the system calls and the contents of /proc, but
+//! [`ProcessOps`] can be implemented to have the reaper operate on something else instead, like
+//! the processes which are acted out by a [`FakeProcesses`] in a test, so what the reaper does
+//! when processes exit or linger can be followed without forking processes as root.
+//!
+//! On Linux, processes are listed from /proc. On FreeBSD, e.g. in a jail, they are listed
+//! through the `kern.proc` sysctl instead, which only shows the processes of the jail.
+//!
+//! Spawning is not part of it: the processes of services are always spawned for real, and can
+//! be added to the fake table afterwards.
+//!
+//! [`ProcessOps`]: trait.ProcessOps.html
+//! [`FakeProcesses`]: struct.FakeProcesses.html
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+#[cfg(target_os = "linux")]
+use std::fs::{read_dir, File};
+use std::io;
+#[cfg(target_os = "linux")]
+use std::io::Read;
+use std::rc::Rc;
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::sys::signal::{kill, raise, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+
+use crate::profile;
+
+/// `PF_KTHREAD` in the flags field of `/proc/<pid>/stat`.
+#[cfg(target_os = "linux")]
+const PF_KTHREAD: u64 = 0x0020_0000;
+
+/// What is known about a process, from `/proc/<pid>/stat` on Linux.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessStat {
+    /// The state, like `R` for running, `S` for sleeping or `Z` for a zombie.
+    pub state: char,
+    pub ppid: Pid,
+    pub pgrp: Pid,
+    /// The flags of the kernel, which tell kernel threads apart, `p_flag` on FreeBSD.
+    pub flags: u64,
+    /// In clock ticks since boot, or in microseconds since the epoch on FreeBSD, which tells a
+    /// process apart from a later one with the same pid.
+    pub start_time: u64,
+}
+
+impl ProcessStat {
+    #[cfg(target_os = "linux")]
+    fn is_kernel_thread(&self) -> bool {
+        self.ppid == Pid::from_raw(2) || self.flags & PF_KTHREAD != 0
+    }
+}
+
+/// The resources a process used so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessUsage {
+    /// The user and system CPU time of the process, including the children it waited for.
+    pub cpu_time: Duration,
+    /// The highest resident memory of the process, in bytes.
+    pub max_rss_bytes: u64,
+}
+
+/// How the reaper operates on processes.
+pub trait ProcessOps {
+    /// Take a change in the state of a child, without blocking, like `waitpid(-1, ...)` with
+    /// WNOHANG and the flags. `StillAlive` is returned if no child changed.
+    fn wait(&self, flags: WaitPidFlag) -> nix::Result<WaitStatus>;
+
+    /// Send the signal to the process, or to the process group for a negative pid. Without a
+    /// signal, this only checks if the process exists.
+    fn kill(&self, pid: Pid, signal: Option<Signal>) -> nix::Result<()>;
+
+    /// List all processes, leaving out kernel threads and processes which can't be signaled.
+    fn processes(&self) -> io::Result<Vec<Pid>>;
+
+    /// Read the stat entry of a process, if it exists.
+    fn stat(&self, pid: Pid) -> Option<ProcessStat>;
+
+    /// Read the resources the process used so far, if they are known.
+    fn usage(&self, _pid: Pid) -> Option<ProcessUsage> {
+        None
+    }
+
+    /// List the children of the process.
+    fn children(&self, parent: Pid) -> io::Result<Vec<Pid>> {
+        Ok(self
+            .processes()?
+            .into_iter()
+            .filter(|pid| self.stat(*pid).is_some_and(|stat| stat.ppid == parent))
+            .collect())
+    }
+
+    /// List the processes in the process group.
+    fn group(&self, pgid: Pid) -> io::Result<Vec<Pid>> {
+        Ok(self
+            .processes()?
+            .into_iter()
+            .filter(|pid| self.stat(*pid).is_some_and(|stat| stat.pgrp == pgid))
+            .collect())
+    }
+}
+
+/// The processes of the system, through the system calls and /proc, or sysctl on FreeBSD.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemProcesses;
+
+#[cfg(target_os = "linux")]
+impl ProcessOps for SystemProcesses {
+    fn wait(&self, flags: WaitPidFlag) -> nix::Result<WaitStatus> {
+        waitpid(None, Some(WaitPidFlag::WNOHANG | flags))
+    }
+
+    fn kill(&self, pid: Pid, signal: Option<Signal>) -> nix::Result<()> {
+        kill(pid, signal)
+    }
+
+    fn processes(&self) -> io::Result<Vec<Pid>> {
+        list_processes(|_| true, false)
+    }
+
+    fn stat(&self, pid: Pid) -> Option<ProcessStat> {
+        process_stat(pid)
+    }
+
+    fn usage(&self, pid: Pid) -> Option<ProcessUsage> {
+        process_usage(pid)
+    }
+
+    /// The children of every thread of the process are listed in
+    /// `/proc/<pid>/task/<tid>/children`, so the stat entries of the other processes on the
+    /// system need not be read. On kernels built without those, a child is identified as a
+    /// process which has the given PID as 4th entry in the stat file in the process id
+    /// directory. Orphans only show up as children of the reaper if it is PID 1 or a child
+    /// subreaper, otherwise they are adopted by the real init.
+    fn children(&self, parent: Pid) -> io::Result<Vec<Pid>> {
+        match read_children(parent) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                list_processes(|stat| stat.ppid == parent, true)
+            }
+            children => children,
+        }
+    }
+
+    fn group(&self, pgid: Pid) -> io::Result<Vec<Pid>> {
+        list_processes(|stat| stat.pgrp == pgid, true)
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+impl ProcessOps for SystemProcesses {
+    fn wait(&self, flags: WaitPidFlag) -> nix::Result<WaitStatus> {
+        waitpid(None, Some(WaitPidFlag::WNOHANG | flags))
+    }
+
+    fn kill(&self, pid: Pid, signal: Option<Signal>) -> nix::Result<()> {
+        kill(pid, signal)
+    }
+
+    fn processes(&self) -> io::Result<Vec<Pid>> {
+        Ok(kern_proc(libc::KERN_PROC_PROC, 0)?
+            .iter()
+            .filter(|kp| kp.ki_flag & libc::c_long::from(libc::P_SYSTEM) == 0)
+            .map(|kp| Pid::from_raw(kp.ki_pid))
+            .collect())
+    }
+
+    fn stat(&self, pid: Pid) -> Option<ProcessStat> {
+        let kp = kern_proc(libc::KERN_PROC_PID, i32::from(pid)).ok()?;
+        kp.first().map(|kp| ProcessStat {
+            state: match kp.ki_stat {
+                libc::SRUN => 'R',
+                libc::SSTOP => 'T',
+                libc::SZOMB => 'Z',
+                _ => 'S',
+            },
+            ppid: Pid::from_raw(kp.ki_ppid),
+            pgrp: Pid::from_raw(kp.ki_pgid),
+            flags: kp.ki_flag as u64,
+            start_time: kp.ki_start.tv_sec as u64 * 1_000_000 + kp.ki_start.tv_usec as u64,
+        })
+    }
+
+    fn usage(&self, pid: Pid) -> Option<ProcessUsage> {
+        let kp = kern_proc(libc::KERN_PROC_PID, i32::from(pid)).ok()?;
+        kp.first().map(|kp| {
+            let time = |tv: libc::timeval| {
+                Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+            };
+            ProcessUsage {
+                cpu_time: time(kp.ki_rusage.ru_utime)
+                    + time(kp.ki_rusage.ru_stime)
+                    + time(kp.ki_rusage_ch.ru_utime)
+                    + time(kp.ki_rusage_ch.ru_stime),
+                max_rss_bytes: kp.ki_rusage.ru_maxrss as u64 * 1024,
+            }
+        })
+    }
+
+    fn group(&self, pgid: Pid) -> io::Result<Vec<Pid>> {
+        Ok(kern_proc(libc::KERN_PROC_PGRP, i32::from(pgid))?
+            .iter()
+            .map(|kp| Pid::from_raw(kp.ki_pid))
+            .collect())
+    }
+}
+
+/// Read the `kinfo_proc` of the processes the `kern.proc` sysctl selects with the given
+/// operation and argument. The processes of other jails are never listed.
+#[cfg(target_os = "freebsd")]
+fn kern_proc(op: libc::c_int, arg: libc::c_int) -> io::Result<Vec<libc::kinfo_proc>> {
+    let _span = profile::span("proc scan");
+
+    let mib = [libc::CTL_KERN, libc::KERN_PROC, op, arg];
+    let entry = std::mem::size_of::<libc::kinfo_proc>();
+    loop {
+        let mut len = 0;
+        let res = unsafe {
+            libc::sysctl(
+                mib.as_ptr(),
+                mib.len() as libc::c_uint,
+                std::ptr::null_mut(),
+                &mut len,
+                std::ptr::null(),
+                0,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // room for the processes which are forked meanwhile
+        len += len / 8;
+        let mut procs: Vec<libc::kinfo_proc> = Vec::with_capacity(len / entry);
+        let mut len = procs.capacity() * entry;
+        let res = unsafe {
+            libc::sysctl(
+                mib.as_ptr(),
+                mib.len() as libc::c_uint,
+                procs.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+                std::ptr::null(),
+                0,
+            )
+        };
+        if res < 0 {
+            match io::Error::last_os_error() {
+                // more processes were forked than there was room for
+                ref e if e.raw_os_error() == Some(libc::ENOMEM) => continue,
+                // a pid or process group which does not exist
+                ref e if e.raw_os_error() == Some(libc::ESRCH) => return Ok(Vec::new()),
+                e => return Err(e),
+            }
+        }
+        unsafe { procs.set_len(len / entry) };
+        return Ok(procs);
+    }
+}
+
+/// Read the children of every thread of the process. Not found is returned if the kernel does
+/// not list them.
+#[cfg(target_os = "linux")]
+fn read_children(pid: Pid) -> io::Result<Vec<Pid>> {
+    let _span = profile::span("children scan");
+
+    let parse = |content: &str| -> Vec<Pid> {
+        content
+            .split_whitespace()
+            .filter_map(|child| child.parse().ok().map(Pid::from_raw))
+            .collect()
+    };
+    // the main thread is around as long as the process is
+    let mut children = parse(&std::fs::read_to_string(format!(
+        "/proc/{}/task/{}/children",
+        pid, pid
+    ))?);
+    for task in read_dir(format!("/proc/{}/task", pid))? {
+        let task = task?;
+        if task.file_name().to_str() == Some(&pid.to_string()) {
+            continue;
+        }
+        // the thread might have exited meanwhile, its children were reparented to another one
+        if let Ok(content) = std::fs::read_to_string(task.path().join("children")) {
+            children.extend(parse(&content));
+        }
+    }
+    Ok(children)
+}
+
+/// List the processes matching the filter by looping over the /proc directory and reading the
+/// stat entry of every process. Kernel threads are never listed. Processes in another PID
+/// namespace are left out unless foreign is set, which it is when the children or the process
+/// group of a known process are listed, as a service may run in a PID namespace of its own. A
+/// scan over all processes, which looks for strays, leaves them out, so they can't be signaled
+/// by accident.
+#[cfg(target_os = "linux")]
+fn list_processes(filter: impl Fn(&ProcessStat) -> bool, foreign: bool) -> io::Result<Vec<Pid>> {
+    let _span = profile::span("proc scan");
+
+    let own_pid_ns = match foreign {
+        true => None,
+        false => std::fs::read_link("/proc/self/ns/pid").ok(),
+    };
+
+    Ok(read_dir("/proc")?
+        .filter_map(|rde| {
+            rde.ok().and_then(|de| {
+                de.file_name()
+                    .to_str()
+                    .and_then(|fname| str::parse(fname).ok())
+                    .map(|p| (de, Pid::from_raw(p)))
+            })
+        })
+        .filter_map(|(de, pid)| {
+            let mut path_buf = de.path();
+            path_buf.push("stat");
+
+            let mut s = String::new();
+            let path = path_buf.as_path();
+            match File::open(path).and_then(|mut f| f.read_to_string(&mut s)) {
+                Ok(_) => match parse_stat(&s) {
+                    Some(stat) => Some((de, pid, stat)),
+                    None => {
+                        warn!("unable to interpret {:?}", path);
+                        None
+                    }
+                },
+                // the process exited since the directory was listed
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    warn!("unable to read {:?}: {}", path, e);
+                    None
+                }
+            }
+        })
+        .filter(|(_, _, stat)| filter(stat))
+        .filter(|(de, pid, stat)| {
+            if stat.is_kernel_thread() {
+                debug!("skipping kernel thread {}", pid);
+                return false;
+            }
+            // kernel threads have an empty command line, and so do zombies
+            let cmdline_empty = std::fs::read(de.path().join("cmdline"))
+                .map(|c| c.is_empty())
+                .unwrap_or(true);
+            if cmdline_empty && stat.state != 'Z' {
+                debug!("skipping {} without command line", pid);
+                return false;
+            }
+            if own_pid_ns.is_some()
+                && std::fs::read_link(de.path().join("ns/pid")).ok() != own_pid_ns
+            {
+                debug!("skipping {} in a foreign PID namespace", pid);
+                return false;
+            }
+            true
+        })
+        .map(|(_, pid, _)| pid)
+        .collect())
+}
+
+/// Check if any user space process other than the calling one is left, in any PID namespace.
+/// Kernel threads don't count, as they can't be signaled. Used by init when shutting down,
+/// where kill(-1, 0) can't tell, as it succeeds for as long as there are kernel threads.
+#[cfg(target_os = "linux")]
+pub(crate) fn user_processes_left() -> io::Result<bool> {
+    let own = std::process::id().to_string();
+    for entry in read_dir("/proc")? {
+        let entry = entry?;
+        let name = entry.file_name();
+        match name.to_str() {
+            Some(name) if name != own && name.parse::<i32>().is_ok() => (),
+            _ => continue,
+        }
+        // the process exited since the directory was listed
+        let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => match parse_stat(&stat) {
+                Some(stat) => stat,
+                None => continue,
+            },
+            Err(_) => continue,
+        };
+        // kernel threads have an empty command line, and so do zombies
+        let cmdline_empty = std::fs::read(entry.path().join("cmdline"))
+            .map(|c| c.is_empty())
+            .unwrap_or(true);
+        if stat.is_kernel_thread() || (cmdline_empty && stat.state != 'Z') {
+            continue;
+        }
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Parse the content of `/proc/<pid>/stat`. The command name in the second field can contain
+/// anything, including spaces and parentheses, so fields are counted from its closing
+/// parenthesis.
+#[cfg(target_os = "linux")]
+fn parse_stat(stat: &str) -> Option<ProcessStat> {
+    let rest = &stat[stat.rfind(')')? + 1..];
+    let mut fields = rest.split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let ppid = Pid::from_raw(fields.next()?.parse().ok()?);
+    let pgrp = Pid::from_raw(fields.next()?.parse().ok()?);
+    // skip session, tty_nr and tpgid
+    let flags = fields.nth(3)?.parse().ok()?;
+    // skip the fault counts, times, priority, nice, threads and itrealvalue
+    let start_time = fields.nth(12)?.parse().ok()?;
+    Some(ProcessStat {
+        state,
+        ppid,
+        pgrp,
+        flags,
+        start_time,
+    })
+}
+
+/// Read the stat entry of a single process, if it exists.
+#[cfg(target_os = "linux")]
+fn process_stat(pid: Pid) -> Option<ProcessStat> {
+    parse_stat(&std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?)
+}
+
+/// Read the CPU time of a process from `/proc/<pid>/stat`, and its peak resident memory from
+/// `/proc/<pid>/status`, if it exists.
+#[cfg(target_os = "linux")]
+fn process_usage(pid: Pid) -> Option<ProcessUsage> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let rest = &stat[stat.rfind(')')? + 1..];
+    // utime, stime, cutime and cstime follow the state and 10 other fields
+    let ticks = rest
+        .split_whitespace()
+        .skip(11)
+        .take(4)
+        .map(|field| field.parse::<i64>().ok())
+        .sum::<Option<i64>>()?
+        .max(0) as u64;
+    let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    // reads like `VmHWM:	    1234 kB`
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let peak = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.split_whitespace().next())
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(0);
+    Some(ProcessUsage {
+        cpu_time: Duration::from_micros(ticks * 1_000_000 / hz),
+        max_rss_bytes: peak * 1024,
+    })
+}
+
+/// A table of made up processes, for a test to act out what they do. Clones share the table, so
+/// the test can keep one while the reaper operates on another.
+///
+/// Signals are only recorded, except SIGKILL, which ends the process right away. Processes
+/// which exit become zombies until the reaper waits for them, and SIGCHLD is raised in the
+/// calling thread, so a reaper on the same thread notices.
+#[derive(Debug, Clone, Default)]
+pub struct FakeProcesses {
+    table: Rc<RefCell<FakeTable>>,
+}
+
+#[derive(Debug, Default)]
+struct FakeTable {
+    processes: HashMap<Pid, ProcessStat>,
+    // the changes of the children which were not waited for yet, oldest first
+    changes: VecDeque<WaitStatus>,
+    signals: Vec<(Pid, Option<Signal>)>,
+}
+
+impl FakeProcesses {
+    pub fn new() -> Self {
+        FakeProcesses::default()
+    }
+
+    /// Add a running process with the given parent, leading a process group of its own.
+    pub fn spawn(&self, pid: Pid, ppid: Pid) {
+        self.spawn_in_group(pid, ppid, pid);
+    }
+
+    /// Add a running process with the given parent, in the given process group.
+    pub fn spawn_in_group(&self, pid: Pid, ppid: Pid, pgrp: Pid) {
+        let stat = ProcessStat {
+            state: 'S',
+            ppid,
+            pgrp,
+            flags: 0,
+            start_time: 0,
+        };
+        self.table.borrow_mut().processes.insert(pid, stat);
+    }
+
+    /// Have the process exit with the given code.
+    pub fn exit(&self, pid: Pid, code: i32) {
+        self.end(pid, WaitStatus::Exited(pid, code));
+    }
+
+    /// Have the process be killed by the given signal.
+    pub fn kill_with(&self, pid: Pid, signal: Signal) {
+        self.end(pid, WaitStatus::Signaled(pid, signal, false));
+    }
+
+    /// The signals which were sent, in order, along with who they were sent to. A negative pid
+    /// is a process group.
+    pub fn signals(&self) -> Vec<(Pid, Option<Signal>)> {
+        self.table.borrow().signals.clone()
+    }
+
+    fn end(&self, pid: Pid, status: WaitStatus) {
+        {
+            let mut table = self.table.borrow_mut();
+            if let Some(stat) = table.processes.get_mut(&pid) {
+                stat.state = 'Z';
+            }
+            table.changes.push_back(status);
+        }
+        let _ = raise(Signal::SIGCHLD);
+    }
+}
+
+impl ProcessOps for FakeProcesses {
+    fn wait(&self, _flags: WaitPidFlag) -> nix::Result<WaitStatus> {
+        let mut table = self.table.borrow_mut();
+        match table.changes.pop_front() {
+            Some(status) => {
+                if let Some(pid) = status.pid() {
+                    table.processes.remove(&pid);
+                }
+                Ok(status)
+            }
+            None => Ok(WaitStatus::StillAlive),
+        }
+    }
+
+    fn kill(&self, pid: Pid, signal: Option<Signal>) -> nix::Result<()> {
+        let targets: Vec<Pid> = {
+            let mut table = self.table.borrow_mut();
+            if signal.is_some() {
+                table.signals.push((pid, signal));
+            }
+            let raw = i32::from(pid);
+            table
+                .processes
+                .iter()
+                .filter(|(p, stat)| {
+                    if raw < 0 {
+                        i32::from(stat.pgrp) == -raw
+                    } else {
+                        **p == pid
+                    }
+                })
+                .map(|(p, _)| *p)
+                .collect()
+        };
+        if targets.is_empty() {
+            return Err(nix::Error::Sys(Errno::ESRCH));
+        }
+        if signal == Some(Signal::SIGKILL) {
+            for pid in targets {
+                self.kill_with(pid, Signal::SIGKILL);
+            }
+        }
+        Ok(())
+    }
+
+    fn processes(&self) -> io::Result<Vec<Pid>> {
+        let mut pids: Vec<Pid> = self.table.borrow().processes.keys().copied().collect();
+        pids.sort_by_key(|pid| i32::from(*pid));
+        Ok(pids)
+    }
+
+    fn stat(&self, pid: Pid) -> Option<ProcessStat> {
+        self.table.borrow().processes.get(&pid).cloned()
+    }
+}
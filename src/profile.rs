@@ -0,0 +1,107 @@
+//! Startup profiling. When enabled, time spent in the phases of init and in expensive
+//! subsystems is recorded with microsecond resolution, so users of fast booting appliances can
+//! see where boot time goes.
+
+use std::fs::metadata;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SAMPLES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+/// Start recording profiling samples.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Check if profiling is enabled.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// A timed section of code. The time between creating the span and dropping it is recorded.
+#[derive(Debug)]
+pub struct Span {
+    name: &'static str,
+    start: Option<Instant>,
+}
+
+/// Start timing a section of code under the given name. This is cheap when profiling is
+/// disabled.
+pub fn span(name: &'static str) -> Span {
+    Span {
+        name,
+        start: if enabled() {
+            Some(Instant::now())
+        } else {
+            None
+        },
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let elapsed = start.elapsed();
+            if let Ok(mut samples) = SAMPLES.lock() {
+                samples.push((self.name, elapsed));
+            }
+        }
+    }
+}
+
+/// Aggregated timing of all spans with the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phase {
+    pub name: &'static str,
+    pub count: usize,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+/// Get the aggregated timings recorded so far, in order of first occurrence.
+pub fn report() -> Vec<Phase> {
+    let samples = match SAMPLES.lock() {
+        Ok(samples) => samples,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut phases: Vec<Phase> = Vec::new();
+    for (name, elapsed) in samples.iter() {
+        match phases.iter_mut().find(|p| p.name == *name) {
+            Some(phase) => {
+                phase.count += 1;
+                phase.total += *elapsed;
+                phase.max = phase.max.max(*elapsed);
+            }
+            None => phases.push(Phase {
+                name,
+                count: 1,
+                total: *elapsed,
+                max: *elapsed,
+            }),
+        }
+    }
+    phases
+}
+
+/// Log the profiling report, if profiling is enabled.
+pub fn log_report() {
+    if !enabled() {
+        return;
+    }
+
+    if let Ok(meta) = metadata("/proc/self/exe") {
+        info!("Profile: rsinit binary is {} bytes", meta.len());
+    }
+    for phase in report() {
+        info!(
+            "Profile: {:<24} {:>10}us total, {:>5} calls, {:>10}us max",
+            phase.name,
+            phase.total.as_micros(),
+            phase.count,
+            phase.max.as_micros()
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Pseudo-services, which stand for a state of the system rather than a process, so services
+//! can be ordered after it like after any other service, e.g. `after = network-online`. A
+//! pseudo-service is ready once its probe passes, and stays ready from then on.
+//!
+//! Two are built in: [`NETWORK_ONLINE`], ready once there is a default route, and
+//! [`TIME_SYNC`], ready once the clock is past [`SANE_EPOCH`]. Their probes can be replaced,
+//! and more pseudo-services can be added, through [`Reaper::pseudo_service`].
+//!
+//! [`NETWORK_ONLINE`]: constant.NETWORK_ONLINE.html
+//! [`TIME_SYNC`]: constant.TIME_SYNC.html
+//! [`SANE_EPOCH`]: constant.SANE_EPOCH.html
+//! [`Reaper::pseudo_service`]: ../struct.Reaper.html#method.pseudo_service
+
+use std::fs;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Ready once the network is up, by default once there is a default route.
+pub const NETWORK_ONLINE: &str = "network-online";
+
+/// Ready once the clock can be trusted, by default once it is past [`SANE_EPOCH`].
+///
+/// [`SANE_EPOCH`]: constant.SANE_EPOCH.html
+pub const TIME_SYNC: &str = "time-sync";
+
+/// The names of the built in pseudo-services.
+pub(crate) const BUILTIN: [&str; 2] = [NETWORK_ONLINE, TIME_SYNC];
+
+/// A clock before this time, in seconds since the epoch, has not been set yet: 2024-01-01 UTC.
+pub const SANE_EPOCH: u64 = 1_704_067_200;
+
+/// Check if there is a default route, over IPv4 or IPv6.
+pub fn default_route() -> bool {
+    // the destination and its mask are the 2nd and 8th columns, in hexadecimal
+    let ipv4 = fs::read_to_string("/proc/net/route").is_ok_and(|routes| {
+        routes.lines().skip(1).any(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            fields.len() > 7 && fields[1] == "00000000" && fields[7] == "00000000"
+        })
+    });
+    // the destination and its prefix length are the first two columns, and the interface the
+    // last one, as the loopback interface holds the unreachable default route
+    let ipv6 = fs::read_to_string("/proc/net/ipv6_route").is_ok_and(|routes| {
+        routes.lines().any(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            fields.len() > 9
+                && fields[0].bytes().all(|b| b == b'0')
+                && fields[1] == "00"
+                && fields[9] != "lo"
+        })
+    });
+    ipv4 || ipv6
+}
+
+/// Check if the clock is past [`SANE_EPOCH`].
+///
+/// [`SANE_EPOCH`]: constant.SANE_EPOCH.html
+pub fn clock_is_sane() -> bool {
+    SystemTime::now() >= UNIX_EPOCH + Duration::from_secs(SANE_EPOCH)
+}
+
+/// A pseudo-service, with the probe which decides when it is ready.
+pub(crate) struct PseudoService<'a> {
+    pub(crate) name: String,
+    probe: Box<dyn Fn() -> bool + 'a>,
+    pub(crate) ready: bool,
+    next_probe: Option<Instant>,
+}
+
+impl<'a> PseudoService<'a> {
+    pub(crate) fn new(name: &str, probe: impl Fn() -> bool + 'a) -> Self {
+        PseudoService {
+            name: name.to_string(),
+            probe: Box::new(probe),
+            ready: false,
+            next_probe: None,
+        }
+    }
+
+    /// The built in pseudo-services, with their default probes.
+    pub(crate) fn builtin() -> Vec<Self> {
+        vec![
+            PseudoService::new(NETWORK_ONLINE, default_route),
+            PseudoService::new(TIME_SYNC, clock_is_sane),
+        ]
+    }
+
+    /// Run the probe, unless it ran less than interval ago or the service is ready already.
+    /// Returns whether the service just became ready.
+    pub(crate) fn probe(&mut self, now: Instant, interval: Duration) -> bool {
+        if self.ready || self.next_probe.is_some_and(|next| next > now) {
+            return false;
+        }
+        self.next_probe = Some(now + interval);
+        self.ready = (self.probe)();
+        self.ready
+    }
+
+    /// When the probe runs again, if the service is not ready yet.
+    pub(crate) fn next_probe(&self) -> Option<Instant> {
+        if self.ready {
+            None
+        } else {
+            self.next_probe
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Deciding whether, and when, a service is restarted after its process exited.
+//!
+//! By default this is decided by the `restart_on_*` flags of a [`PersistentCommand`], but a
+//! [`RestartPolicy`] of its own can be given to a command instead, e.g. a circuit breaker which
+//! gives up after a burst of failures.
+//!
+//! [`PersistentCommand`]: ../command/struct.PersistentCommand.html
+//! [`RestartPolicy`]: trait.RestartPolicy.html
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::Signal;
+
+use crate::command::Event;
+
+/// What to do after the process of a service exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartAction {
+    /// Restart the service after the given delay, which can be zero. The spawn limits of the
+    /// command still apply.
+    Restart(Duration),
+    /// Don't restart the service. It can still be started through the control socket.
+    GiveUp,
+    /// Don't restart the service, and stop supervising altogether: all services are stopped and
+    /// [`Reaper::spawn`] returns an error, so the application can e.g. reboot.
+    ///
+    /// [`Reaper::spawn`]: ../struct.Reaper.html#method.spawn
+    Escalate,
+}
+
+/// What to do once a service failed for good: it is not restarted anymore after it failed, e.g.
+/// because it used up its spawn limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailureAction<'a> {
+    /// Leave it at that. It can still be started through the control socket.
+    #[default]
+    None,
+    /// Run a command, with its arguments separated by whitespace, e.g. to send an alert.
+    RunCommand(&'a str),
+    /// Stop all services and restart the system, e.g. for a service an appliance is useless
+    /// without.
+    Reboot,
+    /// Stop all services and turn the system off.
+    PowerOff,
+}
+
+/// The spawns of a command so far, as far as they are tracked.
+#[derive(Debug)]
+pub struct SpawnHistory<'h> {
+    spawns: usize,
+    spawn_times: &'h VecDeque<Instant>,
+    exit_signals: &'h BTreeMap<Signal, u32>,
+}
+
+impl<'h> SpawnHistory<'h> {
+    pub(crate) fn new(
+        spawns: usize,
+        spawn_times: &'h VecDeque<Instant>,
+        exit_signals: &'h BTreeMap<Signal, u32>,
+    ) -> Self {
+        SpawnHistory {
+            spawns,
+            spawn_times,
+            exit_signals,
+        }
+    }
+
+    /// How often the command was spawned, since the spawns were last reset.
+    pub fn spawns(&self) -> usize {
+        self.spawns
+    }
+
+    /// When the process which just exited was spawned.
+    pub fn last_spawn(&self) -> Option<Instant> {
+        self.spawn_times.back().copied()
+    }
+
+    /// How often a process of the command was killed by the given signal, including the one
+    /// which just exited, since the spawns were last reset.
+    pub fn signal_count(&self, signal: Signal) -> u32 {
+        self.exit_signals.get(&signal).copied().unwrap_or(0)
+    }
+}
+
+/// Decides what happens once the process of a service exited.
+pub trait RestartPolicy {
+    /// Decide what to do now the process exited as described by event.
+    fn decide(&mut self, event: Event, history: &SpawnHistory) -> RestartAction;
+}
+
+/// The default policy, set through the `restart_on_*` methods of a persistent command.
+pub(crate) struct RestartFlags {
+    pub(crate) on_success: bool,
+    pub(crate) on_error: bool,
+    pub(crate) on_signal: bool,
+    pub(crate) codes: Vec<i32>,
+    pub(crate) no_signals: Vec<Signal>,
+    pub(crate) signal_limits: Vec<(Signal, u32)>,
+}
+
+impl RestartFlags {
+    pub(crate) const fn new() -> Self {
+        RestartFlags {
+            on_success: false,
+            on_error: false,
+            on_signal: false,
+            codes: Vec::new(),
+            no_signals: Vec::new(),
+            signal_limits: Vec::new(),
+        }
+    }
+}
+
+impl RestartPolicy for RestartFlags {
+    fn decide(&mut self, event: Event, history: &SpawnHistory) -> RestartAction {
+        let restart = match event {
+            Event::ExitSuccess => self.on_success,
+            Event::ExitCode(code) => {
+                self.on_error && (self.codes.is_empty() || self.codes.contains(&code))
+            }
+            Event::StartFailed { .. } => self.on_error,
+            Event::ExitSignal(signal) => {
+                let count = history.signal_count(signal);
+                match self.signal_limits.iter().find(|(s, _)| *s == signal) {
+                    Some((_, limit)) if count >= *limit => {
+                        error!(
+                            "Command was killed by {:?} {} times, not restarting it anymore",
+                            signal, count
+                        );
+                        false
+                    }
+                    _ => self.on_signal && !self.no_signals.contains(&signal),
+                }
+            }
+        };
+        if restart {
+            RestartAction::Restart(Duration::from_secs(0))
+        } else {
+            RestartAction::GiveUp
+        }
+    }
+}
@@ -0,0 +1,418 @@
+//! Control a running rsinit through its control socket.
+
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::process;
+
+use chrono::{Local, TimeZone};
+use librsinit::json::Value;
+use librsinit::session::UserSession;
+use librsinit::CONTROL_SOCKET;
+use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg};
+
+const USAGE: &str = "usage: rsinitctl [-s SOCKET | --user] COMMAND
+
+commands:
+    status [TARGET]     show the status of all services, or of the targeted ones
+    status --history NAME
+                        show the status of a service, and how its last
+                        processes ended
+    inspect [--json] NAME
+                        show the cgroup, namespaces and listening sockets of
+                        the process of a service
+    logs NAME           show the last output of a service
+    start TARGET        start stopped services
+    stop TARGET         stop services, they are not respawned until started again
+    restart TARGET      stop services and start them again
+    reset TARGET        clear the failure of failed services, without
+                        starting them
+    stop-all            stop all services
+    stats               show statistics of the reaper
+    boot-times          show when booting finished its phases, and how long
+                        the services took to become ready, slowest first
+    passwords           list the services waiting for a password, and the
+                        services they are blocking
+    answer ID           give the password for a query, read from stdin
+    release [TARGET]    start services which are on hold, or all of them
+    switch-target NAME  start the services of a boot target, and stop the
+                        services which are not part of it
+    set-env NAME KEY=VALUE...
+                        set environment variables of a service, reloading it
+    unset-env NAME KEY...
+                        remove environment variables of a service
+    protect PID         never terminate a process as an orphan, e.g. a debug
+                        shell, until it exits
+    ping                check that rsinit is alive
+    reexec              execute rsinit again, e.g. after an upgrade, keeping
+                        the services running
+
+A TARGET is the name of a service, @GROUP for all services in a group, or a
+pattern of service names with * and ? wildcards.";
+
+/// The keys of replies which list the services a command acted on, in the order they are
+/// acted on.
+const ACTED_ON: [&str; 5] = ["stopped", "started", "restarted", "released", "reset"];
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut socket = CONTROL_SOCKET.to_string();
+    if args.len() >= 2 && args[0] == "-s" {
+        socket = args.remove(1);
+        args.remove(0);
+    } else if args.first().map(String::as_str) == Some("--user") {
+        // the services of the user session, supervised by `rsinit --user`
+        socket = match UserSession::detect() {
+            Ok(session) => session.control_socket(),
+            Err(e) => {
+                eprintln!("Failed to find the user session: {}", e);
+                process::exit(1);
+            }
+        };
+        args.remove(0);
+    }
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let request = match args.as_slice() {
+        ["status"] => command("list"),
+        ["status", name] | ["status", "--history", name] => command("status").with("name", *name),
+        ["inspect", name] | ["inspect", "--json", name] => command("inspect").with("name", *name),
+        [cmd @ "start", name]
+        | [cmd @ "stop", name]
+        | [cmd @ "restart", name]
+        | [cmd @ "reset", name] => command(cmd).with("name", *name),
+        ["logs", name] => command("logs").with("name", *name),
+        ["stop-all"] => command("stop-all"),
+        ["stats"] => command("stats"),
+        ["boot-times"] => command("boot-times"),
+        ["passwords"] => command("passwords"),
+        ["ping"] => command("ping"),
+        ["reexec"] => command("reexec"),
+        ["set-env", name, vars @ ..] if !vars.is_empty() => {
+            let mut env = Value::object();
+            for var in vars {
+                match var.find('=') {
+                    Some(idx) => env = env.with(&var[..idx], &var[idx + 1..]),
+                    None => fail(&format!("invalid variable {}, expected KEY=VALUE", var)),
+                }
+            }
+            command("set-env").with("name", *name).with("env", env)
+        }
+        ["unset-env", name, keys @ ..] if !keys.is_empty() => {
+            let env = keys
+                .iter()
+                .fold(Value::object(), |env, key| env.with(key, Value::Null));
+            command("set-env").with("name", *name).with("env", env)
+        }
+        ["release"] => command("release"),
+        ["release", name] => command("release").with("name", *name),
+        ["switch-target", name] => command("switch-target").with("name", *name),
+        ["protect", pid] => match pid.parse::<i32>() {
+            Ok(pid) if pid > 0 => command("protect").with("pid", pid),
+            _ => fail(&format!("invalid pid {}", pid)),
+        },
+        ["answer", id] => {
+            let id: u64 = match id.parse() {
+                Ok(id) => id,
+                Err(_) => fail(&format!("invalid query id {}", id)),
+            };
+            let password = match read_password() {
+                Ok(password) => password,
+                Err(e) => fail(&format!("failed to read password: {}", e)),
+            };
+            command("answer").with("id", id).with("password", password)
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let reply = match send(&socket, &request) {
+        Ok(reply) => reply,
+        Err(e) => fail(&format!("failed to talk to rsinit on {}: {}", socket, e)),
+    };
+    if reply.get("ok").and_then(Value::as_bool) != Some(true) {
+        let error = reply
+            .get("error")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown error");
+        fail(error);
+    }
+
+    if args[0] == "inspect" {
+        print_inspection(reply, args[1] == "--json");
+    } else if args[0] == "boot-times" {
+        print_boot_times(&reply);
+    } else if let Some(lines) = reply.get("lines").and_then(Value::as_array) {
+        for line in lines.iter().filter_map(Value::as_str) {
+            println!("{}", line);
+        }
+    } else if let Some(services) = reply.get("services").and_then(Value::as_array) {
+        print_services(services);
+    } else if reply.get("name").is_some() {
+        let history = reply
+            .get("history")
+            .and_then(Value::as_array)
+            .map(<[_]>::to_vec);
+        let blocking = names(reply.get("blocking"));
+        print_services(&[reply]);
+        if !blocking.is_empty() {
+            println!("blocking {}", blocking);
+        }
+        if let Some(history) = history.filter(|_| args[1] == "--history") {
+            println!();
+            print_history(&history);
+        }
+    } else if ACTED_ON.iter().any(|key| reply.get(key).is_some()) {
+        // switching targets both stops and starts services
+        for key in &ACTED_ON {
+            let names = reply.get(key).and_then(Value::as_array);
+            for name in names.into_iter().flatten().filter_map(Value::as_str) {
+                println!("{} {}", key, name);
+            }
+        }
+        let errors = reply.get("errors").and_then(Value::as_array);
+        if let Some(errors) = errors.filter(|errors| !errors.is_empty()) {
+            for error in errors.iter().filter_map(Value::as_str) {
+                eprintln!("rsinitctl: {}", error);
+            }
+            process::exit(1);
+        }
+    } else if let Some(pid) = reply.get("pid").filter(|_| args[0] == "reexec") {
+        println!("re-executing rsinit ({})", pid);
+    } else if let Some(pid) = reply.get("protected") {
+        println!("protecting {}", pid);
+    } else if let Some(iterations) = reply.get("loop_iterations").filter(|_| args[0] == "ping") {
+        println!("alive, {} loop iterations", iterations);
+    } else if let Some(reloaded) = reply.get("reloaded").and_then(Value::as_bool) {
+        if reloaded {
+            println!("reloaded {}", args[1]);
+        } else {
+            println!("changes apply once {} is started again", args[1]);
+        }
+    } else if reply.get("zombies_reaped").is_some() {
+        if let Value::Object(fields) = &reply {
+            for (key, value) in fields.iter().filter(|(key, _)| key != "ok") {
+                println!("{:20} {}", key, value);
+            }
+        }
+    } else if let Some(queries) = reply.get("queries").and_then(Value::as_array) {
+        for query in queries {
+            println!(
+                "{:>4}  {:16} {}",
+                field(query, "id"),
+                field(query, "service"),
+                field(query, "prompt")
+            );
+            let blocking = names(query.get("blocking"));
+            if !blocking.is_empty() {
+                println!("      blocking {}", blocking);
+            }
+        }
+    }
+}
+
+fn command(cmd: &str) -> Value {
+    Value::object().with("command", cmd)
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("rsinitctl: {}", msg);
+    process::exit(1);
+}
+
+/// Send a request and wait for the reply.
+fn send(socket: &str, request: &Value) -> io::Result<Value> {
+    let mut stream = UnixStream::connect(socket)?;
+    writeln!(stream, "{}", request)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Value::parse(line.trim()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Read a line from stdin, without echoing it if stdin is a terminal.
+fn read_password() -> io::Result<String> {
+    let original = tcgetattr(0).ok();
+    if let Some(original) = &original {
+        eprint!("Password: ");
+        let mut silent = original.clone();
+        silent.local_flags.remove(LocalFlags::ECHO);
+        silent.local_flags.insert(LocalFlags::ECHONL);
+        let _ = tcsetattr(0, SetArg::TCSAFLUSH, &silent);
+    }
+    let mut password = String::new();
+    let res = io::stdin().read_line(&mut password);
+    if let Some(original) = &original {
+        let _ = tcsetattr(0, SetArg::TCSAFLUSH, original);
+    }
+    res?;
+    Ok(password.trim_end_matches('\n').to_string())
+}
+
+/// Render a field of an object for display.
+fn field(value: &Value, key: &str) -> String {
+    render(value.get(key))
+}
+
+/// Join the names in an array for display.
+fn names(value: Option<&Value>) -> String {
+    let names: Vec<&str> = value
+        .and_then(Value::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(Value::as_str)
+        .collect();
+    names.join(", ")
+}
+
+/// Render a value for display.
+fn render(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Null) | None => "-".to_string(),
+        Some(v) => v.to_string(),
+    }
+}
+
+fn print_inspection(reply: Value, json: bool) {
+    let fields = match reply {
+        Value::Object(fields) => fields,
+        _ => return,
+    };
+    let fields: Vec<(String, Value)> = fields.into_iter().filter(|(key, _)| key != "ok").collect();
+    if json {
+        println!("{}", Value::Object(fields));
+        return;
+    }
+    for (key, value) in &fields {
+        match (key.as_str(), value) {
+            ("namespaces", Value::Object(namespaces)) => {
+                for (ns, inode) in namespaces {
+                    println!("{:20} {}", format!("ns.{}", ns), inode);
+                }
+            }
+            ("listen_fds", Value::Array(fds)) => {
+                for fd in fds {
+                    println!(
+                        "{:20} {} {}",
+                        format!("fd.{}", field(fd, "fd")),
+                        field(fd, "protocol"),
+                        field(fd, "address")
+                    );
+                }
+            }
+            _ => println!("{:20} {}", key, render(Some(value))),
+        }
+    }
+}
+
+/// Print how the last processes of a service ended, oldest first.
+fn print_history(history: &[Value]) {
+    println!(
+        "{:19} {:7} {:>9} {:>10}",
+        "EXITED", "EXIT", "CODE", "RUNTIME"
+    );
+    for termination in history {
+        let at = termination
+            .get("at")
+            .and_then(Value::as_f64)
+            .and_then(|secs| Local.timestamp_opt(secs as i64, 0).single())
+            .map_or_else(
+                || "-".to_string(),
+                |at| at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            );
+        let code = match termination.get("signal").and_then(Value::as_str) {
+            Some(signal) => signal.to_string(),
+            None => field(termination, "code"),
+        };
+        let runtime = match termination.get("runtime").and_then(Value::as_f64) {
+            Some(secs) => format!("{:.1}s", secs),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:19} {:7} {:>9} {:>10}",
+            at,
+            field(termination, "exit"),
+            code,
+            runtime
+        );
+    }
+}
+
+/// Print when booting finished its phases, and the services by how long they took to become
+/// ready, slowest first, like `systemd-analyze blame`.
+fn print_boot_times(reply: &Value) {
+    let secs = |value: &Value, key: &str| value.get(key).and_then(Value::as_f64);
+    let phases = reply.get("phases").and_then(Value::as_array).unwrap_or(&[]);
+    for phase in phases {
+        println!(
+            "{:>9.3}s {}",
+            secs(phase, "at").unwrap_or_default(),
+            field(phase, "name")
+        );
+    }
+
+    let mut services: Vec<&Value> = reply
+        .get("services")
+        .and_then(Value::as_array)
+        .unwrap_or(&[])
+        .iter()
+        .collect();
+    let took = |service: &Value| Some(secs(service, "ready")? - secs(service, "spawned")?);
+    // the services which never became ready come first, as they take the longest
+    services.sort_by(|a, b| {
+        let (a, b) = (
+            took(a).unwrap_or(f64::INFINITY),
+            took(b).unwrap_or(f64::INFINITY),
+        );
+        b.total_cmp(&a)
+    });
+    println!();
+    println!("{:>10} {:>10} {:>10} NAME", "TO READY", "SPAWNED", "READY");
+    for service in services {
+        let at = |key| match secs(service, key) {
+            Some(secs) => format!("{:.3}s", secs),
+            None => "-".to_string(),
+        };
+        let took = match took(service) {
+            Some(took) => format!("{:.3}s", took),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:>10} {:>10} {:>10} {}",
+            took,
+            at("spawned"),
+            at("ready"),
+            field(service, "name")
+        );
+    }
+}
+
+fn print_services(services: &[Value]) {
+    println!(
+        "{:16} {:10} {:>7} {:>8} {:9} {:>8} EXIT REASON",
+        "NAME", "STATE", "PID", "RESTARTS", "LAST EXIT", "UPTIME"
+    );
+    for service in services {
+        let state = match service.get("state").and_then(Value::as_str) {
+            Some(state) => state,
+            None if service.get("running").and_then(Value::as_bool) == Some(true) => "running",
+            None => "stopped",
+        };
+        let uptime = match service.get("uptime").and_then(Value::as_f64) {
+            Some(secs) => format!("{}s", secs),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:16} {:10} {:>7} {:>8} {:9} {:>8} {}",
+            field(service, "name"),
+            state,
+            field(service, "pid"),
+            field(service, "restarts"),
+            field(service, "last_exit"),
+            uptime,
+            field(service, "exit_reason")
+        );
+    }
+}
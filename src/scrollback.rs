@@ -0,0 +1,72 @@
+//! The last output of every service, kept in memory so it can be queried at runtime. When a
+//! service keeps crashing before the disk with the log files is mounted, this is where to find
+//! out why.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use nix::unistd::Pid;
+
+use crate::logmux::LineSink;
+
+/// The captured output of a single service.
+#[derive(Default)]
+struct Buffer {
+    lines: VecDeque<String>,
+    /// The number of bytes in all lines together.
+    size: usize,
+}
+
+/// A ring buffer of output lines for every service, which drops the oldest lines of a service
+/// once its lines take up more than the capacity.
+pub(crate) struct Scrollback {
+    capacity: usize,
+    buffers: HashMap<String, Buffer>,
+}
+
+impl Scrollback {
+    /// Create scrollback which keeps up to capacity bytes of output per service, shared between
+    /// the log multiplexer which fills it and the reaper which queries it.
+    pub(crate) fn shared(capacity: usize) -> Arc<Mutex<Scrollback>> {
+        Arc::new(Mutex::new(Scrollback {
+            capacity,
+            buffers: HashMap::new(),
+        }))
+    }
+
+    fn push(&mut self, name: &str, line: String) {
+        let buffer = self.buffers.entry(name.to_string()).or_default();
+        buffer.size += line.len();
+        buffer.lines.push_back(line);
+        while buffer.size > self.capacity {
+            match buffer.lines.pop_front() {
+                Some(line) => buffer.size -= line.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// The lines of output of a service which are kept, oldest first.
+    pub(crate) fn lines(&self, name: &str) -> Vec<String> {
+        self.buffers
+            .get(name)
+            .map(|buffer| buffer.lines.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl LineSink for Arc<Mutex<Scrollback>> {
+    fn write_line(&mut self, name: &str, _: Pid, _: usize, line: &[u8]) {
+        let line = format!(
+            "{} {}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            String::from_utf8_lossy(line)
+        );
+        // a panic while holding the lock leaves the buffers intact
+        let mut scrollback = match self.lock() {
+            Ok(scrollback) => scrollback,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        scrollback.push(name, line);
+    }
+}
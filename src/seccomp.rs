@@ -0,0 +1,160 @@
+//! Seccomp filters, which restrict the system calls a service can make.
+//!
+//! The filter is a classic BPF program, which the kernel runs on every system call of the
+//! service. It first checks the architecture of the call, as the numbers of system calls differ
+//! between them, and then looks the number up in the list. A denied call fails with `EPERM`
+//! instead of killing the service, so a daemon which tries something optional, such as
+//! ptrace-based introspection, can carry on.
+
+use std::io;
+
+use crate::sys::{self, AUDIT_ARCH, SYS_KEXEC_FILE_LOAD};
+
+/// The system calls denied by [`SyscallFilter::Basic`]: debugging other processes, loading a new
+/// kernel and loading kernel modules, which no ordinary service needs. `kexec_file_load` is
+/// denied as well, on the architectures which have it.
+///
+/// [`SyscallFilter::Basic`]: enum.SyscallFilter.html#variant.Basic
+const BASIC_DENIED: &[libc::c_long] = &[
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_kexec_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+];
+
+/// System calls of the x32 ABI, which an x86_64 process can make as well, have this bit set.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+#[cfg(target_arch = "x86_64")]
+const BPF_JSET_K: u16 = 0x45;
+const BPF_RET_K: u16 = 0x06;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_MODE_FILTER: libc::c_ulong = 2;
+
+/// Offsets into `struct seccomp_data`, which the filter inspects.
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+
+/// The system calls a service is restricted to, or from, given by their numbers on the
+/// architecture rsinit is built for, such as `libc::SYS_mount`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyscallFilter {
+    /// Deny debugging other processes, loading a new kernel and loading kernel modules.
+    Basic,
+    /// Deny the given system calls, allowing all others.
+    Deny(Vec<libc::c_long>),
+    /// Allow only the given system calls, and `execve` to start the service with.
+    Allow(Vec<libc::c_long>),
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Instruction {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+impl Instruction {
+    const fn new(code: u16, jt: u8, jf: u8, k: u32) -> Self {
+        Instruction { code, jt, jf, k }
+    }
+}
+
+#[repr(C)]
+struct Program {
+    len: libc::c_ushort,
+    filter: *const Instruction,
+}
+
+/// A filter, compiled up front so it can be installed after fork.
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledFilter(Vec<Instruction>);
+
+impl SyscallFilter {
+    /// Compile the filter into a BPF program.
+    pub(crate) fn compile(&self) -> io::Result<CompiledFilter> {
+        let arch = AUDIT_ARCH.ok_or_else(|| {
+            io::Error::other("seccomp filters are not supported on this architecture")
+        })?;
+        let (mut syscalls, listed, other) = match self {
+            SyscallFilter::Basic => {
+                let mut syscalls = BASIC_DENIED.to_vec();
+                syscalls.extend(SYS_KEXEC_FILE_LOAD);
+                (syscalls, deny(), SECCOMP_RET_ALLOW)
+            }
+            SyscallFilter::Deny(syscalls) => (syscalls.clone(), deny(), SECCOMP_RET_ALLOW),
+            SyscallFilter::Allow(syscalls) => {
+                let mut syscalls = syscalls.clone();
+                syscalls.push(libc::SYS_execve);
+                (syscalls, SECCOMP_RET_ALLOW, deny())
+            }
+        };
+        syscalls.sort_unstable();
+        syscalls.dedup();
+        // jumps are relative and at most 255 instructions far
+        if syscalls.len() > 250 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many system calls in seccomp filter",
+            ));
+        }
+
+        let mut program = vec![
+            Instruction::new(BPF_LD_W_ABS, 0, 0, DATA_ARCH),
+            // another architecture would get around the list, so it is not allowed at all
+            Instruction::new(BPF_JEQ_K, 1, 0, arch),
+            Instruction::new(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS),
+            Instruction::new(BPF_LD_W_ABS, 0, 0, DATA_NR),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        program.extend_from_slice(&[
+            Instruction::new(BPF_JSET_K, 0, 1, X32_SYSCALL_BIT),
+            Instruction::new(BPF_RET_K, 0, 0, deny()),
+        ]);
+        let count = syscalls.len();
+        for (idx, nr) in syscalls.into_iter().enumerate() {
+            // on a match, jump past the other comparisons and the default return
+            let to_listed = (count - idx) as u8;
+            program.push(Instruction::new(BPF_JEQ_K, to_listed, 0, nr as u32));
+        }
+        program.push(Instruction::new(BPF_RET_K, 0, 0, other));
+        program.push(Instruction::new(BPF_RET_K, 0, 0, listed));
+        Ok(CompiledFilter(program))
+    }
+}
+
+/// The action for a denied system call.
+const fn deny() -> u32 {
+    SECCOMP_RET_ERRNO | libc::EPERM as u32
+}
+
+impl CompiledFilter {
+    /// Install the filter for the calling process, and everything it executes. This requires
+    /// no_new_privs to be set, unless the process has CAP_SYS_ADMIN. Only raw syscalls are
+    /// used, so this can be done in between fork and exec.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        let program = Program {
+            len: self.0.len() as libc::c_ushort,
+            filter: self.0.as_ptr(),
+        };
+        let program = &program as *const Program as libc::c_ulong;
+        sys::prctl(libc::PR_SET_SECCOMP, SECCOMP_MODE_FILTER, program).map(drop)
+    }
+}
+
+/// Keep the calling process, and everything it executes, from gaining privileges, e.g. through
+/// setuid binaries or file capabilities.
+pub(crate) fn set_no_new_privs() -> io::Result<()> {
+    sys::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0).map(drop)
+}
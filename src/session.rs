@@ -0,0 +1,75 @@
+//! Running rsinit as the supervisor of a user session with `rsinit --user`, e.g. for the agents
+//! of a login session, instead of as init. Nothing which is up to init is done: the system is
+//! not set up, halted or restarted, and the kernel command line is not read. The reaper is a
+//! child subreaper, so it adopts what the services leave behind. The configuration is read
+//! from `$XDG_CONFIG_HOME/rsinit`, which is `~/.config/rsinit` by default:
+//!
+//! - `inittab`: the services, like `/etc/inittab`
+//! - `options`: options like those on the kernel command line, separated by whitespace, e.g.
+//!   `rsinit.keep_output=64`
+//! - `tmpfiles.d`: the files to create when the session starts, like
+//!   `/etc/rsinit/tmpfiles.d`
+//!
+//! The files which live in `/run` otherwise, like the control socket, and the log live in
+//! `$XDG_RUNTIME_DIR/rsinit`. `rsinitctl --user` controls the services of the session.
+
+use std::env;
+use std::fs::DirBuilder;
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+
+/// The directories of a user session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserSession {
+    config_dir: String,
+    runtime_dir: String,
+}
+
+impl UserSession {
+    /// The directories of the session of the user running rsinit, from the environment.
+    pub fn detect() -> io::Result<Self> {
+        let config_home = match env::var("XDG_CONFIG_HOME") {
+            Ok(dir) if !dir.is_empty() => dir,
+            _ => match env::var("HOME") {
+                Ok(home) if !home.is_empty() => format!("{}/.config", home),
+                _ => return Err(io::Error::other("neither XDG_CONFIG_HOME nor HOME is set")),
+            },
+        };
+        let runtime_dir = match env::var("XDG_RUNTIME_DIR") {
+            Ok(dir) if !dir.is_empty() => dir,
+            _ => return Err(io::Error::other("XDG_RUNTIME_DIR is not set")),
+        };
+        Ok(UserSession {
+            config_dir: format!("{}/rsinit", config_home),
+            runtime_dir: format!("{}/rsinit", runtime_dir),
+        })
+    }
+
+    /// Create the runtime directory, which only the user can access.
+    pub fn create_runtime_dir(&self) -> io::Result<()> {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.runtime_dir)
+    }
+
+    /// The directory the files of the reaper are created in while it runs.
+    pub fn runtime_dir(&self) -> &str {
+        &self.runtime_dir
+    }
+
+    /// The path of the named file in the configuration directory, like `inittab`.
+    pub fn config_path(&self, name: &str) -> String {
+        format!("{}/{}", self.config_dir, name)
+    }
+
+    /// The path of the named file in the runtime directory, like `journal`.
+    pub fn runtime_path(&self, name: &str) -> String {
+        format!("{}/{}", self.runtime_dir, name)
+    }
+
+    /// The path of the control socket of the session.
+    pub fn control_socket(&self) -> String {
+        self.runtime_path("control.sock")
+    }
+}
@@ -0,0 +1,60 @@
+//! Acting on the signals sent to the reaper, like SIGPWR, which a UPS daemon sends when the
+//! power fails, or SIGUSR1, which halts the system unless it is mapped to something else.
+
+use nix::sys::signal::Signal;
+
+use crate::power::PowerAction;
+
+/// What to do when the reaper receives a signal, see [`on_signal`].
+///
+/// [`on_signal`]: ../struct.Reaper.html#method.on_signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalAction<'a> {
+    /// Restart the named service, or start it if it is not running.
+    Restart(&'a str),
+    /// Run a command, with its arguments separated by whitespace.
+    RunCommand(&'a str),
+    /// Stop all services, and then halt, power off or restart the system.
+    Power(PowerAction),
+    /// Load the services again, if the reaper has a config loader.
+    Reload,
+}
+
+impl<'a> SignalAction<'a> {
+    /// Parse an action like `poweroff`, `halt`, `reboot`, `reload`, `restart nginx` or
+    /// `run /sbin/alert power`. As the kernel command line can't hold spaces, the first word
+    /// may be followed by a colon instead, like `restart:nginx`.
+    pub fn parse(action: &'a str) -> Result<Self, String> {
+        let action = action.trim();
+        let (verb, arg) = match action.find(|c: char| c == ':' || c.is_whitespace()) {
+            Some(idx) => (&action[..idx], action[idx + 1..].trim()),
+            None => (action, ""),
+        };
+        match (verb, arg) {
+            ("halt", "") => Ok(SignalAction::Power(PowerAction::Halt)),
+            ("poweroff", "") => Ok(SignalAction::Power(PowerAction::PowerOff)),
+            ("reboot", "") => Ok(SignalAction::Power(PowerAction::Reboot)),
+            ("reload", "") => Ok(SignalAction::Reload),
+            ("halt" | "poweroff" | "reboot" | "reload", _) => {
+                Err(format!("{} takes no argument", verb))
+            }
+            ("restart" | "run", "") => Err(format!("{} needs an argument", verb)),
+            ("restart", name) => Ok(SignalAction::Restart(name)),
+            ("run", command) => Ok(SignalAction::RunCommand(command)),
+            _ => Err(format!("unknown action {}", verb)),
+        }
+    }
+}
+
+/// Parse the name of a signal, like `SIGPWR`, or just `PWR`.
+pub fn parse_signal(name: &str) -> Result<Signal, String> {
+    let name = name.trim();
+    let full = if name.starts_with("SIG") {
+        name.to_string()
+    } else {
+        format!("SIG{}", name)
+    };
+    Signal::iterator()
+        .find(|signal| format!("{:?}", signal) == full)
+        .ok_or_else(|| format!("unknown signal {}", name))
+}
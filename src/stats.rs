@@ -0,0 +1,40 @@
+/// Running totals of the work done by the [`Reaper`].
+///
+/// [`Reaper`]: struct.Reaper.html
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ReaperStats {
+    /// The amount of zombie processes which have been reaped.
+    pub zombies_reaped: u64,
+    /// The amount of orphans which have been terminated because their parent failed.
+    pub orphans_terminated: u64,
+    /// The amount of orphans which were given up on, because they lingered for too long or too
+    /// many orphans were tracked at once.
+    pub orphans_expired: u64,
+    /// The amount of trapped signals which have been handled.
+    pub signals_handled: u64,
+    /// The amount of times spawning was paused because the system ran out of resources.
+    pub spawn_pauses: u64,
+    /// The amount of times the wall clock was set, e.g. by NTP.
+    pub clock_jumps: u64,
+    /// The amount of times reaping was cut short to let the main loop do the rest of its work,
+    /// leaving the remaining zombies for its next pass.
+    pub reaps_deferred: u64,
+    /// The amount of iterations of the main loop, which runs at least every 5 seconds.
+    pub loop_iterations: u64,
+    /// The amount of direct children currently known to the reaper.
+    pub tracked_children: usize,
+    /// The amount of orphans currently being terminated.
+    pub tracked_orphans: usize,
+    /// The amount of processes which are still around long after they were sent SIGKILL.
+    pub unkillable_processes: usize,
+    /// Whether the system is unhealthy, because there are unkillable processes and the reaper
+    /// was asked to mark it as such.
+    pub unhealthy: bool,
+    /// The resident memory of the reaper in bytes, as last recorded.
+    pub rss_bytes: u64,
+    /// The amount of file descriptors the reaper had open, as last recorded.
+    pub open_fds: u64,
+    /// The amount of threads of the reaper, as last recorded.
+    pub threads: u64,
+}
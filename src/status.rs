@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+
+use crate::command::Event;
+use crate::process::ProcessUsage;
+
+/// How many terminations are kept in the history of a service.
+const HISTORY_LENGTH: usize = 10;
+
+/// What a service is doing, in short, e.g. to filter services on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ServiceState {
+    /// The service has no running process.
+    Stopped,
+    /// The process of the service is running, but did not finish starting up yet.
+    Starting,
+    /// The process of the service is running and finished starting up.
+    Ready,
+    /// The process of the service is running, but its health checks fail.
+    Unhealthy,
+    /// The service has no running process, and is waiting to be spawned again.
+    Restarting,
+    /// The service was given up on, e.g. as it reached its spawn limit, and is not spawned
+    /// again until it is started or reset.
+    Failed,
+}
+
+/// The state of a supervised service, as tracked by the [`Reaper`].
+///
+/// [`Reaper`]: struct.Reaper.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServiceStatus {
+    /// The pid of the running process of the service, if any.
+    pub pid: Option<Pid>,
+    /// The amount of times the service has been restarted.
+    pub restarts: usize,
+    /// The amount of times a process of the service failed to start, by exiting within the
+    /// start grace period of its command.
+    pub start_failures: usize,
+    /// Why the last process of the service exited.
+    pub last_exit: Option<Event>,
+    /// What the exit code of the last process means, if the service explains it.
+    pub exit_reason: Option<String>,
+    /// How often processes of the service were killed by each signal.
+    pub exit_signals: BTreeMap<Signal, u32>,
+    /// When the currently running process was started.
+    pub started_at: Option<Instant>,
+    /// Whether the running process finished starting up. Services which don't notify are
+    /// ready as soon as they are spawned.
+    pub ready: bool,
+    /// The last status the running process reported through its notification socket.
+    pub status_text: Option<String>,
+    /// When the running process last reported it is alive through its notification socket.
+    pub last_watchdog: Option<Instant>,
+    /// The executable the service is waiting for to appear, if it is missing.
+    pub waiting_for_executable: Option<PathBuf>,
+    /// Whether the health checks of the running process pass, if it has any.
+    pub healthy: Option<bool>,
+    /// When the running process was stopped by a signal, if it is stopped. This is only known
+    /// if the reaper follows [stopped processes].
+    ///
+    /// [stopped processes]: struct.Reaper.html#method.stopped_processes
+    pub stopped_since: Option<Instant>,
+    /// When the service is spawned again, if it is waiting to be.
+    pub respawn_at: Option<Instant>,
+    /// Why the service was given up on, e.g. `spawn_limit` or `crash_loop`, if it failed. This
+    /// is cleared once it is started again, or reset.
+    pub failure: Option<String>,
+    /// How the last processes of the service ended, oldest first, up to the last 10.
+    pub history: VecDeque<Termination>,
+    /// The resources the processes of the service used, as sampled every tick.
+    pub usage: ResourceUsage,
+}
+
+/// The resources all processes of a service used, as far as they were sampled, which misses
+/// what a process used after it was last sampled. Only the main process of a service is
+/// sampled, along with the processes it waited for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// The user and system CPU time of all processes of the service so far.
+    pub cpu_time: Duration,
+    /// The highest resident memory of a process of the service, in bytes.
+    pub max_rss_bytes: u64,
+    // the CPU time of the processes which exited
+    exited_cpu_time: Duration,
+}
+
+impl ResourceUsage {
+    /// Record what the running process of the service used so far.
+    pub(crate) fn sampled(&mut self, usage: &ProcessUsage) {
+        self.cpu_time = self.exited_cpu_time + usage.cpu_time;
+        self.max_rss_bytes = self.max_rss_bytes.max(usage.max_rss_bytes);
+    }
+
+    /// Record that the running process exited, so the next one is counted on top of it.
+    fn exited(&mut self) {
+        self.exited_cpu_time = self.cpu_time;
+    }
+}
+
+/// How a process of a service ended, as kept in the history of the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Termination {
+    /// When the process was reaped, by the wall clock.
+    pub at: SystemTime,
+    pub event: Event,
+    /// How long the process ran.
+    pub runtime: Option<Duration>,
+}
+
+/// What happens to the process of a service which is stopped by a signal, e.g. SIGSTOP or
+/// SIGTSTP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopPolicy {
+    /// Leave it stopped until something continues it.
+    Report,
+    /// Continue it right away with SIGCONT.
+    Continue,
+    /// Kill it if it is still stopped after the given time, like a service which did not ping
+    /// its watchdog in time.
+    KillAfter(Duration),
+}
+
+impl ServiceStatus {
+    /// Check if the service currently has a running process.
+    pub fn is_running(&self) -> bool {
+        self.pid.is_some()
+    }
+
+    /// Check if the service is running and finished starting up.
+    pub fn is_ready(&self) -> bool {
+        self.is_running() && self.ready
+    }
+
+    /// What the service is doing, in short.
+    pub fn state(&self) -> ServiceState {
+        match (self.is_running(), self.ready, self.healthy) {
+            (false, _, _) if self.failure.is_some() => ServiceState::Failed,
+            (false, _, _) if self.respawn_at.is_some() => ServiceState::Restarting,
+            (false, _, _) => ServiceState::Stopped,
+            (true, _, Some(false)) => ServiceState::Unhealthy,
+            (true, false, _) => ServiceState::Starting,
+            (true, true, _) => ServiceState::Ready,
+        }
+    }
+
+    /// How long the current process has been running.
+    pub fn uptime(&self) -> Option<Duration> {
+        match (self.pid, self.started_at) {
+            (Some(_), Some(started)) => Some(started.elapsed()),
+            _ => None,
+        }
+    }
+
+    /// Record that a new process was spawned for the service, which is ready right away unless
+    /// it notifies when it is.
+    pub(crate) fn spawned(&mut self, pid: Pid, notifies: bool) {
+        if self.started_at.is_some() {
+            self.restarts += 1;
+        }
+        self.pid = Some(pid);
+        self.started_at = Some(Instant::now());
+        self.ready = !notifies;
+        self.status_text = None;
+        self.last_watchdog = None;
+        self.waiting_for_executable = None;
+        self.healthy = None;
+        self.stopped_since = None;
+        self.respawn_at = None;
+        self.failure = None;
+    }
+
+    /// Record that the running process of the service was handed over by a previous reaper,
+    /// along with what that reaper knew about it.
+    pub(crate) fn adopted(&mut self, pid: Pid, restarts: usize, uptime: Duration, ready: bool) {
+        let now = Instant::now();
+        self.pid = Some(pid);
+        self.restarts = restarts;
+        self.started_at = Some(now.checked_sub(uptime).unwrap_or(now));
+        self.ready = ready;
+    }
+
+    /// Record that the process of the service exited.
+    pub(crate) fn exited(&mut self, event: Event, reason: Option<&str>) {
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(Termination {
+            at: SystemTime::now(),
+            event,
+            runtime: self.uptime(),
+        });
+        self.pid = None;
+        self.usage.exited();
+        self.last_exit = Some(event);
+        self.exit_reason = reason.map(str::to_string);
+        self.stopped_since = None;
+        match event {
+            Event::ExitSignal(signal)
+            | Event::StartFailed {
+                signal: Some(signal),
+                ..
+            } => *self.exit_signals.entry(signal).or_insert(0) += 1,
+            _ => (),
+        }
+        if let Event::StartFailed { .. } = event {
+            self.start_failures += 1;
+        }
+        self.ready = false;
+    }
+}
@@ -0,0 +1,77 @@
+//! Kernel ABI constants which the libc crate only provides for some targets, e.g. not when
+//! building against musl, or only in recent versions. Most of them are the same on every
+//! architecture, so they are defined here once instead of per target. The ones which differ are
+//! defined for every architecture rsinit is built for.
+
+/// The magic numbers reboot(2) requires, to make rebooting by accident less likely.
+pub(super) const LINUX_REBOOT_MAGIC1: libc::c_int = 0xfee1_dead_u32 as libc::c_int;
+pub(super) const LINUX_REBOOT_MAGIC2: libc::c_int = 0x2812_1969;
+
+pub(super) const LINUX_REBOOT_CMD_HALT: libc::c_int = 0xcdef_0123_u32 as libc::c_int;
+pub(super) const LINUX_REBOOT_CMD_POWER_OFF: libc::c_int = 0x4321_fedc;
+pub(super) const LINUX_REBOOT_CMD_RESTART: libc::c_int = 0x0123_4567;
+/// Have Ctrl-Alt-Del send SIGINT to init, instead of restarting right away.
+pub(super) const LINUX_REBOOT_CMD_CAD_OFF: libc::c_int = 0;
+/// Don't pass a realtime policy or a negative nice value on to children, see sched(7).
+pub(super) const SCHED_RESET_ON_FORK: libc::c_int = 0x4000_0000;
+
+/// Where the numbers of the system calls of the ABI start. Only mips offsets them, by a base
+/// which differs between its ABIs.
+#[cfg(target_arch = "mips")]
+const SYSCALL_BASE: libc::c_long = 4000;
+#[cfg(all(target_arch = "mips64", target_pointer_width = "64"))]
+const SYSCALL_BASE: libc::c_long = 5000;
+#[cfg(all(target_arch = "mips64", target_pointer_width = "32"))]
+const SYSCALL_BASE: libc::c_long = 6000;
+#[cfg(not(any(target_arch = "mips", target_arch = "mips64")))]
+const SYSCALL_BASE: libc::c_long = 0;
+
+/// close_range(2), which was added in 5.9, when all architectures had come to share the numbers
+/// of new system calls.
+pub(super) const SYS_CLOSE_RANGE: libc::c_long = SYSCALL_BASE + 436;
+
+/// pidfd_send_signal(2) and pidfd_open(2), which were added in 5.1 and 5.3.
+pub(super) const SYS_PIDFD_SEND_SIGNAL: libc::c_long = SYSCALL_BASE + 424;
+pub(super) const SYS_PIDFD_OPEN: libc::c_long = SYSCALL_BASE + 434;
+
+/// kexec_file_load(2), which came before the numbers were shared, and which not every
+/// architecture has.
+#[cfg(target_arch = "x86_64")]
+pub(crate) const SYS_KEXEC_FILE_LOAD: Option<libc::c_long> = Some(320);
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+pub(crate) const SYS_KEXEC_FILE_LOAD: Option<libc::c_long> = Some(294);
+#[cfg(target_arch = "arm")]
+pub(crate) const SYS_KEXEC_FILE_LOAD: Option<libc::c_long> = Some(401);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "arm"
+)))]
+pub(crate) const SYS_KEXEC_FILE_LOAD: Option<libc::c_long> = None;
+
+/// The audit architecture of the system calls of the ABI, which a seccomp filter checks, see
+/// `<linux/audit.h>`: the ELF machine, and whether the ABI is 64 bit and little-endian.
+#[cfg(target_arch = "x86_64")]
+pub(crate) const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+pub(crate) const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(target_arch = "riscv64")]
+pub(crate) const AUDIT_ARCH: Option<u32> = Some(0xc000_00f3);
+#[cfg(target_arch = "x86")]
+pub(crate) const AUDIT_ARCH: Option<u32> = Some(0x4000_0003);
+#[cfg(target_arch = "arm")]
+pub(crate) const AUDIT_ARCH: Option<u32> = Some(0x4000_0028);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "x86",
+    target_arch = "arm"
+)))]
+pub(crate) const AUDIT_ARCH: Option<u32> = None;
+
+/// The flags of swapon(2): a priority in the lower bits, and discarding freed pages.
+pub(super) const SWAP_FLAG_PREFER: libc::c_int = 0x8000;
+pub(super) const SWAP_FLAG_PRIO_MASK: libc::c_int = 0x7fff;
+pub(super) const SWAP_FLAG_DISCARD: libc::c_int = 0x10000;
@@ -0,0 +1,194 @@
+//! Directories which are walked through file descriptors, for init to create, adjust and remove
+//! paths in directories others can write to, like `/tmp`. Entries are reached relative to the
+//! descriptor of their directory and symlinks are never followed, so swapping a path for a
+//! symlink in between checking and using it can't redirect init to somewhere else. Unlike the
+//! rest of sys, this allocates, so it is not meant to be used after fork.
+
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An open directory.
+#[derive(Debug)]
+pub(crate) struct Dir(File);
+
+/// What `fstatat` tells about an entry of a [`Dir`], without following it if it is a symlink.
+///
+/// [`Dir`]: struct.Dir.html
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stat {
+    pub(crate) dev: u64,
+    pub(crate) is_dir: bool,
+    pub(crate) accessed: SystemTime,
+    pub(crate) modified: SystemTime,
+}
+
+fn cstr(path: &Path) -> io::Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+fn check(res: libc::c_int) -> io::Result<libc::c_int> {
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(res)
+}
+
+/// The time of a stat field, where a time from before the epoch counts as the epoch.
+fn time(secs: i64, nsecs: i64) -> SystemTime {
+    u64::try_from(secs)
+        .ok()
+        .and_then(|secs| UNIX_EPOCH.checked_add(Duration::new(secs, nsecs as u32)))
+        .unwrap_or(UNIX_EPOCH)
+}
+
+impl Dir {
+    /// Open the directory at path. Only the last component of the path is refused if it is a
+    /// symlink, the directories leading up to it are trusted.
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let path = cstr(path)?;
+        let fd = check(unsafe {
+            libc::open(
+                path.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        })?;
+        Ok(Dir(unsafe { File::from_raw_fd(fd) }))
+    }
+
+    /// Open the directory which contains path, following symlinks, and return it along with the
+    /// name of path in it.
+    pub(crate) fn open_parent(path: &Path) -> io::Result<(Self, CString)> {
+        let (parent, name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} has no parent directory", path),
+                ))
+            }
+        };
+        let parent = cstr(parent)?;
+        let fd = check(unsafe {
+            libc::open(
+                parent.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+            )
+        })?;
+        let name = CString::new(name.as_bytes())?;
+        Ok((Dir(unsafe { File::from_raw_fd(fd) }), name))
+    }
+
+    /// The open directory, e.g. to change its mode or owner.
+    pub(crate) fn as_file(&self) -> &File {
+        &self.0
+    }
+
+    /// Open the subdirectory name, which fails with ENOTDIR if it is a symlink.
+    pub(crate) fn open_dir(&self, name: &CStr) -> io::Result<Self> {
+        let fd = check(unsafe {
+            libc::openat(
+                self.0.as_raw_fd(),
+                name.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        })?;
+        Ok(Dir(unsafe { File::from_raw_fd(fd) }))
+    }
+
+    /// Open the file name with the given open(2) flags, which fails with ELOOP if it is a
+    /// symlink. Files which are created get the given mode, minus the umask.
+    pub(crate) fn open_file(&self, name: &CStr, flags: libc::c_int, mode: u32) -> io::Result<File> {
+        let fd = check(unsafe {
+            libc::openat(
+                self.0.as_raw_fd(),
+                name.as_ptr(),
+                flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                mode as libc::c_uint,
+            )
+        })?;
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Create the subdirectory name with the given mode, minus the umask.
+    pub(crate) fn create_dir(&self, name: &CStr, mode: u32) -> io::Result<()> {
+        check(unsafe { libc::mkdirat(self.0.as_raw_fd(), name.as_ptr(), mode as libc::mode_t) })
+            .map(drop)
+    }
+
+    /// The names of the entries in the directory, without `.` and `..`.
+    pub(crate) fn entries(&self) -> io::Result<Vec<CString>> {
+        // closing the stream closes its descriptor, so it gets a duplicate
+        let fd = check(unsafe { libc::fcntl(self.0.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) })?;
+        let stream = unsafe { libc::fdopendir(fd) };
+        if stream.is_null() {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+        // the duplicate shares the position in the directory with the original
+        unsafe { libc::rewinddir(stream) };
+
+        let mut names = Vec::new();
+        let res = loop {
+            // readdir returns NULL both at the end and on errors, which only the latter set
+            unsafe { *libc::__errno_location() = 0 };
+            let entry = unsafe { libc::readdir(stream) };
+            if entry.is_null() {
+                match io::Error::last_os_error() {
+                    e if e.raw_os_error() == Some(0) => break Ok(names),
+                    e => break Err(e),
+                }
+            }
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+            if name.to_bytes() != b"." && name.to_bytes() != b".." {
+                names.push(name.to_owned());
+            }
+        };
+        unsafe { libc::closedir(stream) };
+        res
+    }
+
+    /// Stat the entry name, without following it if it is a symlink.
+    pub(crate) fn stat(&self, name: &CStr) -> io::Result<Stat> {
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        check(unsafe {
+            libc::fstatat(
+                self.0.as_raw_fd(),
+                name.as_ptr(),
+                &mut st,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })?;
+        Ok(Stat {
+            dev: st.st_dev,
+            is_dir: st.st_mode & libc::S_IFMT == libc::S_IFDIR,
+            accessed: time(st.st_atime as i64, st.st_atime_nsec as i64),
+            modified: time(st.st_mtime as i64, st.st_mtime_nsec as i64),
+        })
+    }
+
+    /// Remove the entry name, which must be an empty directory if dir is set, and must not be a
+    /// directory otherwise.
+    pub(crate) fn remove(&self, name: &CStr, dir: bool) -> io::Result<()> {
+        let flags = if dir { libc::AT_REMOVEDIR } else { 0 };
+        check(unsafe { libc::unlinkat(self.0.as_raw_fd(), name.as_ptr(), flags) }).map(drop)
+    }
+
+    /// Remove the entry name, and everything below it if it is a directory.
+    pub(crate) fn remove_all(&self, name: &CStr) -> io::Result<()> {
+        if !self.stat(name)?.is_dir {
+            return self.remove(name, false);
+        }
+        let dir = self.open_dir(name)?;
+        for entry in dir.entries()? {
+            dir.remove_all(&entry)?;
+        }
+        self.remove(name, true)
+    }
+}
@@ -0,0 +1,464 @@
+//! Thin wrappers around raw syscalls which are not (yet) exposed by nix. Everything in here is
+//! meant to be usable in between fork and exec, so nothing allocates. What differs between the C
+//! libraries and architectures is kept in here as well, so the rest of rsinit builds the same
+//! against glibc and musl, on every architecture.
+
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+mod arch;
+mod dir;
+#[cfg(test)]
+mod tests;
+
+pub(crate) use arch::{AUDIT_ARCH, SYS_KEXEC_FILE_LOAD};
+pub(crate) use dir::Dir;
+
+/// The `time_t` of the C library. The libc crate deprecates naming it when building against
+/// musl, as musl made it 64 bit on 32 bit architectures as well, which the crate has yet to
+/// follow.
+#[allow(deprecated)]
+pub(crate) type TimeT = libc::time_t;
+
+/// A write of a fixed payload to a file, prepared up front so it can be performed after fork.
+/// Used to manipulate the `/proc/self` attributes of a child before it execs.
+#[derive(Debug)]
+pub(crate) struct PreparedWrite {
+    path: CString,
+    payload: Vec<u8>,
+}
+
+impl PreparedWrite {
+    pub(crate) fn new(path: &str, payload: impl Into<Vec<u8>>) -> io::Result<Self> {
+        Ok(PreparedWrite {
+            path: CString::new(path)?,
+            payload: payload.into(),
+        })
+    }
+
+    /// Perform the write.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        write_raw(&self.path, &self.payload)
+    }
+}
+
+/// Open the file at path for writing and write data to it using only raw syscalls.
+pub(crate) fn write_raw(path: &CStr, data: &[u8]) -> io::Result<()> {
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let written = libc::write(fd, data.as_ptr() as *const libc::c_void, data.len());
+        let err = io::Error::last_os_error();
+        libc::close(fd);
+        if written < 0 {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+const KEYCTL_JOIN_SESSION_KEYRING: libc::c_long = 1;
+
+/// Join a new anonymous session keyring, like `keyctl join_session_keyring` does.
+pub(crate) fn join_session_keyring() -> io::Result<()> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            KEYCTL_JOIN_SESSION_KEYRING,
+            std::ptr::null::<libc::c_char>(),
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Unblock all signals. The reaper blocks the signals it traps, and a blocked signal mask is
+/// inherited across exec, so children would otherwise not respond to e.g. SIGTERM.
+pub(crate) fn unblock_signals() -> io::Result<()> {
+    unsafe {
+        let mut set = std::mem::zeroed::<libc::sigset_t>();
+        libc::sigemptyset(&mut set);
+        let res = libc::pthread_sigmask(libc::SIG_SETMASK, &set, std::ptr::null_mut());
+        if res != 0 {
+            return Err(io::Error::from_raw_os_error(res));
+        }
+    }
+    Ok(())
+}
+
+/// Make a prctl(2) call with the given arguments, the ones after them being 0. The C libraries
+/// declare prctl as variadic, and the kernel reads every argument as an unsigned long, so they
+/// are passed as such: an int passed to a variadic function can leave the upper half of its
+/// register undefined, e.g. on aarch64. Returns what the call returned.
+pub(crate) fn prctl(
+    option: libc::c_int,
+    arg2: libc::c_ulong,
+    arg3: libc::c_ulong,
+) -> io::Result<libc::c_int> {
+    let none: libc::c_ulong = 0;
+    let res = unsafe { libc::prctl(option, arg2, arg3, none, none) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(res)
+}
+
+/// Have the kernel send signal to the calling process once the thread which forked it exits.
+/// The parent is passed in to catch the case where it already exited before this was set up.
+pub(crate) fn signal_on_parent_death(signal: libc::c_int, parent: libc::pid_t) -> io::Result<()> {
+    prctl(libc::PR_SET_PDEATHSIG, signal as libc::c_ulong, 0)?;
+    unsafe {
+        // a parent outside of the PID namespace of the process shows up as 0
+        let ppid = libc::getppid();
+        if ppid != parent && ppid != 0 {
+            libc::_exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// Mark the calling process as a child subreaper, so orphaned descendants are reparented to it
+/// rather than to init.
+pub(crate) fn set_child_subreaper() -> io::Result<()> {
+    prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0).map(drop)
+}
+
+/// Start a new session, making the calling process the leader of it and of a new process group.
+pub(crate) fn new_session() -> io::Result<()> {
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Set the given resource limits. Hard limits can only be raised with privileges, so this is done
+/// before those are dropped.
+pub(crate) fn set_rlimits(limits: &[(libc::c_int, libc::rlimit)]) -> io::Result<()> {
+    for (resource, limit) in limits {
+        if unsafe { libc::setrlimit(*resource as _, limit) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Set the nice value of the calling process.
+pub(crate) fn set_nice(nice: i32) -> io::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Set the scheduling policy of the calling process, with the given realtime priority, which
+/// must be 0 for the normal and idle policies. With reset_on_fork, unlike a nice value, the
+/// policy and priority are not passed on to children: they get the normal policy, and a nice
+/// value of 0 if it was negative.
+pub(crate) fn set_scheduler(
+    policy: libc::c_int,
+    priority: libc::c_int,
+    reset_on_fork: bool,
+) -> io::Result<()> {
+    // musl has more fields, for the sporadic server policy Linux doesn't have
+    let mut param: libc::sched_param = unsafe { std::mem::zeroed() };
+    param.sched_priority = priority;
+    let flags = if reset_on_fork {
+        arch::SCHED_RESET_ON_FORK
+    } else {
+        0
+    };
+    let res = unsafe { libc::sched_setscheduler(0, policy | flags, &param) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Close all file descriptors of the calling process.
+pub(crate) fn close_all_fds() {
+    unsafe {
+        if libc::syscall(arch::SYS_CLOSE_RANGE, 0, libc::c_uint::MAX, 0) == 0 {
+            return;
+        }
+        // kernels before 5.9
+        let mut limit: libc::rlimit = std::mem::zeroed();
+        let max = if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) == 0 {
+            limit.rlim_cur.min(libc::c_int::MAX as libc::rlim_t) as libc::c_int
+        } else {
+            1024
+        };
+        for fd in 0..max {
+            libc::close(fd);
+        }
+    }
+}
+
+/// A file descriptor which refers to a process, and keeps referring to it once it exited, so
+/// it never addresses another process which got the same pid.
+#[derive(Debug)]
+pub(crate) struct PidFd(File);
+
+impl PidFd {
+    /// Refer to the process with the given pid. Requires Linux 5.3 or newer.
+    pub(crate) fn open(pid: libc::pid_t) -> io::Result<Self> {
+        // pidfds are opened with O_CLOEXEC
+        let fd = unsafe { libc::syscall(arch::SYS_PIDFD_OPEN, pid, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PidFd(unsafe { File::from_raw_fd(fd as libc::c_int) }))
+    }
+
+    /// Send the process a signal, which fails with ESRCH once it was reaped.
+    pub(crate) fn signal(&self, signal: libc::c_int) -> io::Result<()> {
+        let res = unsafe {
+            libc::syscall(
+                arch::SYS_PIDFD_SEND_SIGNAL,
+                self.0.as_raw_fd(),
+                signal,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Restrict the calling process to the given set of CPUs.
+pub(crate) fn set_affinity(cpus: &libc::cpu_set_t) -> io::Result<()> {
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), cpus) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Lock all memory of the calling process, current and future, so it is never swapped out.
+pub(crate) fn lock_memory() -> io::Result<()> {
+    if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+/// Set the io priority of the calling process, encoded as class and level.
+pub(crate) fn set_ioprio(ioprio: libc::c_int) -> io::Result<()> {
+    let res = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Change the root directory, and the working directory within it.
+pub(crate) fn chroot(root: &CStr, dir: &CStr) -> io::Result<()> {
+    unsafe {
+        if libc::chroot(root.as_ptr()) < 0 || libc::chdir(dir.as_ptr()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// What to do with the system when calling reboot(2).
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum RebootCommand {
+    Halt,
+    PowerOff,
+    Restart,
+}
+
+/// Halt, power off or restart the system. The C libraries differ in how they expose reboot(2),
+/// so the syscall is made directly. This only returns if it fails, e.g. without CAP_SYS_BOOT.
+pub(crate) fn reboot(command: RebootCommand) -> io::Error {
+    let command = match command {
+        RebootCommand::Halt => arch::LINUX_REBOOT_CMD_HALT,
+        RebootCommand::PowerOff => arch::LINUX_REBOOT_CMD_POWER_OFF,
+        RebootCommand::Restart => arch::LINUX_REBOOT_CMD_RESTART,
+    };
+    unsafe {
+        libc::syscall(
+            libc::SYS_reboot,
+            arch::LINUX_REBOOT_MAGIC1,
+            arch::LINUX_REBOOT_MAGIC2,
+            command,
+            std::ptr::null::<libc::c_void>(),
+        );
+    }
+    io::Error::last_os_error()
+}
+
+/// Have the kernel send SIGINT to init when Ctrl-Alt-Del is pressed, instead of restarting the
+/// system right away. Only init can do so.
+pub(crate) fn disable_ctrl_alt_del() -> io::Result<()> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_reboot,
+            arch::LINUX_REBOOT_MAGIC1,
+            arch::LINUX_REBOOT_MAGIC2,
+            arch::LINUX_REBOOT_CMD_CAD_OFF,
+            std::ptr::null::<libc::c_void>(),
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The part of `struct ifreq` which is used to get and set the flags of a network interface.
+#[repr(C)]
+struct IfreqFlags {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    flags: libc::c_short,
+    // the rest of the union in `struct ifreq`
+    _pad: [u8; 22],
+}
+
+/// Start swapping to the device or file at path, like swapon(8). Swap areas with a higher
+/// priority are used first, and the kernel picks one if none is given. With discard, freed
+/// pages are discarded, for SSDs.
+pub(crate) fn swap_on(path: &CStr, priority: Option<u16>, discard: bool) -> io::Result<()> {
+    let mut flags = 0;
+    if let Some(priority) = priority {
+        flags |= arch::SWAP_FLAG_PREFER | (libc::c_int::from(priority) & arch::SWAP_FLAG_PRIO_MASK);
+    }
+    if discard {
+        flags |= arch::SWAP_FLAG_DISCARD;
+    }
+    if unsafe { libc::swapon(path.as_ptr(), flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Stop swapping to the device or file at path, like swapoff(8), which moves what was swapped
+/// out back into memory.
+pub(crate) fn swap_off(path: &CStr) -> io::Result<()> {
+    if unsafe { libc::swapoff(path.as_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Bring the network interface with the given name up, like `ip link set NAME up`.
+pub(crate) fn interface_up(name: &CStr) -> io::Result<()> {
+    let bytes = name.to_bytes();
+    if bytes.len() >= libc::IFNAMSIZ {
+        return Err(io::Error::from_raw_os_error(libc::ENAMETOOLONG));
+    }
+    let mut req = IfreqFlags {
+        name: [0; libc::IFNAMSIZ],
+        flags: 0,
+        _pad: [0; 22],
+    };
+    for (dst, src) in req.name.iter_mut().zip(bytes) {
+        *dst = *src as libc::c_char;
+    }
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut res = libc::ioctl(fd, libc::SIOCGIFFLAGS as _, &mut req as *mut IfreqFlags);
+        if res == 0 {
+            req.flags |= (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+            res = libc::ioctl(fd, libc::SIOCSIFFLAGS as _, &mut req as *mut IfreqFlags);
+        }
+        let err = io::Error::last_os_error();
+        libc::close(fd);
+        if res < 0 {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// `struct rtc_time`, the same fields as the start of `struct tm`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct RtcTime {
+    pub(crate) sec: libc::c_int,
+    pub(crate) min: libc::c_int,
+    pub(crate) hour: libc::c_int,
+    pub(crate) mday: libc::c_int,
+    pub(crate) mon: libc::c_int,
+    pub(crate) year: libc::c_int,
+    pub(crate) wday: libc::c_int,
+    pub(crate) yday: libc::c_int,
+    pub(crate) isdst: libc::c_int,
+}
+
+// RTC_RD_TIME and RTC_SET_TIME, `_IOR('p', 0x09, struct rtc_time)` and
+// `_IOW('p', 0x0a, struct rtc_time)`. The direction bits differ between architectures.
+#[cfg(any(
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "sparc64"
+))]
+const RTC_RD_TIME: libc::c_ulong = 0x4024_7009;
+#[cfg(any(
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "sparc64"
+))]
+const RTC_SET_TIME: libc::c_ulong = 0x8024_700a;
+#[cfg(not(any(
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "sparc64"
+)))]
+const RTC_RD_TIME: libc::c_ulong = 0x8024_7009;
+#[cfg(not(any(
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "sparc64"
+)))]
+const RTC_SET_TIME: libc::c_ulong = 0x4024_700a;
+
+/// Read the time of the real time clock behind the open device fd, like `hwclock --show`.
+pub(crate) fn rtc_read_time(fd: libc::c_int) -> io::Result<RtcTime> {
+    let mut time = RtcTime::default();
+    if unsafe { libc::ioctl(fd, RTC_RD_TIME as _, &mut time as *mut RtcTime) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(time)
+}
+
+/// Set the time of the real time clock behind the open device fd.
+pub(crate) fn rtc_set_time(fd: libc::c_int, time: &RtcTime) -> io::Result<()> {
+    if unsafe { libc::ioctl(fd, RTC_SET_TIME as _, time as *const RtcTime) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Step the wall clock to the given amount of seconds since the epoch.
+pub(crate) fn set_wall_clock(secs: TimeT, nsecs: libc::c_long) -> io::Result<()> {
+    let spec = libc::timespec {
+        tv_sec: secs,
+        tv_nsec: nsecs,
+    };
+    if unsafe { libc::clock_settime(libc::CLOCK_REALTIME, &spec) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
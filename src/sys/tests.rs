@@ -0,0 +1,104 @@
+use std::ffi::CStr;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+
+use super::*;
+use crate::seccomp::SyscallFilter;
+
+#[test]
+fn kernel_constants_match_libc() {
+    assert_eq!(arch::LINUX_REBOOT_MAGIC1, libc::LINUX_REBOOT_MAGIC1);
+    assert_eq!(arch::LINUX_REBOOT_MAGIC2, libc::LINUX_REBOOT_MAGIC2);
+    assert_eq!(arch::LINUX_REBOOT_CMD_HALT, libc::LINUX_REBOOT_CMD_HALT);
+    assert_eq!(
+        arch::LINUX_REBOOT_CMD_POWER_OFF,
+        libc::LINUX_REBOOT_CMD_POWER_OFF
+    );
+    assert_eq!(
+        arch::LINUX_REBOOT_CMD_RESTART,
+        libc::LINUX_REBOOT_CMD_RESTART
+    );
+    assert_eq!(
+        arch::LINUX_REBOOT_CMD_CAD_OFF,
+        libc::LINUX_REBOOT_CMD_CAD_OFF
+    );
+    assert_eq!(arch::SCHED_RESET_ON_FORK, libc::SCHED_RESET_ON_FORK);
+    assert_eq!(arch::SYS_CLOSE_RANGE, libc::SYS_close_range);
+    assert_eq!(arch::SYS_PIDFD_OPEN, libc::SYS_pidfd_open);
+    assert_eq!(arch::SYS_PIDFD_SEND_SIGNAL, libc::SYS_pidfd_send_signal);
+    #[cfg(all(
+        target_env = "gnu",
+        any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm")
+    ))]
+    assert_eq!(arch::SYS_KEXEC_FILE_LOAD, Some(libc::SYS_kexec_file_load));
+}
+
+#[test]
+fn prctl_passes_pointers() {
+    let name = CStr::from_bytes_with_nul(b"rsinit-prctl\0").unwrap();
+    prctl(libc::PR_SET_NAME, name.as_ptr() as libc::c_ulong, 0).unwrap();
+    let mut buf = [0 as libc::c_char; 16];
+    prctl(libc::PR_GET_NAME, buf.as_mut_ptr() as libc::c_ulong, 0).unwrap();
+    assert_eq!(unsafe { CStr::from_ptr(buf.as_ptr()) }, name);
+}
+
+#[test]
+fn prctl_reports_errors() {
+    let e = prctl(-1, 0, 0).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::EINVAL));
+}
+
+#[test]
+fn pidfd_signals_its_process() {
+    let mut child = Command::new("sleep").arg("60").spawn().unwrap();
+    let pidfd = match PidFd::open(child.id() as libc::pid_t) {
+        Ok(pidfd) => pidfd,
+        // a kernel before 5.3
+        Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return;
+        }
+        Err(e) => panic!("failed to open pidfd: {}", e),
+    };
+    pidfd.signal(libc::SIGKILL).unwrap();
+    assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGKILL));
+    // the pid might be in use by another process by now, the pidfd still refers to the old one
+    let e = pidfd.signal(libc::SIGKILL).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::ESRCH));
+}
+
+#[test]
+fn seccomp_filter_matches_the_architecture() {
+    if AUDIT_ARCH.is_none() {
+        return;
+    }
+    let filter = SyscallFilter::Deny(vec![libc::SYS_getppid])
+        .compile()
+        .unwrap();
+    // a filter for another architecture kills the process, instead of denying the call
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed: {}", io::Error::last_os_error());
+    if pid == 0 {
+        let code = match crate::seccomp::set_no_new_privs().and_then(|_| filter.apply()) {
+            // e.g. under qemu, which would have its own system calls filtered
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => 2,
+            Err(_) => 1,
+            Ok(_) if unsafe { libc::syscall(libc::SYS_getppid) } >= 0 => 1,
+            Ok(_) => match io::Error::last_os_error().raw_os_error() {
+                Some(libc::EPERM) => 0,
+                _ => 1,
+            },
+        };
+        unsafe { libc::_exit(code) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(
+        libc::WIFEXITED(status),
+        "killed by signal {}",
+        libc::WTERMSIG(status)
+    );
+    assert_ne!(libc::WEXITSTATUS(status), 1);
+}
@@ -0,0 +1,167 @@
+//! Templates of services which run as several instances, like `worker@1` to `worker@8`, or a
+//! getty on each of a list of terminals. Every instance is an independent service, named
+//! `name@instance`, with `%i` in its arguments and environment replaced by the instance.
+
+use crate::command::{split_command, PersistentCommand};
+
+/// What is replaced by the instance.
+const PLACEHOLDER: &str = "%i";
+
+/// A service definition with a placeholder for the instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceTemplate {
+    name: String,
+    line: String,
+    env: Vec<(String, String)>,
+    instances: Vec<Instance>,
+}
+
+/// A service of a template, with the instance filled in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Instance {
+    instance: String,
+    name: String,
+    line: String,
+    env: Vec<(String, String)>,
+}
+
+impl ServiceTemplate {
+    /// A template of the services named `name@instance`, which run the executable and
+    /// arguments of line, e.g. `/usr/bin/worker --id %i`.
+    pub fn new(name: &str, line: &str) -> Self {
+        ServiceTemplate {
+            name: name.to_string(),
+            line: line.to_string(),
+            env: Vec::new(),
+            instances: Vec::new(),
+        }
+    }
+
+    /// Set an environment variable for every instance, e.g. `WORKER_ID=%i`.
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.retain(|(k, _)| k != key);
+        self.env.push((key.to_string(), value.to_string()));
+        self.expand();
+        self
+    }
+
+    /// Add instances, e.g. `["tty1", "ttyS0"]`.
+    pub fn instances<I, S>(mut self, instances: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for instance in instances {
+            self.instances.push(Instance {
+                instance: instance.as_ref().to_string(),
+                name: String::new(),
+                line: String::new(),
+                env: Vec::new(),
+            });
+        }
+        self.expand();
+        self
+    }
+
+    /// The services of all instances, in the order the instances were added in. Further
+    /// settings can be made on every command, which borrows from the template.
+    pub fn commands(&self) -> Vec<PersistentCommand<'_>> {
+        self.instances
+            .iter()
+            .map(|instance| {
+                let (cmd, args) = split_command(&instance.line);
+                instance
+                    .env
+                    .iter()
+                    .fold(PersistentCommand::new(cmd, args), |pcmd, (key, value)| {
+                        pcmd.env(key, value)
+                    })
+                    .name(&instance.name)
+            })
+            .collect()
+    }
+
+    /// Fill in every instance, after the template changed.
+    fn expand(&mut self) {
+        for instance in &mut self.instances {
+            instance.name = format!("{}@{}", self.name, instance.instance);
+            instance.line = substitute(&self.line, &instance.instance);
+            instance.env = self
+                .env
+                .iter()
+                .map(|(key, value)| (key.clone(), substitute(value, &instance.instance)))
+                .collect();
+        }
+    }
+}
+
+/// Replace the placeholder in a template string by the instance.
+pub(crate) fn substitute(template: &str, instance: &str) -> String {
+    template.replace(PLACEHOLDER, instance)
+}
+
+/// Parse a list of instances, separated by commas, in which a range of numbers like `1-8`
+/// stands for every number in it.
+pub(crate) fn parse_instances(spec: &str) -> Result<Vec<String>, String> {
+    let mut instances = Vec::new();
+    for item in spec.split(',').map(str::trim) {
+        if item.is_empty() {
+            return Err(format!("empty instance in {}", spec));
+        }
+        let range = item
+            .find('-')
+            .and_then(|idx| Some((item[..idx].parse().ok()?, item[idx + 1..].parse().ok()?)));
+        match range {
+            Some((first, last)) if first <= last => {
+                instances.extend((first..=last).map(|n: u32| n.to_string()))
+            }
+            Some(_) => return Err(format!("empty range {}", item)),
+            None => instances.push(item.to_string()),
+        }
+    }
+    Ok(instances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_instance_lists() {
+        let cases: &[(&str, &[&str])] = &[
+            ("tty1", &["tty1"]),
+            ("tty1, ttyS0", &["tty1", "ttyS0"]),
+            ("1-3", &["1", "2", "3"]),
+            ("7-7,a", &["7", "a"]),
+            ("0-1,x-y,-1", &["0", "1", "x-y", "-1"]),
+            ("4294967294-4294967295", &["4294967294", "4294967295"]),
+            ("1-4294967296", &["1-4294967296"]),
+        ];
+        for (spec, instances) in cases {
+            assert_eq!(parse_instances(spec).unwrap(), *instances, "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn parse_instance_errors() {
+        let cases = &[
+            ("", "empty instance in "),
+            ("a,,b", "empty instance in a,,b"),
+            ("a, ", "empty instance in a, "),
+            ("3-1", "empty range 3-1"),
+        ];
+        for (spec, error) in cases {
+            assert_eq!(parse_instances(spec), Err(error.to_string()), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn substitute_every_placeholder() {
+        assert_eq!(
+            substitute("--id %i --log /var/log/%i", "3"),
+            "--id 3 --log /var/log/3"
+        );
+        assert_eq!(substitute("no placeholder", "3"), "no placeholder");
+        assert_eq!(substitute("%%i", "x"), "%x");
+    }
+}
@@ -0,0 +1,248 @@
+//! Commands which are run periodically, like cron jobs, so minimal systems don't need a cron
+//! daemon for the likes of log rotation and backups. The processes are children of the reaper,
+//! and are reaped like any other.
+
+use std::process::Child;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, TimeZone};
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+
+use crate::command::OneShotCommand;
+
+/// When a [`TimerCommand`] runs.
+///
+/// [`TimerCommand`]: struct.TimerCommand.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every interval, starting an interval after the reaper started.
+    Every(Duration),
+    /// Every day at the given hour and minute, in local time.
+    Daily(u32, u32),
+}
+
+impl Schedule {
+    /// How long it is until the next run.
+    fn until_next(&self) -> Duration {
+        let (hour, minute) = match self {
+            Schedule::Every(interval) => return *interval,
+            Schedule::Daily(hour, minute) => (*hour, *minute),
+        };
+        let now = Local::now();
+        let mut day = now.naive_local().date();
+        // a day on which the time does not exist, due to a DST change, is skipped
+        for _ in 0..3 {
+            let at = day
+                .and_hms_opt(hour, minute, 0)
+                .and_then(|at| Local.from_local_datetime(&at).earliest());
+            match at {
+                Some(at) if at > now => return (at - now).to_std().unwrap_or_default(),
+                _ => match day.succ_opt() {
+                    Some(next) => day = next,
+                    None => break,
+                },
+            }
+        }
+        Duration::from_secs(24 * 60 * 60)
+    }
+}
+
+/// Parse a schedule like `every 15m`, with a unit of `s`, `m`, `h` or `d`, or `daily at 03:00`.
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = spec.split_whitespace().collect();
+        match words.as_slice() {
+            ["every", interval] => {
+                let split = interval.len() - interval.chars().last().map_or(0, char::len_utf8);
+                let (count, unit) = interval.split_at(split);
+                let count: u64 = count
+                    .parse()
+                    .map_err(|_| format!("invalid interval {}", interval))?;
+                let secs = match unit {
+                    "s" => 1,
+                    "m" => 60,
+                    "h" => 60 * 60,
+                    "d" => 24 * 60 * 60,
+                    _ => return Err(format!("invalid unit in interval {}", interval)),
+                };
+                match count.checked_mul(secs) {
+                    Some(0) => Err("the interval must not be 0".to_string()),
+                    Some(secs) => Ok(Schedule::Every(Duration::from_secs(secs))),
+                    None => Err(format!("interval {} is too long", interval)),
+                }
+            }
+            ["daily", "at", time] => {
+                let invalid = || format!("invalid time {}, expected HH:MM", time);
+                let idx = time.find(':').ok_or_else(invalid)?;
+                let hour: u32 = time[..idx].parse().map_err(|_| invalid())?;
+                let minute: u32 = time[idx + 1..].parse().map_err(|_| invalid())?;
+                if hour >= 24 || minute >= 60 {
+                    return Err(invalid());
+                }
+                Ok(Schedule::Daily(hour, minute))
+            }
+            _ => Err(format!("invalid schedule {:?}", spec)),
+        }
+    }
+}
+
+/// A command which the [`Reaper`] runs on a schedule. A run is skipped if the previous one is
+/// still going.
+///
+/// [`Reaper`]: struct.Reaper.html
+pub struct TimerCommand<'a> {
+    cmd: OneShotCommand<'a>,
+    schedule: Schedule,
+
+    next_run: Option<Instant>,
+    running: Option<Pid>,
+}
+
+impl<'a> TimerCommand<'a> {
+    /// Run the command on the given schedule.
+    pub fn new(cmd: OneShotCommand<'a>, schedule: Schedule) -> Self {
+        TimerCommand {
+            cmd,
+            schedule,
+
+            next_run: None,
+            running: None,
+        }
+    }
+
+    /// The name the command is known by.
+    pub fn service_name(&self) -> &str {
+        self.cmd.service_name()
+    }
+
+    /// Schedule the next run of the command.
+    pub(crate) fn schedule(&mut self, now: Instant) {
+        self.next_run = Some(now + self.schedule.until_next());
+    }
+
+    /// Schedule the next run again after the wall clock changed, if it depends on it.
+    pub(crate) fn clock_changed(&mut self, now: Instant) {
+        if let Schedule::Daily(..) = self.schedule {
+            self.schedule(now);
+        }
+    }
+
+    /// When the command is due to run next, once it is scheduled.
+    pub(crate) fn next_run(&self) -> Option<Instant> {
+        self.next_run
+    }
+
+    /// The process of the current run, if any.
+    pub(crate) fn running(&self) -> Option<Pid> {
+        self.running
+    }
+
+    /// Start the command if it is due. Returns the process if one was spawned.
+    pub(crate) fn run_if_due(
+        &mut self,
+        now: Instant,
+        capture_output: bool,
+        death_signal: Signal,
+    ) -> Option<Child> {
+        match self.next_run {
+            Some(next) if next <= now => (),
+            _ => return None,
+        }
+        self.schedule(now);
+
+        if self.running.is_some() {
+            warn!(
+                "Timer command {} is still running, skipping",
+                self.service_name()
+            );
+            return None;
+        }
+        debug!("Running timer command {}", self.service_name());
+        match self.cmd.spawn(capture_output, death_signal) {
+            Ok(child) => {
+                self.running = Some(Pid::from_raw(child.id() as i32));
+                Some(child)
+            }
+            Err(e) => {
+                error!(
+                    "Failed to spawn timer command ({}): {}",
+                    self.service_name(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Record that the process of the current run exited.
+    pub(crate) fn finished(&mut self) {
+        self.running = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_schedules() {
+        let cases = &[
+            ("every 30s", Schedule::Every(Duration::from_secs(30))),
+            ("every 15m", Schedule::Every(Duration::from_secs(15 * 60))),
+            (
+                "  every   2h ",
+                Schedule::Every(Duration::from_secs(2 * 60 * 60)),
+            ),
+            (
+                "every 1d",
+                Schedule::Every(Duration::from_secs(24 * 60 * 60)),
+            ),
+            ("daily at 03:00", Schedule::Daily(3, 0)),
+            ("daily at 0:5", Schedule::Daily(0, 5)),
+            ("daily at 23:59", Schedule::Daily(23, 59)),
+        ];
+        for (spec, schedule) in cases {
+            assert_eq!(spec.parse::<Schedule>(), Ok(*schedule), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = &[
+            ("", "invalid schedule \"\""),
+            ("every", "invalid schedule \"every\""),
+            ("every 5 m", "invalid schedule \"every 5 m\""),
+            ("hourly", "invalid schedule \"hourly\""),
+            ("every 0s", "the interval must not be 0"),
+            ("every m", "invalid interval m"),
+            ("every -5m", "invalid interval -5m"),
+            ("every 5w", "invalid unit in interval 5w"),
+            ("every 5é", "invalid unit in interval 5é"),
+            (
+                "every 99999999999999999999s",
+                "invalid interval 99999999999999999999s",
+            ),
+            (
+                "every 18446744073709551615m",
+                "interval 18446744073709551615m is too long",
+            ),
+            ("daily at 24:00", "invalid time 24:00, expected HH:MM"),
+            ("daily at 12:60", "invalid time 12:60, expected HH:MM"),
+            ("daily at 1200", "invalid time 1200, expected HH:MM"),
+            ("daily at :30", "invalid time :30, expected HH:MM"),
+            ("daily at -1:30", "invalid time -1:30, expected HH:MM"),
+        ];
+        for (spec, error) in cases {
+            assert_eq!(
+                spec.parse::<Schedule>(),
+                Err(error.to_string()),
+                "{:?}",
+                spec
+            );
+        }
+    }
+}
@@ -0,0 +1,505 @@
+//! A subset of the tmpfiles.d format, used to provision volatile directories and files such as
+//! the structure of `/run`, which many daemons expect to exist when they start.
+//!
+//! Every line has the form `Type Path Mode User Group Age Argument`, where trailing fields can
+//! be omitted and `-` selects the default. Supported types are:
+//!
+//! - `d`: create a directory, and clean it up by age
+//! - `D`: like `d`, but the contents are also removed when run at boot
+//! - `e`: adjust an existing directory, and clean it up by age
+//! - `f`: create a file if it does not exist, with the argument as content
+//! - `F`: create or truncate a file, with the argument as content
+//! - `L`: create a symlink pointing to the argument
+//! - `r`: remove a file or empty directory
+//! - `R`: recursively remove a path
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::{fchown, symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::credentials::{group_id, Passwd};
+use crate::maintenance::clean_directory;
+use crate::sys::Dir;
+
+/// Directory from which tmpfiles configuration is loaded at boot.
+pub const TMPFILES_DIR: &str = "/etc/rsinit/tmpfiles.d";
+
+/// The kind of action a tmpfiles entry describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    CreateDirectory,
+    CreateCleanDirectory,
+    AdjustDirectory,
+    CreateFile,
+    TruncateFile,
+    Symlink,
+    Remove,
+    RemoveRecursive,
+}
+
+/// A single line of tmpfiles configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub kind: EntryType,
+    pub path: PathBuf,
+    pub mode: Option<u32>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub age: Option<Duration>,
+    pub argument: Option<String>,
+}
+
+/// Error while parsing tmpfiles configuration.
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parse tmpfiles configuration. Empty lines and lines starting with `#` are ignored.
+pub fn parse(content: &str) -> Result<Vec<Entry>, ParseError> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(nr, line)| parse_line(line).map_err(|reason| ParseError { line: nr, reason }))
+        .collect()
+}
+
+fn parse_line(line: &str) -> Result<Entry, String> {
+    // the argument is the remainder of the line and can contain whitespace
+    let mut fields = Vec::with_capacity(7);
+    let mut rest = line;
+    while fields.len() < 6 {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        fields.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    let rest = rest.trim();
+    if !rest.is_empty() {
+        fields.push(rest);
+    }
+
+    let field = |idx: usize| fields.get(idx).copied().filter(|f| *f != "-");
+
+    let kind = match field(0) {
+        Some("d") => EntryType::CreateDirectory,
+        Some("D") => EntryType::CreateCleanDirectory,
+        Some("e") => EntryType::AdjustDirectory,
+        Some("f") => EntryType::CreateFile,
+        Some("F") => EntryType::TruncateFile,
+        Some("L") => EntryType::Symlink,
+        Some("r") => EntryType::Remove,
+        Some("R") => EntryType::RemoveRecursive,
+        Some(t) => return Err(format!("unsupported type {}", t)),
+        None => return Err("missing type".to_string()),
+    };
+
+    let path = match field(1) {
+        Some(p) if p.starts_with('/') => PathBuf::from(p),
+        Some(p) => return Err(format!("path {} is not absolute", p)),
+        None => return Err("missing path".to_string()),
+    };
+
+    let mode = match field(2) {
+        Some(m) => Some(u32::from_str_radix(m, 8).map_err(|_| format!("invalid mode {}", m))?),
+        None => None,
+    };
+
+    let age = match field(5) {
+        Some(a) => Some(parse_age(a)?),
+        None => None,
+    };
+
+    let argument = field(6).map(|a| a.to_string());
+    if kind == EntryType::Symlink && argument.is_none() {
+        return Err("symlink without target".to_string());
+    }
+
+    Ok(Entry {
+        kind,
+        path,
+        mode,
+        user: field(3).map(|u| u.to_string()),
+        group: field(4).map(|g| g.to_string()),
+        age,
+        argument,
+    })
+}
+
+/// Parse an age like `10d`, `1h30m` or `90` (seconds).
+fn parse_age(age: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid age {}", age);
+    let too_long = || format!("age {} is too long", age);
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in age.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let multiplier = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let secs = digits.parse::<u64>().map_err(|_| invalid())?;
+        total = secs
+            .checked_mul(multiplier)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(too_long)?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        let secs = digits.parse::<u64>().map_err(|_| invalid())?;
+        total = total.checked_add(secs).ok_or_else(too_long)?;
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Load all `*.conf` files in a directory, in lexical order of their file name. Files which
+/// fail to be read or parsed are skipped with a warning.
+pub fn load_dir<P: AsRef<Path>>(dir: P) -> io::Result<Vec<Entry>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().map(|e| e == "conf").unwrap_or(false))
+        .collect();
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+    let mut entries = Vec::new();
+    for file in files {
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Failed to read tmpfiles configuration {:?}: {}", file, e);
+                continue;
+            }
+        };
+        match parse(&content) {
+            Ok(mut e) => entries.append(&mut e),
+            Err(e) => warn!("Ignoring tmpfiles configuration {:?}: {}", file, e),
+        }
+    }
+    Ok(entries)
+}
+
+/// Create, adjust and remove the paths described by the entries. When run at boot, the contents
+/// of `D` directories are removed as well. Failures are logged per entry.
+pub fn create(entries: &[Entry], boot: bool) {
+    for entry in entries {
+        if let Err(e) = entry.create(boot) {
+            warn!("Failed to provision {:?}: {}", entry.path, e);
+        }
+    }
+}
+
+/// Remove stale files from all directories with an age set.
+pub fn clean(entries: &[Entry]) {
+    for entry in entries {
+        let age = match (entry.kind, entry.age) {
+            (EntryType::CreateDirectory, Some(age))
+            | (EntryType::CreateCleanDirectory, Some(age))
+            | (EntryType::AdjustDirectory, Some(age)) => age,
+            _ => continue,
+        };
+        // an age from before the epoch leaves everything in place
+        let cutoff = SystemTime::now().checked_sub(age).unwrap_or(UNIX_EPOCH);
+        match clean_directory(&entry.path, cutoff) {
+            Ok(removed) => debug!("Removed {} stale entries from {:?}", removed, entry.path),
+            Err(e) => warn!("Failed to clean {:?}: {}", entry.path, e),
+        }
+    }
+}
+
+/// Load the configuration in dir, then create and clean everything it describes.
+pub fn run<P: AsRef<Path>>(dir: P, boot: bool) -> io::Result<()> {
+    let entries = load_dir(dir)?;
+    create(&entries, boot);
+    clean(&entries);
+    Ok(())
+}
+
+impl Entry {
+    /// Create or adjust the path of the entry. The path is opened without following symlinks, and
+    /// its mode and owner are applied to the open file, so a symlink planted in a directory
+    /// others can write to can't redirect init.
+    pub(crate) fn create(&self, boot: bool) -> io::Result<()> {
+        let path = &self.path;
+        match self.kind {
+            EntryType::CreateDirectory | EntryType::CreateCleanDirectory => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let (parent, name) = Dir::open_parent(path)?;
+                match parent.create_dir(&name, self.mode.unwrap_or(0o755)) {
+                    Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+                    res => res?,
+                }
+                let dir = parent.open_dir(&name)?;
+                if self.kind == EntryType::CreateCleanDirectory && boot {
+                    for entry in dir.entries()? {
+                        dir.remove_all(&entry)?;
+                    }
+                }
+                self.set_attributes(dir.as_file(), 0o755)
+            }
+            EntryType::AdjustDirectory => {
+                let (parent, name) = Dir::open_parent(path)?;
+                match parent.open_dir(&name) {
+                    Ok(dir) => self.set_attributes(dir.as_file(), 0o755),
+                    Err(ref e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+                    Err(e) => Err(e),
+                }
+            }
+            EntryType::CreateFile | EntryType::TruncateFile => {
+                let (parent, name) = Dir::open_parent(path)?;
+                // a FIFO would block the open, it is refused below like anything but a file
+                let flags = libc::O_WRONLY | libc::O_NONBLOCK | libc::O_CREAT;
+                let mode = self.mode.unwrap_or(0o644);
+                let (mut file, existed) = if self.kind == EntryType::CreateFile {
+                    match parent.open_file(&name, flags | libc::O_EXCL, mode) {
+                        Ok(file) => (file, false),
+                        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => (
+                            parent.open_file(&name, libc::O_RDONLY | libc::O_NONBLOCK, 0)?,
+                            true,
+                        ),
+                        Err(e) => return Err(e),
+                    }
+                } else {
+                    (parent.open_file(&name, flags, mode)?, false)
+                };
+                if !file.metadata()?.is_file() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "not a regular file",
+                    ));
+                }
+                if !existed {
+                    // only truncated once it is known to be a file
+                    file.set_len(0)?;
+                    if let Some(content) = &self.argument {
+                        file.write_all(content.as_bytes())?;
+                    }
+                }
+                self.set_attributes(&file, 0o644)
+            }
+            EntryType::Symlink => {
+                if fs::symlink_metadata(path).is_ok() {
+                    return Ok(());
+                }
+                // the argument is validated to be present when parsing
+                symlink(self.argument.as_deref().unwrap_or_default(), path)
+            }
+            EntryType::Remove => match fs::symlink_metadata(path) {
+                Ok(meta) if meta.is_dir() => fs::remove_dir(path),
+                Ok(_) => fs::remove_file(path),
+                Err(_) => Ok(()),
+            },
+            EntryType::RemoveRecursive => {
+                let (parent, name) = Dir::open_parent(path)?;
+                match parent.remove_all(&name) {
+                    Err(ref e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+                    res => res,
+                }
+            }
+        }
+    }
+
+    /// Apply the mode and ownership of the entry to the open file.
+    fn set_attributes(&self, file: &File, default_mode: u32) -> io::Result<()> {
+        file.set_permissions(fs::Permissions::from_mode(
+            self.mode.unwrap_or(default_mode),
+        ))?;
+
+        let uid = match &self.user {
+            Some(user) => match user.parse() {
+                Ok(uid) => Some(uid),
+                Err(_) => Some(Passwd::from_name(user)?.uid),
+            },
+            None => None,
+        };
+        let gid = match &self.group {
+            Some(group) => match group.parse() {
+                Ok(gid) => Some(gid),
+                Err(_) => Some(group_id(group)?.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("group {} does not exist", group),
+                    )
+                })?),
+            },
+            None => None,
+        };
+        if uid.is_some() || gid.is_some() {
+            fchown(file, uid, gid)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_lines() {
+        let entries = parse(
+            "# comment\n\
+             \n\
+             d /run/foo 0750 root adm 10d\n\
+             F /run/foo/motd - - - - hello  world \n\
+             L /run/link - - - - /run/foo\n\
+             R /run/old\n",
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(
+            entries[0],
+            Entry {
+                kind: EntryType::CreateDirectory,
+                path: PathBuf::from("/run/foo"),
+                mode: Some(0o750),
+                user: Some("root".to_string()),
+                group: Some("adm".to_string()),
+                age: Some(Duration::from_secs(10 * 24 * 60 * 60)),
+                argument: None,
+            }
+        );
+        // the argument is the rest of the line, with inner whitespace kept
+        assert_eq!(entries[1].argument.as_deref(), Some("hello  world"));
+        assert_eq!(entries[1].mode, None);
+        assert_eq!(entries[2].argument.as_deref(), Some("/run/foo"));
+        assert_eq!(entries[3].kind, EntryType::RemoveRecursive);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (content, line, reason) in [
+            ("x /run/foo", 1, "unsupported type x"),
+            ("- /run/foo", 1, "missing type"),
+            ("d", 1, "missing path"),
+            ("d run/foo", 1, "path run/foo is not absolute"),
+            ("d /run/foo 0789", 1, "invalid mode 0789"),
+            ("d /run/foo - - - 1y", 1, "invalid age 1y"),
+            ("# ok\nL /run/link", 2, "symlink without target"),
+        ] {
+            let e = parse(content).unwrap_err();
+            assert_eq!((e.line, e.reason.as_str()), (line, reason), "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn parse_ages() {
+        for (age, secs) in [
+            ("", 0),
+            ("0", 0),
+            ("90", 90),
+            ("90s", 90),
+            ("1h30m", 90 * 60),
+            ("2w1d", 15 * 24 * 60 * 60),
+            ("1m30", 90),
+        ] {
+            assert_eq!(parse_age(age), Ok(Duration::from_secs(secs)), "{}", age);
+        }
+        for (age, reason) in [
+            ("h", "invalid age h"),
+            ("10x", "invalid age 10x"),
+            ("-1d", "invalid age -1d"),
+            ("99999999999999999999", "invalid age 99999999999999999999"),
+            ("40000000000000w", "age 40000000000000w is too long"),
+            (
+                "18446744073709551615s1s",
+                "age 18446744073709551615s1s is too long",
+            ),
+        ] {
+            assert_eq!(parse_age(age), Err(reason.to_string()), "{}", age);
+        }
+    }
+
+    #[test]
+    fn create_does_not_follow_symlinks() {
+        let base = std::env::temp_dir().join(format!("rsinit-tmpfiles-{}", std::process::id()));
+        let outside = base.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        fs::set_permissions(outside.join("secret"), fs::Permissions::from_mode(0o600)).unwrap();
+        symlink(outside.join("secret"), base.join("file")).unwrap();
+        symlink(&outside, base.join("dir")).unwrap();
+
+        let entries = parse(&format!(
+            "F {base}/file 0666 - - - pwned\n\
+             d {base}/dir 0777\n\
+             e {base}/dir 0777\n\
+             D {base}/dir 0777\n",
+            base = base.display()
+        ))
+        .unwrap();
+        for entry in &entries {
+            assert!(entry.create(true).is_err(), "{:?}", entry.kind);
+        }
+        let secret = outside.join("secret");
+        assert_eq!(fs::read_to_string(&secret).unwrap(), "secret");
+        assert_eq!(
+            fs::metadata(&secret).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        assert_ne!(
+            fs::metadata(&outside).unwrap().permissions().mode() & 0o777,
+            0o777
+        );
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn create_entries() {
+        let base =
+            std::env::temp_dir().join(format!("rsinit-tmpfiles-create-{}", std::process::id()));
+        let entries = parse(&format!(
+            "D {base}/a/b 0700\n\
+             f {base}/a/b/f 0600 - - - first\n\
+             f {base}/a/b/f 0640 - - - second\n\
+             F {base}/a/b/g - - - - new\n",
+            base = base.display()
+        ))
+        .unwrap();
+        fs::create_dir_all(base.join("a/b/stale/deeper")).unwrap();
+        fs::write(base.join("a/b/g"), "old content").unwrap();
+        create(&entries, true);
+
+        let b = base.join("a/b");
+        assert_eq!(
+            fs::metadata(&b).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+        assert!(!b.join("stale").exists());
+        // an existing file keeps its content, but gets the mode of the entry
+        assert_eq!(fs::read_to_string(b.join("f")).unwrap(), "first");
+        assert_eq!(
+            fs::metadata(b.join("f")).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        assert_eq!(fs::read_to_string(b.join("g")).unwrap(), "new");
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+
+use crate::credentials::group_id;
+
+/// Open a terminal device to attach a process to. If an owner is given, the terminal is handed
+/// over to that user the way login does: owned by the user and the `tty` group, mode 0620.
+pub(crate) fn open_tty(path: &str, owner: Option<libc::uid_t>) -> io::Result<File> {
+    let tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)?;
+
+    if let Some(uid) = owner {
+        hand_over(&tty, uid)?;
+    }
+
+    Ok(tty)
+}
+
+/// Allocate a pseudo-terminal, returning its master and its slave, to attach a process to the
+/// slave and read its output from the master. The slave is handed over to the owner, if any,
+/// like with [`open_tty`]. Newlines are written as they are, rather than as CR LF, as the output
+/// ends up in logs rather than on a screen.
+///
+/// [`open_tty`]: fn.open_tty.html
+pub(crate) fn open_pty(owner: Option<libc::uid_t>) -> io::Result<(File, File)> {
+    let (mut master, mut slave) = (0, 0);
+    let (master, slave) = unsafe {
+        if libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        (File::from_raw_fd(master), File::from_raw_fd(slave))
+    };
+
+    unsafe {
+        // neither end is to be inherited by anything but the process the slave is attached to
+        for fd in [master.as_raw_fd(), slave.as_raw_fd()] {
+            if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut termios) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        termios.c_oflag &= !libc::ONLCR;
+        if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    if let Some(uid) = owner {
+        hand_over(&slave, uid)?;
+    }
+
+    Ok((master, slave))
+}
+
+/// Hand a terminal over to a user the way login does: owned by the user and the `tty` group,
+/// mode 0620.
+fn hand_over(tty: &File, uid: libc::uid_t) -> io::Result<()> {
+    // if there is no tty group, leave the group as is
+    let gid = group_id("tty")?.unwrap_or(libc::gid_t::MAX);
+    unsafe {
+        if libc::fchown(tty.as_raw_fd(), uid, gid) < 0 || libc::fchmod(tty.as_raw_fd(), 0o620) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Start a new session and make the terminal open on fd the controlling terminal of it. This
+/// only performs raw syscalls, so it is safe to call in between fork and exec.
+pub(crate) fn set_controlling_tty(fd: RawFd) -> io::Result<()> {
+    unsafe {
+        if libc::setsid() < 0 || libc::ioctl(fd, libc::TIOCSCTTY as _, 1) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// A sensible `TERM` value for a terminal device: virtual consoles are `linux`, anything else is
+/// most likely a serial line.
+pub(crate) fn term_for(path: &str) -> &'static str {
+    let name = Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("");
+    if name.starts_with("tty") && name[3..].chars().all(|c| c.is_ascii_digit()) && name.len() > 3 {
+        "linux"
+    } else {
+        "vt220"
+    }
+}
@@ -0,0 +1,152 @@
+//! Accounting of the resources the reaper itself uses. A leak in init only shows once the system
+//! misbehaves, so its memory, file descriptors and threads are recorded periodically, and a
+//! warning is logged when one of them crosses its threshold.
+
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::stats::ReaperStats;
+
+/// How often the resource usage is recorded.
+const USAGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Thresholds on the resources the reaper uses, above which a warning is logged. No thresholds
+/// are set by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UsageLimits {
+    rss_bytes: Option<u64>,
+    open_fds: Option<u64>,
+    threads: Option<u64>,
+}
+
+impl UsageLimits {
+    pub fn new() -> Self {
+        UsageLimits::default()
+    }
+
+    /// Warn once the resident memory of the reaper exceeds the given amount of bytes.
+    pub fn rss_bytes(mut self, bytes: u64) -> Self {
+        self.rss_bytes = Some(bytes);
+        self
+    }
+
+    /// Warn once the reaper has more than the given amount of file descriptors open.
+    pub fn open_fds(mut self, fds: u64) -> Self {
+        self.open_fds = Some(fds);
+        self
+    }
+
+    /// Warn once the reaper runs more than the given amount of threads.
+    pub fn threads(mut self, threads: u64) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+}
+
+/// The resources in use by the reaper at one point in time.
+struct Usage {
+    rss_bytes: u64,
+    open_fds: u64,
+    threads: u64,
+}
+
+impl Usage {
+    fn read() -> io::Result<Usage> {
+        let status = fs::read_to_string("/proc/self/status")?;
+        // lines read like `VmRSS:	    1234 kB`
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|value| value.split_whitespace().next())
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+        // the directory being read is open as well
+        let open_fds = fs::read_dir("/proc/self/fd")?.count().saturating_sub(1) as u64;
+        Ok(Usage {
+            rss_bytes: field("VmRSS:") * 1024,
+            open_fds,
+            threads: field("Threads:"),
+        })
+    }
+}
+
+/// Records the resource usage of the reaper every interval.
+pub(crate) struct UsageMonitor {
+    limits: UsageLimits,
+    next_check: Option<Instant>,
+    // which thresholds are crossed, so they are only reported once
+    exceeded: [bool; 3],
+}
+
+impl UsageMonitor {
+    pub(crate) fn new(limits: UsageLimits) -> Self {
+        UsageMonitor {
+            limits,
+            next_check: None,
+            exceeded: [false; 3],
+        }
+    }
+
+    /// Change the thresholds.
+    pub(crate) fn set_limits(&mut self, limits: UsageLimits) {
+        self.limits = limits;
+    }
+
+    /// When the usage is due to be recorded next.
+    pub(crate) fn next_check(&self) -> Option<Instant> {
+        self.next_check
+    }
+
+    /// Record the resource usage in stats, if it is due, and warn about crossed thresholds.
+    pub(crate) fn check_if_due(&mut self, now: Instant, stats: &mut ReaperStats) {
+        if self.next_check.is_some_and(|next| next > now) {
+            return;
+        }
+        self.next_check = Some(now + USAGE_INTERVAL);
+        let usage = match Usage::read() {
+            Ok(usage) => usage,
+            Err(e) => {
+                debug!("Failed to read the resource usage of the reaper: {}", e);
+                return;
+            }
+        };
+        stats.rss_bytes = usage.rss_bytes;
+        stats.open_fds = usage.open_fds;
+        stats.threads = usage.threads;
+
+        let checks = [
+            (
+                "bytes of resident memory",
+                usage.rss_bytes,
+                self.limits.rss_bytes,
+            ),
+            (
+                "open file descriptors",
+                usage.open_fds,
+                self.limits.open_fds,
+            ),
+            ("threads", usage.threads, self.limits.threads),
+        ];
+        for (idx, (what, value, limit)) in checks.iter().enumerate() {
+            let over = limit.is_some_and(|limit| *value > limit);
+            match (over, self.exceeded[idx]) {
+                (true, false) => warn!(
+                    "The reaper uses {} {}, more than the threshold of {}",
+                    value,
+                    what,
+                    limit.unwrap_or_default()
+                ),
+                (false, true) => info!(
+                    "The reaper is back below the threshold of {} {}",
+                    limit.unwrap_or_default(),
+                    what
+                ),
+                _ => (),
+            }
+            self.exceeded[idx] = over;
+        }
+    }
+}
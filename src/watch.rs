@@ -0,0 +1,145 @@
+//! Watching the files services depend on, such as their configuration, so a service can be
+//! restarted or reloaded when they change. The inotify file descriptor is watched by the event
+//! loop, just like the control socket.
+//!
+//! The directories containing the files are watched instead of the files themselves, since
+//! editors and configuration management tend to replace a file rather than write to it, which
+//! would silently end a watch on the file.
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long it must be quiet after a change before the service is restarted, so a burst of
+/// writes only results in a single restart.
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+const WATCH_MASK: u32 =
+    libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE | libc::IN_DELETE;
+
+pub(crate) struct FileWatcher {
+    fd: libc::c_int,
+    // the watched file names in every watched directory, with the services they belong to
+    watches: HashMap<libc::c_int, Vec<(Vec<u8>, String)>>,
+    // services with changed files, and when to act on them
+    changed: HashMap<String, Instant>,
+}
+
+impl FileWatcher {
+    pub(crate) fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(FileWatcher {
+            fd,
+            watches: HashMap::new(),
+            changed: HashMap::new(),
+        })
+    }
+
+    /// Watch the file at path on behalf of a service.
+    pub(crate) fn watch(&mut self, path: &str, service: &str) -> io::Result<()> {
+        let path = Path::new(path);
+        let (dir, name) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => (dir, name),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} is not a file", path),
+                ))
+            }
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let dir = CString::new(dir.as_os_str().as_bytes())?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, dir.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.watches
+            .entry(wd)
+            .or_default()
+            .push((name.as_bytes().to_vec(), service.to_string()));
+        Ok(())
+    }
+
+    /// Read all pending change events.
+    pub(crate) fn read_events(&mut self, now: Instant) {
+        let mut buf = [0u8; 4096];
+        loop {
+            let len =
+                unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if len < 0 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    io::ErrorKind::WouldBlock => return,
+                    _ => {
+                        warn!("Failed to read file changes: {}", err);
+                        return;
+                    }
+                }
+            }
+
+            let mut offset = 0;
+            let header = std::mem::size_of::<libc::inotify_event>();
+            while offset + header <= len as usize {
+                // the buffer is not necessarily aligned for the event struct
+                let event: libc::inotify_event = unsafe {
+                    std::ptr::read_unaligned(buf[offset..].as_ptr() as *const libc::inotify_event)
+                };
+                let name = &buf[offset + header..offset + header + event.len as usize];
+                // the name is padded with NUL bytes
+                let name = OsStr::from_bytes(name.split(|b| *b == 0).next().unwrap_or(&[]));
+                offset += header + event.len as usize;
+
+                for (file, service) in self.watches.get(&event.wd).into_iter().flatten() {
+                    if file.as_slice() == name.as_bytes() {
+                        debug!("{:?}, watched by {}, changed", name, service);
+                        self.changed.insert(service.clone(), now + DEBOUNCE);
+                    }
+                }
+            }
+        }
+    }
+
+    /// When the next service is due to be acted upon.
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.changed.values().min().copied()
+    }
+
+    /// Take the services of which the files changed, and have been quiet since.
+    pub(crate) fn due(&mut self, now: Instant) -> Vec<String> {
+        let mut due: Vec<String> = self
+            .changed
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(service, _)| service.clone())
+            .collect();
+        due.sort();
+        for service in &due {
+            self.changed.remove(service);
+        }
+        due
+    }
+}
+
+impl AsRawFd for FileWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
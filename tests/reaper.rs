@@ -0,0 +1,90 @@
+//! Drive the reaper through processes acted out by a `FakeProcesses`. The processes of the
+//! services are still spawned for real, but the reaper only learns about them, and what they
+//! leave behind, through the fake table.
+
+use std::time::Duration;
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{getpid, Pid};
+
+use librsinit::process::FakeProcesses;
+use librsinit::{PersistentCommand, Reaper, ReaperEvent};
+
+// far above what pid_max allows, so never a real process
+const ORPHAN: i32 = 1 << 23;
+
+fn step(reaper: &mut Reaper) -> Vec<ReaperEvent> {
+    reaper.step(Duration::from_millis(100)).unwrap()
+}
+
+/// The pid the named service was spawned with, as the reaper told.
+fn spawned(events: &[ReaperEvent], name: &str) -> Pid {
+    events
+        .iter()
+        .find_map(|event| match event {
+            ReaperEvent::Spawned { service, pid } if service == name => Some(*pid),
+            _ => None,
+        })
+        .expect("the service was not spawned")
+}
+
+#[test]
+fn restarts_a_service_which_exits() {
+    let fake = FakeProcesses::new();
+    let mut reaper = Reaper::new().unwrap().process_ops(fake.clone());
+    reaper
+        .start(vec![PersistentCommand::new("sleep", "60")
+            .name("sleeper")
+            .restart_on_error(true)])
+        .unwrap();
+    let first = spawned(&step(&mut reaper), "sleeper");
+
+    fake.spawn(first, getpid());
+    fake.exit(first, 1);
+    let events = step(&mut reaper);
+    let second = spawned(&events, "sleeper");
+    assert_ne!(first, second);
+    assert!(events.iter().any(|event| matches!(
+        event,
+        ReaperEvent::Restarted { service, pid, restarts: 1 }
+            if service == "sleeper" && *pid == second
+    )));
+
+    for pid in [first, second] {
+        let _ = kill(pid, Signal::SIGKILL);
+    }
+}
+
+#[test]
+fn terminates_what_a_failed_service_left_behind() {
+    let fake = FakeProcesses::new();
+    let mut reaper = Reaper::new().unwrap().process_ops(fake.clone());
+    reaper
+        .start(vec![PersistentCommand::new("sleep", "60")
+            .name("leaky")
+            .restart_on_error(false)])
+        .unwrap();
+    let service = spawned(&step(&mut reaper), "leaky");
+
+    // the service forked a process, which was reparented to the reaper when the service failed
+    let orphan = Pid::from_raw(ORPHAN);
+    fake.spawn(service, getpid());
+    fake.spawn(orphan, getpid());
+    fake.exit(service, 1);
+    let events = step(&mut reaper);
+    assert!(fake.signals().contains(&(orphan, Some(Signal::SIGTERM))));
+    assert!(events.iter().any(|event| matches!(
+        event,
+        ReaperEvent::OrphanSignaled { pid, signal: Signal::SIGTERM } if *pid == orphan
+    )));
+
+    fake.exit(orphan, 0);
+    let events = step(&mut reaper);
+    assert!(events.iter().any(|event| matches!(
+        event,
+        ReaperEvent::OrphanKilled(carcass) if carcass.pid == orphan
+    )));
+    assert!(reaper.into_parts().orphans.is_empty());
+
+    let _ = kill(service, Signal::SIGKILL);
+}